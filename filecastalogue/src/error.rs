@@ -10,7 +10,7 @@ use std::ops::FromResidual;
 use std::convert::Infallible;
 
 
-pub trait Payload: Debug + Display {}
+pub trait Payload: Debug + Display + Send + Sync {}
 
 pub type FcResult<T> = std::result::Result<T, Error>;
 
@@ -137,13 +137,31 @@ impl Display for ErrorPathBuf {
 pub enum ErrorKind {
     RepoFileOperationFailed,
     FileAlreadyTracked,
+    IndexInconsistent,
     VersionEntryDoesNotExist,
     VersionEntryAlreadyExists,
     UntrackedFile,
     DoubleDotFileName,
+    InvalidTrackedPath,
     PathDoesNotExistInCollection,
     TestSetupSafetyCheckFailed,
     PuttingFileIntoCollectionFailed,
+    UnsupportedBlobEncoding,
+    UnsupportedHashAlgorithm,
+    EncryptionKeyUnavailable,
+    DecryptionFailed,
+    RepoLocked,
+    ReadOnlyCollection,
+    HttpRequestFailed,
+    ContentHashMismatch,
+    WriteConflict,
+    DatabaseOperationFailed,
+    SyncConflict,
+    InvalidBundle,
+    UnsupportedTarEntry,
+    InvalidMtreeSpec,
+    TargetSystemOperationFailed,
+    TargetSystemConflict,
     Io,
     Serde
 }
@@ -153,13 +171,31 @@ impl ErrorKind {
         match *self {
             ErrorKind::RepoFileOperationFailed => "Repo-file operation failed.",
             ErrorKind::FileAlreadyTracked => "File already tracked.",
+            ErrorKind::IndexInconsistent => "Index with contradicting entries encountered.",
             ErrorKind::VersionEntryDoesNotExist => "Version entry doesn't exist.",
             ErrorKind::VersionEntryAlreadyExists => "Version entry already exists.",
             ErrorKind::UntrackedFile => "Path for which there is no file tracked encountered.",
             ErrorKind::DoubleDotFileName => "Double-dot (..) file name encountered.",
+            ErrorKind::InvalidTrackedPath => "Path not valid for tracking encountered.",
             ErrorKind::PathDoesNotExistInCollection => "Path doesn't exist in collection.",
             ErrorKind::PuttingFileIntoCollectionFailed => "Putting file into collection failed.",
+            ErrorKind::UnsupportedBlobEncoding => "Blob stored with an unsupported encoding encountered.",
+            ErrorKind::UnsupportedHashAlgorithm => "Hash made with an unsupported algorithm encountered.",
+            ErrorKind::EncryptionKeyUnavailable => "Encryption key not available.",
+            ErrorKind::DecryptionFailed => "Decrypting file failed.",
+            ErrorKind::RepoLocked => "Repo locked by someone else.",
+            ErrorKind::ReadOnlyCollection => "Writing to a read-only collection attempted.",
+            ErrorKind::HttpRequestFailed => "HTTP request failed.",
+            ErrorKind::ContentHashMismatch => "File with content not matching its hash encountered.",
+            ErrorKind::WriteConflict => "File changed by someone else since it was read.",
+            ErrorKind::DatabaseOperationFailed => "Database operation failed.",
+            ErrorKind::SyncConflict => "Both repos changed since they were last synced.",
+            ErrorKind::InvalidBundle => "Bundle that's malformed or incomplete encountered.",
+            ErrorKind::UnsupportedTarEntry => "Tar entry of a type that can't be tracked encountered.",
+            ErrorKind::InvalidMtreeSpec => "mtree spec that's malformed or doesn't match the files it describes encountered.",
             ErrorKind::TestSetupSafetyCheckFailed => "Test setup safety check failed.",
+            ErrorKind::TargetSystemOperationFailed => "Operation on the target system failed.",
+            ErrorKind::TargetSystemConflict => "File on the target system can't be brought in line with its tracked aspects.",
             ErrorKind::Io => "Standard IO Error: std::io::Error.",
             ErrorKind::Serde => "Error with JSON (de)serialization: serde_json::Error.",
         }
@@ -280,13 +316,29 @@ impl StdError for Error {
 }
 
 impl From<io::Error> for Error {
+    /// An io::Error carrying an Error, see `From<Error> for io::Error`,
+    /// gives back the Error it carries, so its kind isn't lost.
     fn from(e: io::Error) -> Self {
-        Error::new(
-            ErrorKind::Io, 
-            "<converted from std::io::Error>",
-            None,
-            Some(WrappedError::Io(e))
-        )
+        match e.downcast::<Error>() {
+            Ok(carried_error) => carried_error,
+            Err(e) => Error::new(
+                ErrorKind::Io, 
+                "<converted from std::io::Error>",
+                None,
+                Some(WrappedError::Io(e))
+            )
+        }
+    }
+}
+
+/* Notes:
+    This is for getting an Error through code that only deals in
+    io::Errors, like a Read or Write implementation, e.g. one which
+    verifies what's read, without it being reduced to ErrorKind::Io.
+*/
+impl From<Error> for io::Error {
+    fn from(e: Error) -> Self {
+        io::Error::other(e)
     }
 }
 
@@ -304,7 +356,7 @@ impl From<serde_json::Error> for Error {
 pub trait KeyValuePayloadValue: Debug + Display {}
 
 pub struct KeyValuePayload {
-    store: HashMap<String, Box<dyn Debug>>
+    store: HashMap<String, Box<dyn Debug + Send + Sync>>
 }
 
 impl KeyValuePayload {
@@ -317,7 +369,7 @@ impl KeyValuePayload {
     <
         KeyRef: AsRef<str>,
     >
-    (mut self, key: KeyRef, value: Box<(dyn Debug)>) -> Self {
+    (mut self, key: KeyRef, value: Box<(dyn Debug + Send + Sync)>) -> Self {
         self.store.insert(key.as_ref().to_owned(), value);
         self
     }
@@ -2,6 +2,7 @@ use std::{fmt::{self, Debug, Display}, io::{Read, Write}};
 use crate::error::{FcResult, Payload};
 
 pub mod blob;
+pub mod blob_encoding;
 pub mod tracked_ordinary_blob;
 pub mod index;
 pub mod state;
@@ -212,6 +213,8 @@ files at the end of the day doesn't really concern us here.
  */
 pub trait RepoFile {
     fn load(self: &mut Self, readable: &mut (dyn Read)) -> FcResult<()>;
+    /// Writes the file out in its entirety, flushing the Write once done,
+    /// as that's how some collections tell that a file is complete.
     fn save(self: &mut Self, writeable: &mut dyn Write) -> FcResult<()>;
 }
 
@@ -1,14 +1,35 @@
+use std::fs::File;
+use std::io::{Read, Seek};
 use crate::{error::FcResult, meta::blob::model::Blob};
-use super::hashable::Hashable;
+use super::hashable::{Hashable, hash_readable};
 
 /// Intended for access to the actual binary of either a tracked
 /// or an index file, e.g. for hashing or applying state.
 /// It's also used to provide access to the blob packaged or
 /// referenced in the data returned from file getter functions
 /// of Repo.
+/// 
+/// `get_readable` is the way to get at the blob without having to hold
+/// all of it in memory, which is what anything potentially dealing with
+/// large files should use. `clone_blob` and `into_blob` are conveniences
+/// for when that's not a concern, e.g. for indexes.
 pub trait BlobProvider {
     fn clone_blob(&self) -> FcResult<Blob>;
     fn into_blob(self: Box<Self>) -> FcResult<Blob>;
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>>;
+}
+
+/// Writes a blob to an anonymous temporary file using `write`, returning
+/// the file, rewound, along with the length of what was written. This is
+/// for writing blobs somewhere their length has to be known before they
+/// are, like into a tar archive, without holding them in memory.
+pub fn spool_blob<Writing: FnOnce(&mut File) -> FcResult<()>>(write: Writing)
+-> FcResult<(File, u64)> {
+    let mut file = tempfile::tempfile()?;
+    write(&mut file)?;
+    let length = file.stream_position()?;
+    file.rewind()?;
+    Ok((file, length))
 }
 
 /*
@@ -23,6 +44,7 @@ pub trait BlobProvider {
 /// 
 /// Example:
 /// ```
+/// use std::io::{empty, Read};
 /// use filecastalogue::{
 ///     error::FcResult,
 ///     meta::blob::model::Blob,
@@ -39,6 +61,9 @@ pub trait BlobProvider {
 ///         let example_blob = Blob::default();
 ///         Ok(example_blob)
 ///     }
+///     fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+///         Ok(Box::new(empty()))
+///     }
 /// }
 /// impl Hashable for Example {
 ///     fn get_hash(&self) -> FcResult<String> {
@@ -52,6 +77,6 @@ pub trait BlobProvider {
 /// gain access to the herein implemented method get_hash.
 impl<'maybe_not_static> Hashable for dyn BlobProvider + 'maybe_not_static {
     fn get_hash(&self) -> FcResult<String> {
-        Ok(blake3::hash(&self.clone_blob()?).to_hex().to_string())
+        hash_readable(&mut self.get_readable()?)
     }
 }
\ No newline at end of file
@@ -0,0 +1,250 @@
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+use crate::error::{Error, ErrorKind, FcResult, Payload};
+use crate::opaque_collection_handler::ReadableSource;
+
+/* Notes:
+    The magic starts with a NUL byte, which keeps it clear of the text
+    files that make up the bulk of what's usually tracked. It could still
+    occur at the start of a binary blob, which is why a blob stored
+    uncompressed that happens to start with it gets a header as well,
+    marking it as not encoded. That way the header is never ambiguous,
+    while blobs stored without one stay exactly as they are, which is how
+    all blobs were stored before there was compression.
+*/
+/// Marks a blob stored in a blob collection as having a header, which is
+/// the magic followed by a single byte identifying the encoding of what
+/// follows it.
+pub const BLOB_HEADER_MAGIC: &[u8; 7] = b"\0fcblob";
+pub const BLOB_HEADER_LENGTH: usize = BLOB_HEADER_MAGIC.len() + 1;
+
+const ENCODING_ID_NONE: u8 = 0;
+const ENCODING_ID_ZSTD: u8 = 1;
+
+/// How blobs put into a blob collection are to be stored.
+///
+/// Either way, blobs are addressed by the hash of their uncompressed
+/// content, and blobs stored with any of these can be read regardless of
+/// what the collection is currently set to, so changing it only affects
+/// blobs put from then on.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BlobCompression {
+    None,
+    /// zstd with the specified compression level.
+    Zstd { level: i32 },
+}
+
+impl Default for BlobCompression {
+    /// None, as that's how blobs have been stored before there was
+    /// compression.
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+pub struct UnsupportedBlobEncodingErrorPayload {
+    pub encoding_id: u8,
+}
+
+impl fmt::Debug for UnsupportedBlobEncodingErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for UnsupportedBlobEncodingErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Blob header names unknown encoding ID: {}.", self.encoding_id)
+    }
+}
+
+impl Payload for UnsupportedBlobEncodingErrorPayload {}
+
+/// Reads until `buf` is full or `readable` ends, returning how much
+/// was read.
+pub(crate) fn read_up_to(readable: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut read_count = 0;
+    while read_count < buf.len() {
+        match readable.read(&mut buf[read_count..]) {
+            Ok(0) => break,
+            Ok(count) => read_count += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(read_count)
+}
+
+fn write_header(writeable: &mut dyn Write, encoding_id: u8) -> io::Result<()> {
+    writeable.write_all(BLOB_HEADER_MAGIC)?;
+    writeable.write_all(&[encoding_id])
+}
+
+/// Writes everything `readable` provides to `writeable`, encoded
+/// as specified by `compression`, including the header, if any.
+pub fn encode_blob(
+    readable: &mut dyn Read,
+    writeable: &mut dyn Write,
+    compression: BlobCompression
+) -> FcResult<()> {
+    match compression {
+        BlobCompression::None => {
+            let mut start = [0u8; BLOB_HEADER_MAGIC.len()];
+            let start_length = read_up_to(readable, &mut start)?;
+            if start[..start_length] == BLOB_HEADER_MAGIC[..] {
+                write_header(writeable, ENCODING_ID_NONE)?;
+            }
+            writeable.write_all(&start[..start_length])?;
+            io::copy(readable, writeable)?;
+        },
+        BlobCompression::Zstd { level } => {
+            write_header(writeable, ENCODING_ID_ZSTD)?;
+            let mut encoder = zstd::Encoder::new(writeable, level)?;
+            io::copy(readable, &mut encoder)?;
+            encoder.finish()?;
+        }
+    }
+    Ok(())
+}
+
+/// Provides the uncompressed content of a blob as stored in a blob
+/// collection, whichever way it was stored.
+pub struct DecodingSource {
+    source: Box<dyn ReadableSource>
+}
+
+impl DecodingSource {
+    pub fn new(source: Box<dyn ReadableSource>) -> Self {
+        Self {
+            source
+        }
+    }
+}
+
+impl ReadableSource for DecodingSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        let mut readable = self.source.open()?;
+        let mut header = [0u8; BLOB_HEADER_LENGTH];
+        let header_length = read_up_to(&mut readable, &mut header)?;
+        if header_length < BLOB_HEADER_LENGTH
+        || header[..BLOB_HEADER_MAGIC.len()] != BLOB_HEADER_MAGIC[..] {
+            // No header, so what we've read is part of the blob.
+            return Ok(Box::new(
+                Cursor::new(header[..header_length].to_vec()).chain(readable)
+            ))
+        }
+        match header[BLOB_HEADER_MAGIC.len()] {
+            ENCODING_ID_NONE => Ok(readable),
+            ENCODING_ID_ZSTD => Ok(Box::new(zstd::Decoder::new(readable)?)),
+            encoding_id => Err(error!(
+                ErrorKind::UnsupportedBlobEncoding,
+                "Opening blob to decode it.",
+                payload => UnsupportedBlobEncodingErrorPayload { encoding_id }
+            )),
+        }
+    }
+}
+
+/// How a `DecodingWriter` passes on what's written to it.
+enum DecodingWriterState<W: Write> {
+    /// It isn't known yet whether there's a header, so what's been
+    /// written so far is held back.
+    Starting(W, Vec<u8>),
+    Plain(W),
+    Zstd(zstd::stream::write::Decoder<'static, W>),
+}
+
+fn get_decoding_failed_before_error() -> io::Error {
+    io::Error::other("Decoding the blob failed before.")
+}
+
+/* Notes:
+    This is `DecodingSource` the other way around, for blobs that are
+    pushed rather than read, e.g. to verify one while passing it on as
+    it's stored, without holding it in memory.
+*/
+/// Writes the uncompressed content of a blob into the wrapped Write as
+/// the blob, as stored in a blob collection, is written to it, whichever
+/// way it was stored.
+pub struct DecodingWriter<W: Write> {
+    /// None once starting to decode failed.
+    state: Option<DecodingWriterState<W>>,
+}
+
+impl<W: Write> DecodingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            state: Some(DecodingWriterState::Starting(inner, vec!())),
+        }
+    }
+
+    /// Decides how to decode what's written, going by what's been held
+    /// back, which is all there is if it's shorter than a header.
+    fn start_decoding(&mut self) -> io::Result<()> {
+        let (mut inner, start) = match self.state.take() {
+            Some(DecodingWriterState::Starting(inner, start)) => (inner, start),
+            state => {
+                self.state = state;
+                return Ok(())
+            },
+        };
+        if start.len() < BLOB_HEADER_LENGTH
+        || start[..BLOB_HEADER_MAGIC.len()] != BLOB_HEADER_MAGIC[..] {
+            // No header, so what's been held back is part of the blob.
+            inner.write_all(&start)?;
+            self.state = Some(DecodingWriterState::Plain(inner));
+            return Ok(())
+        }
+        self.state = Some(match start[BLOB_HEADER_MAGIC.len()] {
+            ENCODING_ID_NONE => DecodingWriterState::Plain(inner),
+            ENCODING_ID_ZSTD => DecodingWriterState::Zstd(
+                zstd::stream::write::Decoder::new(inner)?),
+            encoding_id => return Err(io::Error::from(error!(
+                ErrorKind::UnsupportedBlobEncoding,
+                "Starting to decode a blob as it's written.",
+                payload => UnsupportedBlobEncodingErrorPayload { encoding_id }
+            ))),
+        });
+        Ok(())
+    }
+
+    /// Passes on whatever is still held back, once all of the blob has
+    /// been written, returning the wrapped Write.
+    pub fn finish(&mut self) -> io::Result<&mut W> {
+        self.start_decoding()?;
+        match self.state.as_mut() {
+            Some(DecodingWriterState::Plain(inner)) => Ok(inner),
+            Some(DecodingWriterState::Zstd(decoder)) => {
+                decoder.flush()?;
+                Ok(decoder.get_mut())
+            },
+            _ => Err(get_decoding_failed_before_error()),
+        }
+    }
+}
+
+impl<W: Write> Write for DecodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(DecodingWriterState::Starting(_, start)) = self.state.as_mut() {
+            let taken_count = buf.len().min(BLOB_HEADER_LENGTH - start.len());
+            start.extend_from_slice(&buf[..taken_count]);
+            if start.len() == BLOB_HEADER_LENGTH {
+                self.start_decoding()?;
+            }
+            return Ok(taken_count)
+        }
+        match self.state.as_mut() {
+            Some(DecodingWriterState::Plain(inner)) => inner.write(buf),
+            Some(DecodingWriterState::Zstd(decoder)) => decoder.write(buf),
+            _ => Err(get_decoding_failed_before_error()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.state.as_mut() {
+            Some(DecodingWriterState::Plain(inner)) => inner.flush(),
+            Some(DecodingWriterState::Zstd(decoder)) => decoder.flush(),
+            _ => Ok(()),
+        }
+    }
+}
@@ -1,5 +1,253 @@
-use crate::error::FcResult;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Read, Write};
+use serde::{Serialize, Deserialize};
+use sha2::Digest;
+use crate::error::{Error, ErrorKind, FcResult, Payload};
 
 pub trait Hashable {
     fn get_hash(&self) -> FcResult<String>;
-}
\ No newline at end of file
+}
+
+/* Notes:
+    Hashes are identified as "<algorithm>:<hex digest>", e.g.
+    "sha256:e3b0c442…", so a hash always tells how it came about and
+    algorithms can be added or retired without ambiguity. Hashes without
+    a prefix predate this and are blake3.
+*/
+/// The algorithms blobs, indexes and the like can be hashed with.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[serde(rename_all(
+    serialize = "snake_case",
+    deserialize = "snake_case"
+))]
+pub enum HashAlgorithm {
+    Blake3,
+    Sha256,
+}
+
+impl HashAlgorithm {
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Self::Blake3 => "blake3",
+            Self::Sha256 => "sha256",
+        }
+    }
+
+    /// Returns the algorithm the specified hash was made with.
+    pub fn of_hash(hash: &str) -> FcResult<Self> {
+        match hash.split_once(':') {
+            None => Ok(Self::Blake3),
+            Some(("blake3", _)) => Ok(Self::Blake3),
+            Some(("sha256", _)) => Ok(Self::Sha256),
+            Some((algorithm, _)) => Err(error!(
+                ErrorKind::UnsupportedHashAlgorithm,
+                "Getting the algorithm of a hash.",
+                payload => UnsupportedHashAlgorithmErrorPayload {
+                    hash: hash.to_owned(),
+                    algorithm: algorithm.to_owned()
+                }
+            )),
+        }
+    }
+
+    /// Hashes everything `readable` provides until it ends.
+    pub fn hash_readable(&self, readable: &mut dyn Read) -> FcResult<String> {
+        let mut hashing_readable = HashingReader::new_with_algorithm(readable, *self);
+        io::copy(&mut hashing_readable, &mut io::sink())?;
+        Ok(hashing_readable.finalize())
+    }
+}
+
+impl Default for HashAlgorithm {
+    /// Blake3, as that's what all hashes were before there was a choice.
+    fn default() -> Self {
+        Self::Blake3
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub struct UnsupportedHashAlgorithmErrorPayload {
+    pub hash: String,
+    pub algorithm: String,
+}
+
+impl fmt::Debug for UnsupportedHashAlgorithmErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for UnsupportedHashAlgorithmErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Hash: {}, algorithm: {}.", self.hash, self.algorithm)
+    }
+}
+
+impl Payload for UnsupportedHashAlgorithmErrorPayload {}
+
+/// Returns the hash the way it would be written now, i.e. with the
+/// algorithm prefix also for hashes which predate those.
+pub fn normalize_hash(hash: &str) -> String {
+    match hash.contains(':') {
+        true => hash.to_owned(),
+        false => format!("{}:{}", HashAlgorithm::Blake3, hash),
+    }
+}
+
+/// What separates the algorithm from the digest in the names of files
+/// named after hashes, see `get_file_name_of_hash`.
+pub const FILE_NAME_HASH_SEPARATOR: char = '-';
+
+/* Notes:
+    Hashes themselves, e.g. in the state or an index, keep their ":",
+    it's only the names of files in collections that do without it, as
+    ":" isn't allowed in file names everywhere, e.g. on Windows, and
+    is asking for trouble in object keys and archive entries.
+*/
+/// Returns the name a file named after the hash is stored under in a
+/// collection, e.g. "blake3-af13…" for "blake3:af13…". Hashes without
+/// an algorithm prefix are the name as they are.
+pub fn get_file_name_of_hash(hash: &str) -> OsString {
+    OsString::from(hash.replacen(':', &FILE_NAME_HASH_SEPARATOR.to_string(), 1))
+}
+
+/// Returns the hash a file in a collection is named after, undoing
+/// `get_file_name_of_hash`, if it's named after one at all.
+pub fn get_hash_of_file_name(file_name: &OsStr) -> Option<String> {
+    let file_name = file_name.to_str()?;
+    let (hash, digest) = match file_name.split_once(FILE_NAME_HASH_SEPARATOR) {
+        Some((algorithm, digest)) => (format!("{}:{}", algorithm, digest), digest),
+        None => (file_name.to_owned(), file_name),
+    };
+    match !digest.is_empty() && digest.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        true => Some(hash),
+        false => None,
+    }
+}
+
+/// Hashes everything `readable` provides until it ends, with the
+/// default algorithm.
+/// 
+/// This is the single source of process for turning the contents of a
+/// blob into its hash, whether it's one of our own or one found
+/// elsewhere, e.g. on a target system we're comparing against.
+/// `HashAlgorithm::hash_readable` is the same for other algorithms.
+pub fn hash_readable(readable: &mut dyn Read) -> FcResult<String> {
+    HashAlgorithm::default().hash_readable(readable)
+}
+
+enum Hasher {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(sha2::Sha256),
+}
+
+impl Hasher {
+
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::new(blake3::Hasher::new())),
+            HashAlgorithm::Sha256 => Self::Sha256(sha2::Sha256::new()),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        match self {
+            Self::Blake3(hasher) => { hasher.update(bytes); },
+            Self::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+
+    fn finalize(&self, algorithm: HashAlgorithm) -> String {
+        let hex_digest = match self {
+            Self::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Self::Sha256(hasher) => hex::encode(hasher.clone().finalize()),
+        };
+        format!("{}:{}", algorithm, hex_digest)
+    }
+}
+
+/// Passes through whatever the wrapped Read provides, hashing it on
+/// the way, which allows for hashing a blob while copying it somewhere
+/// else, without having to hold it in memory or read it twice.
+/// 
+/// The hash is the same `hash_readable` would produce for the same blob.
+pub struct HashingReader<R: Read> {
+    inner: R,
+    algorithm: HashAlgorithm,
+    hasher: Hasher,
+}
+
+impl<R: Read> HashingReader<R> {
+
+    pub fn new(inner: R) -> Self {
+        Self::new_with_algorithm(inner, HashAlgorithm::default())
+    }
+
+    pub fn new_with_algorithm(inner: R, algorithm: HashAlgorithm) -> Self {
+        Self {
+            inner,
+            algorithm,
+            hasher: Hasher::new(algorithm)
+        }
+    }
+
+    /// Returns the wrapped Read.
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Returns the hash of everything read so far.
+    pub fn finalize(&self) -> String {
+        self.hasher.finalize(self.algorithm)
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_count = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read_count]);
+        Ok(read_count)
+    }
+}
+
+/// Hashes whatever is written to it, for content that's pushed rather
+/// than read, e.g. as it comes out of a decoder.
+/// 
+/// The hash is the same `hash_readable` would produce for the same blob.
+pub struct HashingWriter {
+    algorithm: HashAlgorithm,
+    hasher: Hasher,
+}
+
+impl HashingWriter {
+
+    pub fn new_with_algorithm(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            hasher: Hasher::new(algorithm)
+        }
+    }
+
+    /// Returns the hash of everything written so far.
+    pub fn finalize(&self) -> String {
+        self.hasher.finalize(self.algorithm)
+    }
+}
+
+impl Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.hasher.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -7,7 +7,7 @@ use crate::files::OffendingAction;
 use crate::meta::blob::model::Blob;
 use crate::{error::FcResult, meta::index::model::Index};
 use std::convert::TryInto;
-use std::io::{Read, Write};
+use std::io::{Cursor, Read, Write};
 use crate::files::hashable::Hashable;
 use super::{RepoFile, blob::BlobProvider};
 
@@ -113,8 +113,8 @@ impl RepoFile for IndexFile {
 
     /// Serialize the index as we're currently holding it to a Write.
     fn save(self: &mut Self, writeable: &mut (dyn Write)) -> FcResult<()> {
-        let blob: Blob = self.index.clone().try_into()?;
-        match writeable.write(&blob) {
+        let blob: Blob = (&self.index).try_into()?;
+        match writeable.write_all(&blob).and_then(|_| writeable.flush()) {
             Ok(_) => Ok(()),
             Err(error) => Err(access_repo_file_error!(
                 OffendingAction::SavingRepoFile,
@@ -143,13 +143,18 @@ impl IndexProvider for IndexFile {
 impl BlobProvider for IndexFile {
 
     fn clone_blob(&self) -> FcResult<Blob> {
-        Ok(self.index.clone().try_into()?)
+        (&self.index).try_into()
     }
 
     fn into_blob(self: Box<Self>) -> FcResult<Blob> {
         Ok(self.index.try_into()?)
     }
-    
+
+    /// Serializes the index without cloning it, so only the serialized
+    /// form is held in memory on top of the index itself.
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+        Ok(Box::new(Cursor::new(self.clone_blob()?.into_vec())))
+    }
 }
 
 impl Hashable for IndexFile {
@@ -1,6 +1,9 @@
 use crate::error::{Error, ErrorKind, FcResult, KeyValuePayload, WrappedError};
-use super::{index::{IndexFile, RepoIndexFile}};
-use std::ffi::OsStr;
+use super::{hashable::{HashAlgorithm, get_file_name_of_hash}, index::{IndexFile, RepoIndexFile}};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use crate::meta::state::model::State;
+use crate::meta::version::accessor::VersionAccessor;
 use crate::opaque_collection_handler::OpaqueCollectionHandler;
 
 
@@ -11,8 +14,16 @@ pub trait IndexFileCollection {
     fn get_index_file(self: &mut Self, index: &str)
     -> FcResult<Box<(dyn RepoIndexFile)>>;
     fn put_index_file<'putting>(
-        self: &mut Self, index_file: Box<dyn RepoIndexFile>)
+        self: &mut Self, index_file: Box<dyn RepoIndexFile>,
+        hash_algorithm: HashAlgorithm)
     -> FcResult<String>;
+    /// Gets the index as it's stored, without deserializing it.
+    fn get_index_readable(&mut self, index: &str) -> FcResult<Box<dyn Read + '_>>;
+    /// Stores whatever `readable` provides as an index, as it is,
+    /// returning its hash, made with the specified algorithm.
+    fn put_index_readable(&mut self, readable: &mut dyn Read, hash_algorithm: HashAlgorithm)
+    -> FcResult<String>;
+    fn remove_index(&mut self, hash: &str) -> FcResult<()>;
 }
 
 // TODO: Evaluate the nature of this struct, as "its "local"
@@ -36,13 +47,62 @@ impl<Handler: OpaqueCollectionHandler> MiscIndexFileCollection<Handler> {
             handler: handler
         }
     }
+
+    /* Notes:
+        Each index file is copied under a temporary name first and only
+        renamed to its actual name once it's complete, and it's only removed
+        from `source` after that. If this gets interrupted, running it again
+        picks up where it left off, finishing the removal of index files
+        already moved along the way.
+
+        Index files are moved as they are, so they keep their names and
+        `state` doesn't need to change.
+    */
+    /// Moves the index files the versions in `state` refer to from `source`
+    /// into this collection, returning how many were moved.
+    ///
+    /// This is meant for repos which still keep their index files in their
+    /// blob collection, as all repos used to. Index files which aren't in
+    /// `source` are left alone, as they might already be in this collection.
+    pub fn migrate_indexes_from<Source: OpaqueCollectionHandler>(
+        &mut self, source: &mut Source, state: &State
+    ) -> FcResult<usize> {
+        self.handler.create_collection_ignore_exists()?;
+        let mut moved_count = 0;
+        for version in state.versions.iter() {
+            let index_id = match version.get_index_id() {
+                Some(index_id) => index_id,
+                None => continue,
+            };
+            let file_name = get_file_name_of_hash(&index_id);
+            if !source.has_file(&file_name)? {
+                continue
+            }
+            if !self.handler.has_file(&file_name)? {
+                let mut tmp_name = OsString::from(".tmp-migrating-");
+                tmp_name.push(&file_name);
+                self.handler.create_file(&tmp_name)?;
+                let mut writeable = self.handler.get_file_writeable(&tmp_name)?;
+                io::copy(
+                    &mut source.get_file_readable(&file_name)?,
+                    &mut writeable
+                )?;
+                writeable.flush()?;
+                drop(writeable);
+                self.handler.rename_file(&tmp_name, &file_name)?;
+                moved_count += 1;
+            }
+            source.remove_file(&file_name)?;
+        }
+        Ok(moved_count)
+    }
 }
 
 impl<
     Handler: OpaqueCollectionHandler
 > IndexFileCollection for MiscIndexFileCollection<Handler> {
     fn has_index(self: &mut Self, index: &str) -> FcResult<bool> {
-        self.handler.has_file(index)
+        self.handler.has_file(get_file_name_of_hash(index))
     }
 
     fn create_unwritten_empty_index_file_box(&self)
@@ -53,16 +113,15 @@ impl<
     /// Get an index file from the collection.
     fn get_index_file(self: &mut Self, hash: &str)
     -> FcResult<Box<(dyn RepoIndexFile)>> {
-        let mut reader = self.handler.get_file_readable(
-            OsStr::new(hash)
-        )?;
+        let file_name = get_file_name_of_hash(hash);
+        let mut reader = self.handler.get_file_readable(&file_name)?;
         match IndexFile::from_existing(&mut reader) {
             Ok(index_file) => Ok(Box::new(index_file)),
             Err(e) => Err(Error::new(
                 ErrorKind::RepoFileOperationFailed,
                 "Reading and deserializing the contents of \
                 an index file from an index file collection.",
-                Some(Box::new(self.handler.get_debug_info_for_file(hash))),
+                Some(Box::new(self.handler.get_debug_info_for_file(&file_name))),
                 Some(WrappedError::Fc(Box::new(e)))
             )),
         }
@@ -72,14 +131,15 @@ impl<
     /// This will get the hash of the file's contents, write them to
     /// to a file with the hash for a name and return the hash.
     fn put_index_file(
-        self: &mut Self, index_file: Box<dyn RepoIndexFile>)
+        self: &mut Self, index_file: Box<dyn RepoIndexFile>,
+        hash_algorithm: HashAlgorithm)
     -> FcResult<String> {
-        let hash = index_file.get_hash()?;
-        if !self.handler.has_file(&hash)? {
-            self.handler.create_file(&hash)?
+        let hash = hash_algorithm.hash_readable(&mut index_file.get_readable()?)?;
+        let file_name = get_file_name_of_hash(&hash);
+        if !self.handler.has_file(&file_name)? {
+            self.handler.create_file(&file_name)?
         };
-        let mut writeable = self.handler.get_file_writeable(
-            OsStr::new(&hash))?;
+        let mut writeable = self.handler.get_file_writeable(&file_name)?;
         // TODO: This doesn't look right. ^^"
         let mut index_file = index_file;
         match index_file.save(&mut writeable) {
@@ -92,4 +152,31 @@ impl<
             ))
         }
     }
+
+    fn get_index_readable(&mut self, index: &str) -> FcResult<Box<dyn Read + '_>> {
+        self.handler.get_file_readable(&get_file_name_of_hash(index))
+    }
+
+    /* Notes:
+        Indexes are small, so unlike blobs they're simply read into memory
+        to be hashed before they're written under their hash.
+    */
+    fn put_index_readable(&mut self, readable: &mut dyn Read, hash_algorithm: HashAlgorithm)
+    -> FcResult<String> {
+        let mut content = vec!();
+        readable.read_to_end(&mut content)?;
+        let hash = hash_algorithm.hash_readable(&mut content.as_slice())?;
+        let file_name = get_file_name_of_hash(&hash);
+        if !self.handler.has_file(&file_name)? {
+            self.handler.create_file(&file_name)?
+        };
+        let mut writeable = self.handler.get_file_writeable(&file_name)?;
+        writeable.write_all(&content)?;
+        writeable.flush()?;
+        Ok(hash)
+    }
+
+    fn remove_index(&mut self, hash: &str) -> FcResult<()> {
+        self.handler.remove_file(&get_file_name_of_hash(hash))
+    }
 }
@@ -96,14 +96,19 @@ impl RepoFile for StateFile {
     fn save(self: &mut Self, writeable: &mut dyn Write) -> FcResult<()> {
 
         let blob: Blob = self.state.clone().try_into()?;
-        match writeable.write(&blob) {
+        match writeable.write_all(&blob).and_then(|_| writeable.flush()) {
             Ok(_) => Ok(()),
-            Err(io_error) => Err(access_repo_file_error!(
-                OffendingAction::SavingRepoFile,
-                context => "Trying to save state to a Write.",
-                variety => "State",
-                wrapped => WrappedError::Io(io_error)
-            ))
+            // E.g. an `ErrorKind::WriteConflict` of a collection writing
+            // conditionally, which is what whoever saves has to know about.
+            Err(io_error) => match io_error.downcast::<Error>() {
+                Ok(carried_error) => Err(carried_error),
+                Err(io_error) => Err(access_repo_file_error!(
+                    OffendingAction::SavingRepoFile,
+                    context => "Trying to save state to a Write.",
+                    variety => "State",
+                    wrapped => WrappedError::Io(io_error)
+                )),
+            }
         }
     }
 }
@@ -1,5 +1,6 @@
-use std::{convert::{TryInto}, io::{Read, Write}};
-use crate::{error::{FcResult}, meta::blob::model::Blob};
+use std::{convert::{TryInto}, io::{self, Cursor, Read, Write}};
+use crate::{error::{FcResult}, meta::blob::model::Blob,
+    opaque_collection_handler::ReadableSource};
 use super::{RepoFile, blob::BlobProvider, hashable::Hashable};
 
 /* Notes:
@@ -44,7 +45,8 @@ impl RepoFile for TrackedOrdinaryBlobFile {
     }
 
     fn save(self: &mut Self, writeable: &mut (dyn Write))-> FcResult<()> {
-        writeable.write(&self.blob)?;
+        writeable.write_all(&self.blob)?;
+        writeable.flush()?;
         Ok(())
     }
 }
@@ -59,7 +61,9 @@ impl BlobProvider for TrackedOrdinaryBlobFile {
         Ok(self.blob)
     }
 
-    
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+        Ok(Box::new(Cursor::new(&self.blob[..])))
+    }
 }
 
 impl Hashable for TrackedOrdinaryBlobFile {
@@ -86,3 +90,85 @@ impl RepoTrackedOrdinaryBlobFile for TrackedOrdinaryBlobFile {
         self
     }
 }
+
+/// A Tracked blob file which isn't held in memory, but read from
+/// wherever it's stored whenever its blob is needed.
+/// 
+/// This is what blob collections hand out, so that getting at a blob
+/// doesn't require memory in proportion to its size, unless explicitly
+/// asked for with `clone_blob` or `into_blob`.
+pub struct SourcedTrackedOrdinaryBlobFile {
+    source: Box<dyn ReadableSource>
+}
+
+impl SourcedTrackedOrdinaryBlobFile {
+    pub fn new(source: Box<dyn ReadableSource>) -> Self {
+        Self {
+            source
+        }
+    }
+}
+
+/// Makes a Blob usable as a ReadableSource, which is what a
+/// SourcedTrackedOrdinaryBlobFile ends up with when loaded from a Read.
+struct HeapSource {
+    blob: Blob
+}
+
+impl ReadableSource for HeapSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(Box::new(Cursor::new(self.blob.clone().into_vec())))
+    }
+}
+
+impl RepoFile for SourcedTrackedOrdinaryBlobFile {
+    fn load(&mut self, readable: &mut dyn Read) -> FcResult<()> {
+        self.source = Box::new(HeapSource {
+            blob: readable.try_into()?
+        });
+        Ok(())
+    }
+
+    fn save(&mut self, writeable: &mut dyn Write) -> FcResult<()> {
+        io::copy(&mut self.source.open()?, writeable)?;
+        writeable.flush()?;
+        Ok(())
+    }
+}
+
+impl BlobProvider for SourcedTrackedOrdinaryBlobFile {
+
+    fn clone_blob(&self) -> FcResult<Blob> {
+        let mut blob = Blob::default();
+        self.source.open()?.read_to_end(&mut blob)?;
+        Ok(blob)
+    }
+
+    fn into_blob(self: Box<Self>) -> FcResult<Blob> {
+        self.clone_blob()
+    }
+
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+        self.source.open()
+    }
+}
+
+impl Hashable for SourcedTrackedOrdinaryBlobFile {
+    fn get_hash(&self) -> FcResult<String> {
+        (self as &dyn BlobProvider).get_hash()
+    }
+}
+
+impl TrackedOrdinaryBlobProvider for SourcedTrackedOrdinaryBlobFile {}
+
+impl RepoTrackedOrdinaryBlobFile for SourcedTrackedOrdinaryBlobFile {
+    fn as_tracked_ordinary_blob_provider_ref(&self)
+    -> &dyn TrackedOrdinaryBlobProvider {
+        self
+    }
+
+    fn as_tracked_ordinary_blob_provider_box(self: Box<Self>)
+    -> Box<dyn TrackedOrdinaryBlobProvider> {
+        self
+    }
+}
@@ -1,56 +1,142 @@
 use std::ffi::{OsStr, OsString};
+use std::io::{Read, Write};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use crate::{error::FcResult,
-    opaque_collection_handler::OpaqueCollectionHandler};
-use super::tracked_ordinary_blob::{RepoTrackedOrdinaryBlobFile, TrackedOrdinaryBlobFile};
+    opaque_collection_handler::{OpaqueCollectionHandler, VerifyingSource}};
+use super::blob_encoding::{BlobCompression, DecodingSource, encode_blob};
+use super::hashable::{HashAlgorithm, HashingReader, get_file_name_of_hash};
+use super::tracked_ordinary_blob::{RepoTrackedOrdinaryBlobFile,
+    SourcedTrackedOrdinaryBlobFile};
 
 pub trait TrackedOrdinaryBlobFileCollection {
     fn has_file(self: &mut Self, hash: &str) -> FcResult<bool>;
     fn get_file(self: &mut Self, hash: &str)
     -> FcResult<Box<dyn RepoTrackedOrdinaryBlobFile>>;
     fn put_file(
-        self: &mut Self, tracked_file: &mut (dyn RepoTrackedOrdinaryBlobFile))
+        self: &mut Self, tracked_file: &mut (dyn RepoTrackedOrdinaryBlobFile),
+        hash_algorithm: HashAlgorithm)
     -> FcResult<String>;
+    /// Stores whatever `readable` provides as a blob, returning its hash,
+    /// made with the specified algorithm.
+    /// 
+    /// The blob is hashed while it's being stored, so it's read exactly
+    /// once and never held in memory as a whole.
+    fn put_readable(&mut self, readable: &mut dyn Read, hash_algorithm: HashAlgorithm)
+    -> FcResult<String>;
+    fn remove_file(&mut self, hash: &str) -> FcResult<()>;
 }
 
+/// Tells apart the temporary files of blobs being put concurrently
+/// by the same process.
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
 pub struct MiscTrackedOrdinaryBlobFileCollection<Handler>
 where Handler: OpaqueCollectionHandler<> {
-    pub handler: Handler
+    pub handler: Handler,
+    /// How blobs are stored from now on. Blobs already in the collection
+    /// are read regardless of how they were stored.
+    pub compression: BlobCompression
 }
 
 impl<Handler: OpaqueCollectionHandler> MiscTrackedOrdinaryBlobFileCollection<Handler> {
     pub fn new(handler: Handler) -> Self {
+        Self::new_with_compression(handler, BlobCompression::default())
+    }
+
+    pub fn new_with_compression(handler: Handler, compression: BlobCompression)
+    -> Self {
         Self {
-            handler: handler
+            handler,
+            compression
         }
     }
+
+    /* Notes:
+        Hashes are hex strings, so a name starting with a "." can never
+        be confused with a blob.
+    */
+    fn get_tmp_file_name() -> OsString {
+        OsString::from(format!(
+            ".tmp-{}-{}",
+            process::id(),
+            TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ))
+    }
+
+    /// Writes the blob to the temporary file and puts it in place under
+    /// its hash, which is returned.
+    fn put_tmp_file(
+        &mut self,
+        tmp_file_name: &OsStr,
+        readable: &mut dyn Read,
+        hash_algorithm: HashAlgorithm
+    ) -> FcResult<String> {
+        let mut hashing_readable = HashingReader::new_with_algorithm(
+            readable, hash_algorithm);
+        {
+            let mut writeable = self.handler.get_file_writeable(tmp_file_name)?;
+            encode_blob(&mut hashing_readable, &mut writeable, self.compression)?;
+            writeable.flush()?;
+        }
+        let hash = hashing_readable.finalize();
+        let file_name = get_file_name_of_hash(&hash);
+        if self.handler.has_file(&file_name)? {
+            self.handler.remove_file(tmp_file_name)?;
+        } else {
+            self.handler.rename_file(tmp_file_name, &file_name)?;
+        }
+        Ok(hash)
+    }
 }
 
 impl<Handler: OpaqueCollectionHandler> TrackedOrdinaryBlobFileCollection
 for MiscTrackedOrdinaryBlobFileCollection<Handler> {
     fn has_file(self: &mut Self, hash: &str) -> FcResult<bool> {
-        self.handler.has_file(hash)
+        self.handler.has_file(get_file_name_of_hash(hash))
     }
 
     fn get_file(self: &mut Self, hash: &str)
     -> FcResult<Box<dyn RepoTrackedOrdinaryBlobFile>> {
-        let mut readable = self.handler.get_file_readable(
-            OsStr::new(hash)
+        let source = self.handler.get_file_readable_source(
+            &get_file_name_of_hash(hash)
         )?;
-        let tracked_file: Box<dyn RepoTrackedOrdinaryBlobFile> = Box::new(
-            TrackedOrdinaryBlobFile::from_existing(
-                &mut readable
-            )?
-        );
-        Ok(tracked_file)
+        Ok(Box::new(SourcedTrackedOrdinaryBlobFile::new(
+            Box::new(VerifyingSource::new(Box::new(DecodingSource::new(source)), hash)?)
+        )))
     }
 
     fn put_file(
-        self: &mut Self, tracked_file: &mut (dyn RepoTrackedOrdinaryBlobFile))
+        self: &mut Self, tracked_file: &mut (dyn RepoTrackedOrdinaryBlobFile),
+        hash_algorithm: HashAlgorithm)
     -> FcResult<String> {
-        let hash = tracked_file.get_hash()?;
-        let mut writeable = self.handler.get_file_writeable(
-            &OsString::from(&hash))?;
-        tracked_file.save(&mut writeable)?;
-        Ok(hash)
+        self.put_readable(&mut tracked_file.get_readable()?, hash_algorithm)
     }
-}
\ No newline at end of file
+
+    /* Notes:
+        The blob is written to a temporary file first, since its name,
+        the hash, is only known once all of it has been read. The hash
+        is taken from the blob as provided, before it's encoded. It's then
+        renamed to its hash, unless there's already a blob of that hash,
+        in which case it's simply thrown away. Whatever goes wrong, the
+        temporary file doesn't stay behind.
+    */
+    fn put_readable(&mut self, readable: &mut dyn Read, hash_algorithm: HashAlgorithm)
+    -> FcResult<String> {
+        let tmp_file_name = Self::get_tmp_file_name();
+        self.handler.create_file(&tmp_file_name)?;
+        match self.put_tmp_file(&tmp_file_name, readable, hash_algorithm) {
+            Ok(hash) => Ok(hash),
+            Err(put_error) => {
+                // It's the error that got us here that's worth reporting,
+                // and the temporary file might already be gone anyway.
+                let _ = self.handler.remove_file(&tmp_file_name);
+                Err(put_error)
+            }
+        }
+    }
+
+    fn remove_file(&mut self, hash: &str) -> FcResult<()> {
+        self.handler.remove_file(&get_file_name_of_hash(hash))
+    }
+}
@@ -14,4 +14,10 @@
 /// The state file's name in fileoid repos.
 pub(crate) const STATE_FILE_NAME: &str = "state.json";
 /// The name of the directory where the blobs are in fileoid repos.
-pub(crate) const BLOBS_DIR_NAME: &str = "blobs";
\ No newline at end of file
+pub(crate) const BLOBS_DIR_NAME: &str = "blobs";
+/// The name of the directory where the indexes are in fileoid repos.
+pub(crate) const INDEXES_DIR_NAME: &str = "indexes";
+/// The name of the directory where the journal is in fileoid repos.
+pub(crate) const JOURNAL_DIR_NAME: &str = "journal";
+/// The name of the directory where the lock is in fileoid repos.
+pub(crate) const LOCK_DIR_NAME: &str = "lock";
\ No newline at end of file
@@ -1,9 +1,80 @@
+use serde::{Serialize, Deserialize};
+use crate::error::FcResult;
+use crate::meta::state::model::State;
+
 pub mod drivers {
     pub mod local;
+    pub mod sqlite;
+}
+
+/// Identifies an entry within its journal.
+pub type JournalEntryId = u64;
+
+/* Notes:
+    Blobs and indexes are put under the hash of their content and never
+    overwritten by anything else, so putting them can't break what's
+    already in the repo. What can is the state, which gets rewritten in
+    place as the last step of everything changing the repo. That's why
+    the full state an operation is about to write is recorded before it's
+    written, and the blobs and indexes put along the way are recorded
+    so they can be removed again if the operation never gets there.
+*/
+/// A step of an operation changing a repo, as recorded in a journal.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(
+    tag = "kind",
+    rename_all(
+        serialize = "snake_case",
+        deserialize = "snake_case"
+    )
+)]
+pub enum JournalRecord {
+    /// The operation has begun. This is always the first record.
+    Begin { operation: String },
+    /// A blob has been put into the blob collection.
+    PutBlob { hash: String },
+    /// An index has been put into the index collection.
+    PutIndex { hash: String },
+    /// The state is about to be written. `started_from` is the hash of the
+    /// state the operation started from, if there was one, so recovery
+    /// can tell whether the state has changed since. Records written
+    /// before it existed don't have it.
+    PutState {
+        state: State,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        started_from: Option<String>
+    },
+}
+
+/// An operation recorded in a journal, with all its records so far.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct JournalEntry {
+    pub id: JournalEntryId,
+    pub operation: String,
+    pub records: Vec<JournalRecord>,
+}
+
+impl JournalEntry {
+    /// Returns the state the operation was about to write, if it got
+    /// that far.
+    pub fn get_state_to_put(&self) -> Option<&State> {
+        self.records.iter().rev().find_map(|record| match record {
+            JournalRecord::PutState { state, .. } => Some(state),
+            _ => None
+        })
+    }
+
+    /// Returns the hash of the state the operation started from, if it
+    /// recorded one along with the state it was about to write.
+    pub fn get_started_from_state_hash(&self) -> Option<&str> {
+        self.records.iter().rev().find_map(|record| match record {
+            JournalRecord::PutState { started_from, .. } => started_from.as_deref(),
+            _ => None
+        })
+    }
 }
 
 // Journal that does nothing but act as if everything was okay.
-// Might need an implementation at some point.
 pub struct OptimisticDummyJournal {}
 
 impl OptimisticDummyJournal {
@@ -12,6 +83,46 @@ impl OptimisticDummyJournal {
     }
 }
 
-pub trait Journal {}
+/// Records operations changing a repo before they're carried out, so
+/// the repo can be recovered if they get interrupted.
+///
+/// Records have to be durable once the methods recording them return.
+pub trait Journal {
+    /// Records the beginning of an operation, returning the ID of the
+    /// entry for the operation.
+    fn begin(&mut self, operation: &str) -> FcResult<JournalEntryId>;
+    /// Records a step of the operation of the specified entry.
+    fn record(&mut self, entry_id: JournalEntryId, record: JournalRecord)
+    -> FcResult<()>;
+    /// Marks the operation of the specified entry as complete, after
+    /// which it's no longer returned by `get_unfinished_entries`.
+    fn complete(&mut self, entry_id: JournalEntryId) -> FcResult<()>;
+    /// Returns the entries of all operations which have begun, but
+    /// weren't completed, in the order they've begun in.
+    fn get_unfinished_entries(&mut self) -> FcResult<Vec<JournalEntry>>;
+    /// Called when an operation changing the repo failed, for journals
+    /// that can undo what it did right away. Others leave that to the
+    /// repo being recovered.
+    fn roll_back_unfinished(&mut self) -> FcResult<()> {
+        Ok(())
+    }
+}
+
+impl Journal for OptimisticDummyJournal {
+    fn begin(&mut self, _operation: &str) -> FcResult<JournalEntryId> {
+        Ok(0)
+    }
+
+    fn record(&mut self, _entry_id: JournalEntryId, _record: JournalRecord)
+    -> FcResult<()> {
+        Ok(())
+    }
+
+    fn complete(&mut self, _entry_id: JournalEntryId) -> FcResult<()> {
+        Ok(())
+    }
 
-impl Journal for OptimisticDummyJournal {}
\ No newline at end of file
+    fn get_unfinished_entries(&mut self) -> FcResult<Vec<JournalEntry>> {
+        Ok(vec!())
+    }
+}
@@ -1,11 +1,130 @@
-use crate::journal::Journal;
+use std::fs::{File, OpenOptions, create_dir_all, read_dir, remove_file};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use crate::error::FcResult;
+use crate::journal::{Journal, JournalEntry, JournalEntryId, JournalRecord};
 
-pub struct LocalJournal {}
+const ENTRY_FILE_EXTENSION: &str = "jsonl";
+
+/* Notes:
+    Each entry is a file of its own in the journal directory, named after
+    its ID and holding one JSON record per line. Completing an entry
+    removes its file, so the directory is empty whenever no operation is
+    underway.
+
+    A record is only durable once its line is complete, so a last line cut
+    short by a crash is taken to have never been recorded.
+*/
+/// A journal kept in a local directory, which gets created as needed.
+pub struct LocalJournal {
+    path: PathBuf
+}
 
 impl LocalJournal {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new<PathRef: AsRef<Path>>(path: PathRef) -> Self {
+        Self {
+            path: path.as_ref().to_owned()
+        }
+    }
+
+    fn get_entry_path(&self, entry_id: JournalEntryId) -> PathBuf {
+        self.path.join(format!("{}.{}", entry_id, ENTRY_FILE_EXTENSION))
+    }
+
+    /// Returns the IDs of all entries in the journal, in ascending order.
+    fn get_entry_ids(&self) -> FcResult<Vec<JournalEntryId>> {
+        let entries = match read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec!()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entry_ids = vec!();
+        for entry in entries {
+            let entry_path = entry?.path();
+            if entry_path.extension().and_then(|extension| extension.to_str())
+            != Some(ENTRY_FILE_EXTENSION) {
+                continue
+            }
+            if let Some(Ok(entry_id)) = entry_path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(|stem| stem.parse::<JournalEntryId>()) {
+                entry_ids.push(entry_id);
+            }
+        }
+        entry_ids.sort_unstable();
+        Ok(entry_ids)
+    }
+
+    fn append(&self, entry_id: JournalEntryId, record: &JournalRecord)
+    -> FcResult<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(self.get_entry_path(entry_id))?;
+        file.write_all(&line)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn read_entry(&self, entry_id: JournalEntryId) -> FcResult<Option<JournalEntry>> {
+        let mut records = vec!();
+        for line in BufReader::new(File::open(self.get_entry_path(entry_id))?).split(b'\n') {
+            match serde_json::from_slice::<JournalRecord>(&line?) {
+                Ok(record) => records.push(record),
+                // Cut short, so it's the last line anyway.
+                Err(_) => break,
+            }
+        }
+        let operation = match records.first() {
+            Some(JournalRecord::Begin { operation }) => operation.to_owned(),
+            // Not even the beginning made it, so nothing else did either.
+            _ => return Ok(None),
+        };
+        Ok(Some(JournalEntry {
+            id: entry_id,
+            operation,
+            records
+        }))
     }
 }
 
-impl Journal for LocalJournal {}
\ No newline at end of file
+impl Journal for LocalJournal {
+    fn begin(&mut self, operation: &str) -> FcResult<JournalEntryId> {
+        create_dir_all(&self.path)?;
+        let entry_id = match self.get_entry_ids()?.last() {
+            Some(last_entry_id) => last_entry_id + 1,
+            None => 0,
+        };
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(self.get_entry_path(entry_id))?;
+        self.append(entry_id, &JournalRecord::Begin {
+            operation: operation.to_owned()
+        })?;
+        Ok(entry_id)
+    }
+
+    fn record(&mut self, entry_id: JournalEntryId, record: JournalRecord)
+    -> FcResult<()> {
+        self.append(entry_id, &record)
+    }
+
+    fn complete(&mut self, entry_id: JournalEntryId) -> FcResult<()> {
+        remove_file(self.get_entry_path(entry_id))?;
+        Ok(())
+    }
+
+    fn get_unfinished_entries(&mut self) -> FcResult<Vec<JournalEntry>> {
+        let mut entries = vec!();
+        for entry_id in self.get_entry_ids()? {
+            match self.read_entry(entry_id)? {
+                Some(entry) => entries.push(entry),
+                // There's nothing to recover, so we're done with it.
+                None => self.complete(entry_id)?,
+            }
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+use crate::error::FcResult;
+use crate::journal::{Journal, JournalEntry, JournalEntryId, JournalRecord};
+use crate::opaque_collection_handler::drivers::sqlite::SqliteDatabase;
+
+/* Notes:
+    Every operation runs in a transaction of the database the repo is in,
+    which begins along with the entry and is committed when the entry is
+    completed, so an interrupted operation never changed anything, and
+    there's nothing to record along the way or to recover later.
+
+    An operation that failed before completing its entry is rolled back as
+    soon as the repo learns of it, or when the next one begins, or when the
+    repo is recovered. One the process died in the middle of was never
+    committed to begin with.
+*/
+/// A journal for repos kept in a SqliteDatabase, making each operation
+/// a database transaction.
+pub struct SqliteJournal {
+    database: Arc<SqliteDatabase>,
+    last_entry_id: JournalEntryId,
+}
+
+impl SqliteJournal {
+    pub fn new(database: Arc<SqliteDatabase>) -> Self {
+        Self {
+            database,
+            last_entry_id: 0,
+        }
+    }
+
+    fn roll_back_if_in_transaction(&self) -> FcResult<()> {
+        match self.database.is_in_transaction() {
+            true => self.database.rollback_transaction(),
+            false => Ok(()),
+        }
+    }
+}
+
+impl Journal for SqliteJournal {
+    fn begin(&mut self, _operation: &str) -> FcResult<JournalEntryId> {
+        self.roll_back_if_in_transaction()?;
+        self.database.begin_transaction()?;
+        self.last_entry_id += 1;
+        Ok(self.last_entry_id)
+    }
+
+    fn record(&mut self, _entry_id: JournalEntryId, _record: JournalRecord)
+    -> FcResult<()> {
+        Ok(())
+    }
+
+    fn complete(&mut self, _entry_id: JournalEntryId) -> FcResult<()> {
+        self.database.commit_transaction()
+    }
+
+    fn get_unfinished_entries(&mut self) -> FcResult<Vec<JournalEntry>> {
+        self.roll_back_if_in_transaction()?;
+        Ok(vec!())
+    }
+
+    fn roll_back_unfinished(&mut self) -> FcResult<()> {
+        self.roll_back_if_in_transaction()
+    }
+}
@@ -6,9 +6,12 @@ pub mod globals;
 pub mod error;
 pub mod meta;
 pub mod journal;
+pub mod lock;
 #[macro_use]
 pub mod files;
 #[cfg(test)]
 mod tests;
 pub mod repo;
-pub mod opaque_collection_handler;
\ No newline at end of file
+pub mod opaque_collection_handler;
+#[cfg(unix)]
+pub mod target_system;
\ No newline at end of file
@@ -0,0 +1,121 @@
+use std::fmt;
+use std::path::PathBuf;
+use serde::{Serialize, Deserialize};
+use crate::error::{ErrorPathBuf, FcResult, Payload};
+
+pub mod drivers {
+    pub mod local;
+}
+
+/// How a repo is locked.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+#[serde(
+    rename_all(
+        serialize = "snake_case",
+        deserialize = "snake_case"
+    )
+)]
+pub enum LockMode {
+    /// For reading, which any number of holders can do at the same time.
+    Shared,
+    /// For writing, which only one holder can do at a time, with nobody
+    /// reading in the meantime.
+    Exclusive,
+}
+
+impl LockMode {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Shared => "shared",
+            Self::Exclusive => "exclusive",
+        }
+    }
+}
+
+impl fmt::Display for LockMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Who holds a lock on a repo.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct LockHolder {
+    pub mode: LockMode,
+    pub pid: u32,
+    pub hostname: String,
+}
+
+pub struct RepoLockedErrorPayload {
+    pub lock_path: PathBuf,
+    pub requested_mode: LockMode,
+    /// None if it couldn't be determined who it is.
+    pub holder: Option<LockHolder>,
+}
+
+impl fmt::Debug for RepoLockedErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} Lock path: {}.", self, ErrorPathBuf::from(self.lock_path.to_owned()))
+    }
+}
+
+impl fmt::Display for RepoLockedErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.holder {
+            Some(holder) => write!(
+                f,
+                "Couldn't get a {} lock, as process {} on host \"{}\" holds a {} lock.",
+                self.requested_mode, holder.pid, holder.hostname, holder.mode
+            ),
+            None => write!(
+                f,
+                "Couldn't get a {} lock, as someone else holds a lock.",
+                self.requested_mode
+            ),
+        }
+    }
+}
+
+impl Payload for RepoLockedErrorPayload {}
+
+// Lock that does nothing, for repos that are never shared.
+#[derive(Default)]
+pub struct DummyLock {}
+
+impl DummyLock {
+    pub fn new() -> Self {
+        Self {}
+    }
+}
+
+/// An advisory lock on a repo, keeping processes working on the same repo
+/// from getting in each other's way.
+///
+/// It's reentrant: acquiring it again while holding it only has to be
+/// matched by releasing it again. Acquiring it exclusively while holding it
+/// shared upgrades it until it's released again.
+pub trait RepoLock {
+    /// Acquires the lock in the specified mode, waiting for others to
+    /// release it as long as the lock is configured to, failing with
+    /// `ErrorKind::RepoLocked` after that.
+    fn acquire(&mut self, mode: LockMode) -> FcResult<()>;
+    /// Releases what the last acquisition still being held acquired.
+    fn release(&mut self) -> FcResult<()>;
+    /// Tells which lock this is, for acquiring the locks of more than one
+    /// repo in the same order wherever they're acquired together, so two
+    /// holders never wait for each other, see `Repo::sync_to`. None for
+    /// locks that don't lock anything others could acquire.
+    fn get_lock_id(&self) -> Option<String> {
+        None
+    }
+}
+
+impl RepoLock for DummyLock {
+    fn acquire(&mut self, _mode: LockMode) -> FcResult<()> {
+        Ok(())
+    }
+
+    fn release(&mut self) -> FcResult<()> {
+        Ok(())
+    }
+}
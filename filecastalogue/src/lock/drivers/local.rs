@@ -0,0 +1,324 @@
+use std::fs::{File, create_dir_all, hard_link, metadata, read_dir, remove_file, write};
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{self, Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+use crate::error::{Error, ErrorKind, FcResult};
+use crate::lock::{LockHolder, LockMode, RepoLock, RepoLockedErrorPayload};
+
+/// How long a LocalLock waits for others to release it by default.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+const EXCLUSIVE_HOLDER_FILE_NAME: &str = "exclusive";
+const SHARED_HOLDER_FILE_NAME_PREFIX: &str = "shared-";
+
+/// Tells apart the files of the LocalLocks of this process.
+static HOLDER_FILE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+enum HolderStatus {
+    /// Released, or left behind by a process that's gone.
+    Gone,
+    /// None if the holder file couldn't be made sense of.
+    Holding(Option<LockHolder>),
+}
+
+/* Notes:
+    Every holder has a file of its own in the lock directory, naming it.
+    Holding it shared takes a file per holder, named uniquely, whilst
+    holding it exclusively takes the one file named "exclusive". Holder
+    files are written under a temporary name and then linked to their
+    actual name, which fails if there's a file by that name already, so
+    there's never more than one exclusive holder, and holder files are
+    always complete.
+
+    Shared holders check for an exclusive holder after creating their
+    file, backing off if there is one, whilst an exclusive holder waits
+    for all shared holders to go away after creating its file, so they
+    never hold it at the same time.
+
+    Processes holding the lock might die without releasing it. Their
+    holder files are removed once found, but only if they were on the
+    same host, as that's the only place where we can tell whether a
+    process is still alive. Several might find the same one, and by the
+    time one of them removes it by name, another might have removed it
+    already and put its own in its place. So whoever removes one holds
+    an flock on it while checking that it's still the one at that name,
+    which it stays as long as the flock is held, as only a process that's
+    gone could remove it otherwise.
+*/
+/// A lock kept in a local directory, which gets created as needed.
+pub struct LocalLock {
+    path: PathBuf,
+    /// How long to wait for others to release the lock before giving up.
+    pub timeout: Duration,
+    hostname: String,
+    /// What each acquisition still being held asked for, in order.
+    acquisitions: Vec<LockMode>,
+    shared_holder_file_path: Option<PathBuf>,
+    holds_exclusive: bool,
+}
+
+impl LocalLock {
+    pub fn new<PathRef: AsRef<Path>>(path: PathRef) -> Self {
+        Self::new_with_timeout(path, DEFAULT_LOCK_TIMEOUT)
+    }
+
+    pub fn new_with_timeout<PathRef: AsRef<Path>>(path: PathRef, timeout: Duration)
+    -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            timeout,
+            hostname: get_hostname(),
+            acquisitions: vec!(),
+            shared_holder_file_path: None,
+            holds_exclusive: false,
+        }
+    }
+
+    fn get_own_holder(&self, mode: LockMode) -> LockHolder {
+        LockHolder {
+            mode,
+            pid: process::id(),
+            hostname: self.hostname.to_owned(),
+        }
+    }
+
+    /// Creates a holder file naming us at the specified path, returning
+    /// false if there's already one.
+    fn create_holder_file(&self, path: &Path, mode: LockMode) -> FcResult<bool> {
+        create_dir_all(&self.path)?;
+        let tmp_path = self.path.join(format!(
+            ".tmp-{}-{}", process::id(), HOLDER_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        write(&tmp_path, serde_json::to_vec(&self.get_own_holder(mode))?)?;
+        let link_result = hard_link(&tmp_path, path);
+        remove_file(&tmp_path)?;
+        match link_result {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Finds out who holds the holder file at the specified path, removing
+    /// it if it was left behind by a process that's gone.
+    fn check_holder_file(&self, path: &Path) -> FcResult<HolderStatus> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(HolderStatus::Gone),
+            Err(e) => return Err(e.into()),
+        };
+        let mut content = vec!();
+        file.read_to_end(&mut content)?;
+        let holder = match serde_json::from_slice::<LockHolder>(&content) {
+            Ok(holder) => holder,
+            Err(_) => return Ok(HolderStatus::Holding(None)),
+        };
+        if holder.hostname == self.hostname && !is_process_alive(holder.pid) {
+            return self.remove_stale_holder_file(path, &file, holder)
+        }
+        Ok(HolderStatus::Holding(Some(holder)))
+    }
+
+    /// Removes the holder file at the specified path, which `file` was
+    /// opened from, and which was left behind by `holder`, unless someone
+    /// else is removing it already, or it's been replaced in the meantime,
+    /// in which case it's the one replacing it that's checked.
+    fn remove_stale_holder_file(&self, path: &Path, file: &File, holder: LockHolder)
+    -> FcResult<HolderStatus> {
+        // SAFETY: The file descriptor is that of `file`, which is open for
+        // the whole call. The flock goes away along with it.
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+                true => Ok(HolderStatus::Holding(Some(holder))),
+                false => Err(e.into()),
+            }
+        }
+        let file_metadata = file.metadata()?;
+        let is_still_at_path = match metadata(path) {
+            Ok(path_metadata) => path_metadata.dev() == file_metadata.dev()
+                && path_metadata.ino() == file_metadata.ino(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => return Err(e.into()),
+        };
+        match is_still_at_path {
+            true => {
+                remove_file(path)?;
+                Ok(HolderStatus::Gone)
+            },
+            false => self.check_holder_file(path),
+        }
+    }
+
+    /// Finds a shared holder other than us, if there is one.
+    fn check_other_shared_holders(&self) -> FcResult<HolderStatus> {
+        for entry in read_dir(&self.path)? {
+            let entry_path = entry?.path();
+            let is_shared_holder_file = entry_path.file_name()
+                .and_then(|file_name| file_name.to_str())
+                .is_some_and(|file_name| file_name.starts_with(SHARED_HOLDER_FILE_NAME_PREFIX));
+            if !is_shared_holder_file
+            || Some(&entry_path) == self.shared_holder_file_path.as_ref() {
+                continue
+            }
+            if let HolderStatus::Holding(holder) = self.check_holder_file(&entry_path)? {
+                return Ok(HolderStatus::Holding(holder))
+            }
+        }
+        Ok(HolderStatus::Gone)
+    }
+
+    /// Waits a bit for the specified holder to release the lock, failing
+    /// if we've waited long enough already.
+    fn wait_for_holder(
+        &self,
+        deadline: Instant,
+        requested_mode: LockMode,
+        holder: Option<LockHolder>
+    ) -> FcResult<()> {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(error!(
+                ErrorKind::RepoLocked,
+                "Waiting for the holder of a lock on a repo to release it.",
+                payload => RepoLockedErrorPayload {
+                    lock_path: self.path.to_owned(),
+                    requested_mode,
+                    holder
+                }
+            ))
+        }
+        sleep(POLL_INTERVAL.min(deadline - now));
+        Ok(())
+    }
+
+    fn acquire_shared(&mut self, deadline: Instant) -> FcResult<()> {
+        let exclusive_holder_file_path = self.path.join(EXCLUSIVE_HOLDER_FILE_NAME);
+        loop {
+            let shared_holder_file_path = self.path.join(format!(
+                "{}{}-{}-{}",
+                SHARED_HOLDER_FILE_NAME_PREFIX,
+                self.hostname,
+                process::id(),
+                HOLDER_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            self.create_holder_file(&shared_holder_file_path, LockMode::Shared)?;
+            self.shared_holder_file_path = Some(shared_holder_file_path);
+            match self.check_holder_file(&exclusive_holder_file_path)? {
+                HolderStatus::Gone => return Ok(()),
+                HolderStatus::Holding(holder) => {
+                    self.release_shared()?;
+                    self.wait_for_holder(deadline, LockMode::Shared, holder)?;
+                }
+            }
+        }
+    }
+
+    fn acquire_exclusive(&mut self, deadline: Instant) -> FcResult<()> {
+        let exclusive_holder_file_path = self.path.join(EXCLUSIVE_HOLDER_FILE_NAME);
+        while !self.create_holder_file(&exclusive_holder_file_path, LockMode::Exclusive)? {
+            if let HolderStatus::Holding(holder)
+            = self.check_holder_file(&exclusive_holder_file_path)? {
+                self.wait_for_holder(deadline, LockMode::Exclusive, holder)?;
+            }
+        }
+        self.holds_exclusive = true;
+        while let HolderStatus::Holding(holder) = self.check_other_shared_holders()? {
+            if let Err(e) = self.wait_for_holder(deadline, LockMode::Exclusive, holder) {
+                self.release_exclusive()?;
+                return Err(e)
+            }
+        }
+        Ok(())
+    }
+
+    fn release_shared(&mut self) -> FcResult<()> {
+        if let Some(shared_holder_file_path) = self.shared_holder_file_path.take() {
+            remove_file(shared_holder_file_path)?;
+        }
+        Ok(())
+    }
+
+    fn release_exclusive(&mut self) -> FcResult<()> {
+        if self.holds_exclusive {
+            remove_file(self.path.join(EXCLUSIVE_HOLDER_FILE_NAME))?;
+            self.holds_exclusive = false;
+        }
+        Ok(())
+    }
+}
+
+impl RepoLock for LocalLock {
+    fn acquire(&mut self, mode: LockMode) -> FcResult<()> {
+        let deadline = Instant::now() + self.timeout;
+        match mode {
+            LockMode::Shared if self.acquisitions.is_empty()
+                => self.acquire_shared(deadline)?,
+            LockMode::Exclusive if !self.holds_exclusive
+                => self.acquire_exclusive(deadline)?,
+            _ => (),
+        }
+        self.acquisitions.push(mode);
+        Ok(())
+    }
+
+    fn release(&mut self) -> FcResult<()> {
+        self.acquisitions.pop();
+        if !self.acquisitions.contains(&LockMode::Exclusive) {
+            self.release_exclusive()?;
+        }
+        if self.acquisitions.is_empty() {
+            self.release_shared()?;
+        }
+        Ok(())
+    }
+
+    /// The absolute path of the lock directory.
+    fn get_lock_id(&self) -> Option<String> {
+        let path = path::absolute(&self.path).unwrap_or_else(|_| self.path.to_owned());
+        Some(path.to_string_lossy().into_owned())
+    }
+}
+
+impl Drop for LocalLock {
+    /// Releases whatever is still being held, so holder files don't
+    /// outlive us, e.g. when unwinding.
+    fn drop(&mut self) {
+        self.acquisitions.clear();
+        let _ = self.release_exclusive();
+        let _ = self.release_shared();
+    }
+}
+
+fn get_hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: The length passed is that of the buffer.
+    let result = unsafe {
+        libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len())
+    };
+    if result != 0 {
+        return String::from("unknown")
+    }
+    let length = buf.iter().position(|byte| *byte == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..length]).into_owned()
+}
+
+fn is_process_alive(pid: u32) -> bool {
+    // Not a PID we could check without signalling a whole process group.
+    if pid == 0 || pid > libc::pid_t::MAX as u32 {
+        return true
+    }
+    // Signal 0 only checks whether the process could be signalled.
+    // SAFETY: No signal gets sent.
+    match unsafe { libc::kill(pid as libc::pid_t, 0) } {
+        0 => true,
+        // It exists, it just isn't ours to signal.
+        _ => io::Error::last_os_error().raw_os_error() == Some(libc::EPERM),
+    }
+}
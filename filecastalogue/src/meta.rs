@@ -3,4 +3,5 @@ pub mod index;
 pub mod repo_exported_file_list;
 pub mod version;
 pub mod state;
-pub mod blob;
\ No newline at end of file
+pub mod blob;
+pub mod tracked_path;
\ No newline at end of file
@@ -1,4 +1,4 @@
-use std::{convert::TryFrom, fmt::Debug};
+use std::{convert::TryFrom, fmt::Debug, io::{Cursor, Read}};
 use serde::{Deserialize, Serialize};
 use crate::{error::{Error, FcResult}, files::{blob::BlobProvider, tracked_ordinary_blob::TrackedOrdinaryBlobProvider}};
 use super::model::Blob;
@@ -44,6 +44,43 @@ impl BlobProvider for RepoExportedHeapOrdinaryBlobProvider {
     fn into_blob(self: Box<Self>) -> crate::error::FcResult<Blob> {
         Ok(self.blob)
     }
+
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+        Ok(Box::new(Cursor::new(&self.blob[..])))
+    }
+}
+
+impl RepoExportedOrdinaryBlobProvider for RepoExportedHeapOrdinaryBlobProvider {}
+
+/// Passes the blob of a Tracked file through as it's provided, e.g.
+/// straight from the collection it's stored in, instead of reading
+/// it into memory when the file is exported.
+pub struct RepoExportedStreamedOrdinaryBlobProvider {
+    blob_provider: Box<dyn TrackedOrdinaryBlobProvider>
+}
+
+impl RepoExportedStreamedOrdinaryBlobProvider {
+
+    pub fn from_tracked_ordinary_blob_provider(blob_provider: Box<dyn TrackedOrdinaryBlobProvider>) -> Self {
+        Self {
+            blob_provider
+        }
+    }
+}
+
+impl BlobProvider for RepoExportedStreamedOrdinaryBlobProvider {
+
+    fn clone_blob(&self) -> FcResult<Blob> {
+        self.blob_provider.clone_blob()
+    }
+
+    fn into_blob(self: Box<Self>) -> FcResult<Blob> {
+        self.blob_provider.into_blob()
+    }
+
+    fn get_readable(&self) -> FcResult<Box<dyn Read + '_>> {
+        self.blob_provider.get_readable()
+    }
 }
 
-impl RepoExportedOrdinaryBlobProvider for RepoExportedHeapOrdinaryBlobProvider {}
\ No newline at end of file
+impl RepoExportedOrdinaryBlobProvider for RepoExportedStreamedOrdinaryBlobProvider {}
\ No newline at end of file
@@ -1,30 +1,116 @@
+use std::ffi::OsStr;
 use serde::{Serialize, Deserialize};
 use super::super::attributes::Attributes;
 
+/// How much of a directory's contents is managed by whoever tracks it.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(
+    tag = "kind",
+    rename_all(
+        serialize = "snake_case",
+        deserialize = "snake_case"
+    )
+)]
+pub enum DirectoryMode {
+    /// Only the directory itself is managed, anything else in it that
+    /// isn't tracked is left alone.
+    Shared,
+    /// The directory's contents are managed exclusively, e.g. for drop-in
+    /// directories like `/etc/sudoers.d`. Any direct child that isn't tracked
+    /// is considered drift and removed when applying, unless its name matches
+    /// one of the `allowed` patterns.
+    ///
+    /// Patterns match whole file names, with `*` matching any number of
+    /// characters and `?` matching exactly one.
+    Exclusive {
+        allowed: Vec<String>
+    },
+}
+
+impl DirectoryMode {
+
+    pub fn is_exclusive(&self) -> bool {
+        matches!(self, Self::Exclusive { .. })
+    }
+
+    pub fn is_shared(&self) -> bool {
+        matches!(self, Self::Shared)
+    }
+
+    /// Returns true if an untracked child of that name may stay in
+    /// a directory with this mode.
+    pub fn allows_untracked(&self, file_name: &OsStr) -> bool {
+        match self {
+            Self::Shared => true,
+            Self::Exclusive { allowed } => allowed.iter().any(
+                |pattern| matches_pattern(
+                    pattern.as_bytes(),
+                    file_name.as_encoded_bytes()
+                )
+            )
+        }
+    }
+}
+
+impl Default for DirectoryMode {
+    /// Shared, as that's what directories have been before there were modes.
+    fn default() -> Self {
+        Self::Shared
+    }
+}
+
+/// Matches `name` against a pattern with `*` and `?` wildcards.
+fn matches_pattern(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => matches_pattern(&pattern[1..], name)
+            || (!name.is_empty() && matches_pattern(pattern, &name[1..])),
+        (Some(b'?'), Some(_)) => matches_pattern(&pattern[1..], &name[1..]),
+        (Some(expected), Some(found)) if expected == found
+            => matches_pattern(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+/* Notes:
+    `mode` is left out of the JSON if it's shared, so indexes written
+    before there were modes keep their hash.
+*/
 /// Aspects of a directory relevant when not tracked in a Repo.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct TrackableDirectoryAspects {
-    pub attributes: Attributes
+    pub attributes: Attributes,
+    #[serde(default, skip_serializing_if = "DirectoryMode::is_shared")]
+    pub mode: DirectoryMode
 }
 
 /// Aspects of a directory relevant when it's tracked in a Repo.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct TrackedDirectoryAspects {
-    pub attributes: Attributes
+    pub attributes: Attributes,
+    #[serde(default, skip_serializing_if = "DirectoryMode::is_shared")]
+    pub mode: DirectoryMode
 }
 
 /// Representation of the tracking of a directory in a repo when
 /// exported from it.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct RepoExportedDirectoryAspects {
-    pub attributes: Attributes
+    pub attributes: Attributes,
+    #[serde(default, skip_serializing_if = "DirectoryMode::is_shared")]
+    pub mode: DirectoryMode
 }
 
 impl TrackableDirectoryAspects {
 
     pub fn new(attributes: Attributes) -> Self {
+        Self::new_with_mode(attributes, DirectoryMode::default())
+    }
+
+    pub fn new_with_mode(attributes: Attributes, mode: DirectoryMode) -> Self {
         Self {
-            attributes
+            attributes,
+            mode
         }
     }
 }
@@ -32,14 +118,20 @@ impl TrackableDirectoryAspects {
 impl TrackedDirectoryAspects {
 
     pub fn new(attributes: Attributes) -> Self {
+        Self::new_with_mode(attributes, DirectoryMode::default())
+    }
+
+    pub fn new_with_mode(attributes: Attributes, mode: DirectoryMode) -> Self {
         Self {
-            attributes
+            attributes,
+            mode
         }
     }
 
     pub fn from_trackable(trackable_aspects: TrackableDirectoryAspects) -> Self {
-        Self::new(
-            trackable_aspects.attributes
+        Self::new_with_mode(
+            trackable_aspects.attributes,
+            trackable_aspects.mode
         )
     }
 }
@@ -47,14 +139,20 @@ impl TrackedDirectoryAspects {
 impl RepoExportedDirectoryAspects {
 
     pub fn new(attributes: Attributes) -> Self {
+        Self::new_with_mode(attributes, DirectoryMode::default())
+    }
+
+    pub fn new_with_mode(attributes: Attributes, mode: DirectoryMode) -> Self {
         Self {
-            attributes
+            attributes,
+            mode
         }
     }
 
     pub fn from_tracked(tracked_aspects: TrackedDirectoryAspects) -> Self {
-        Self::new(
-            tracked_aspects.attributes
+        Self::new_with_mode(
+            tracked_aspects.attributes,
+            tracked_aspects.mode
         )
     }
 }
@@ -1,43 +1,144 @@
 use serde::{Serialize, Deserialize};
 
+/// Restricts a non-existing entry to files of a particular kind. Files of
+/// any other kind found at its path are left alone.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+#[serde(rename_all(
+    serialize = "snake_case",
+    deserialize = "snake_case"
+))]
+pub enum NonExistingKindConstraint {
+    Directory,
+    Ordinary,
+    Symlink,
+}
+
+impl NonExistingKindConstraint {
+    /// The name of the kind, matching `TrackedFileAspects::kind_str`.
+    pub fn kind_str(&self) -> &'static str {
+        match *self {
+            Self::Directory => "directory",
+            Self::Ordinary => "ordinary",
+            Self::Symlink => "symlink",
+        }
+    }
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/* Notes:
+    `recursive` defaults to false, so that an index written before it
+    existed never causes more to be removed than the path itself, which
+    for directories means only empty ones. Both are left out of the JSON
+    at their defaults, so indexes written before they existed keep their
+    hash.
+*/
 /// Aspects of a non-existing file relevant when not being tracked in a repo.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
-pub struct TrackableNonExistingAspects {}
+pub struct TrackableNonExistingAspects {
+    /// Whether a directory found at the path is to be removed along with
+    /// everything in it, instead of only if it's empty.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub recursive: bool,
+    /// If set, only a file of that kind is considered to be in the way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only_if_kind: Option<NonExistingKindConstraint>,
+}
 
 /// Aspects relevant when tracking the non-existence of a file in a Repo.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
-pub struct TrackedNonExistingAspects {}
+pub struct TrackedNonExistingAspects {
+    /// Whether a directory found at the path is to be removed along with
+    /// everything in it, instead of only if it's empty.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub recursive: bool,
+    /// If set, only a file of that kind is considered to be in the way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only_if_kind: Option<NonExistingKindConstraint>,
+}
 
 /// Representation of the tracking of a non-existing file in a repo when
 /// exported from it.
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
-pub struct RepoExportedNonExistingAspects {}
+pub struct RepoExportedNonExistingAspects {
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub recursive: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only_if_kind: Option<NonExistingKindConstraint>,
+}
 
 impl TrackableNonExistingAspects {
 
     pub fn new() -> Self {
-        Self {}
+        Self::new_with_constraints(false, None)
+    }
+
+    pub fn new_with_constraints(
+        recursive: bool,
+        only_if_kind: Option<NonExistingKindConstraint>
+    ) -> Self {
+        Self {
+            recursive,
+            only_if_kind
+        }
     }
 }
 
 impl TrackedNonExistingAspects {
 
     pub fn new() -> Self {
-        Self {}
+        Self::new_with_constraints(false, None)
+    }
+
+    pub fn new_with_constraints(
+        recursive: bool,
+        only_if_kind: Option<NonExistingKindConstraint>
+    ) -> Self {
+        Self {
+            recursive,
+            only_if_kind
+        }
     }
 
     pub fn from_trackable(trackable_aspects: TrackableNonExistingAspects) -> Self {
-        Self::new()
+        Self::new_with_constraints(
+            trackable_aspects.recursive,
+            trackable_aspects.only_if_kind
+        )
     }
 }
 
 impl RepoExportedNonExistingAspects {
 
     pub fn new() -> Self {
-        Self {}
+        Self::new_with_constraints(false, None)
+    }
+
+    pub fn new_with_constraints(
+        recursive: bool,
+        only_if_kind: Option<NonExistingKindConstraint>
+    ) -> Self {
+        Self {
+            recursive,
+            only_if_kind
+        }
     }
 
     pub fn from_tracked(tracked_aspects: TrackedNonExistingAspects) -> Self {
-        Self::new()
+        Self::new_with_constraints(
+            tracked_aspects.recursive,
+            tracked_aspects.only_if_kind
+        )
+    }
+
+    /// Returns true if a file of the specified kind found at the path
+    /// is in the way of it not existing.
+    pub fn is_in_the_way(&self, found_kind: &str) -> bool {
+        match self.only_if_kind {
+            Some(kind_constraint) => kind_constraint.kind_str() == found_kind,
+            None => true,
+        }
     }
 }
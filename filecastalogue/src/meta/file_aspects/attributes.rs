@@ -13,5 +13,10 @@ use serde::{Serialize, Deserialize};
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct Attributes {
     pub posix_user: String,
-    pub posix_group: String
+    pub posix_group: String,
+    /// The permission bits, e.g. `0o644`, if they're tracked at all.
+    /// Left out of the JSON if they aren't, so indexes written before
+    /// there were modes keep their hash.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub posix_mode: Option<u32>
 }
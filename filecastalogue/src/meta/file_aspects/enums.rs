@@ -26,6 +26,19 @@ pub enum TrackedFileAspects {
     // Hardlink(TrackedHardlinkAspects) // TODO [maybe]
 }
 
+impl TrackedFileAspects {
+    /// The name of the kind of file, as it appears in the `kind` tag
+    /// of the JSON model.
+    pub fn kind_str(&self) -> &'static str {
+        match *self {
+            Self::NonExisting(_) => "non_existing",
+            Self::Directory(_) => "directory",
+            Self::Ordinary(_) => "ordinary",
+            Self::Symlink(_) => "symlink",
+        }
+    }
+}
+
 pub enum RepoExportedFileAspects {
     NonExisting(RepoExportedNonExistingAspects),
     Directory(RepoExportedDirectoryAspects),
@@ -33,3 +46,15 @@ pub enum RepoExportedFileAspects {
     Symlink(RepoExportedSymlinkAspects),
     // Hardlink(RepoExportedHardlinkAspects) // TODO [maybe]
 }
+
+impl RepoExportedFileAspects {
+    /// The name of the kind of file, matching `TrackedFileAspects::kind_str`.
+    pub fn kind_str(&self) -> &'static str {
+        match *self {
+            Self::NonExisting(_) => "non_existing",
+            Self::Directory(_) => "directory",
+            Self::Ordinary(_) => "ordinary",
+            Self::Symlink(_) => "symlink",
+        }
+    }
+}
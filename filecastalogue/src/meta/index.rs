@@ -1,4 +1,5 @@
 pub mod error;
 pub mod model;
 pub mod accessor;
+pub mod consistency;
 pub mod principal_conversions;
\ No newline at end of file
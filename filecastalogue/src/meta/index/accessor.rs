@@ -1,6 +1,6 @@
-use std::{ffi::{OsStr, OsString}};
 use crate::{error::{Error, ErrorKind, FcResult}};
 use super::super::file_aspects::enums::TrackedFileAspects;
+use super::super::tracked_path::model::TrackedPath;
 use super::{
     error::{FileAlreadyTrackedErrorPayload, UntrackedFileErrorPayload},
     model::Index};
@@ -9,11 +9,11 @@ pub trait IndexAccessor {
     fn is_empty(&mut self) -> bool;
         // fn get_all_file_paths(&mut self) -> Keys<String, FileAspects>;
     fn tracks_files(&mut self) -> bool;
-    fn tracks_file(&mut self, path: &OsStr) -> bool;
-    fn track_file(&mut self, path: OsString, aspects: TrackedFileAspects)
+    fn tracks_file(&mut self, path: &TrackedPath) -> bool;
+    fn track_file(&mut self, path: TrackedPath, aspects: TrackedFileAspects)
     -> FcResult<&mut Self>;
-    fn untrack_file(&mut self, path: &OsStr) -> FcResult<&mut Self>;
-    fn get_aspects(&mut self, path: &OsStr) -> FcResult<TrackedFileAspects>;
+    fn untrack_file(&mut self, path: &TrackedPath) -> FcResult<&mut Self>;
+    fn get_aspects(&mut self, path: &TrackedPath) -> FcResult<TrackedFileAspects>;
 }
 
 impl IndexAccessor for Index {
@@ -29,7 +29,7 @@ impl IndexAccessor for Index {
         self.files.len() > 0
     }
 
-    fn tracks_file(&mut self, path: &OsStr) -> bool {
+    fn tracks_file(&mut self, path: &TrackedPath) -> bool {
         if self.files.contains_key(path) {
             true
         }
@@ -38,7 +38,7 @@ impl IndexAccessor for Index {
         }
     }
     
-    fn track_file(&mut self, path: OsString, aspects: TrackedFileAspects)
+    fn track_file(&mut self, path: TrackedPath, aspects: TrackedFileAspects)
     -> FcResult<&mut Self> {
         let path_for_error = path.clone();
         match self.files.insert(path, aspects) {
@@ -59,7 +59,7 @@ impl IndexAccessor for Index {
         }
     }
 
-    fn untrack_file(&mut self, path: &OsStr) -> FcResult<&mut Self> {
+    fn untrack_file(&mut self, path: &TrackedPath) -> FcResult<&mut Self> {
         match self.files.remove_entry(path) {
             Some(_) => Ok(self),
             None => Err(error!(
@@ -73,7 +73,7 @@ impl IndexAccessor for Index {
         }
     }
     
-    fn get_aspects(&mut self, path: &OsStr) -> FcResult<TrackedFileAspects> {
+    fn get_aspects(&mut self, path: &TrackedPath) -> FcResult<TrackedFileAspects> {
         match self.files.get(path) {
             Some(aspects) => Ok(aspects.to_owned()),
             None => Err(
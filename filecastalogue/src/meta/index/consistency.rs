@@ -0,0 +1,157 @@
+use crate::error::{Error, ErrorKind, FcResult};
+use super::super::file_aspects::aspects::directory::TrackedDirectoryAspects;
+use super::super::file_aspects::enums::TrackedFileAspects;
+use super::super::tracked_path::model::TrackedPath;
+use super::error::IndexInconsistentErrorPayload;
+use super::model::Index;
+
+/* Notes:
+    An index describes the state of a whole tree at once, so entries can
+    contradict each other. Applying such an index would fail halfway
+    through (or worse, do something nobody intended), e.g. when writing
+    `/etc/app/conf` after making sure `/etc/app` doesn't exist. These rules
+    are meant to catch that before an index is written, not when it's
+    applied.
+*/
+
+/// What to do about ancestors of tracked paths that aren't tracked
+/// themselves.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum ImpliedParentDirectories {
+    /// Leave them untracked. Whatever happens to be at these paths
+    /// on the target system is left alone.
+    Ignore,
+    /// Track them as directories with the specified aspects.
+    Add(TrackedDirectoryAspects),
+}
+
+/// A single pair of contradicting index entries.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IndexConsistencyViolation {
+    /// The path of the entry that can't exist the way it's tracked.
+    pub path: TrackedPath,
+    /// The kind the entry at `path` is tracked as.
+    pub kind: &'static str,
+    /// The tracked ancestor of `path` that contradicts it.
+    pub conflicting_parent: TrackedPath,
+    /// The kind `conflicting_parent` is tracked as.
+    pub conflicting_parent_kind: &'static str,
+}
+
+/// The rules an index has to adhere to before it's written.
+///
+/// An entry contradicts a tracked ancestor if:
+///   - It's expected to exist (directory, ordinary file, symlink), but the
+///     ancestor isn't tracked as a directory.
+///   - It's expected not to exist, but the ancestor is a symlink, as we
+///     can't tell where the path would end up on the target system.
+///
+/// Non-existing entries below non-existing or ordinary files are fine, as
+/// they're implied by their ancestor anyway.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct IndexConsistencyRules {
+    pub implied_parent_directories: ImpliedParentDirectories,
+}
+
+impl IndexConsistencyRules {
+
+    /// Construct the default rules, which leave implied parent
+    /// directories alone.
+    pub fn new() -> Self {
+        Self {
+            implied_parent_directories: ImpliedParentDirectories::Ignore
+        }
+    }
+
+    /// Construct rules which track untracked ancestors of tracked paths as
+    /// directories with the specified aspects.
+    pub fn with_implied_parent_directories(aspects: TrackedDirectoryAspects)
+    -> Self {
+        Self {
+            implied_parent_directories: ImpliedParentDirectories::Add(aspects)
+        }
+    }
+
+    /// Returns every violation found in the index, sorted by path.
+    pub fn find_violations(&self, index: &Index) -> Vec<IndexConsistencyViolation> {
+        let mut violations = vec!();
+        for (path, aspects) in &index.files {
+            for ancestor in path.ancestors() {
+                let ancestor_aspects = match index.files.get(&ancestor) {
+                    Some(ancestor_aspects) => ancestor_aspects,
+                    None => continue,
+                };
+                if Self::contradicts(aspects, ancestor_aspects) {
+                    violations.push(IndexConsistencyViolation {
+                        path: path.to_owned(),
+                        kind: aspects.kind_str(),
+                        conflicting_parent: ancestor,
+                        conflicting_parent_kind: ancestor_aspects.kind_str(),
+                    });
+                }
+            }
+        }
+        violations.sort_by(|a, b| (&a.path, &a.conflicting_parent)
+            .cmp(&(&b.path, &b.conflicting_parent)));
+        violations
+    }
+
+    /// Applies the rules to the index.
+    ///
+    /// Adds implied parent directories if configured to do so, then fails
+    /// with `ErrorKind::IndexInconsistent` listing every violation, if
+    /// there are any.
+    pub fn enforce(&self, index: &mut Index) -> FcResult<()> {
+        if let ImpliedParentDirectories::Add(aspects) = &self.implied_parent_directories {
+            let implied_paths: Vec<TrackedPath> = index.files.keys()
+                .flat_map(|path| path.ancestors())
+                .filter(|ancestor| !index.files.contains_key(ancestor))
+                .collect();
+            for implied_path in implied_paths {
+                index.files.entry(implied_path).or_insert_with(
+                    || TrackedFileAspects::Directory(aspects.to_owned())
+                );
+            }
+        }
+
+        self.check(index)
+    }
+
+    /// Applies the rules to the index without changing it, for indexes
+    /// which have to be written exactly as they are, e.g. when they're
+    /// transferred from another repo.
+    ///
+    /// Fails with `ErrorKind::IndexInconsistent` listing every violation,
+    /// if there are any. Implied parent directories are left alone.
+    pub fn check(&self, index: &Index) -> FcResult<()> {
+        let violations = self.find_violations(index);
+        if violations.is_empty() {
+            Ok(())
+        }
+        else {
+            Err(error!(
+                ErrorKind::IndexInconsistent,
+                "Checking index consistency before writing it.",
+                payload => IndexInconsistentErrorPayload {
+                    violations
+                }
+            ))
+        }
+    }
+
+    fn contradicts(aspects: &TrackedFileAspects, ancestor_aspects: &TrackedFileAspects)
+    -> bool {
+        !matches!(
+            (aspects, ancestor_aspects),
+            (_, TrackedFileAspects::Directory(_))
+            | (TrackedFileAspects::NonExisting(_), TrackedFileAspects::NonExisting(_))
+            | (TrackedFileAspects::NonExisting(_), TrackedFileAspects::Ordinary(_))
+        )
+    }
+}
+
+impl Default for IndexConsistencyRules {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,9 +1,11 @@
-use std::{ffi::OsString, fmt};
+use std::fmt;
 use crate::error::Payload;
+use crate::meta::tracked_path::model::TrackedPath;
+use super::consistency::IndexConsistencyViolation;
 use super::model::Index;
 
 pub struct FileAlreadyTrackedErrorPayload {
-    pub path: OsString,
+    pub path: TrackedPath,
     pub index_struct: Index,
 }
 
@@ -33,7 +35,7 @@ impl Payload for FileAlreadyTrackedErrorPayload {}
 
 
 pub struct UntrackedFileErrorPayload {
-    pub path: OsString,
+    pub path: TrackedPath,
 }
 
 impl fmt::Debug for UntrackedFileErrorPayload {
@@ -56,4 +58,37 @@ impl fmt::Display for UntrackedFileErrorPayload {
     }
 }
 
-impl Payload for UntrackedFileErrorPayload {}
\ No newline at end of file
+impl Payload for UntrackedFileErrorPayload {}
+
+pub struct IndexInconsistentErrorPayload {
+    pub violations: Vec<IndexConsistencyViolation>,
+}
+
+impl fmt::Debug for IndexInconsistentErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for IndexInconsistentErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The index has {} inconsistent entries:",
+            self.violations.len()
+        )?;
+        for violation in &self.violations {
+            write!(
+                f,
+                " \"{:?}\" ({}) conflicts with \"{:?}\" ({}).",
+                violation.path,
+                violation.kind,
+                violation.conflicting_parent,
+                violation.conflicting_parent_kind,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Payload for IndexInconsistentErrorPayload {}
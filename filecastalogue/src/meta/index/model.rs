@@ -1,7 +1,9 @@
-use std::{collections::HashMap, ffi::{OsString, OsStr}};
+use std::{collections::{BTreeMap, HashMap}, ffi::OsString};
 use serde::{Deserialize, Serialize};
-use crate::error::FcResult;
+use crate::error::{Error, ErrorKind, FcResult};
 use super::super::file_aspects::enums::TrackedFileAspects;
+use super::super::tracked_path::model::TrackedPath;
+use super::error::FileAlreadyTrackedErrorPayload;
 
 pub enum Conversion {
     LossyGraphemes,
@@ -11,9 +13,27 @@ pub enum Conversion {
 
 #[derive(Debug, Eq, PartialEq, Clone)]
 pub struct Index {
-    pub files: HashMap<OsString, TrackedFileAspects>
+    pub files: HashMap<TrackedPath, TrackedFileAspects>
 }
 
+/* Notes:
+    `files` used to be a `HashMap`, which serialized files in a different
+    order every time, so the same index could get a different hash, and
+    thus ID, whenever it was written. Being sorted, an index written now
+    always gets the same ID, which is what lets a repo tell it has an
+    index already, e.g. when syncing, and makes archives of the same repo
+    the same.
+
+    Indexes written before keep their IDs, as they're read by the ID
+    they were stored under and never rewritten in place. An index that
+    gets changed is written anew under a new ID anyway, and an unchanged
+    one serialized again, like when it's archived, gets the ID of the
+    sorted serialization, which can make for a second copy of the same
+    index in a repo, but never for a state referring to the wrong one.
+
+    Anything constructing a `UnicodePathIndex` itself has to go with a
+    `BTreeMap` now, which `collect()` takes care of for most.
+*/
 /// The serializable version of `Index`, with `String` keys instead
 /// of `OsString`.
 /// 
@@ -28,9 +48,10 @@ pub struct UnicodePathIndex {
     // "dict" the JSON representation of the Index actually is, which is
     // why we're using `flatten` here. That way we don't get a `files`
     // attribute in JSON, but everything in `files` is popped right into
-    // the JSON's top level instead.
+    // the JSON's top level instead. It's sorted, so the same index is
+    // always serialized the same way and thus gets the same hash.
     #[serde(flatten)]
-    pub files: HashMap<String, TrackedFileAspects>
+    pub files: BTreeMap<String, TrackedFileAspects>
 }
 
 impl Index {
@@ -48,18 +69,67 @@ impl Index {
         match conversion_type {
             Conversion::LossyGraphemes => {
                 for (k_path, v_aspects) in unicode_path_index.files {
-                    index.files.insert(OsString::from(k_path), v_aspects);
+                    index.insert_deserialized(OsString::from(k_path), v_aspects)?;
                 }
                 Ok(index)
             },
             Conversion::NonLossyBytes => {
                 for (k_path, v_aspects) in unicode_path_index.files {
-                    index.files.insert(serde_json::from_str(&k_path)?, v_aspects);
+                    index.insert_deserialized(serde_json::from_str(&k_path)?, v_aspects)?;
                 }
                 Ok(index)
             }
         }
     }
+
+    /// Validates a path coming from a serialized index and inserts it.
+    /// 
+    /// Serialized indexes might predate `TrackedPath` validation, so two
+    /// keys can turn out to be the same path once normalized. Since we
+    /// can't tell which of them is supposed to win, that's an error.
+    fn insert_deserialized(&mut self, path: OsString, aspects: TrackedFileAspects)
+    -> FcResult<()> {
+        let tracked_path = TrackedPath::new(path)?;
+        if self.files.contains_key(&tracked_path) {
+            return Err(error!(
+                ErrorKind::FileAlreadyTracked,
+                "Deserializing an index with paths that normalize to the same path.",
+                payload => FileAlreadyTrackedErrorPayload {
+                    path: tracked_path,
+                    index_struct: self.to_owned()
+                }
+            ))
+        }
+        self.files.insert(tracked_path, aspects);
+        Ok(())
+    }
+}
+
+/// A `UnicodePathIndex` borrowing the aspects from the `Index` it's
+/// made from, so an index can be serialized without cloning it first.
+///
+/// It's serialized exactly like the `UnicodePathIndex` it stands in for.
+#[derive(Serialize, Debug, Eq, PartialEq)]
+pub struct UnicodePathIndexRef<'index> {
+    #[serde(flatten)]
+    pub files: BTreeMap<String, &'index TrackedFileAspects>
+}
+
+/// Converts a path of an `Index` to its unicode representation in a
+/// `UnicodePathIndex`.
+fn to_unicode_path(path: &TrackedPath, conversion_type: &Conversion)
+-> FcResult<String> {
+    match conversion_type {
+        Conversion::LossyGraphemes => Ok(path.as_os_str().to_string_lossy().to_string()),
+        // NOTE [caveat]: When processing path input in some way that might
+        //  have been serialized on another platform, take into account
+        //  that what might intuitively seem like it would have to be the
+        //  same string might still differ in the world of OsString, even
+        //  if it's just the "Unix" and "Windows" prefixes in their
+        //  serialized counterparts, which would e.g. make comparisons
+        //  between them fail.
+        Conversion::NonLossyBytes => Ok(serde_json::to_string(path.as_os_str())?),
+    }
 }
 
 impl UnicodePathIndex {
@@ -68,28 +138,26 @@ impl UnicodePathIndex {
         conversion_type: Conversion
     ) -> FcResult<Self> {
         let mut unicode_path_index = Self {
-            files: HashMap::new()
+            files: BTreeMap::new()
         };
-        match conversion_type {
-            Conversion::LossyGraphemes => {
-                for (k_path, v_aspects) in index.files {
-                    unicode_path_index.files.insert(k_path.to_string_lossy().to_string(), v_aspects);
-                };
-                Ok(unicode_path_index)
-            },
-            Conversion::NonLossyBytes => {
-                for (k_path, v_aspects) in index.files {
-                    // NOTE [caveat]: When processing path input in some way that might
-                    //  have been serialized on another platform, take into account
-                    //  that what might intuitively seem like it would have to be the
-                    //  same string might still differ in the world of OsString, even
-                    //  if it's just the "Unix" and "Windows" prefixes in their
-                    //  serialized counterparts, which would e.g. make comparisons
-                    //  between them fail.
-                    unicode_path_index.files.insert(serde_json::to_string(&k_path)?, v_aspects);
-                };
-                Ok(unicode_path_index)
-            },
-        }
+        for (k_path, v_aspects) in index.files {
+            unicode_path_index.files.insert(to_unicode_path(&k_path, &conversion_type)?, v_aspects);
+        };
+        Ok(unicode_path_index)
+    }
+}
+
+impl<'index> UnicodePathIndexRef<'index> {
+    pub fn from_index(
+        index: &'index Index,
+        conversion_type: Conversion
+    ) -> FcResult<Self> {
+        let mut unicode_path_index = Self {
+            files: BTreeMap::new()
+        };
+        for (k_path, v_aspects) in &index.files {
+            unicode_path_index.files.insert(to_unicode_path(k_path, &conversion_type)?, v_aspects);
+        };
+        Ok(unicode_path_index)
     }
 }
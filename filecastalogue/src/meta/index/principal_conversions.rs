@@ -1,7 +1,7 @@
 use std::io::Read;
 use std::convert::{TryFrom, TryInto};
 use crate::{error::{Error, ErrorKind, WrappedError}, meta::blob::model::Blob};
-use super::model::{Index, UnicodePathIndex, Conversion};
+use super::model::{Index, UnicodePathIndex, UnicodePathIndexRef, Conversion};
 
 /// Principal conversions between Index and various other forms.
 /// 
@@ -54,12 +54,23 @@ impl TryFrom<Index> for Blob {
     /// Principal conversion from Index to Blob.
     /// 
     /// This is the one way which should be used to obtain a Blob from
-    /// an Index.
+    /// an Index. It's the same as converting a reference to it.
+    fn try_from(index: Index) -> Result<Self, Self::Error> {
+        Blob::try_from(&index)
+    }
+}
+
+impl TryFrom<&Index> for Blob {
+    type Error = Error;
+
+    /// Principal conversion from a reference to an Index to Blob, for
+    /// when the Index is still needed afterwards, without having to
+    /// clone it.
     /// 
     /// This converts the paths of the Index to their unicode representation
     /// and uses `serde_json::to_vec_pretty` to create the blob.
-    fn try_from(index: Index) -> Result<Self, Self::Error> {
-        let unicode_path_index: UnicodePathIndex = UnicodePathIndex::from_index(
+    fn try_from(index: &Index) -> Result<Self, Self::Error> {
+        let unicode_path_index = UnicodePathIndexRef::from_index(
             index,
             Conversion::NonLossyBytes
         )?;
@@ -75,4 +86,4 @@ impl TryFrom<Index> for Blob {
             )),
         }
     }
-}
\ No newline at end of file
+}
@@ -1 +1,3 @@
-pub mod model;
\ No newline at end of file
+pub mod manifest;
+pub mod model;
+pub mod tarball;
\ No newline at end of file
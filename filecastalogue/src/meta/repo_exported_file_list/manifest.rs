@@ -0,0 +1,251 @@
+use std::ffi::OsString;
+use std::io::Write;
+use serde::{Serialize, Deserialize};
+use crate::{
+    error::FcResult,
+    files::tracked_ordinary_blob::TrackedOrdinaryBlobProvider,
+    meta::{
+        file_aspects::{
+            aspects::{directory::{RepoExportedDirectoryAspects, TrackedDirectoryAspects},
+            non_existing::{RepoExportedNonExistingAspects, TrackedNonExistingAspects},
+            ordinary::TrackedOrdinaryAspects,
+            symlink::{RepoExportedSymlinkAspects, TrackedSymlinkAspects}},
+            attributes::Attributes
+        },
+        tracked_path::model::TrackedPath
+    }
+};
+use super::model::{RepoExportedFile, RepoExportedFileList};
+
+/// The format of the manifests written by this version of the crate.
+/// Raised whenever a manifest changes in a way consumers can't just
+/// ignore, which adding fields doesn't count as.
+pub const MANIFEST_FORMAT_VERSION: u32 = 1;
+
+/// The way a manifest is written.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ManifestFormat {
+    /// A single `Manifest` object.
+    Json,
+    /// A `ManifestHeader` object on the first line, followed by a
+    /// `ManifestFile` object on each line after it.
+    JsonLines,
+}
+
+/// A manifest written as JSON, see `ManifestFileList`.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct Manifest {
+    pub format_version: u32,
+    /// Sorted by path.
+    pub files: Vec<ManifestFile>,
+}
+
+/// The first line of a manifest written as JSON Lines.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestHeader {
+    pub format_version: u32,
+}
+
+/// A file in a manifest, with its path and aspects side by side in the
+/// same object, the `kind` of file telling which aspects there are, just
+/// like in an index.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestFile {
+    pub path: String,
+    #[serde(flatten)]
+    pub aspects: ManifestFileAspects,
+}
+
+/// The aspects of a file in a manifest, see `TrackedFileAspects`.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(
+    tag = "kind",
+    rename_all(
+        serialize = "snake_case",
+        deserialize = "snake_case"
+    )
+)]
+pub enum ManifestFileAspects {
+    NonExisting(RepoExportedNonExistingAspects),
+    Directory(RepoExportedDirectoryAspects),
+    Ordinary(ManifestOrdinaryAspects),
+    Symlink(RepoExportedSymlinkAspects),
+}
+
+/// Aspects of an ordinary file in a manifest, which are those of
+/// `RepoExportedOrdinaryAspects`, with the blob inlined if at all.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct ManifestOrdinaryAspects {
+    pub repo_blob_hash: String,
+    pub attributes: Attributes,
+    /// The content of the file, base64 encoded, if the manifest was written
+    /// with content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_base64: Option<String>,
+}
+
+impl ManifestFile {
+    /// Paths that aren't valid unicode are converted lossily, like they
+    /// are when an index is written.
+    fn new(path: &OsString, aspects: ManifestFileAspects) -> FcResult<Self> {
+        Ok(Self {
+            path: TrackedPath::new(path)?.as_os_str().to_string_lossy().into_owned(),
+            aspects
+        })
+    }
+}
+
+/* Notes:
+    JSON Lines are written as files are added, so consumers can start on
+    a version before it's written in full, and so a version with large
+    inlined contents isn't held in memory as a whole. A JSON manifest is
+    written once it's finished, sorted, so the same version always gives
+    the same manifest.
+*/
+/// A `RepoExportedFileList` writing the files added to it as a manifest,
+/// as `Repo::get_files` hands them over, for tools that don't link this
+/// crate. Every file comes with its path, kind and aspects, with ordinary
+/// files having their attributes and `repo_blob_hash`, as well as their
+/// content if the manifest is written with it.
+///
+/// Manifests carry their `format_version`, see `MANIFEST_FORMAT_VERSION`.
+pub struct ManifestFileList<Writeable: Write> {
+    writeable: Writeable,
+    format: ManifestFormat,
+    /// Whether the content of ordinary files is inlined.
+    has_content: bool,
+    /// The files added so far, for a JSON manifest.
+    files: Vec<ManifestFile>,
+    is_header_written: bool,
+}
+
+impl<Writeable: Write> ManifestFileList<Writeable> {
+    pub fn new(writeable: Writeable, format: ManifestFormat) -> Self {
+        Self {
+            writeable,
+            format,
+            has_content: false,
+            files: vec!(),
+            is_header_written: false,
+        }
+    }
+
+    /// Inlines the content of ordinary files into the manifest, base64
+    /// encoded.
+    pub fn new_with_content(writeable: Writeable, format: ManifestFormat) -> Self {
+        Self {
+            has_content: true,
+            ..Self::new(writeable, format)
+        }
+    }
+
+    fn write_header(&mut self) -> FcResult<()> {
+        if !self.is_header_written {
+            serde_json::to_writer(
+                &mut self.writeable,
+                &ManifestHeader { format_version: MANIFEST_FORMAT_VERSION }
+            )?;
+            self.writeable.write_all(b"\n")?;
+            self.is_header_written = true;
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, file: ManifestFile) -> FcResult<()> {
+        match self.format {
+            ManifestFormat::Json => self.files.push(file),
+            ManifestFormat::JsonLines => {
+                self.write_header()?;
+                serde_json::to_writer(&mut self.writeable, &file)?;
+                self.writeable.write_all(b"\n")?;
+            },
+        }
+        Ok(())
+    }
+
+    /// Finishes the manifest, returning what it was written to.
+    pub fn finish(mut self) -> FcResult<Writeable> {
+        match self.format {
+            ManifestFormat::Json => {
+                let mut files = std::mem::take(&mut self.files);
+                files.sort_by(|file, other_file| file.path.cmp(&other_file.path));
+                serde_json::to_writer(&mut self.writeable, &Manifest {
+                    format_version: MANIFEST_FORMAT_VERSION,
+                    files
+                })?;
+                self.writeable.write_all(b"\n")?;
+            },
+            ManifestFormat::JsonLines => self.write_header()?,
+        }
+        self.writeable.flush()?;
+        Ok(self.writeable)
+    }
+}
+
+impl<Writeable: Write> RepoExportedFileList for ManifestFileList<Writeable> {
+
+    /// Everything added is either written already or held as a
+    /// `ManifestFile`, so there's nothing to consume.
+    fn consume_as_vec(self: Box<Self>) -> Vec<Box<dyn RepoExportedFile>> {
+        vec!()
+    }
+
+    fn add_non_existing(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedNonExistingAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        self.add_file(ManifestFile::new(
+            &path,
+            ManifestFileAspects::NonExisting(
+                RepoExportedNonExistingAspects::from_tracked(tracked_aspects))
+        )?)?;
+        Ok(self)
+    }
+
+    fn add_directory(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedDirectoryAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        self.add_file(ManifestFile::new(
+            &path,
+            ManifestFileAspects::Directory(
+                RepoExportedDirectoryAspects::from_tracked(tracked_aspects))
+        )?)?;
+        Ok(self)
+    }
+
+    fn add_ordinary(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedOrdinaryAspects,
+        blob_provider: Box<dyn TrackedOrdinaryBlobProvider>
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        let content_base64 = match self.has_content {
+            true => Some(base64::encode(blob_provider.into_blob()?.into_vec())),
+            false => None,
+        };
+        self.add_file(ManifestFile::new(
+            &path,
+            ManifestFileAspects::Ordinary(ManifestOrdinaryAspects {
+                repo_blob_hash: tracked_aspects.hash,
+                attributes: tracked_aspects.attributes,
+                content_base64
+            })
+        )?)?;
+        Ok(self)
+    }
+
+    fn add_symlink(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedSymlinkAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        self.add_file(ManifestFile::new(
+            &path,
+            ManifestFileAspects::Symlink(RepoExportedSymlinkAspects::from_tracked(tracked_aspects))
+        )?)?;
+        Ok(self)
+    }
+}
@@ -3,17 +3,35 @@ use crate::{
     error::FcResult,
     files::tracked_ordinary_blob::TrackedOrdinaryBlobProvider,
     meta::{
-        blob::repo_exported::RepoExportedHeapOrdinaryBlobProvider,
+        blob::repo_exported::RepoExportedStreamedOrdinaryBlobProvider,
         file_aspects::{
             aspects::{directory::{RepoExportedDirectoryAspects, TrackedDirectoryAspects},
             non_existing::{RepoExportedNonExistingAspects, TrackedNonExistingAspects},
             ordinary::{RepoExportedOrdinaryAspects, TrackedOrdinaryAspects},
             symlink::{RepoExportedSymlinkAspects, TrackedSymlinkAspects}},
             enums::RepoExportedFileAspects
-        }
+        },
+        tracked_path::model::TrackedPath
     }
 };
 
+/// Something about a file that couldn't be carried over, as there's
+/// nothing to represent it with on the other side.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct UnrepresentedAspect {
+    pub path: TrackedPath,
+    pub description: String,
+}
+
+impl UnrepresentedAspect {
+    pub(crate) fn new(path: &TrackedPath, description: String) -> Self {
+        Self {
+            path: path.clone(),
+            description
+        }
+    }
+}
+
 // TODO [doc]: Needs documentation.
 // TODO [directory-structure]: Evaluate how well this really fits into `meta`.
 
@@ -114,9 +132,9 @@ impl RepoExportedFileList for RepoExportedVecFileList {
         tracked_blob_provider: Box<dyn TrackedOrdinaryBlobProvider>
     ) -> FcResult<&mut dyn RepoExportedFileList> {
         let repo_exported_blob_provider = Box::new(
-            RepoExportedHeapOrdinaryBlobProvider::from_tracked_ordinary_blob_provider(
+            RepoExportedStreamedOrdinaryBlobProvider::from_tracked_ordinary_blob_provider(
                 tracked_blob_provider
-            )?
+            )
         );
         let file: Box<dyn RepoExportedFile> = Box::new(
             RepoExportedHeapFile::new(
@@ -0,0 +1,243 @@
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use crate::{
+    error::FcResult,
+    files::blob::spool_blob,
+    files::tracked_ordinary_blob::TrackedOrdinaryBlobProvider,
+    meta::{
+        file_aspects::{
+            aspects::{directory::{DirectoryMode, TrackedDirectoryAspects},
+            non_existing::TrackedNonExistingAspects,
+            ordinary::TrackedOrdinaryAspects,
+            symlink::TrackedSymlinkAspects}
+        },
+        tracked_path::model::TrackedPath
+    }
+};
+use super::model::{RepoExportedFile, RepoExportedFileList, UnrepresentedAspect};
+
+const DEFAULT_DIRECTORY_MODE: u32 = 0o755;
+const DEFAULT_ORDINARY_MODE: u32 = 0o644;
+const SYMLINK_MODE: u32 = 0o777;
+/// What the name of a whiteout in an OCI layer starts with, followed by
+/// the name of the file it removes.
+pub(crate) const WHITEOUT_PREFIX: &str = ".wh.";
+/// The name of the whiteout in an OCI layer marking its directory as
+/// opaque, hiding whatever lower layers have in it.
+pub(crate) const OPAQUE_WHITEOUT_NAME: &str = ".wh..wh..opq";
+
+/// Returns the path of a tracked file within a tarball, which is relative,
+/// with the root being `./`.
+fn get_entry_path(path: &OsString) -> FcResult<PathBuf> {
+    let tracked_path = TrackedPath::new(path)?;
+    match tracked_path.as_relative_path().as_os_str().is_empty() {
+        true => Ok(PathBuf::from("./")),
+        false => Ok(tracked_path.as_relative_path().to_owned()),
+    }
+}
+
+/* Notes:
+    Only names are written for owners, with 0 for their IDs, as the IDs
+    on whatever system the tarball is extracted on might differ from
+    those on the system it's written on anyway. Tools extracting tarballs
+    look owners up by name first.
+    Owners which are nothing but a number, e.g. those of tarball entries
+    without names, are taken to be IDs, which are written without a name
+    instead.
+*/
+fn get_header(entry_type: tar::EntryType, posix_user: &str, posix_group: &str, mode: u32)
+-> FcResult<tar::Header> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(entry_type);
+    header.set_size(0);
+    header.set_mtime(0);
+    match posix_user.parse::<u64>() {
+        Ok(uid) => header.set_uid(uid),
+        Err(_) => {
+            header.set_uid(0);
+            header.set_username(posix_user)?;
+        },
+    }
+    match posix_group.parse::<u64>() {
+        Ok(gid) => header.set_gid(gid),
+        Err(_) => {
+            header.set_gid(0);
+            header.set_groupname(posix_group)?;
+        },
+    }
+    header.set_mode(mode);
+    Ok(header)
+}
+
+/// A `RepoExportedFileList` writing the files added to it into a tarball
+/// right away, as `Repo::get_files` hands them over, with their owners,
+/// modes, symlinks and directories, so a version can be streamed into a
+/// tarball without being held in memory as a whole. As the length of an
+/// entry goes before its content, each blob is spooled to a temporary
+/// file on its way into the tarball, so neither are blobs.
+///
+/// Files tracked as non-existing are left out, as there's no such thing
+/// in a plain tarball, see `new_oci_layer` for one there is. Files without
+/// a mode get the usual defaults of 0o755 for directories and 0o644 for
+/// anything else.
+pub struct TarballFileList<Writeable: Write> {
+    builder: tar::Builder<Writeable>,
+    /// Whether this is an OCI layer, with whiteouts.
+    is_oci_layer: bool,
+    unrepresented_aspects: Vec<UnrepresentedAspect>,
+}
+
+impl<Writeable: Write> TarballFileList<Writeable> {
+    pub fn new(writeable: Writeable) -> Self {
+        Self {
+            builder: tar::Builder::new(writeable),
+            is_oci_layer: false,
+            unrepresented_aspects: vec!(),
+        }
+    }
+
+    /* Notes:
+        Whiteouts remove whatever is at their path along with anything in
+        it, regardless of its kind, so a non-existing file tracked only if
+        it's of a particular kind, or not recursively, is removed more
+        eagerly by the layer than when applied. Likewise, the `allowed`
+        patterns of exclusive directories can't be expressed, as an opaque
+        directory hides everything lower layers have in it.
+    */
+    /// Makes the tarball an OCI image layer, with files tracked as
+    /// non-existing becoming whiteouts and exclusive directories being
+    /// marked as opaque. Patterns exclusive directories allow are left
+    /// out, as are the constraints of non-existing files, see
+    /// `get_unrepresented_aspects`.
+    pub fn new_oci_layer(writeable: Writeable) -> Self {
+        Self {
+            builder: tar::Builder::new(writeable),
+            is_oci_layer: true,
+            unrepresented_aspects: vec!(),
+        }
+    }
+
+    /// Appends an empty file owned by root, which is what whiteouts are.
+    fn append_whiteout(&mut self, entry_path: PathBuf) -> FcResult<()> {
+        let mut header = get_header(
+            tar::EntryType::Regular, "root", "root", DEFAULT_ORDINARY_MODE)?;
+        self.builder.append_data(&mut header, entry_path, std::io::empty())?;
+        Ok(())
+    }
+
+    /// Returns what couldn't be carried over into the tarball for the
+    /// files added so far.
+    pub fn get_unrepresented_aspects(&self) -> &[UnrepresentedAspect] {
+        &self.unrepresented_aspects
+    }
+
+    /// Finishes the tarball, returning what it was written to.
+    pub fn finish(self) -> FcResult<Writeable> {
+        let mut writeable = self.builder.into_inner()?;
+        writeable.flush()?;
+        Ok(writeable)
+    }
+}
+
+impl<Writeable: Write> RepoExportedFileList for TarballFileList<Writeable> {
+
+    /// Everything added has been written into the tarball already, so
+    /// there's nothing left to consume.
+    fn consume_as_vec(self: Box<Self>) -> Vec<Box<dyn RepoExportedFile>> {
+        vec!()
+    }
+
+    fn add_non_existing(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedNonExistingAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        if !self.is_oci_layer {
+            return Ok(self)
+        }
+        let entry_path = get_entry_path(&path)?;
+        let file_name = match entry_path.file_name() {
+            Some(file_name) => file_name,
+            // The root can't be removed.
+            None => return Ok(self),
+        };
+        let mut whiteout_name = OsString::from(WHITEOUT_PREFIX);
+        whiteout_name.push(file_name);
+        self.append_whiteout(entry_path.with_file_name(whiteout_name))?;
+        if !tracked_aspects.recursive {
+            self.unrepresented_aspects.push(UnrepresentedAspect::new(
+                &TrackedPath::new(&path)?,
+                String::from("Removing a directory only if it's empty, left out.")
+            ));
+        }
+        if let Some(kind) = tracked_aspects.only_if_kind {
+            self.unrepresented_aspects.push(UnrepresentedAspect::new(
+                &TrackedPath::new(&path)?,
+                format!("Removing only a file of kind {}, left out.", kind.kind_str())
+            ));
+        }
+        Ok(self)
+    }
+
+    fn add_directory(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedDirectoryAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        let mut header = get_header(
+            tar::EntryType::Directory,
+            &tracked_aspects.attributes.posix_user,
+            &tracked_aspects.attributes.posix_group,
+            tracked_aspects.attributes.posix_mode.unwrap_or(DEFAULT_DIRECTORY_MODE)
+        )?;
+        let entry_path = get_entry_path(&path)?;
+        self.builder.append_data(&mut header, &entry_path, std::io::empty())?;
+        if self.is_oci_layer {
+            if let DirectoryMode::Exclusive { allowed } = &tracked_aspects.mode {
+                self.append_whiteout(entry_path.join(OPAQUE_WHITEOUT_NAME))?;
+                if !allowed.is_empty() {
+                    self.unrepresented_aspects.push(UnrepresentedAspect::new(
+                        &TrackedPath::new(&path)?,
+                        format!("Allowed patterns {:?}, left out.", allowed)
+                    ));
+                }
+            }
+        }
+        Ok(self)
+    }
+
+    fn add_ordinary(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedOrdinaryAspects,
+        blob_provider: Box<dyn TrackedOrdinaryBlobProvider>
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        let mut header = get_header(
+            tar::EntryType::Regular,
+            &tracked_aspects.attributes.posix_user,
+            &tracked_aspects.attributes.posix_group,
+            tracked_aspects.attributes.posix_mode.unwrap_or(DEFAULT_ORDINARY_MODE)
+        )?;
+        let (spooled_file, length) = spool_blob(|file| {
+            io::copy(&mut blob_provider.get_readable()?, file)?;
+            Ok(())
+        })?;
+        header.set_size(length);
+        self.builder.append_data(&mut header, get_entry_path(&path)?, spooled_file)?;
+        Ok(self)
+    }
+
+    /// Symlinks aren't tracked with attributes, so they're written as
+    /// owned by root.
+    fn add_symlink(
+        &mut self,
+        path: OsString,
+        tracked_aspects: TrackedSymlinkAspects
+    ) -> FcResult<&mut dyn RepoExportedFileList> {
+        let mut header = get_header(tar::EntryType::Symlink, "root", "root", SYMLINK_MODE)?;
+        self.builder.append_link(
+            &mut header, get_entry_path(&path)?, &tracked_aspects.linked_to)?;
+        Ok(self)
+    }
+}
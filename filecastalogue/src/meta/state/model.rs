@@ -1,15 +1,20 @@
 use serde::{Serialize, Deserialize};
+use crate::files::hashable::HashAlgorithm;
 use super::super::version::model::Version;
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
 pub struct State {
-    pub versions: Vec<Version>
+    pub versions: Vec<Version>,
+    /// The algorithm blobs and indexes put into the repo are hashed with.
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm
 }
 
 impl State {
     pub fn new() -> Self {
         Self {
             versions: vec!(),
+            hash_algorithm: HashAlgorithm::default(),
         }
     }
 }
\ No newline at end of file
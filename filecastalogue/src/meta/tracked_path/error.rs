@@ -0,0 +1,57 @@
+use std::{ffi::OsString, fmt};
+use crate::error::Payload;
+
+/// The reason a path was rejected as a `TrackedPath`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum InvalidTrackedPathReason {
+    NotAbsolute,
+    DotComponent,
+    DoubleDotComponent,
+    NulByte,
+}
+
+impl InvalidTrackedPathReason {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match *self {
+            Self::NotAbsolute => "it isn't absolute",
+            Self::DotComponent => "it contains a single-dot (.) component",
+            Self::DoubleDotComponent => "it contains a double-dot (..) component",
+            Self::NulByte => "it contains a NUL byte",
+        }
+    }
+}
+
+impl fmt::Display for InvalidTrackedPathReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+pub struct InvalidTrackedPathErrorPayload {
+    pub path: OsString,
+    pub reason: InvalidTrackedPathReason,
+}
+
+impl fmt::Debug for InvalidTrackedPathErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The path \"{:?}\" can't be tracked, as {}.",
+            self.path,
+            self.reason,
+        )
+    }
+}
+
+impl fmt::Display for InvalidTrackedPathErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The path \"{:?}\" can't be tracked, as {}.",
+            self.path,
+            self.reason,
+        )
+    }
+}
+
+impl Payload for InvalidTrackedPathErrorPayload {}
@@ -0,0 +1,160 @@
+use std::{ffi::{OsStr, OsString}, fmt, path::Path};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use crate::error::{Error, ErrorKind, FcResult};
+use super::error::{InvalidTrackedPathErrorPayload, InvalidTrackedPathReason};
+
+/// The separator used by tracked paths, regardless of the platform
+/// we're running on.
+const SEPARATOR: u8 = b'/';
+
+/* Notes:
+    Tracked paths describe locations on the tracked system, not on
+    the system the library happens to be running on. They're always
+    '/'-separated and absolute, which is why the validation below works
+    on the bytes of the OsStr instead of `Path::components`,
+    the latter also silently dropping `.` components we'd like to reject.
+*/
+/// A validated and normalized absolute path of a file tracked in an Index.
+///
+/// Constructing one guarantees that the path:
+///   - Is absolute (starts with `/`).
+///   - Has no empty components, so `//` collapses to `/` and trailing
+///     separators are removed (except for the root itself).
+///   - Contains no `.` or `..` components, so it can't escape whatever
+///     root it ends up getting applied to.
+///   - Contains no NUL bytes.
+///
+/// That way, `/etc//foo` and `/etc/foo/` end up being the same entry,
+/// whilst `etc/foo`, `/etc/./foo` and `/etc/../etc/foo` are rejected
+/// with `ErrorKind::InvalidTrackedPath`.
+#[derive(Eq, PartialEq, Hash, Ord, PartialOrd, Clone)]
+pub struct TrackedPath {
+    inner: OsString
+}
+
+impl TrackedPath {
+
+    /// Validate and normalize the specified path.
+    pub fn new<PathRef: AsRef<OsStr>>(path: PathRef) -> FcResult<Self> {
+        let path = path.as_ref();
+        let bytes = path.as_bytes();
+        let reject = |reason| Err(error!(
+            ErrorKind::InvalidTrackedPath,
+            "Validating a path to be tracked.",
+            payload => InvalidTrackedPathErrorPayload {
+                path: path.to_owned(),
+                reason,
+            }
+        ));
+
+        if bytes.contains(&0) {
+            return reject(InvalidTrackedPathReason::NulByte);
+        }
+        if bytes.first() != Some(&SEPARATOR) {
+            return reject(InvalidTrackedPathReason::NotAbsolute);
+        }
+
+        let mut normalized: Vec<u8> = Vec::with_capacity(bytes.len());
+        for component in bytes.split(|byte| *byte == SEPARATOR) {
+            match component {
+                b"" => continue,
+                b"." => return reject(InvalidTrackedPathReason::DotComponent),
+                b".." => return reject(
+                    InvalidTrackedPathReason::DoubleDotComponent
+                ),
+                _ => {
+                    normalized.push(SEPARATOR);
+                    normalized.extend_from_slice(component);
+                }
+            }
+        }
+        if normalized.is_empty() {
+            normalized.push(SEPARATOR);
+        }
+
+        Ok(Self {
+            inner: OsString::from_vec(normalized)
+        })
+    }
+
+    pub fn as_os_str(&self) -> &OsStr {
+        &self.inner
+    }
+
+    pub fn as_path(&self) -> &Path {
+        Path::new(&self.inner)
+    }
+
+    /// Returns the path without its leading separator, which makes it safe
+    /// to join onto the root of wherever the tracked system is located.
+    pub fn as_relative_path(&self) -> &Path {
+        Path::new(OsStr::from_bytes(&self.inner.as_bytes()[1..]))
+    }
+
+    pub fn into_os_string(self) -> OsString {
+        self.inner
+    }
+
+    /// Returns the path of the child with the specified file name.
+    ///
+    /// The file name is validated along with the rest of the joined path,
+    /// so it can't sneak in `.` or `..` components.
+    pub fn join<NameRef: AsRef<OsStr>>(&self, file_name: NameRef) -> FcResult<TrackedPath> {
+        let mut joined = self.inner.clone();
+        joined.push("/");
+        joined.push(file_name.as_ref());
+        Self::new(joined)
+    }
+
+    /// Returns the path of the parent directory, or None for the root.
+    pub fn parent(&self) -> Option<TrackedPath> {
+        let bytes = self.inner.as_bytes();
+        let separator_position = bytes.iter().rposition(|byte| *byte == SEPARATOR)?;
+        if bytes.len() == 1 {
+            return None
+        }
+        let parent_bytes = match separator_position {
+            0 => &bytes[..1],
+            _ => &bytes[..separator_position]
+        };
+        Some(Self {
+            inner: OsString::from_vec(parent_bytes.to_vec())
+        })
+    }
+
+    /// Returns all the paths above this one, nearest one first, ending
+    /// with the root.
+    pub fn ancestors(&self) -> Vec<TrackedPath> {
+        let mut ancestors = vec!();
+        let mut current = self.parent();
+        while let Some(path) = current {
+            current = path.parent();
+            ancestors.push(path);
+        }
+        ancestors
+    }
+}
+
+impl AsRef<OsStr> for TrackedPath {
+    fn as_ref(&self) -> &OsStr {
+        self.as_os_str()
+    }
+}
+
+impl From<TrackedPath> for OsString {
+    fn from(tracked_path: TrackedPath) -> Self {
+        tracked_path.into_os_string()
+    }
+}
+
+impl fmt::Debug for TrackedPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.inner)
+    }
+}
+
+impl fmt::Display for TrackedPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.inner.to_string_lossy())
+    }
+}
@@ -1,14 +1,30 @@
 use std::ffi::OsStr;
+use std::ffi::OsString;
 use std::fmt::Display;
+use std::io;
+use std::io::Cursor;
 use std::io::Read;
 use std::io::Write;
 use std::path::PathBuf;
+use std::sync::Arc;
+use crate::error::Error;
+use crate::error::ErrorKind;
 use crate::error::FcResult;
 use crate::error::ErrorPathBuf;
 use crate::error::Payload;
+use crate::files::blob_encoding::{DecodingSource, DecodingWriter};
+use crate::files::hashable::{HashAlgorithm, HashingReader, HashingWriter, get_file_name_of_hash,
+    get_hash_of_file_name, normalize_hash};
 
 pub mod drivers {
     pub mod local;
+    pub mod encrypted;
+    pub mod fault_injecting;
+    pub mod http;
+    pub mod memory;
+    pub mod s3;
+    pub mod sqlite;
+    pub mod tar;
 }
 
 #[derive(Debug)]
@@ -30,6 +46,245 @@ impl Display for PathDoesNotExistInCollectionPayload {
 }
 impl Payload for PathDoesNotExistInCollectionPayload {}
 
+#[derive(Debug)]
+pub struct ReadOnlyCollectionPayload {
+    pub collection_path: PathBuf
+}
+impl Display for ReadOnlyCollectionPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Collection path: {}.",
+            ErrorPathBuf::from(self.collection_path.to_owned())
+        )
+    }
+}
+impl Payload for ReadOnlyCollectionPayload {}
+
+/// Opens a particular file of a collection for reading, as often as needed.
+/// 
+/// Unlike a Read obtained from a collection handler directly, this is
+/// detached from the handler it was obtained from, so it can be handed out
+/// as part of long lived things like exported file lists, which then
+/// read the file straight from the collection once they need it, without
+/// having to hold it in memory in the meantime.
+pub trait ReadableSource {
+    fn open(&self) -> FcResult<Box<dyn Read>>;
+}
+
+/// Content already in memory, e.g. as fetched by a collection handler
+/// which can't read files bit by bit, shared by every Read opened.
+#[derive(Clone)]
+pub struct BytesSource {
+    content: Arc<Vec<u8>>
+}
+
+impl BytesSource {
+    pub fn new(content: Arc<Vec<u8>>) -> Self {
+        Self {
+            content
+        }
+    }
+}
+
+/// Lets a Cursor read the content without copying it.
+struct SharedBytes(Arc<Vec<u8>>);
+
+impl AsRef<[u8]> for SharedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl ReadableSource for BytesSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(Box::new(Cursor::new(SharedBytes(Arc::clone(&self.content)))))
+    }
+}
+
+#[derive(Debug)]
+pub struct ContentHashMismatchPayload {
+    pub file_name: OsString,
+    pub actual_hash: String
+}
+impl Display for ContentHashMismatchPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "File name: {:?}, actual hash: {}.",
+            self.file_name, self.actual_hash
+        )
+    }
+}
+impl Payload for ContentHashMismatchPayload {}
+
+/// How a collection handler fetching files from somewhere it doesn't
+/// trust checks that it got what it asked for, going by the names of
+/// files which are hashes of their content. Files named otherwise, like
+/// the state, are never checked.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ContentVerification {
+    /// Nothing is checked, e.g. for encrypted collections, whose file
+    /// names aren't hashes of what's stored.
+    None,
+    /// The content has to have the hash it's named by, like indexes.
+    Content,
+    /// The content has to have the hash it's named by once it's decoded
+    /// the way blobs are, see `DecodingSource`.
+    BlobContent,
+}
+
+/// Returns the hash the file is named after, along with its algorithm,
+/// if it's named after one.
+fn get_hash_of_name(file_name: &OsStr) -> Option<(String, HashAlgorithm)> {
+    let hash = get_hash_of_file_name(file_name)?;
+    let hash_algorithm = HashAlgorithm::of_hash(&hash).ok()?;
+    Some((hash, hash_algorithm))
+}
+
+impl ContentVerification {
+    /// Fails with `ErrorKind::ContentHashMismatch` unless the content is
+    /// what the file name says it is.
+    pub fn verify(&self, file_name: &OsStr, content: &Arc<Vec<u8>>) -> FcResult<()> {
+        let (expected_hash, hash_algorithm) = match (self, get_hash_of_name(file_name)) {
+            (Self::None, _) | (_, None) => return Ok(()),
+            (_, Some(hash_of_name)) => hash_of_name,
+        };
+        let source = BytesSource::new(Arc::clone(content));
+        let actual_hash = match self {
+            Self::BlobContent => hash_algorithm.hash_readable(
+                &mut DecodingSource::new(Box::new(source)).open()?)?,
+            _ => hash_algorithm.hash_readable(&mut source.open()?)?,
+        };
+        if normalize_hash(&actual_hash) == normalize_hash(&expected_hash) {
+            return Ok(())
+        }
+        Err(error!(
+            ErrorKind::ContentHashMismatch,
+            "Verifying fetched content against the hash it's named by.",
+            payload => ContentHashMismatchPayload {
+                file_name: file_name.to_owned(),
+                actual_hash
+            }
+        ))
+    }
+
+    /// Returns a Read passing through what `readable` provides, which
+    /// fails once it ends unless the content is what the file name says
+    /// it is, for content too large to be fetched whole and then checked
+    /// with `verify`. See `VerifyingSource` on what that means for
+    /// whatever reads it.
+    pub fn get_verifying_reader<R: Read + 'static>(&self, file_name: &OsStr, readable: R)
+    -> FcResult<Box<dyn Read>> {
+        let (expected_hash, hash_algorithm) = match (self, get_hash_of_name(file_name)) {
+            (Self::None, _) | (_, None) => return Ok(Box::new(readable)),
+            (_, Some(hash_of_name)) => hash_of_name,
+        };
+        Ok(match self {
+            Self::BlobContent => Box::new(BlobVerifyingReader {
+                inner: readable,
+                decoding_writer: DecodingWriter::new(
+                    HashingWriter::new_with_algorithm(hash_algorithm)),
+                expected_hash,
+            }),
+            _ => Box::new(VerifyingReader {
+                hashing_reader: HashingReader::new_with_algorithm(readable, hash_algorithm),
+                expected_hash,
+            }),
+        })
+    }
+}
+
+/// Passes through what the wrapped Read provides, failing once it ends
+/// with an io::Error carrying an `ErrorKind::ContentHashMismatch`,
+/// unless it had the expected hash, see `VerifyingSource`.
+pub struct VerifyingReader<R: Read> {
+    hashing_reader: HashingReader<R>,
+    expected_hash: String,
+}
+
+/// Fails with an io::Error carrying an `ErrorKind::ContentHashMismatch`
+/// unless the hashes are the same, for content verified as it's read.
+fn verify_read_hash(expected_hash: &str, actual_hash: String) -> io::Result<()> {
+    if normalize_hash(&actual_hash) == normalize_hash(expected_hash) {
+        return Ok(())
+    }
+    Err(io::Error::from(error!(
+        ErrorKind::ContentHashMismatch,
+        "Verifying content against its hash as it's read.",
+        payload => ContentHashMismatchPayload {
+            file_name: get_file_name_of_hash(expected_hash),
+            actual_hash
+        }
+    )))
+}
+
+impl<R: Read> Read for VerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_count = self.hashing_reader.read(buf)?;
+        if read_count > 0 || buf.is_empty() {
+            return Ok(read_count)
+        }
+        verify_read_hash(&self.expected_hash, self.hashing_reader.finalize())?;
+        Ok(0)
+    }
+}
+
+/// Passes through a blob as stored in a blob collection, like
+/// `VerifyingReader` does, with the hash being that of its content once
+/// it's decoded, see `DecodingWriter`.
+pub struct BlobVerifyingReader<R: Read> {
+    inner: R,
+    decoding_writer: DecodingWriter<HashingWriter>,
+    expected_hash: String,
+}
+
+impl<R: Read> Read for BlobVerifyingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_count = self.inner.read(buf)?;
+        if read_count > 0 || buf.is_empty() {
+            self.decoding_writer.write_all(&buf[..read_count])?;
+            return Ok(read_count)
+        }
+        let actual_hash = self.decoding_writer.finish()?.finalize();
+        verify_read_hash(&self.expected_hash, actual_hash)?;
+        Ok(0)
+    }
+}
+
+/* Notes:
+    The content can only be known to be what it should be once all of it
+    has been read, so whatever reads it only finds out that it isn't at
+    the very end. Anything reading just part of it doesn't find out.
+*/
+/// Provides what the wrapped source does, with every Read opened
+/// verifying the content against the expected hash, see `VerifyingReader`.
+pub struct VerifyingSource {
+    source: Box<dyn ReadableSource>,
+    expected_hash: String,
+    hash_algorithm: HashAlgorithm,
+}
+
+impl VerifyingSource {
+    pub fn new(source: Box<dyn ReadableSource>, expected_hash: &str) -> FcResult<Self> {
+        Ok(Self {
+            source,
+            expected_hash: expected_hash.to_owned(),
+            hash_algorithm: HashAlgorithm::of_hash(expected_hash)?,
+        })
+    }
+}
+
+impl ReadableSource for VerifyingSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(Box::new(VerifyingReader {
+            hashing_reader: HashingReader::new_with_algorithm(
+                self.source.open()?, self.hash_algorithm),
+            expected_hash: self.expected_hash.clone(),
+        }))
+    }
+}
+
 // A collection of files of which we know nothing except that
 // it holds an unknown number (incl. 0) of files of a certain kind.
 pub trait OpaqueCollectionHandler {
@@ -41,6 +296,12 @@ pub trait OpaqueCollectionHandler {
     -> FcResult<Box<(dyn Read)>>;
     fn get_file_writeable(&self, name: &OsStr)
     -> FcResult<Box<(dyn Write)>>;
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>>;
+    /// Renames a file in the collection, replacing whatever file might
+    /// already be there under the new name.
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()>;
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()>;
     fn collection_exists(self: &mut Self) -> bool;
     fn create_collection(self: &mut Self) -> FcResult<()>;
     fn create_collection_ignore_exists(self: &mut Self) -> FcResult<()>;
@@ -0,0 +1,536 @@
+use std::convert::TryInto;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use chacha20poly1305::aead::{Aead, KeyInit, OsRng, Payload as AeadPayload};
+use chacha20poly1305::aead::rand_core::RngCore;
+use crate::error::{Error, ErrorKind, ErrorPathBuf, FcResult, Payload, WrappedError};
+use crate::files::blob_encoding::read_up_to;
+use crate::opaque_collection_handler::{OpaqueCollectionHandler, ReadableSource};
+
+/* Notes:
+    File layout:
+        - ENCRYPTED_FILE_MAGIC
+        - A random nonce prefix, unique to the file.
+        - The content, in chunks of CHUNK_LENGTH bytes (the last one
+            might be shorter, or empty), each encrypted separately with
+            XChaCha20-Poly1305.
+
+    The nonce of a chunk is the file's nonce prefix followed by the chunk's
+    counter, with its highest bit set for the last chunk. That way, chunks
+    can neither be reordered nor dropped from the end without decryption
+    failing. The name the file is stored under is authenticated as well,
+    so files can't be swapped for one another either.
+
+    Names are replaced with a keyed hash of them, so the names of blobs,
+    which are hashes of their plaintext, can't be used to confirm whether
+    a collection holds a particular piece of known content. As indexes
+    reference blobs by the very same hashes, indexes need to be stored in
+    an encrypted collection as well for that to hold.
+*/
+const ENCRYPTED_FILE_MAGIC: &[u8; 7] = b"\0fcenc1";
+const NONCE_PREFIX_LENGTH: usize = 16;
+const CHUNK_LENGTH: usize = 64 * 1024;
+const TAG_LENGTH: usize = 16;
+const LAST_CHUNK_FLAG: u64 = 1 << 63;
+
+/// Tells apart the temporary files of files being renamed concurrently
+/// by the same process.
+static TMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+const ENCRYPTION_KEY_CONTEXT: &str = "filecastalogue 2021 encrypted collection content key";
+const NAME_KEY_CONTEXT: &str = "filecastalogue 2021 encrypted collection name key";
+
+pub struct EncryptionKeyErrorPayload {
+    pub key_file_path: PathBuf,
+    pub reason: &'static str,
+}
+
+impl fmt::Debug for EncryptionKeyErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for EncryptionKeyErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Key file: {}, reason: {}.",
+            ErrorPathBuf::from(self.key_file_path.to_owned()),
+            self.reason
+        )
+    }
+}
+
+impl Payload for EncryptionKeyErrorPayload {}
+
+pub struct DecryptionFailedErrorPayload {
+    /// The name the file is stored under, as the name it's known by
+    /// might be the hash of its content.
+    pub stored_file_name: OsString,
+}
+
+impl fmt::Debug for DecryptionFailedErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for DecryptionFailedErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Stored file name: {}. Either the key is wrong, or the file was tampered with or damaged.",
+            self.stored_file_name.to_string_lossy()
+        )
+    }
+}
+
+impl Payload for DecryptionFailedErrorPayload {}
+
+/// The key of an encrypted collection, as kept in a key file.
+///
+/// A key file holds the key as 64 hexadecimal digits.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    content_key: [u8; 32],
+    name_key: [u8; 32],
+}
+
+impl fmt::Debug for EncryptionKey {
+    /// Keeps the key itself out of debug output.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptionKey {{ .. }}")
+    }
+}
+
+impl EncryptionKey {
+
+    pub fn from_bytes(key: &[u8; 32]) -> Self {
+        let mut content_key = [0u8; 32];
+        let mut name_key = [0u8; 32];
+        blake3::derive_key(ENCRYPTION_KEY_CONTEXT, key, &mut content_key);
+        blake3::derive_key(NAME_KEY_CONTEXT, key, &mut name_key);
+        Self {
+            content_key,
+            name_key
+        }
+    }
+
+    /// Reads the key from the specified key file, failing with
+    /// `EncryptionKeyUnavailable` if there's no usable key in it, or if
+    /// it doesn't exist.
+    pub fn from_key_file<PathRef: AsRef<Path>>(key_file_path: PathRef)
+    -> FcResult<Self> {
+        let key_file_path = key_file_path.as_ref();
+        let key_unavailable = |reason| move |io_error: Option<io::Error>| Error::new(
+            ErrorKind::EncryptionKeyUnavailable,
+            "Reading encryption key from key file.",
+            Some(Box::new(EncryptionKeyErrorPayload {
+                key_file_path: key_file_path.to_owned(),
+                reason
+            })),
+            io_error.map(WrappedError::Io)
+        );
+        let mut hex_key = String::new();
+        File::open(key_file_path)
+            .and_then(|mut key_file| key_file.read_to_string(&mut hex_key))
+            .map_err(|e| key_unavailable("key file can't be read")(Some(e)))?;
+        let key = hex::decode(hex_key.trim())
+            .map_err(|_| key_unavailable("key isn't hexadecimal")(None))?;
+        let key: [u8; 32] = key.try_into()
+            .map_err(|_| key_unavailable("key isn't 32 bytes long")(None))?;
+        Ok(Self::from_bytes(&key))
+    }
+
+    /// Generates a new key and writes it to a new key file at the
+    /// specified path. Fails if there's already a file there.
+    pub fn generate_key_file<PathRef: AsRef<Path>>(key_file_path: PathRef)
+    -> FcResult<Self> {
+        let mut key = [0u8; 32];
+        OsRng.fill_bytes(&mut key);
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut key_file = options.open(key_file_path)?;
+        writeln!(key_file, "{}", hex::encode(key))?;
+        Ok(Self::from_bytes(&key))
+    }
+
+    fn get_stored_name(&self, name: &OsStr) -> OsString {
+        OsString::from(blake3::keyed_hash(
+            &self.name_key,
+            name.as_encoded_bytes()
+        ).to_hex().as_str())
+    }
+
+    fn get_cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.content_key.into())
+    }
+}
+
+fn get_nonce(nonce_prefix: &[u8; NONCE_PREFIX_LENGTH], counter: u64, is_last: bool)
+-> XNonce {
+    let mut nonce = XNonce::default();
+    nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(nonce_prefix);
+    let counter = if is_last { counter | LAST_CHUNK_FLAG } else { counter };
+    nonce[NONCE_PREFIX_LENGTH..].copy_from_slice(&counter.to_be_bytes());
+    nonce
+}
+
+/* Notes:
+    A Write has no way of learning that everything has been written to
+    it, other than being flushed, so collections have to flush once
+    they're done writing a file. Anything written after that is an error.
+    Dropping it doesn't end the file, as whatever was written up to then
+    might not be all of it, e.g. when bailing out of writing on an error.
+    The file is then left without its last chunk, which makes reading it
+    fail instead of passing off what's there as all of it.
+*/
+/// Encrypts what's written to it chunk by chunk, ending the file once
+/// it's flushed.
+struct EncryptingWriter {
+    inner: Box<dyn Write>,
+    cipher: XChaCha20Poly1305,
+    aad: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+    counter: u64,
+    buffer: Vec<u8>,
+    is_finished: bool,
+}
+
+impl EncryptingWriter {
+
+    fn new(mut inner: Box<dyn Write>, key: &EncryptionKey, aad: Vec<u8>)
+    -> io::Result<Self> {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LENGTH];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        inner.write_all(ENCRYPTED_FILE_MAGIC)?;
+        inner.write_all(&nonce_prefix)?;
+        Ok(Self {
+            inner,
+            cipher: key.get_cipher(),
+            aad,
+            nonce_prefix,
+            counter: 0,
+            buffer: Vec::with_capacity(CHUNK_LENGTH),
+            is_finished: false,
+        })
+    }
+
+    fn write_chunk(&mut self, is_last: bool) -> io::Result<()> {
+        let nonce = get_nonce(&self.nonce_prefix, self.counter, is_last);
+        let encrypted_chunk = self.cipher.encrypt(
+            &nonce,
+            AeadPayload { msg: &self.buffer, aad: &self.aad }
+        ).map_err(|_| io::Error::other("Encrypting chunk failed."))?;
+        self.inner.write_all(&encrypted_chunk)?;
+        self.buffer.clear();
+        self.counter += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if !self.is_finished {
+            self.is_finished = true;
+            self.write_chunk(true)?;
+        }
+        self.inner.flush()
+    }
+}
+
+impl Write for EncryptingWriter {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.is_finished {
+            return Err(io::Error::other(
+                "Writing to an encrypted file after it has been flushed."
+            ))
+        }
+        // A full buffer is only written once there's more to come, as the
+        // last chunk has to be marked as such.
+        if self.buffer.len() == CHUNK_LENGTH && !buf.is_empty() {
+            self.write_chunk(false)?;
+        }
+        let count = buf.len().min(CHUNK_LENGTH - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..count]);
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.finish()
+    }
+}
+
+/// Decrypts a file written by an EncryptingWriter.
+struct DecryptingReader {
+    inner: Box<dyn Read>,
+    cipher: XChaCha20Poly1305,
+    aad: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+    counter: u64,
+    /// The next encrypted chunk, read ahead to tell whether the current
+    /// one is the last.
+    next_chunk: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+    is_done: bool,
+}
+
+impl DecryptingReader {
+
+    fn new(mut inner: Box<dyn Read>, key: &EncryptionKey, aad: Vec<u8>)
+    -> io::Result<Self> {
+        let mut header = [0u8; ENCRYPTED_FILE_MAGIC.len() + NONCE_PREFIX_LENGTH];
+        let header_length = read_up_to(&mut inner, &mut header)?;
+        if header_length < header.len()
+        || header[..ENCRYPTED_FILE_MAGIC.len()] != ENCRYPTED_FILE_MAGIC[..] {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File isn't encrypted."
+            ))
+        }
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_LENGTH];
+        nonce_prefix.copy_from_slice(&header[ENCRYPTED_FILE_MAGIC.len()..]);
+        let mut reader = Self {
+            inner,
+            cipher: key.get_cipher(),
+            aad,
+            nonce_prefix,
+            counter: 0,
+            next_chunk: vec!(),
+            plaintext: vec!(),
+            position: 0,
+            is_done: false,
+        };
+        reader.next_chunk = reader.read_encrypted_chunk()?;
+        reader.decrypt_next_chunk()?;
+        Ok(reader)
+    }
+
+    fn read_encrypted_chunk(&mut self) -> io::Result<Vec<u8>> {
+        let mut chunk = vec![0u8; CHUNK_LENGTH + TAG_LENGTH];
+        let length = read_up_to(&mut self.inner, &mut chunk)?;
+        chunk.truncate(length);
+        Ok(chunk)
+    }
+
+    fn decrypt_next_chunk(&mut self) -> io::Result<()> {
+        let chunk = std::mem::take(&mut self.next_chunk);
+        self.next_chunk = self.read_encrypted_chunk()?;
+        let is_last = self.next_chunk.is_empty();
+        let nonce = get_nonce(&self.nonce_prefix, self.counter, is_last);
+        self.plaintext = self.cipher.decrypt(
+            &nonce,
+            AeadPayload { msg: &chunk, aad: &self.aad }
+        ).map_err(|_| io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Decrypting chunk failed."
+        ))?;
+        self.position = 0;
+        self.counter += 1;
+        self.is_done = is_last;
+        Ok(())
+    }
+}
+
+impl Read for DecryptingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.is_done {
+                return Ok(0)
+            }
+            self.decrypt_next_chunk()?;
+        }
+        let count = buf.len().min(self.plaintext.len() - self.position);
+        buf[..count].copy_from_slice(&self.plaintext[self.position..][..count]);
+        self.position += count;
+        Ok(count)
+    }
+}
+
+fn open_decrypting(
+    readable: Box<dyn Read>,
+    key: &EncryptionKey,
+    name: &OsStr
+) -> FcResult<Box<dyn Read>> {
+    // Decrypting the first chunk right away, so a wrong key fails here
+    // with a proper error, instead of somewhere down the line in a Read.
+    let reader = DecryptingReader::new(
+        readable, key, name.as_encoded_bytes().to_vec()
+    ).map_err(|io_error| error!(
+        kind => ErrorKind::DecryptionFailed,
+        context => "Opening a file of an encrypted collection.",
+        payload => DecryptionFailedErrorPayload {
+            stored_file_name: key.get_stored_name(name)
+        },
+        wrapped => WrappedError::Io(io_error)
+    ))?;
+    Ok(Box::new(reader))
+}
+
+struct DecryptingSource {
+    source: Box<dyn ReadableSource>,
+    key: EncryptionKey,
+    name: OsString,
+}
+
+impl ReadableSource for DecryptingSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        open_decrypting(self.source.open()?, &self.key, &self.name)
+    }
+}
+
+/// Encrypts the files of the collection it wraps, as well as their names.
+///
+/// Everything written through it is encrypted with authenticated
+/// encryption, using a key typically read from a key file with
+/// `EncryptionKey::from_key_file`. Reading fails with `DecryptionFailed`
+/// when the key is wrong or a file has been tampered with.
+pub struct EncryptedCollectionHandler<Inner: OpaqueCollectionHandler> {
+    inner: Inner,
+    key: EncryptionKey,
+}
+
+impl<Inner: OpaqueCollectionHandler> EncryptedCollectionHandler<Inner> {
+
+    pub fn new(inner: Inner, key: EncryptionKey) -> Self {
+        Self {
+            inner,
+            key
+        }
+    }
+
+    /// Wraps the collection with the key from the specified key file,
+    /// failing with `EncryptionKeyUnavailable` if there's no usable one.
+    pub fn from_key_file<PathRef: AsRef<Path>>(inner: Inner, key_file_path: PathRef)
+    -> FcResult<Self> {
+        Ok(Self::new(inner, EncryptionKey::from_key_file(key_file_path)?))
+    }
+
+    pub fn into_inner(self) -> Inner {
+        self.inner
+    }
+
+    /// Writes the content of the file into the file of the wrapped
+    /// collection with the specified stored name, encrypted as it would
+    /// be under the new name.
+    fn encrypt_copy(&self, name: &OsStr, new_name: &OsStr, stored_name: &OsStr)
+    -> FcResult<()> {
+        let mut readable = self.get_file_readable(name)?;
+        let mut writeable = EncryptingWriter::new(
+            self.inner.get_file_writeable(stored_name)?,
+            &self.key,
+            new_name.as_encoded_bytes().to_vec()
+        )?;
+        io::copy(&mut readable, &mut writeable)?;
+        writeable.flush()?;
+        Ok(())
+    }
+}
+
+impl<Inner: OpaqueCollectionHandler> OpaqueCollectionHandler
+for EncryptedCollectionHandler<Inner> {
+
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        let stored_name = self.key.get_stored_name(name.as_ref());
+        self.inner.has_file(stored_name)
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<()> {
+        self.inner.create_file(self.key.get_stored_name(name.as_ref()))
+    }
+
+    fn get_file_readable(&self, name: &OsStr) -> FcResult<Box<dyn Read>> {
+        let readable = self.inner.get_file_readable(
+            &self.key.get_stored_name(name)
+        )?;
+        open_decrypting(readable, &self.key, name)
+    }
+
+    fn get_file_writeable(&self, name: &OsStr) -> FcResult<Box<dyn Write>> {
+        let writeable = self.inner.get_file_writeable(
+            &self.key.get_stored_name(name)
+        )?;
+        Ok(Box::new(EncryptingWriter::new(
+            writeable, &self.key, name.as_encoded_bytes().to_vec()
+        )?))
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        Ok(Box::new(DecryptingSource {
+            source: self.inner.get_file_readable_source(
+                &self.key.get_stored_name(name)
+            )?,
+            key: self.key.clone(),
+            name: name.to_owned(),
+        }))
+    }
+
+    /* Notes:
+        As the name is authenticated along with the content, a renamed
+        file has to be encrypted anew under its new name. That's done in
+        a temporary file, which is then renamed to the new name, so there's
+        never a file under the new name that isn't complete, which, for
+        blobs named after their hash, would never be written again.
+    */
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        let tmp_stored_name = OsString::from(format!(
+            ".tmp-{}-{}",
+            process::id(),
+            TMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        self.inner.create_file(&tmp_stored_name)?;
+        let renamed = self.encrypt_copy(name, new_name, &tmp_stored_name)
+            .and_then(|()| self.inner.rename_file(
+                &tmp_stored_name, &self.key.get_stored_name(new_name)));
+        if let Err(rename_error) = renamed {
+            // It's the error that got us here that's worth reporting, and
+            // the temporary file might already be gone anyway.
+            let _ = self.inner.remove_file(&tmp_stored_name);
+            return Err(rename_error)
+        }
+        self.remove_file(name)
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        let stored_name = self.key.get_stored_name(name);
+        self.inner.remove_file(&stored_name)
+    }
+
+    fn collection_exists(&mut self) -> bool {
+        self.inner.collection_exists()
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        self.inner.create_collection()
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        self.inner.create_collection_ignore_exists()
+    }
+
+    /// Only tells the name the file is stored under, as the name it's
+    /// known by might be the hash of its content.
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> String {
+        format!(
+            "Encrypted file stored as: {}",
+            self.inner.get_debug_info_for_file(
+                self.key.get_stored_name(name.as_ref())
+            )
+        )
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::error::FcResult;
+use crate::opaque_collection_handler::{OpaqueCollectionHandler, ReadableSource};
+
+/// Something going wrong with an operation of a FaultInjectingHandler.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum Fault {
+    /// The operation fails without doing anything.
+    Fail,
+    /// Writing to the Write the operation returns fails once `length`
+    /// bytes have been written, like it would with a full disk. Only
+    /// affects `get_file_writeable`.
+    TruncateWrite { length: usize },
+    /// Reading from the Reads the operation returns never returns more
+    /// than `max_length` bytes at a time. Only affects
+    /// `get_file_readable` and `get_file_readable_source`.
+    ShortReads { max_length: usize },
+    /// Reading from the Reads the operation returns flips all the bits of
+    /// the byte at `offset`. Only affects `get_file_readable` and
+    /// `get_file_readable_source`.
+    CorruptRead { offset: usize },
+}
+
+struct FaultPlan {
+    operation_count: usize,
+    faults: HashMap<usize, Fault>,
+}
+
+/// Scripts which operations of the FaultInjectingHandlers sharing it go
+/// wrong, and how. Clones share the same script.
+///
+/// Operations are numbered from 0 in the order they're called, across
+/// all handlers sharing the injector, which makes it possible to fail
+/// every single step of a repo operation in turn.
+#[derive(Clone)]
+pub struct FaultInjector {
+    plan: Arc<Mutex<FaultPlan>>,
+}
+
+impl FaultInjector {
+    pub fn new() -> Self {
+        Self {
+            plan: Arc::new(Mutex::new(FaultPlan {
+                operation_count: 0,
+                faults: HashMap::new(),
+            }))
+        }
+    }
+
+    /// Makes the operation with the specified number go wrong.
+    pub fn inject(&self, operation_number: usize, fault: Fault) {
+        self.lock().faults.insert(operation_number, fault);
+    }
+
+    /// Makes the operation `operations_from_now` operations after the
+    /// next one go wrong, so 0 is the next one.
+    pub fn inject_from_now(&self, operations_from_now: usize, fault: Fault) {
+        let mut plan = self.lock();
+        let operation_number = plan.operation_count + operations_from_now;
+        plan.faults.insert(operation_number, fault);
+    }
+
+    /// Removes all faults not injected yet.
+    pub fn clear(&self) {
+        self.lock().faults.clear();
+    }
+
+    /// Returns how many operations there have been so far.
+    pub fn get_operation_count(&self) -> usize {
+        self.lock().operation_count
+    }
+
+    /// Returns whether there are faults left which haven't been injected.
+    pub fn has_pending_faults(&self) -> bool {
+        !self.lock().faults.is_empty()
+    }
+
+    /// Counts an operation, returning the fault it's supposed to have.
+    fn next_operation(&self) -> Option<Fault> {
+        let mut plan = self.lock();
+        let operation_number = plan.operation_count;
+        plan.operation_count += 1;
+        plan.faults.remove(&operation_number)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, FaultPlan> {
+        self.plan.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Default for FaultInjector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_injected_io_error() -> io::Error {
+    io::Error::other("Injected fault.")
+}
+
+/// Fails an operation if that's its fault.
+fn fail_if_scripted(fault: Option<Fault>) -> FcResult<Option<Fault>> {
+    match fault {
+        Some(Fault::Fail) => Err(get_injected_io_error().into()),
+        fault => Ok(fault),
+    }
+}
+
+struct FaultInjectingReadable {
+    inner: Box<dyn Read>,
+    fault: Option<Fault>,
+    position: usize,
+}
+
+impl Read for FaultInjectingReadable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = match self.fault {
+            Some(Fault::ShortReads { max_length }) => buf.len().min(max_length.max(1)),
+            _ => buf.len(),
+        };
+        let read_count = self.inner.read(&mut buf[..length])?;
+        if let Some(Fault::CorruptRead { offset }) = self.fault {
+            if offset >= self.position && offset < self.position + read_count {
+                buf[offset - self.position] ^= 0xff;
+            }
+        }
+        self.position += read_count;
+        Ok(read_count)
+    }
+}
+
+fn wrap_readable(inner: Box<dyn Read>, fault: Option<Fault>) -> Box<dyn Read> {
+    match fault {
+        Some(Fault::ShortReads { .. }) | Some(Fault::CorruptRead { .. })
+            => Box::new(FaultInjectingReadable { inner, fault, position: 0 }),
+        _ => inner,
+    }
+}
+
+struct TruncatingWriteable {
+    inner: Box<dyn Write>,
+    remaining_length: usize,
+}
+
+impl Write for TruncatingWriteable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.remaining_length == 0 {
+            return Err(get_injected_io_error())
+        }
+        let length = buf.len().min(self.remaining_length);
+        let written_count = self.inner.write(&buf[..length])?;
+        self.remaining_length -= written_count;
+        Ok(written_count)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A file opened through a FaultInjectingHandler, whose Reads all get the
+/// fault of the operation it was obtained with.
+struct FaultInjectingSource {
+    inner: Box<dyn ReadableSource>,
+    fault: Option<Fault>,
+}
+
+impl ReadableSource for FaultInjectingSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(wrap_readable(self.inner.open()?, self.fault))
+    }
+}
+
+/* Notes:
+    Every method of the handler counts as one operation, except for
+    `get_debug_info_for_file`, which is only there to assemble errors.
+*/
+/// Wraps another collection handler, making the operations its
+/// FaultInjector scripts go wrong, to see how whatever is using the
+/// collection copes with that.
+pub struct FaultInjectingHandler<Handler: OpaqueCollectionHandler> {
+    inner: Handler,
+    injector: FaultInjector,
+}
+
+impl<Handler: OpaqueCollectionHandler> FaultInjectingHandler<Handler> {
+    /// A handler with a FaultInjector of its own, see `get_injector`.
+    pub fn new(inner: Handler) -> Self {
+        Self::new_with_injector(inner, FaultInjector::new())
+    }
+
+    /// A handler sharing the specified injector, e.g. with the handlers
+    /// of the other collections of a repo.
+    pub fn new_with_injector(inner: Handler, injector: FaultInjector) -> Self {
+        Self {
+            inner,
+            injector
+        }
+    }
+
+    pub fn get_injector(&self) -> FaultInjector {
+        self.injector.clone()
+    }
+
+    pub fn into_inner(self) -> Handler {
+        self.inner
+    }
+}
+
+impl<Handler: OpaqueCollectionHandler> OpaqueCollectionHandler for FaultInjectingHandler<Handler> {
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.has_file(name)
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<()> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.create_file(name)
+    }
+
+    fn get_file_readable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Read>> {
+        let fault = fail_if_scripted(self.injector.next_operation())?;
+        Ok(wrap_readable(self.inner.get_file_readable(name)?, fault))
+    }
+
+    fn get_file_writeable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Write>> {
+        let fault = fail_if_scripted(self.injector.next_operation())?;
+        let writeable = self.inner.get_file_writeable(name)?;
+        match fault {
+            Some(Fault::TruncateWrite { length }) => Ok(Box::new(TruncatingWriteable {
+                inner: writeable,
+                remaining_length: length
+            })),
+            _ => Ok(writeable),
+        }
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        let fault = fail_if_scripted(self.injector.next_operation())?;
+        Ok(Box::new(FaultInjectingSource {
+            inner: self.inner.get_file_readable_source(name)?,
+            fault
+        }))
+    }
+
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.rename_file(name, new_name)
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.remove_file(name)
+    }
+
+    fn collection_exists(&mut self) -> bool {
+        // There's no way to fail this, so the fault is ignored.
+        self.injector.next_operation();
+        self.inner.collection_exists()
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.create_collection()
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        fail_if_scripted(self.injector.next_operation())?;
+        self.inner.create_collection_ignore_exists()
+    }
+
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
+        self.inner.get_debug_info_for_file(name)
+    }
+}
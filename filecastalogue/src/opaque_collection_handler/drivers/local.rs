@@ -5,9 +5,15 @@ use std::fmt::Display;
 use std::fs::File;
 use std::fs::OpenOptions;
 use std::fs::create_dir;
+use std::fs::create_dir_all;
+use std::fs::read_dir;
+use std::fs::remove_dir;
+use std::fs::remove_file;
+use std::fs::rename;
 use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
+use std::process;
 use crate::error::ErrorKind;
 use crate::error::ErrorPathBuf;
 use crate::error::FcResult;
@@ -15,13 +21,48 @@ use crate::error::Payload;
 use crate::error::Error;
 use crate::opaque_collection_handler::OpaqueCollectionHandler;
 use crate::opaque_collection_handler::PathDoesNotExistInCollectionPayload;
+use crate::opaque_collection_handler::ReadableSource;
+use self::layout::{DEFAULT_SHARD_PREFIX_LENGTH, LAYOUT_MARKER_FILE_NAME, LayoutMarker, LocalDirLayout};
 
-
+pub mod layout;
 
 pub struct LocalDir {
+    path: PathBuf,
+    layout: LocalDirLayout,
+    /// See `LayoutMarker`.
+    previous_layout: Option<LocalDirLayout>
+}
+
+/// A file in a LocalDir collection, opened by its path.
+pub struct LocalFileSource {
     path: PathBuf
 }
 
+impl ReadableSource for LocalFileSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(Box::new(File::open(&self.path)?))
+    }
+}
+
+/// A file in a LocalDir collection, opened for writing.
+///
+/// Flushing it gets its content onto the disk, so whatever is flushed
+/// survives a crash once e.g. the state refers to it.
+pub struct LocalFileWriteable {
+    file: File
+}
+
+impl Write for LocalFileWriteable {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        self.file.sync_all()
+    }
+}
+
 #[derive(Debug)]
 pub struct DoubleDotFileName {
     pub original_path: PathBuf,
@@ -38,10 +79,179 @@ impl Display for DoubleDotFileName {
 impl Payload for DoubleDotFileName {}
 
 impl LocalDir {
+    /// A LocalDir with a flat layout, whatever layout is recorded in the
+    /// directory. Use `open` for a directory that might already exist.
     pub fn new<PathRef: AsRef<Path>>(path: PathRef) -> Self {
+        Self::new_with_layout(path, LocalDirLayout::default())
+    }
+
+    /// A LocalDir with the specified layout, which is recorded in the
+    /// directory once the collection gets created.
+    pub fn new_with_layout<PathRef: AsRef<Path>>(path: PathRef, layout: LocalDirLayout)
+    -> Self {
         Self {
-            path: path.as_ref().to_owned()
+            path: path.as_ref().to_owned(),
+            layout,
+            previous_layout: None
+        }
+    }
+
+    /// A LocalDir with the layout recorded in the directory, or a flat one
+    /// if there's none recorded, e.g. because the directory doesn't exist yet.
+    pub fn open<PathRef: AsRef<Path>>(path: PathRef) -> FcResult<Self> {
+        let marker_path = path.as_ref().join(LAYOUT_MARKER_FILE_NAME);
+        let marker: LayoutMarker = match File::open(&marker_path) {
+            Ok(marker_file) => serde_json::from_reader(marker_file)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => LayoutMarker {
+                layout: LocalDirLayout::default(),
+                previous_layout: None
+            },
+            Err(e) => return Err(e.into()),
+        };
+        Ok(Self {
+            previous_layout: marker.previous_layout,
+            ..Self::new_with_layout(path, marker.layout)
+        })
+    }
+
+    pub fn get_layout(&self) -> LocalDirLayout {
+        self.layout
+    }
+
+    /* Notes:
+        This works while the directory is in use: the new layout is
+        recorded before anything is moved, so files put from then on
+        go where they belong right away, and every file is moved with
+        a single rename. Lookups fall back on where a file would be in
+        the layout migrated from, as recorded along with the new one, or
+        in a flat or default sharded layout, so files not moved yet (or
+        put by anything still using the old layout) are found all along.
+        Running it again picks up whatever it might have missed.
+    */
+    /// Changes the layout of the directory, moving all files accordingly.
+    pub fn migrate_layout(&mut self, layout: LocalDirLayout) -> FcResult<()> {
+        if layout != self.layout {
+            self.previous_layout = Some(self.layout);
+        }
+        self.layout = layout;
+        self.write_layout_marker()?;
+        let mut shard_dir_paths = vec!();
+        for entry in read_dir(&self.path)? {
+            let entry = entry?;
+            if entry.file_name().as_encoded_bytes().starts_with(b".") {
+                continue
+            }
+            if entry.file_type()?.is_dir() {
+                for shard_entry in read_dir(entry.path())? {
+                    let shard_entry = shard_entry?;
+                    let file_name = LocalDirLayout::get_sharded_file_name(
+                        &entry.file_name(), &shard_entry.file_name()
+                    );
+                    self.move_into_place(&shard_entry.path(), &file_name)?;
+                }
+                shard_dir_paths.push(entry.path());
+            } else {
+                self.move_into_place(&entry.path(), &entry.file_name())?;
+            }
+        }
+        for shard_dir_path in shard_dir_paths {
+            // Shards still in use by the new layout won't be empty.
+            if read_dir(&shard_dir_path)?.next().is_none() {
+                remove_dir(&shard_dir_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the names of all files in the collection, in order, no
+    /// matter where the layout puts them. Hidden files, like those still
+    /// being put, are left out.
+    pub fn get_file_names(&self) -> FcResult<Vec<OsString>> {
+        let entries = match read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec!()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut file_names = vec!();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_name().as_encoded_bytes().starts_with(b".") {
+                continue
+            }
+            if entry.file_type()?.is_dir() {
+                for shard_entry in read_dir(entry.path())? {
+                    let shard_entry = shard_entry?;
+                    if !shard_entry.file_name().as_encoded_bytes().starts_with(b".") {
+                        file_names.push(LocalDirLayout::get_sharded_file_name(
+                            &entry.file_name(), &shard_entry.file_name()
+                        ));
+                    }
+                }
+            } else {
+                file_names.push(entry.file_name());
+            }
         }
+        file_names.sort();
+        Ok(file_names)
+    }
+
+    fn move_into_place(&self, current_path: &Path, file_name: &OsStr) -> FcResult<()> {
+        let path = self.get_file_path(file_name)?;
+        if path != current_path {
+            self.create_parent_dir(&path)?;
+            rename(current_path, path)?;
+        }
+        Ok(())
+    }
+
+    /* Notes:
+        The marker is written to a temporary file which then replaces it,
+        so anything opening the directory meanwhile finds either the old
+        marker or the new one, never one that's only partly written.
+    */
+    fn write_layout_marker(&self) -> FcResult<()> {
+        let marker_path = self.path.join(LAYOUT_MARKER_FILE_NAME);
+        if self.layout == LocalDirLayout::Flat && self.previous_layout.is_none() {
+            // Flat directories don't have a marker, as before there
+            // were layouts.
+            return match remove_file(marker_path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+                _ => Ok(())
+            }
+        }
+        let tmp_marker_path = self.path.join(format!(
+            "{}.tmp-{}", LAYOUT_MARKER_FILE_NAME, process::id()));
+        let mut tmp_marker_file = File::create(&tmp_marker_path)?;
+        serde_json::to_writer(&mut tmp_marker_file, &LayoutMarker {
+            layout: self.layout,
+            previous_layout: self.previous_layout
+        })?;
+        tmp_marker_file.sync_all()?;
+        rename(tmp_marker_path, marker_path)?;
+        Ok(())
+    }
+
+    fn create_parent_dir(&self, file_path: &Path) -> FcResult<()> {
+        match file_path.parent() {
+            Some(parent_path) if parent_path != self.path => {
+                create_dir_all(parent_path)?;
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Gets the entry of a file that was just created or renamed onto
+    /// the disk, including that of the shard directory it's in, which
+    /// might've been created along with it.
+    fn sync_parent_dir(&self, file_path: &Path) -> FcResult<()> {
+        if let Some(parent_path) = file_path.parent() {
+            File::open(parent_path)?.sync_all()?;
+            if parent_path != self.path {
+                File::open(&self.path)?.sync_all()?;
+            }
+        }
+        Ok(())
     }
 
     // Making sure our file name isn't absolute, to prevent
@@ -62,17 +272,46 @@ impl LocalDir {
         }
     }
 
+    /// Returns where the file of that name belongs according to the
+    /// layout, whether it's there or not.
     fn get_file_path<NameRef: AsRef<OsStr>>(&self, name: NameRef)
     -> FcResult<PathBuf> {
         let file_path = PathBuf::from(&self.path);
         match self.get_deabsolutized_file_name(name) {
-            Ok(file_name) => Ok(file_path.join(file_name)),
+            Ok(file_name) => Ok(file_path.join(
+                self.layout.get_relative_path(&file_name)
+            )),
             Err(e) => Err(e)
         }
     }
 
+    /// Returns where the file of that name is, which might not be where
+    /// it belongs while the directory is being migrated to another layout.
+    /// If it's nowhere to be found, that's where it belongs.
+    fn find_file_path<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<PathBuf> {
+        let path = self.get_file_path(&name)?;
+        if path.exists() {
+            return Ok(path)
+        }
+        let file_name = self.get_deabsolutized_file_name(&name)?;
+        let fallback_layouts = self.previous_layout.into_iter().chain([
+            LocalDirLayout::Flat,
+            LocalDirLayout::Sharded { prefix_length: DEFAULT_SHARD_PREFIX_LENGTH }
+        ]);
+        for fallback_layout in fallback_layouts {
+            let fallback_path = self.path.join(
+                fallback_layout.get_relative_path(&file_name)
+            );
+            if fallback_path.exists() {
+                return Ok(fallback_path)
+            }
+        }
+        Ok(path)
+    }
+
     fn get_file<NameRef: AsRef<OsStr>>(&self, name: NameRef, options: &mut OpenOptions) -> FcResult<File> {
-        let path = self.get_file_path(name)?;
+        let path = self.find_file_path(name)?;
         match path.exists() {
             true => Ok(options.open(path.to_owned())?
             ),
@@ -89,6 +328,7 @@ impl LocalDir {
 
     fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> FcResult<()> {
         let path = self.get_file_path(name)?;
+        self.create_parent_dir(&path)?;
         File::create(path)?;
         Ok(())
     }
@@ -101,6 +341,7 @@ impl LocalDir {
     */
     fn create(&self) -> FcResult<&Self> {
         create_dir(&self.path)?;
+        self.write_layout_marker()?;
         Ok(self)
     }
 
@@ -111,7 +352,10 @@ impl LocalDir {
     fn create_ignore_exists(self: &mut Self) -> FcResult<&mut Self> {
         let result = create_dir(&self.path);
         match result {
-            Ok(_) => Ok(self),
+            Ok(_) => {
+                self.write_layout_marker()?;
+                Ok(self)
+            },
             Err(e) => match e.kind() {
                 std::io::ErrorKind::AlreadyExists => Ok(self),
                 _ => Err(e.into())
@@ -130,7 +374,7 @@ impl OpaqueCollectionHandler for LocalDir
 {
     fn has_file<NameRef: AsRef<OsStr>>(self: &mut Self, name: NameRef)
     -> FcResult<bool> {
-        Ok(self.get_file_path(name)?.exists())
+        Ok(self.find_file_path(name)?.exists())
     }
 
     fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
@@ -148,7 +392,37 @@ impl OpaqueCollectionHandler for LocalDir
 
     fn get_file_writeable(&self, name: &OsStr)
     -> FcResult<Box<(dyn Write)>> {
-        Ok(Box::new(self.get_file(name, OpenOptions::new().write(true))?))
+        Ok(Box::new(LocalFileWriteable {
+            file: self.get_file(name, OpenOptions::new().write(true).truncate(true))?
+        }))
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        // Opening it once, so a file that doesn't exist fails right here
+        // with a proper error, not once the source is used.
+        self.get_file(name, OpenOptions::new().read(true))?;
+        Ok(Box::new(LocalFileSource {
+            path: self.find_file_path(name)?
+        }))
+    }
+
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        // Fails with `PathDoesNotExistInCollection` if it doesn't exist.
+        // Its content has to be on the disk before it's renamed into
+        // place, or a crash could leave an empty or partial file there.
+        self.get_file(name, OpenOptions::new().read(true))?.sync_all()?;
+        let new_path = self.get_file_path(new_name)?;
+        self.create_parent_dir(&new_path)?;
+        rename(self.find_file_path(name)?, &new_path)?;
+        self.sync_parent_dir(&new_path)?;
+        Ok(())
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        self.get_file(name, OpenOptions::new().read(true))?;
+        remove_file(self.find_file_path(name)?)?;
+        Ok(())
     }
 
     fn collection_exists(self: &mut Self) -> bool {
@@ -169,6 +443,6 @@ impl OpaqueCollectionHandler for LocalDir
     /// the file corresponding to the specified name. If there's an error
     /// retrieving the path, it will contain the error instead.
     fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
-        format!("path: {:#?}", self.get_file_path(name))
+        format!("path: {:#?}", self.find_file_path(name))
     }
 }
\ No newline at end of file
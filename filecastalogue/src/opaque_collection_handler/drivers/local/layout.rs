@@ -0,0 +1,110 @@
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use serde::{Serialize, Deserialize};
+use crate::files::hashable::FILE_NAME_HASH_SEPARATOR;
+
+/// Name of the file in a LocalDir which records its layout, unless it's
+/// flat and always has been, see `LayoutMarker`. It starts with a "." so
+/// it can't be mistaken for a blob or an index, which are named after
+/// their hex hash.
+pub const LAYOUT_MARKER_FILE_NAME: &str = ".layout.json";
+
+/// The prefix length fanning out to 256 directories for hex names.
+pub const DEFAULT_SHARD_PREFIX_LENGTH: usize = 2;
+
+/// How a LocalDir arranges its files in its directory.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+#[serde(
+    tag = "kind",
+    rename_all(
+        serialize = "snake_case",
+        deserialize = "snake_case"
+    )
+)]
+pub enum LocalDirLayout {
+    /// All files are directly in the directory.
+    Flat,
+    /// Files are in subdirectories named after the first `prefix_length`
+    /// characters of their name, e.g. `ab/cdef…` for `abcdef…` with a
+    /// prefix length of 2. For names with an algorithm prefix, like
+    /// those of files named after hashes usually have, it's the
+    /// characters after it that count, with the name kept whole, e.g.
+    /// `ab/sha256-abcdef…`. Files with names too short to be split that
+    /// way, as well as hidden ones, are kept directly in the directory.
+    Sharded {
+        prefix_length: usize
+    },
+}
+
+/// What the layout marker of a LocalDir records.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub struct LayoutMarker {
+    #[serde(flatten)]
+    pub layout: LocalDirLayout,
+    /// The layout the directory was last migrated from, as files might
+    /// still be where it put them, be it because they haven't been moved
+    /// yet or because something still using it put them there.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous_layout: Option<LocalDirLayout>,
+}
+
+impl LocalDirLayout {
+
+    pub fn new_sharded() -> Self {
+        Self::Sharded { prefix_length: DEFAULT_SHARD_PREFIX_LENGTH }
+    }
+
+    /// Returns the path, relative to the directory, of the file with
+    /// the specified name.
+    pub fn get_relative_path(&self, file_name: &OsStr) -> PathBuf {
+        match *self {
+            Self::Flat => PathBuf::from(file_name),
+            Self::Sharded { prefix_length } => {
+                let bytes = file_name.as_encoded_bytes();
+                if bytes.starts_with(b".") {
+                    return PathBuf::from(file_name)
+                }
+                let (is_prefixed, digest) = match bytes.iter().rposition(
+                    |byte| *byte == FILE_NAME_HASH_SEPARATOR as u8) {
+                    Some(separator_position) => (true, &bytes[separator_position + 1..]),
+                    None => (false, bytes),
+                };
+                // Splitting only ASCII prefixes, so we never split within
+                // a character, and never name a shard after a dot file.
+                if digest.len() <= prefix_length
+                || !digest[..prefix_length].iter().all(u8::is_ascii_alphanumeric) {
+                    return PathBuf::from(file_name)
+                }
+                // SAFETY: Both parts come from an `OsStr` and are split
+                // right after ASCII characters.
+                let (shard, rest) = unsafe { (
+                    OsStr::from_encoded_bytes_unchecked(&digest[..prefix_length]),
+                    OsStr::from_encoded_bytes_unchecked(&digest[prefix_length..])
+                ) };
+                match is_prefixed {
+                    true => Path::new(shard).join(file_name),
+                    false => Path::new(shard).join(rest),
+                }
+            }
+        }
+    }
+
+    /// Returns the name of a file found in a shard directory, undoing
+    /// what `get_relative_path` does.
+    pub fn get_sharded_file_name(shard_name: &OsStr, name_in_shard: &OsStr) -> OsString {
+        if name_in_shard.as_encoded_bytes().contains(&(FILE_NAME_HASH_SEPARATOR as u8)) {
+            return name_in_shard.to_owned()
+        }
+        let mut file_name = shard_name.to_owned();
+        file_name.push(name_in_shard);
+        file_name
+    }
+}
+
+impl Default for LocalDirLayout {
+    /// Flat, as that's how all LocalDirs were laid out before there
+    /// were layouts.
+    fn default() -> Self {
+        Self::Flat
+    }
+}
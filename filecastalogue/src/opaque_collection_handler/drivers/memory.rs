@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use crate::error::{Error, ErrorKind, FcResult};
+use crate::opaque_collection_handler::{OpaqueCollectionHandler, PathDoesNotExistInCollectionPayload, ReadableSource};
+use super::local::DoubleDotFileName;
+
+/// What errors name as the path of a MemoryCollection, as it has none.
+pub const MEMORY_COLLECTION_PATH: &str = "<memory>";
+
+struct MemoryCollectionContent {
+    exists: bool,
+    files: BTreeMap<OsString, Vec<u8>>,
+}
+
+/* Notes:
+    This mirrors how a LocalDir behaves, down to the errors, so anything
+    built on collection handlers can run against it as it would against
+    a LocalDir, only without touching the disk.
+*/
+/// A collection kept in memory.
+///
+/// Clones are handles to the same collection, so a collection handed to
+/// a repo can still be inspected through a clone kept around. Use
+/// `duplicate` for a copy of it that's independent of it.
+#[derive(Clone)]
+pub struct MemoryCollection {
+    content: Arc<Mutex<MemoryCollectionContent>>,
+}
+
+impl MemoryCollection {
+    /// An empty collection, which, unlike a new LocalDir, already exists.
+    pub fn new() -> Self {
+        Self::new_with_files(BTreeMap::new())
+    }
+
+    pub fn new_with_files(files: BTreeMap<OsString, Vec<u8>>) -> Self {
+        Self {
+            content: Arc::new(Mutex::new(MemoryCollectionContent {
+                exists: true,
+                files
+            }))
+        }
+    }
+
+    /// A collection that doesn't exist until it's created.
+    pub fn new_uncreated() -> Self {
+        let collection = Self::new();
+        collection.lock().exists = false;
+        collection
+    }
+
+    /// A copy of the collection as it is right now, which won't change
+    /// along with it.
+    pub fn duplicate(&self) -> Self {
+        let content = self.lock();
+        let duplicate = Self::new_with_files(content.files.clone());
+        duplicate.lock().exists = content.exists;
+        duplicate
+    }
+
+    /// Returns the names of all files in the collection, in order.
+    pub fn get_file_names(&self) -> Vec<OsString> {
+        self.lock().files.keys().cloned().collect()
+    }
+
+    /// Returns the content of the file of that name, if there is one.
+    pub fn get_file_content<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> Option<Vec<u8>> {
+        self.lock().files.get(name.as_ref()).cloned()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoryCollectionContent> {
+        // Nothing holding the lock can leave the content half changed,
+        // so it's still good after a panic.
+        self.content.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Reduces the name to its file name, just like LocalDir does.
+    fn get_file_name<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> FcResult<OsString> {
+        let file_name_path = Path::new(name.as_ref());
+        match file_name_path.file_name() {
+            Some(file_name) => Ok(file_name.to_owned()),
+            None => Err(error!(
+                ErrorKind::DoubleDotFileName,
+                "Getting file_name portion of a name for a MemoryCollection.",
+                payload => DoubleDotFileName {
+                    original_path: file_name_path.to_owned()
+                }
+            ))
+        }
+    }
+
+    /// Returns the file name the name boils down to, failing if there's
+    /// no file by that name.
+    fn get_existing_file_name<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<OsString> {
+        let file_name = self.get_file_name(name)?;
+        match self.lock().files.contains_key(&file_name) {
+            true => Ok(file_name),
+            false => Err(get_path_does_not_exist_error(&file_name)),
+        }
+    }
+}
+
+impl Default for MemoryCollection {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn get_path_does_not_exist_error(file_name: &OsStr) -> Error {
+    error!(
+        ErrorKind::PathDoesNotExistInCollection,
+        "Getting a file from a MemoryCollection collection handler.",
+        payload => PathDoesNotExistInCollectionPayload {
+            collection_path: PathBuf::from(MEMORY_COLLECTION_PATH),
+            file_name: PathBuf::from(file_name)
+        }
+    )
+}
+
+/// Writes to a file in a MemoryCollection as it goes.
+struct MemoryFileWriteable {
+    collection: MemoryCollection,
+    file_name: OsString,
+}
+
+impl Write for MemoryFileWriteable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Like writing to a file that's been removed in the meantime,
+        // this doesn't bring it back.
+        if let Some(content) = self.collection.lock().files.get_mut(&self.file_name) {
+            content.extend_from_slice(buf);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A file in a MemoryCollection, read as it is once it's opened.
+struct MemoryFileSource {
+    collection: MemoryCollection,
+    file_name: OsString,
+}
+
+impl ReadableSource for MemoryFileSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        match self.collection.get_file_content(&self.file_name) {
+            Some(content) => Ok(Box::new(Cursor::new(content))),
+            None => Err(get_path_does_not_exist_error(&self.file_name)),
+        }
+    }
+}
+
+impl OpaqueCollectionHandler for MemoryCollection {
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        let file_name = self.get_file_name(name)?;
+        Ok(self.lock().files.contains_key(&file_name))
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<()> {
+        let file_name = self.get_file_name(name)?;
+        let mut content = self.lock();
+        if !content.exists {
+            return Err(io::Error::from(io::ErrorKind::NotFound).into())
+        }
+        // Truncating it if it exists, like `File::create`.
+        content.files.insert(file_name, vec!());
+        Ok(())
+    }
+
+    fn get_file_readable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Read>> {
+        let file_name = self.get_existing_file_name(name)?;
+        Ok(Box::new(Cursor::new(self.get_file_content(file_name).unwrap_or_default())))
+    }
+
+    fn get_file_writeable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Write>> {
+        let file_name = self.get_existing_file_name(name)?;
+        self.lock().files.insert(file_name.to_owned(), vec!());
+        Ok(Box::new(MemoryFileWriteable {
+            collection: self.clone(),
+            file_name
+        }))
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        Ok(Box::new(MemoryFileSource {
+            collection: self.clone(),
+            file_name: self.get_existing_file_name(name)?
+        }))
+    }
+
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_existing_file_name(name)?;
+        let new_file_name = self.get_file_name(new_name)?;
+        let mut content = self.lock();
+        if let Some(file_content) = content.files.remove(&file_name) {
+            content.files.insert(new_file_name, file_content);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_existing_file_name(name)?;
+        self.lock().files.remove(&file_name);
+        Ok(())
+    }
+
+    fn collection_exists(&mut self) -> bool {
+        self.lock().exists
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        let mut content = self.lock();
+        if content.exists {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into())
+        }
+        content.exists = true;
+        Ok(())
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        self.lock().exists = true;
+        Ok(())
+    }
+
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
+        format!("memory collection file name: {:#?}", self.get_file_name(name))
+    }
+}
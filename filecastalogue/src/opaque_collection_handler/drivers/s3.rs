@@ -0,0 +1,519 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::SystemTime;
+use crate::error::{Error, ErrorKind, FcResult, Payload};
+use crate::opaque_collection_handler::{OpaqueCollectionHandler, PathDoesNotExistInCollectionPayload, ReadableSource};
+use self::signing::{S3Credentials, SignableRequest, format_amz_date, get_authorization, sha256_hex, uri_encode};
+use super::http::{DEFAULT_HTTP_TIMEOUT, HttpRequestFailedPayload, redact_url};
+use super::local::DoubleDotFileName;
+
+pub mod signing;
+
+pub struct WriteConflictPayload {
+    pub collection_url: String,
+    pub file_name: OsString,
+}
+
+impl fmt::Debug for WriteConflictPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for WriteConflictPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Collection URL: {}, file name: {:?}.", self.collection_url, self.file_name)
+    }
+}
+
+impl Payload for WriteConflictPayload {}
+
+/// A bucket of an S3-compatible object storage, addressed path-style, as
+/// in "<endpoint>/<bucket>/<key>", which works with AWS as well as with
+/// stand-ins like MinIO.
+pub struct S3Bucket {
+    /// Without a trailing "/".
+    endpoint: String,
+    region: String,
+    bucket: String,
+    /// None for anonymous requests.
+    credentials: Option<S3Credentials>,
+    agent: ureq::Agent,
+}
+
+impl S3Bucket {
+    /// The bucket of that name at the endpoint, e.g.
+    /// "https://s3.eu-central-1.amazonaws.com" in "eu-central-1".
+    pub fn new<EndpointRef: AsRef<str>, RegionRef: AsRef<str>, BucketRef: AsRef<str>>(
+        endpoint: EndpointRef,
+        region: RegionRef,
+        bucket: BucketRef,
+        credentials: Option<S3Credentials>
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            endpoint: endpoint.as_ref().trim_end_matches('/').to_owned(),
+            region: region.as_ref().to_owned(),
+            bucket: bucket.as_ref().to_owned(),
+            credentials,
+            agent: ureq::AgentBuilder::new().timeout(DEFAULT_HTTP_TIMEOUT).build(),
+        })
+    }
+
+    /// Returns the collection of the objects whose keys start with the
+    /// prefix and a "/", e.g. "blobs", or with no prefix at all for "".
+    ///
+    /// Its writes aren't conditional, see `S3Collection::conditional_writes`.
+    pub fn get_collection<PrefixRef: AsRef<str>>(self: &Arc<Self>, prefix: PrefixRef)
+    -> S3Collection {
+        S3Collection {
+            bucket: Arc::clone(self),
+            prefix: prefix.as_ref().trim_matches('/').to_owned(),
+            etags: Arc::new(Mutex::new(HashMap::new())),
+            conditional_writes: false,
+        }
+    }
+
+    fn get_path(&self, key: Option<&str>) -> String {
+        match key {
+            Some(key) => format!("/{}/{}", uri_encode(&self.bucket, true), uri_encode(key, false)),
+            None => format!("/{}", uri_encode(&self.bucket, true)),
+        }
+    }
+
+    fn get_url(&self, key: Option<&str>) -> String {
+        format!("{}{}", self.endpoint, self.get_path(key))
+    }
+
+    /// Signs and sends a request about the object of that key, or the
+    /// bucket itself for None.
+    fn send(
+        &self,
+        method: &str,
+        key: Option<&str>,
+        query: &[(&str, &str)],
+        headers: &[(&str, &str)],
+        body: &[u8]
+    ) -> Result<ureq::Response, Box<ureq::Error>> {
+        let path = self.get_path(key);
+        let mut encoded_query: Vec<String> = query.iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+            .collect();
+        encoded_query.sort();
+        let canonical_query = encoded_query.join("&");
+        let url = match canonical_query.is_empty() {
+            true => format!("{}{}", self.endpoint, path),
+            false => format!("{}{}?{}", self.endpoint, path, canonical_query),
+        };
+        let payload_hash = sha256_hex(body);
+        let amz_date = format_amz_date(SystemTime::now());
+        let host = self.endpoint.split_once("://")
+            .map_or(self.endpoint.as_str(), |(_, rest)| rest);
+        let mut signed_headers = BTreeMap::new();
+        signed_headers.insert(String::from("host"), host.to_owned());
+        signed_headers.insert(String::from("x-amz-content-sha256"), payload_hash.to_owned());
+        signed_headers.insert(String::from("x-amz-date"), amz_date.to_owned());
+        if let Some(session_token) = self.credentials.as_ref()
+        .and_then(|credentials| credentials.session_token.as_ref()) {
+            signed_headers.insert(String::from("x-amz-security-token"), session_token.to_owned());
+        }
+        for (name, value) in headers {
+            signed_headers.insert(name.to_lowercase(), (*value).to_owned());
+        }
+        let mut request = self.agent.request(method, &url);
+        for (name, value) in signed_headers.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        if let Some(credentials) = &self.credentials {
+            request = request.set("Authorization", &get_authorization(
+                credentials,
+                &self.region,
+                &amz_date,
+                &SignableRequest {
+                    method,
+                    canonical_uri: &path,
+                    canonical_query: &canonical_query,
+                    headers: &signed_headers,
+                    payload_hash: &payload_hash,
+                }
+            ));
+        }
+        match method {
+            "PUT" | "POST" => request.send_bytes(body),
+            _ => request.call(),
+        }.map_err(Box::new)
+    }
+
+    fn get_request_failed_error(&self, key: Option<&str>, error: Box<ureq::Error>) -> Error {
+        let (status, message) = match *error {
+            ureq::Error::Status(status, response) => (
+                Some(status), response.status_text().to_owned()),
+            ureq::Error::Transport(transport) => (None, transport.kind().to_string()),
+        };
+        error!(
+            ErrorKind::HttpRequestFailed,
+            "Requesting an object from an S3 bucket.",
+            payload => HttpRequestFailedPayload {
+                url: redact_url(&self.get_url(key)),
+                status,
+                message
+            }
+        )
+    }
+}
+
+fn has_status(error: &ureq::Error, status: u16) -> bool {
+    matches!(error, ureq::Error::Status(error_status, _) if *error_status == status)
+}
+
+/* Notes:
+    Objects can't be written bit by bit, so what's written to a Write is
+    kept in memory and only uploaded once it's flushed, replacing the
+    object all at once. That also means nobody ever sees it half written.
+
+    With `conditional_writes`, a write only goes through if the object is
+    still as it was when the collection last read or wrote it, or still
+    doesn't exist if that's what it found, so two processes writing the
+    state at the same time can't clobber each other's changes. Objects the
+    collection knows nothing about are written regardless.
+
+    That's only of use for the collection the state is in. Blobs and
+    indexes are named by their content, so writing one someone else has
+    written as well changes nothing, but would fail if it were conditional.
+*/
+/// The objects of an S3 bucket under a particular prefix, as a collection.
+///
+/// Clones share what they know about the objects they've read.
+#[derive(Clone)]
+pub struct S3Collection {
+    bucket: Arc<S3Bucket>,
+    prefix: String,
+    /// The ETag of each object as last read or written, with None for
+    /// objects found not to exist.
+    etags: Arc<Mutex<HashMap<OsString, Option<String>>>>,
+    /// Whether writes fail with `ErrorKind::WriteConflict` if someone else
+    /// changed the object since it was last read or written. Meant to be
+    /// turned on for the collection the state is in.
+    pub conditional_writes: bool,
+}
+
+impl S3Collection {
+    /// Returns the names of all files in the collection, in order,
+    /// which are the keys below its prefix, save for those further down.
+    pub fn get_file_names(&self) -> FcResult<Vec<OsString>> {
+        let key_prefix = match self.prefix.is_empty() {
+            true => String::new(),
+            false => format!("{}/", self.prefix),
+        };
+        let mut file_names = vec!();
+        let mut continuation_token: Option<String> = None;
+        loop {
+            let mut query = vec!(("list-type", "2"), ("prefix", key_prefix.as_str()));
+            if let Some(continuation_token) = &continuation_token {
+                query.push(("continuation-token", continuation_token.as_str()));
+            }
+            let listing = self.bucket.send("GET", None, &query, &[], &[])
+                .map_err(|e| self.bucket.get_request_failed_error(None, e))?
+                .into_string()?;
+            for key in get_xml_element_texts(&listing, "Key") {
+                if let Some(file_name) = key.strip_prefix(&key_prefix) {
+                    if !file_name.is_empty() && !file_name.contains('/') {
+                        file_names.push(OsString::from(file_name));
+                    }
+                }
+            }
+            continuation_token = get_xml_element_texts(&listing, "NextContinuationToken")
+                .into_iter().next();
+            if continuation_token.is_none()
+            || get_xml_element_texts(&listing, "IsTruncated").first().map(String::as_str) != Some("true") {
+                break
+            }
+        }
+        file_names.sort();
+        Ok(file_names)
+    }
+
+    fn lock_etags(&self) -> MutexGuard<'_, HashMap<OsString, Option<String>>> {
+        self.etags.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Reduces the name to its file name, just like LocalDir does.
+    fn get_file_name<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> FcResult<OsString> {
+        let file_name_path = Path::new(name.as_ref());
+        match file_name_path.file_name() {
+            Some(file_name) => Ok(file_name.to_owned()),
+            None => Err(error!(
+                ErrorKind::DoubleDotFileName,
+                "Getting file_name portion of a name for an S3Collection.",
+                payload => DoubleDotFileName {
+                    original_path: file_name_path.to_owned()
+                }
+            ))
+        }
+    }
+
+    fn get_key(&self, file_name: &OsStr) -> String {
+        match self.prefix.is_empty() {
+            true => file_name.to_string_lossy().into_owned(),
+            false => format!("{}/{}", self.prefix, file_name.to_string_lossy()),
+        }
+    }
+
+    fn get_collection_url(&self) -> String {
+        redact_url(&format!("{}/{}", self.bucket.get_url(None), self.prefix))
+    }
+
+    fn get_path_does_not_exist_error(&self, file_name: &OsStr) -> Error {
+        error!(
+            ErrorKind::PathDoesNotExistInCollection,
+            "Getting an object from an S3Collection collection handler.",
+            payload => PathDoesNotExistInCollectionPayload {
+                collection_path: PathBuf::from(self.get_collection_url()),
+                file_name: PathBuf::from(file_name)
+            }
+        )
+    }
+
+    /// Returns whether there's an object for the file, without taking note
+    /// of its ETag, as that's no reason to think the caller knows what's in it.
+    fn has_object(&self, file_name: &OsStr) -> FcResult<bool> {
+        let key = self.get_key(file_name);
+        match self.bucket.send("HEAD", Some(&key), &[], &[], &[]) {
+            Ok(_) => Ok(true),
+            Err(e) if has_status(&e, 404) => Ok(false),
+            Err(e) => Err(self.bucket.get_request_failed_error(Some(&key), e)),
+        }
+    }
+
+    /// Downloads the object of the file, taking note of its ETag.
+    fn get_object(&self, file_name: &OsStr) -> FcResult<Box<dyn Read>> {
+        let key = self.get_key(file_name);
+        match self.bucket.send("GET", Some(&key), &[], &[], &[]) {
+            Ok(response) => {
+                let etag = response.header("ETag").map(|etag| etag.to_owned());
+                self.lock_etags().insert(file_name.to_owned(), etag);
+                Ok(response.into_reader())
+            },
+            Err(e) if has_status(&e, 404) => {
+                self.lock_etags().insert(file_name.to_owned(), None);
+                Err(self.get_path_does_not_exist_error(file_name))
+            },
+            Err(e) => Err(self.bucket.get_request_failed_error(Some(&key), e)),
+        }
+    }
+
+    /// Uploads the content as the object of the file, replacing it, on
+    /// the conditions laid out for `conditional_writes`.
+    fn put_object(&self, file_name: &OsStr, content: &[u8]) -> FcResult<()> {
+        let key = self.get_key(file_name);
+        let known_etag = match self.conditional_writes {
+            true => self.lock_etags().get(file_name).cloned(),
+            false => None,
+        };
+        let condition = match &known_etag {
+            Some(Some(etag)) => vec!(("If-Match", etag.as_str())),
+            Some(None) => vec!(("If-None-Match", "*")),
+            None => vec!(),
+        };
+        match self.bucket.send("PUT", Some(&key), &[], &condition, content) {
+            Ok(response) => {
+                match response.header("ETag") {
+                    Some(etag) => self.lock_etags().insert(file_name.to_owned(), Some(etag.to_owned())),
+                    None => self.lock_etags().remove(file_name),
+                };
+                Ok(())
+            },
+            Err(e) if has_status(&e, 412) => {
+                // Whatever is there now is news to us.
+                self.lock_etags().remove(file_name);
+                Err(error!(
+                    ErrorKind::WriteConflict,
+                    "Conditionally writing an object to an S3 bucket.",
+                    payload => WriteConflictPayload {
+                        collection_url: self.get_collection_url(),
+                        file_name: file_name.to_owned()
+                    }
+                ))
+            },
+            Err(e) => Err(self.bucket.get_request_failed_error(Some(&key), e)),
+        }
+    }
+}
+
+/// Returns the text of every element of that name in the XML, unescaped,
+/// which is all there is to the responses we're interested in.
+fn get_xml_element_texts(xml: &str, name: &str) -> Vec<String> {
+    let start_tag = format!("<{}>", name);
+    let end_tag = format!("</{}>", name);
+    let mut texts = vec!();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&start_tag) {
+        rest = &rest[start + start_tag.len()..];
+        let end = match rest.find(&end_tag) {
+            Some(end) => end,
+            None => break,
+        };
+        texts.push(rest[..end]
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&"));
+        rest = &rest[end + end_tag.len()..];
+    }
+    texts
+}
+
+/// Collects what's written to an object, uploading it once flushed.
+/// Whatever hasn't been flushed by the time it's dropped is discarded,
+/// so a write cut short never replaces the object.
+struct S3ObjectWriteable {
+    collection: S3Collection,
+    file_name: OsString,
+    content: Vec<u8>,
+    /// Whether there's something that hasn't been uploaded yet, which
+    /// starts out true, as even nothing at all replaces the object.
+    is_dirty: bool,
+}
+
+impl Write for S3ObjectWriteable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.content.extend_from_slice(buf);
+        self.is_dirty = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.is_dirty {
+            // Only ever tried once, so a conflict isn't retried by
+            // flushing again.
+            self.is_dirty = false;
+            // Carrying the error, so a conflict still is one once it's
+            // converted back.
+            self.collection.put_object(&self.file_name, &self.content)
+                .map_err(io::Error::from)?;
+        }
+        Ok(())
+    }
+}
+
+/// An object in an S3Collection, downloaded whenever it's opened.
+struct S3ObjectSource {
+    collection: S3Collection,
+    file_name: OsString,
+}
+
+impl ReadableSource for S3ObjectSource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        self.collection.get_object(&self.file_name)
+    }
+}
+
+impl OpaqueCollectionHandler for S3Collection {
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        let file_name = self.get_file_name(name)?;
+        self.has_object(&file_name)
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<()> {
+        self.put_object(&self.get_file_name(name)?, &[])
+    }
+
+    fn get_file_readable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Read>> {
+        self.get_object(&self.get_file_name(name)?)
+    }
+
+    fn get_file_writeable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Write>> {
+        let file_name = self.get_file_name(name)?;
+        if !self.has_object(&file_name)? {
+            return Err(self.get_path_does_not_exist_error(&file_name))
+        }
+        Ok(Box::new(S3ObjectWriteable {
+            collection: self.clone(),
+            file_name,
+            content: vec!(),
+            is_dirty: true,
+        }))
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        let file_name = self.get_file_name(name)?;
+        if !self.has_object(&file_name)? {
+            return Err(self.get_path_does_not_exist_error(&file_name))
+        }
+        Ok(Box::new(S3ObjectSource {
+            collection: self.clone(),
+            file_name
+        }))
+    }
+
+    /// Copies the object and removes the original, as objects can't be
+    /// renamed.
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_file_name(name)?;
+        let new_file_name = self.get_file_name(new_name)?;
+        let key = self.get_key(&file_name);
+        let copy_source = format!(
+            "/{}/{}", uri_encode(&self.bucket.bucket, true), uri_encode(&key, false));
+        let new_key = self.get_key(&new_file_name);
+        match self.bucket.send(
+            "PUT", Some(&new_key), &[], &[("x-amz-copy-source", copy_source.as_str())], &[]
+        ) {
+            Ok(_) => (),
+            Err(e) if has_status(&e, 404)
+                => return Err(self.get_path_does_not_exist_error(&file_name)),
+            Err(e) => return Err(self.bucket.get_request_failed_error(Some(&new_key), e)),
+        }
+        self.lock_etags().remove(&new_file_name);
+        self.remove_file(&file_name)
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_file_name(name)?;
+        // Deleting objects that don't exist succeeds, unlike removing files.
+        if !self.has_object(&file_name)? {
+            return Err(self.get_path_does_not_exist_error(&file_name))
+        }
+        let key = self.get_key(&file_name);
+        self.bucket.send("DELETE", Some(&key), &[], &[], &[])
+            .map_err(|e| self.bucket.get_request_failed_error(Some(&key), e))?;
+        self.lock_etags().insert(file_name, None);
+        Ok(())
+    }
+
+    /// Prefixes don't have to be created, so this is about the bucket.
+    fn collection_exists(&mut self) -> bool {
+        self.bucket.send("HEAD", None, &[], &[], &[]).is_ok()
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        if self.collection_exists() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into())
+        }
+        self.create_collection_ignore_exists()
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        match self.bucket.send("PUT", None, &[], &[], &[]) {
+            Ok(_) => Ok(()),
+            // Already there.
+            Err(e) if has_status(&e, 409) => Ok(()),
+            Err(e) => Err(self.bucket.get_request_failed_error(None, e)),
+        }
+    }
+
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
+        format!(
+            "S3 collection URL: {}, file name: {:#?}",
+            self.get_collection_url(), self.get_file_name(name)
+        )
+    }
+}
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const SERVICE: &str = "s3";
+
+/// What requests to an S3 API are signed with.
+#[derive(Clone)]
+pub struct S3Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// For temporary credentials.
+    pub session_token: Option<String>,
+}
+
+impl S3Credentials {
+    pub fn new<IdRef: AsRef<str>, KeyRef: AsRef<str>>(access_key_id: IdRef, secret_access_key: KeyRef)
+    -> Self {
+        Self {
+            access_key_id: access_key_id.as_ref().to_owned(),
+            secret_access_key: secret_access_key.as_ref().to_owned(),
+            session_token: None,
+        }
+    }
+}
+
+impl fmt::Debug for S3Credentials {
+    /// Leaves out everything secret.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "S3Credentials {{ access_key_id: {:?}, .. }}", self.access_key_id)
+    }
+}
+
+/// Escapes the string the way AWS Signature Version 4 expects it, which
+/// is also how it has to be sent, leaving slashes alone unless asked to.
+pub fn uri_encode(string: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(string.len());
+    for byte in string.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~'
+                => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+pub fn sha256_hex(content: &[u8]) -> String {
+    hex::encode(Sha256::digest(content))
+}
+
+fn hmac_sha256(key: &[u8], content: &str) -> Vec<u8> {
+    // HMAC takes keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length.");
+    mac.update(content.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Returns the time as AWS expects it in requests, e.g. "20130524T000000Z".
+pub fn format_amz_date(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+    let (days, second_of_day) = ((seconds / 86_400) as i64, seconds % 86_400);
+    // Howard Hinnant's days-to-civil algorithm.
+    let shifted_days = days + 719_468;
+    let era = shifted_days.div_euclid(146_097);
+    let day_of_era = shifted_days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1_460 + day_of_era / 36_524
+        - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year, month, day,
+        second_of_day / 3_600, second_of_day % 3_600 / 60, second_of_day % 60
+    )
+}
+
+/// A request as far as signing it is concerned.
+pub struct SignableRequest<'req> {
+    pub method: &'req str,
+    /// The path, already escaped with `uri_encode`.
+    pub canonical_uri: &'req str,
+    /// The query, with its parameters escaped and sorted.
+    pub canonical_query: &'req str,
+    /// All headers to sign, by lowercase name, including "host",
+    /// "x-amz-date" and "x-amz-content-sha256".
+    pub headers: &'req BTreeMap<String, String>,
+    pub payload_hash: &'req str,
+}
+
+/// Returns the Authorization header for the request, signed with AWS
+/// Signature Version 4, `amz_date` being its "x-amz-date" header.
+pub fn get_authorization(
+    credentials: &S3Credentials,
+    region: &str,
+    amz_date: &str,
+    request: &SignableRequest
+) -> String {
+    let canonical_headers: String = request.headers.iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = request.headers.keys()
+        .map(|name| name.as_str())
+        .collect::<Vec<&str>>()
+        .join(";");
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method, request.canonical_uri, request.canonical_query,
+        canonical_headers, signed_headers, request.payload_hash
+    );
+    let date = &amz_date[..8];
+    let scope = format!("{}/{}/{}/aws4_request", date, region, SERVICE);
+    let string_to_sign = format!(
+        "{}\n{}\n{}\n{}",
+        ALGORITHM, amz_date, scope, sha256_hex(canonical_request.as_bytes())
+    );
+    let date_key = hmac_sha256(
+        format!("AWS4{}", credentials.secret_access_key).as_bytes(), date);
+    let region_key = hmac_sha256(&date_key, region);
+    let service_key = hmac_sha256(&region_key, SERVICE);
+    let signing_key = hmac_sha256(&service_key, "aws4_request");
+    format!(
+        "{} Credential={}/{}, SignedHeaders={}, Signature={}",
+        ALGORITHM, credentials.access_key_id, scope, signed_headers,
+        hex::encode(hmac_sha256(&signing_key, &string_to_sign))
+    )
+}
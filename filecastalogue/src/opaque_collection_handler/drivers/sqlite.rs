@@ -0,0 +1,355 @@
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+use rusqlite::{Connection, OptionalExtension, params};
+use crate::error::{Error, ErrorKind, FcResult, Payload};
+use crate::opaque_collection_handler::{BytesSource, OpaqueCollectionHandler, PathDoesNotExistInCollectionPayload, ReadableSource};
+use super::local::DoubleDotFileName;
+
+/// How long to wait for other processes to finish writing to the database.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(30);
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS collections (
+        name TEXT PRIMARY KEY NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        collection TEXT NOT NULL,
+        name BLOB NOT NULL,
+        content BLOB NOT NULL,
+        PRIMARY KEY (collection, name)
+    );
+";
+
+pub struct DatabaseOperationFailedPayload {
+    pub database_path: PathBuf,
+    pub message: String,
+}
+
+impl fmt::Debug for DatabaseOperationFailedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for DatabaseOperationFailedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Database path: {:?}, message: {}.", self.database_path, self.message)
+    }
+}
+
+impl Payload for DatabaseOperationFailedPayload {}
+
+/* Notes:
+    Every collection is a set of rows in the same table, so everything
+    written between `begin_transaction` and `commit_transaction` becomes
+    visible all at once, or not at all if the transaction is rolled back,
+    or never committed because the process died. Anything written outside
+    of a transaction is committed right away.
+
+    Transactions are of the connection, not of a collection, so they span
+    all collections of the database, which is what makes them useful for
+    a repo, see `SqliteJournal`.
+*/
+/// A SQLite database holding any number of collections.
+pub struct SqliteDatabase {
+    path: PathBuf,
+    connection: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    /// Opens the database at the specified path, creating it if needed.
+    pub fn open<PathRef: AsRef<Path>>(path: PathRef) -> FcResult<Arc<Self>> {
+        let path = path.as_ref().to_owned();
+        let connection = Connection::open(&path)
+            .map_err(|e| get_database_error(&path, e))?;
+        let database = Self {
+            path,
+            connection: Mutex::new(connection),
+        };
+        {
+            let connection = database.lock();
+            connection.busy_timeout(BUSY_TIMEOUT)
+                .and_then(|_| connection.execute_batch(SCHEMA))
+                .map_err(|e| database.get_error(e))?;
+        }
+        Ok(Arc::new(database))
+    }
+
+    /// Returns the collection of that name, e.g. "blobs", which doesn't
+    /// have to exist yet.
+    pub fn get_collection<NameRef: AsRef<str>>(self: &Arc<Self>, name: NameRef)
+    -> SqliteCollection {
+        SqliteCollection {
+            database: Arc::clone(self),
+            name: name.as_ref().to_owned(),
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Begins a transaction, waiting for other processes to finish theirs.
+    pub fn begin_transaction(&self) -> FcResult<()> {
+        self.execute_batch("BEGIN IMMEDIATE")
+    }
+
+    pub fn commit_transaction(&self) -> FcResult<()> {
+        self.execute_batch("COMMIT")
+    }
+
+    /// Undoes everything written since the transaction began.
+    pub fn rollback_transaction(&self) -> FcResult<()> {
+        self.execute_batch("ROLLBACK")
+    }
+
+    /// Returns whether a transaction has begun and not ended yet.
+    pub fn is_in_transaction(&self) -> bool {
+        !self.lock().is_autocommit()
+    }
+
+    fn execute_batch(&self, sql: &str) -> FcResult<()> {
+        self.lock().execute_batch(sql).map_err(|e| self.get_error(e))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.connection.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn get_error(&self, error: rusqlite::Error) -> Error {
+        get_database_error(&self.path, error)
+    }
+}
+
+fn get_database_error(path: &Path, error: rusqlite::Error) -> Error {
+    error!(
+        ErrorKind::DatabaseOperationFailed,
+        "Accessing a SQLite database.",
+        payload => DatabaseOperationFailedPayload {
+            database_path: path.to_owned(),
+            message: error.to_string()
+        }
+    )
+}
+
+/// Collects what's written to a file, storing it once flushed.
+/// Whatever hasn't been flushed by the time it's dropped is discarded,
+/// so a write cut short never replaces the file.
+struct SqliteFileWriteable {
+    collection: SqliteCollection,
+    file_name: OsString,
+    content: Vec<u8>,
+    /// Whether there's something that hasn't been stored yet, which
+    /// starts out true, as even nothing at all replaces the file.
+    is_dirty: bool,
+}
+
+impl Write for SqliteFileWriteable {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.content.extend_from_slice(buf);
+        self.is_dirty = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.is_dirty {
+            self.is_dirty = false;
+            self.collection.put_content(&self.file_name, &self.content)
+                .map_err(io::Error::from)?;
+        }
+        Ok(())
+    }
+}
+
+/// The files of a collection in a SqliteDatabase.
+///
+/// Files are read and written whole, so they're held in memory while
+/// they're being read or written.
+#[derive(Clone)]
+pub struct SqliteCollection {
+    database: Arc<SqliteDatabase>,
+    name: String,
+}
+
+impl SqliteCollection {
+    /// Returns the names of all files in the collection, in order.
+    pub fn get_file_names(&self) -> FcResult<Vec<OsString>> {
+        let connection = self.database.lock();
+        let mut statement = connection
+            .prepare("SELECT name FROM files WHERE collection = ?1 ORDER BY name")
+            .map_err(|e| self.database.get_error(e))?;
+        let file_names = statement
+            .query_map(params![self.name], |row| row.get::<_, Vec<u8>>(0))
+            .and_then(|rows| rows.collect::<Result<Vec<Vec<u8>>, _>>())
+            .map_err(|e| self.database.get_error(e))?;
+        Ok(file_names.into_iter().map(OsString::from_vec).collect())
+    }
+
+    /// Reduces the name to its file name, just like LocalDir does.
+    fn get_file_name<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> FcResult<OsString> {
+        let file_name_path = Path::new(name.as_ref());
+        match file_name_path.file_name() {
+            Some(file_name) => Ok(file_name.to_owned()),
+            None => Err(error!(
+                ErrorKind::DoubleDotFileName,
+                "Getting file_name portion of a name for a SqliteCollection.",
+                payload => DoubleDotFileName {
+                    original_path: file_name_path.to_owned()
+                }
+            ))
+        }
+    }
+
+    fn get_path_does_not_exist_error(&self, file_name: &OsStr) -> Error {
+        error!(
+            ErrorKind::PathDoesNotExistInCollection,
+            "Getting a file from a SqliteCollection collection handler.",
+            payload => PathDoesNotExistInCollectionPayload {
+                collection_path: self.database.path.join(&self.name),
+                file_name: PathBuf::from(file_name)
+            }
+        )
+    }
+
+    fn get_content(&self, file_name: &OsStr) -> FcResult<Option<Vec<u8>>> {
+        self.database.lock()
+            .query_row(
+                "SELECT content FROM files WHERE collection = ?1 AND name = ?2",
+                params![self.name, file_name.as_bytes()],
+                |row| row.get(0)
+            )
+            .optional()
+            .map_err(|e| self.database.get_error(e))
+    }
+
+    fn get_existing_content(&self, file_name: &OsStr) -> FcResult<Vec<u8>> {
+        match self.get_content(file_name)? {
+            Some(content) => Ok(content),
+            None => Err(self.get_path_does_not_exist_error(file_name)),
+        }
+    }
+
+    fn put_content(&self, file_name: &OsStr, content: &[u8]) -> FcResult<()> {
+        self.database.lock()
+            .execute(
+                "INSERT OR REPLACE INTO files (collection, name, content) VALUES (?1, ?2, ?3)",
+                params![self.name, file_name.as_bytes(), content]
+            )
+            .map(|_| ())
+            .map_err(|e| self.database.get_error(e))
+    }
+}
+
+impl OpaqueCollectionHandler for SqliteCollection {
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        let file_name = self.get_file_name(name)?;
+        self.database.lock()
+            .query_row(
+                "SELECT 1 FROM files WHERE collection = ?1 AND name = ?2",
+                params![self.name, file_name.as_bytes()],
+                |_| Ok(())
+            )
+            .optional()
+            .map(|row| row.is_some())
+            .map_err(|e| self.database.get_error(e))
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, name: NameRef)
+    -> FcResult<()> {
+        self.put_content(&self.get_file_name(name)?, &[])
+    }
+
+    fn get_file_readable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Read>> {
+        let file_name = self.get_file_name(name)?;
+        Ok(Box::new(io::Cursor::new(self.get_existing_content(&file_name)?)))
+    }
+
+    fn get_file_writeable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Write>> {
+        let file_name = self.get_file_name(name)?;
+        self.get_existing_content(&file_name)?;
+        Ok(Box::new(SqliteFileWriteable {
+            collection: self.clone(),
+            file_name,
+            content: vec!(),
+            is_dirty: true,
+        }))
+    }
+
+    /// Reads the file right away, so what's read from the source is what
+    /// was there now.
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        let file_name = self.get_file_name(name)?;
+        Ok(Box::new(BytesSource::new(Arc::new(self.get_existing_content(&file_name)?))))
+    }
+
+    /// Replaces whatever file has the new name already, like renaming a
+    /// file in a LocalDir does.
+    fn rename_file(&mut self, name: &OsStr, new_name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_file_name(name)?;
+        let new_file_name = self.get_file_name(new_name)?;
+        let changed_count = self.database.lock()
+            .execute(
+                "UPDATE OR REPLACE files SET name = ?3 WHERE collection = ?1 AND name = ?2",
+                params![self.name, file_name.as_bytes(), new_file_name.as_bytes()]
+            )
+            .map_err(|e| self.database.get_error(e))?;
+        match changed_count {
+            0 => Err(self.get_path_does_not_exist_error(&file_name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn remove_file(&mut self, name: &OsStr) -> FcResult<()> {
+        let file_name = self.get_file_name(name)?;
+        let removed_count = self.database.lock()
+            .execute(
+                "DELETE FROM files WHERE collection = ?1 AND name = ?2",
+                params![self.name, file_name.as_bytes()]
+            )
+            .map_err(|e| self.database.get_error(e))?;
+        match removed_count {
+            0 => Err(self.get_path_does_not_exist_error(&file_name)),
+            _ => Ok(()),
+        }
+    }
+
+    fn collection_exists(&mut self) -> bool {
+        self.database.lock()
+            .query_row(
+                "SELECT 1 FROM collections WHERE name = ?1",
+                params![self.name],
+                |_| Ok(())
+            )
+            .is_ok()
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        if self.collection_exists() {
+            return Err(io::Error::from(io::ErrorKind::AlreadyExists).into())
+        }
+        self.create_collection_ignore_exists()
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        self.database.lock()
+            .execute("INSERT OR IGNORE INTO collections (name) VALUES (?1)", params![self.name])
+            .map(|_| ())
+            .map_err(|e| self.database.get_error(e))
+    }
+
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
+        format!(
+            "SQLite database: {:#?}, collection: {}, file name: {:#?}",
+            self.database.path, self.name, self.get_file_name(name)
+        )
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use crate::error::{Error, ErrorKind, FcResult};
+use crate::opaque_collection_handler::{OpaqueCollectionHandler, PathDoesNotExistInCollectionPayload, ReadOnlyCollectionPayload, ReadableSource};
+use super::local::DoubleDotFileName;
+
+/// How a zstd frame starts, which is how compressed archives are told apart.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Where the content of a file is within the archive.
+#[derive(Clone, Copy)]
+struct TarEntry {
+    offset: u64,
+    length: u64,
+}
+
+/* Notes:
+    A tar archive can be read from anywhere without reading everything in
+    front of it, as long as it's known where the content of its files is,
+    so the archive is read once when it's opened, noting that down, and
+    from then on, files are read straight from where they are.
+
+    That doesn't work for compressed archives, which are decompressed into
+    an anonymous temporary file when they're opened, which goes away along
+    with the TarArchive.
+*/
+/// A tar archive, as opened for the TarCollections reading from it.
+pub struct TarArchive {
+    path: PathBuf,
+    file: Arc<File>,
+    entries: BTreeMap<PathBuf, TarEntry>,
+}
+
+impl TarArchive {
+    /// Opens the tar archive at the specified path, which may also be
+    /// compressed with zstd, e.g. a `.tar.zst` file.
+    pub fn open<PathRef: AsRef<Path>>(path: PathRef) -> FcResult<Arc<Self>> {
+        let mut file = File::open(path.as_ref())?;
+        let mut magic = [0u8; ZSTD_MAGIC.len()];
+        let is_compressed = match file.read_exact(&mut magic) {
+            Ok(()) => magic == ZSTD_MAGIC,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => false,
+            Err(e) => return Err(e.into()),
+        };
+        file.seek(SeekFrom::Start(0))?;
+        if is_compressed {
+            let mut decompressed_file = tempfile::tempfile()?;
+            io::copy(&mut zstd::Decoder::new(file)?, &mut decompressed_file)?;
+            decompressed_file.seek(SeekFrom::Start(0))?;
+            file = decompressed_file;
+        }
+        let entries = Self::read_entries(&mut file)?;
+        Ok(Arc::new(Self {
+            path: path.as_ref().to_owned(),
+            file: Arc::new(file),
+            entries,
+        }))
+    }
+
+    /// Returns the collection of the files in the specified directory of
+    /// the archive, with "" being the top directory.
+    pub fn get_collection<PathRef: AsRef<Path>>(self: &Arc<Self>, dir: PathRef)
+    -> TarCollection {
+        TarCollection {
+            archive: Arc::clone(self),
+            dir: normalize_archive_path(dir.as_ref()),
+        }
+    }
+
+    /// Returns the paths of all files in the archive, in order.
+    pub fn get_file_paths(&self) -> Vec<PathBuf> {
+        self.entries.keys().cloned().collect()
+    }
+
+    fn read_entries(file: &mut File) -> FcResult<BTreeMap<PathBuf, TarEntry>> {
+        let mut entries = BTreeMap::new();
+        let mut archive = tar::Archive::new(file);
+        for entry in archive.entries()? {
+            let entry = entry?;
+            // Only files can be in a collection.
+            if !entry.header().entry_type().is_file() {
+                continue
+            }
+            entries.insert(normalize_archive_path(&entry.path()?), TarEntry {
+                offset: entry.raw_file_position(),
+                length: entry.size(),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+/// Turns the path of a file in an archive into the form it's looked up
+/// by, so "./blobs/x" and "blobs/x" are the same file.
+fn normalize_archive_path(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+/// Reads the content of a file in a TarArchive, without moving the
+/// position of the archive file, which is shared.
+struct TarEntryReadable {
+    file: Arc<File>,
+    offset: u64,
+    remaining_length: u64,
+}
+
+impl Read for TarEntryReadable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.remaining_length as usize);
+        if length == 0 {
+            return Ok(0)
+        }
+        let read_count = self.file.read_at(&mut buf[..length], self.offset)?;
+        self.offset += read_count as u64;
+        self.remaining_length -= read_count as u64;
+        Ok(read_count)
+    }
+}
+
+/// A file in a TarArchive, which can be read as often as needed.
+struct TarEntrySource {
+    file: Arc<File>,
+    entry: TarEntry,
+}
+
+impl ReadableSource for TarEntrySource {
+    fn open(&self) -> FcResult<Box<dyn Read>> {
+        Ok(Box::new(TarEntryReadable {
+            file: Arc::clone(&self.file),
+            offset: self.entry.offset,
+            remaining_length: self.entry.length,
+        }))
+    }
+}
+
+/// The files in a directory of a TarArchive, which can only be read.
+///
+/// Anything writing to it fails with `ErrorKind::ReadOnlyCollection`.
+#[derive(Clone)]
+pub struct TarCollection {
+    archive: Arc<TarArchive>,
+    dir: PathBuf,
+}
+
+impl TarCollection {
+    /// Reduces the name to its file name, just like LocalDir does.
+    fn get_file_name<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> FcResult<OsString> {
+        let file_name_path = Path::new(name.as_ref());
+        match file_name_path.file_name() {
+            Some(file_name) => Ok(file_name.to_owned()),
+            None => Err(error!(
+                ErrorKind::DoubleDotFileName,
+                "Getting file_name portion of a name for a TarCollection.",
+                payload => DoubleDotFileName {
+                    original_path: file_name_path.to_owned()
+                }
+            ))
+        }
+    }
+
+    fn get_entry(&self, name: &OsStr) -> FcResult<TarEntry> {
+        let file_name = self.get_file_name(name)?;
+        match self.archive.entries.get(&self.dir.join(&file_name)) {
+            Some(entry) => Ok(*entry),
+            None => Err(error!(
+                ErrorKind::PathDoesNotExistInCollection,
+                "Getting a file from a TarCollection collection handler.",
+                payload => PathDoesNotExistInCollectionPayload {
+                    collection_path: self.archive.path.join(&self.dir),
+                    file_name: PathBuf::from(file_name)
+                }
+            )),
+        }
+    }
+
+    fn get_read_only_error(&self) -> Error {
+        error!(
+            ErrorKind::ReadOnlyCollection,
+            "Writing to a TarCollection, which can only be read.",
+            payload => ReadOnlyCollectionPayload {
+                collection_path: self.archive.path.join(&self.dir)
+            }
+        )
+    }
+}
+
+impl OpaqueCollectionHandler for TarCollection {
+    fn has_file<NameRef: AsRef<OsStr>>(&mut self, name: NameRef)
+    -> FcResult<bool> {
+        let file_name = self.get_file_name(name)?;
+        Ok(self.archive.entries.contains_key(&self.dir.join(file_name)))
+    }
+
+    fn create_file<NameRef: AsRef<OsStr>>(&self, _name: NameRef)
+    -> FcResult<()> {
+        Err(self.get_read_only_error())
+    }
+
+    fn get_file_readable(&self, name: &OsStr)
+    -> FcResult<Box<dyn Read>> {
+        self.get_file_readable_source(name)?.open()
+    }
+
+    fn get_file_writeable(&self, _name: &OsStr)
+    -> FcResult<Box<dyn Write>> {
+        Err(self.get_read_only_error())
+    }
+
+    fn get_file_readable_source(&self, name: &OsStr)
+    -> FcResult<Box<dyn ReadableSource>> {
+        Ok(Box::new(TarEntrySource {
+            file: Arc::clone(&self.archive.file),
+            entry: self.get_entry(name)?,
+        }))
+    }
+
+    fn rename_file(&mut self, _name: &OsStr, _new_name: &OsStr) -> FcResult<()> {
+        Err(self.get_read_only_error())
+    }
+
+    fn remove_file(&mut self, _name: &OsStr) -> FcResult<()> {
+        Err(self.get_read_only_error())
+    }
+
+    /// Archives don't keep track of empty directories, so a directory
+    /// exists as long as there's a file in it.
+    fn collection_exists(&mut self) -> bool {
+        self.dir.as_os_str().is_empty()
+        || self.archive.entries.keys().any(|path| path.starts_with(&self.dir))
+    }
+
+    fn create_collection(&mut self) -> FcResult<()> {
+        Err(self.get_read_only_error())
+    }
+
+    fn create_collection_ignore_exists(&mut self) -> FcResult<()> {
+        match self.collection_exists() {
+            true => Ok(()),
+            false => Err(self.get_read_only_error()),
+        }
+    }
+
+    fn get_debug_info_for_file<NameRef: AsRef<OsStr>>(&self, name: NameRef) -> String {
+        format!(
+            "tar archive: {:#?}, directory in archive: {:#?}, file name: {:#?}",
+            self.archive.path, self.dir, self.get_file_name(name)
+        )
+    }
+}
@@ -1,21 +1,52 @@
-use std::ffi::OsString;
-use std::{ffi::OsStr, io::Read};
+use std::collections::HashSet;
+use std::convert::TryInto;
+use std::io::Read;
 use crate::error::FcResult;
+use crate::files::hashable::HashAlgorithm;
+use crate::files::state::RepoStateFile;
 use crate::files::state_collection::StateFileCollection;
 use crate::journal;
-use crate::files::index_collection::IndexFileCollection;
+use crate::journal::{JournalEntryId, JournalRecord};
+use crate::lock::{DummyLock, LockMode, RepoLock};
+use crate::files::index::RepoIndexFile;
+use crate::files::index_collection::{IndexFileCollection, MiscIndexFileCollection};
 use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
 use crate::meta::file_aspects::aspects::directory::TrackableDirectoryAspects;
+use crate::meta::file_aspects::aspects::directory::TrackedDirectoryAspects;
 use crate::meta::file_aspects::aspects::non_existing::TrackableNonExistingAspects;
 use crate::meta::file_aspects::aspects::non_existing::TrackedNonExistingAspects;
 use crate::meta::file_aspects::aspects::ordinary::TrackableOrdinaryAspects;
+use crate::meta::file_aspects::aspects::ordinary::TrackedOrdinaryAspects;
 use crate::meta::file_aspects::aspects::symlink::TrackableSymlinkAspects;
+use crate::meta::file_aspects::aspects::symlink::TrackedSymlinkAspects;
 use crate::meta::file_aspects::enums::TrackedFileAspects;
 use crate::meta::index::accessor::IndexAccessor;
+use crate::meta::index::consistency::IndexConsistencyRules;
 use crate::meta::repo_exported_file_list::model::RepoExportedFileList;
 use crate::meta::state::accessor::StateAccessor;
+use crate::meta::tracked_path::model::TrackedPath;
 use crate::meta::version::accessor::VersionAccessor;
+use crate::meta::blob::model::Blob;
+use crate::meta::state::model::State;
 use crate::meta::version::model::Version;
+use crate::opaque_collection_handler::OpaqueCollectionHandler;
+
+pub mod archive;
+pub mod batch;
+pub mod bundle;
+pub mod mtree;
+mod recovery;
+mod rehash;
+pub mod sqlite;
+pub mod sync;
+pub mod tarball;
+
+/// Returns the hash identifying the state, which is that of its serialized
+/// form.
+fn hash_state(state: State) -> FcResult<String> {
+    let blob: Blob = state.try_into()?;
+    HashAlgorithm::default().hash_readable(&mut &blob[..])
+}
 
 pub struct Repo<
     // Handler: FiniteStreamHandler,
@@ -28,7 +59,17 @@ pub struct Repo<
         pub state_collection: StateFile,
         pub indexes: Indexes,
         pub blobs: Blobs,
-        pub journal: Journal
+        pub journal: Journal,
+        /// Taken shared by everything reading the repo and exclusively by
+        /// everything changing it.
+        pub lock: Box<dyn RepoLock>,
+        /// The rules indexes have to adhere to before they're written.
+        pub index_consistency_rules: IndexConsistencyRules,
+        /// Whether an operation changing the repo is underway.
+        is_changing: bool,
+        /// The hash of the state the operation changing the repo started
+        /// from, if there was one.
+        started_from_state_hash: Option<String>,
     }
 
 // TODO: Change e.g. state to only be a file-thing and load()
@@ -56,17 +97,193 @@ impl<
             indexes: Indexes,
             blobs: Blobs,
             journal: Journal,
+        ) -> Repo<StateCollection, Indexes, Blobs, Journal> {
+            Self::new_with_lock(
+                state_collection, indexes, blobs, journal, Box::new(DummyLock::new()))
+        }
+
+        /// Like `new`, but for repos other processes might be working on at
+        /// the same time, which take the same lock.
+        pub fn new_with_lock(
+            state_collection: StateCollection,
+            indexes: Indexes,
+            blobs: Blobs,
+            journal: Journal,
+            lock: Box<dyn RepoLock>,
         ) -> Repo<StateCollection, Indexes, Blobs, Journal> {
             Repo {
                 state_collection,
                 indexes: indexes,
                 blobs: blobs,
-                journal: journal
+                journal: journal,
+                lock,
+                index_consistency_rules: IndexConsistencyRules::new(),
+                is_changing: false,
+                started_from_state_hash: None,
             }
         }
-        pub fn has_version(self: &'rpo mut Self, version_index: usize) -> FcResult<bool> {
+
+        /// Runs `operation` while holding the lock in the specified mode.
+        fn locked<T>(
+            &mut self,
+            mode: LockMode,
+            operation: impl FnOnce(&mut Self) -> FcResult<T>
+        ) -> FcResult<T> {
+            self.lock.acquire(mode)?;
+            let result = match mode == LockMode::Exclusive && !self.is_changing {
+                true => self.changing(operation),
+                false => operation(self),
+            };
+            let release_result = self.lock.release();
+            // The error of the operation is the more telling one.
+            let value = result?;
+            release_result?;
+            Ok(value)
+        }
+
+        /* Notes:
+            A failed operation is rolled back right away, rather than left
+            to recovery, as recovering it later would write the state it
+            recorded, if it got that far, over whatever was done since.
+            The state it started from is noted for the same reason, see
+            `recover`.
+        */
+        /// Runs `operation` as one changing the repo, rolling back whatever
+        /// it began in the journal if it fails.
+        fn changing<T>(
+            &mut self,
+            operation: impl FnOnce(&mut Self) -> FcResult<T>
+        ) -> FcResult<T> {
+            let unfinished_entry_ids: HashSet<JournalEntryId> = self.journal
+                .get_unfinished_entries()?.iter().map(|entry| entry.id).collect();
+            // A state that can't be read is left for the operation to fail on.
+            self.started_from_state_hash = self.get_state_hash().unwrap_or(None);
+            self.is_changing = true;
+            let result = operation(self);
+            self.is_changing = false;
+            self.started_from_state_hash = None;
+            if result.is_err() {
+                // Whatever can't be rolled back now is left to recovery.
+                let _ = self.roll_back_failed(&unfinished_entry_ids);
+            }
+            result
+        }
+
+        /// Rolls back and completes the entries of the journal which aren't
+        /// among the specified ones, which were unfinished before.
+        fn roll_back_failed(&mut self, unfinished_entry_ids: &HashSet<JournalEntryId>)
+        -> FcResult<()> {
+            self.journal.roll_back_unfinished()?;
+            for entry in self.journal.get_unfinished_entries()? {
+                if !unfinished_entry_ids.contains(&entry.id) {
+                    self.roll_back(&entry)?;
+                    self.journal.complete(entry.id)?;
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns the hash of the state as it's currently put, if there
+        /// is one.
+        fn get_state_hash(&mut self) -> FcResult<Option<String>> {
+            if !self.state_collection.has_state()? {
+                return Ok(None)
+            }
             let mut state_file = self.state_collection.get_state_file()?;
-            Ok(state_file.get_state_ref()?.clone().has_version(version_index))
+            Ok(Some(hash_state(state_file.get_state_ref()?.clone())?))
+        }
+
+        /// Returns the algorithm blobs and indexes put into the repo
+        /// are hashed with.
+        pub fn get_hash_algorithm(&mut self) -> FcResult<HashAlgorithm> {
+            self.locked(LockMode::Shared, |repo| {
+                let mut state_file = repo.state_collection.get_state_file()?;
+                Ok(state_file.get_state_ref()?.hash_algorithm)
+            })
+        }
+
+        /// Sets the algorithm blobs and indexes put into the repo from now
+        /// on are hashed with. Whatever is in the repo already stays as it
+        /// is, use `rehash` for that.
+        pub fn set_hash_algorithm(&'rpo mut self, hash_algorithm: HashAlgorithm)
+        -> FcResult<&'rpo mut Self> {
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("set_hash_algorithm")?;
+                let mut state_file = repo.state_collection.get_state_file()?;
+                state_file.get_state_ref()?.hash_algorithm = hash_algorithm;
+                repo.put_state_file(journal_entry_id, state_file)
+            })?;
+            Ok(self)
+        }
+
+        /// Records that the specified blob has been put as part of the
+        /// operation of the specified journal entry.
+        fn record_put_blob(&mut self, journal_entry_id: JournalEntryId, hash: &str)
+        -> FcResult<()> {
+            self.journal.record(journal_entry_id, JournalRecord::PutBlob {
+                hash: hash.to_owned()
+            })
+        }
+
+        /// Puts the index file, recording it as part of the operation of the
+        /// specified journal entry, as long as the index adheres to
+        /// `index_consistency_rules`.
+        fn put_index_file(
+            &mut self,
+            journal_entry_id: JournalEntryId,
+            mut index_file: Box<dyn RepoIndexFile>,
+            hash_algorithm: HashAlgorithm
+        ) -> FcResult<String> {
+            self.index_consistency_rules.enforce(index_file.get_index_ref()?)?;
+            let hash = self.indexes.put_index_file(index_file, hash_algorithm)?;
+            self.journal.record(journal_entry_id, JournalRecord::PutIndex {
+                hash: hash.to_owned()
+            })?;
+            Ok(hash)
+        }
+
+        /// Puts the index `readable` provides as it is, like `put_index_file`,
+        /// for indexes that have to keep their hash, e.g. those transferred
+        /// from another repo. They're checked against
+        /// `index_consistency_rules`, but not changed to adhere to them.
+        fn put_index_readable(
+            &mut self,
+            journal_entry_id: JournalEntryId,
+            readable: &mut dyn Read,
+            hash_algorithm: HashAlgorithm
+        ) -> FcResult<String> {
+            let mut content = vec!();
+            readable.read_to_end(&mut content)?;
+            let mut index_file = self.indexes.create_unwritten_empty_index_file_box();
+            index_file.load(&mut content.as_slice())?;
+            self.index_consistency_rules.check(index_file.get_index_ref()?)?;
+            let hash = self.indexes.put_index_readable(&mut content.as_slice(), hash_algorithm)?;
+            self.journal.record(journal_entry_id, JournalRecord::PutIndex {
+                hash: hash.to_owned()
+            })?;
+            Ok(hash)
+        }
+
+        /// Puts the state file as the last step of the operation of the
+        /// specified journal entry, completing it.
+        fn put_state_file(
+            &mut self,
+            journal_entry_id: JournalEntryId,
+            mut state_file: Box<dyn RepoStateFile>
+        ) -> FcResult<()> {
+            self.journal.record(journal_entry_id, JournalRecord::PutState {
+                state: state_file.get_state_ref()?.clone(),
+                started_from: self.started_from_state_hash.clone()
+            })?;
+            self.state_collection.put_state_file(state_file)?;
+            self.journal.complete(journal_entry_id)
+        }
+
+        pub fn has_version(self: &'rpo mut Self, version_index: usize) -> FcResult<bool> {
+            self.locked(LockMode::Shared, |repo| {
+                let mut state_file = repo.state_collection.get_state_file()?;
+                Ok(state_file.get_state_ref()?.clone().has_version(version_index))
+            })
         }
 
         pub fn add_version(self: &'rpo mut Self)
@@ -77,19 +294,23 @@ impl<
                     - Make sure index file exists.
             */
 
-            let mut version = Version::new();
-            let index_file = self.indexes.create_unwritten_empty_index_file_box();
-            let hash = self.indexes.put_index_file(index_file)?;
-            // TODO [api]: `&hash`, even though the hash is consumed by `set_index_id`. Either
-            //  take a value only or decide whether AsRef is appropriate, or some other sugar.
-            version.set_index_id(&hash);
-            let mut state_file = self.state_collection.get_state_file()?;
-            let version_index = state_file.get_state_ref()?.add_version(version);
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("add_version")?;
+                let mut version = Version::new();
+                let mut state_file = repo.state_collection.get_state_file()?;
+                let hash_algorithm = state_file.get_state_ref()?.hash_algorithm;
+                let index_file = repo.indexes.create_unwritten_empty_index_file_box();
+                let hash = repo.put_index_file(journal_entry_id, index_file, hash_algorithm)?;
+                // TODO [api]: `&hash`, even though the hash is consumed by `set_index_id`. Either
+                //  take a value only or decide whether AsRef is appropriate, or some other sugar.
+                version.set_index_id(&hash);
+                let version_index = state_file.get_state_ref()?.add_version(version);
 
-            // TODO: Saving state?
-            self.state_collection.put_state_file(state_file)?;
+                // TODO: Saving state?
+                repo.put_state_file(journal_entry_id, state_file)?;
 
-            Ok(version_index)
+                Ok(version_index)
+            })
         }
 
         /// Track a file that doesn't exist.
@@ -103,31 +324,20 @@ impl<
         pub fn track_non_existing(
             &'rpo mut self,
             version_index: usize,
-            file_path: OsString,
+            file_path: TrackedPath,
             trackable_aspects: TrackableNonExistingAspects,
         ) -> FcResult<&'rpo mut Self> {
-            let mut state_file  = self.state_collection.get_state_file()?;
-            let mut version = state_file
-                .get_state_ref()?
-                .get_version(version_index)?;
-            let mut index_file = match version.get_index_id() {
-                Some(index_id) => self.indexes.get_index_file(&index_id)?,
-                None => self.indexes.create_unwritten_empty_index_file_box()
-            };
-            
-            index_file.get_index_ref()?.track_file(
-                file_path, 
-                TrackedFileAspects::NonExisting(
-                    TrackedNonExistingAspects::from_trackable(trackable_aspects)
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("track_non_existing")?;
+                repo.track_file(
+                    journal_entry_id,
+                    version_index,
+                    file_path,
+                    TrackedFileAspects::NonExisting(
+                        TrackedNonExistingAspects::from_trackable(trackable_aspects)
+                    )
                 )
-            )?;
-            let hash = self.indexes.put_index_file(index_file)?;
-            version.set_index_id(&hash);
-            state_file.get_state_ref()?.put_version(&version_index, version);
-
-            // TODO: Saving state?
-            self.state_collection.put_state_file(state_file)?;
-
+            })?;
             Ok(self)
         }
 
@@ -139,10 +349,21 @@ impl<
         pub fn track_directory(
             &'rpo mut self,
             version_index: usize,
-            file_path: OsString,
+            file_path: TrackedPath,
             trackable_aspects: TrackableDirectoryAspects,
         ) -> FcResult<&'rpo mut Self> {
-            todo!();
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("track_directory")?;
+                repo.track_file(
+                    journal_entry_id,
+                    version_index,
+                    file_path,
+                    TrackedFileAspects::Directory(
+                        TrackedDirectoryAspects::from_trackable(trackable_aspects)
+                    )
+                )
+            })?;
+            Ok(self)
         }
 
         /// Track an ordinary (blob) file.
@@ -154,11 +375,25 @@ impl<
         pub fn track_ordinary(
             &'rpo mut self,
             version_index: usize,
-            file_path: OsString,
+            file_path: TrackedPath,
             trackable_aspects: TrackableOrdinaryAspects,
-            blob_readable: &(dyn Read)
+            blob_readable: &mut dyn Read
         ) -> FcResult<&'rpo mut Self> {
-            todo!();
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("track_ordinary")?;
+                let hash_algorithm = repo.get_hash_algorithm()?;
+                let hash = repo.blobs.put_readable(blob_readable, hash_algorithm)?;
+                repo.record_put_blob(journal_entry_id, &hash)?;
+                repo.track_file(
+                    journal_entry_id,
+                    version_index,
+                    file_path,
+                    TrackedFileAspects::Ordinary(
+                        TrackedOrdinaryAspects::from_trackable(trackable_aspects, &hash)
+                    )
+                )
+            })?;
+            Ok(self)
         }
 
         /// Track a symlink.
@@ -169,10 +404,55 @@ impl<
         pub fn track_symlink(
             &'rpo mut self,
             version_index: usize,
-            file_path: OsString,
+            file_path: TrackedPath,
             trackable_aspects: TrackableSymlinkAspects,
         ) -> FcResult<&'rpo mut Self> {
-            todo!();
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("track_symlink")?;
+                repo.track_file(
+                    journal_entry_id,
+                    version_index,
+                    file_path,
+                    TrackedFileAspects::Symlink(
+                        TrackedSymlinkAspects::from_trackable(trackable_aspects)
+                    )
+                )
+            })?;
+            Ok(self)
+        }
+
+        /// Add the specified aspects to the index of the specified version.
+        /// 
+        /// This is what all the `track_*` methods boil down to once they've
+        /// turned their trackable aspects into tracked ones. It writes a new
+        /// index and points the version at it, as long as the index still
+        /// adheres to `index_consistency_rules` with the file added.
+        /// 
+        /// This completes the operation of the specified journal entry.
+        fn track_file(
+            &mut self,
+            journal_entry_id: JournalEntryId,
+            version_index: usize,
+            file_path: TrackedPath,
+            tracked_aspects: TrackedFileAspects,
+        ) -> FcResult<()> {
+            let mut state_file  = self.state_collection.get_state_file()?;
+            let mut version = state_file
+                .get_state_ref()?
+                .get_version(version_index)?;
+            let mut index_file = match version.get_index_id() {
+                Some(index_id) => self.indexes.get_index_file(&index_id)?,
+                None => self.indexes.create_unwritten_empty_index_file_box()
+            };
+            
+            index_file.get_index_ref()?.track_file(file_path, tracked_aspects)?;
+            let hash_algorithm = state_file.get_state_ref()?.hash_algorithm;
+            let hash = self.put_index_file(journal_entry_id, index_file, hash_algorithm)?;
+            version.set_index_id(&hash);
+            state_file.get_state_ref()?.put_version(&version_index, version);
+
+            // TODO: Saving state?
+            self.put_state_file(journal_entry_id, state_file)
         }
         
         pub fn get_files(
@@ -180,77 +460,108 @@ impl<
             version_index: usize,
             file_list: &mut (dyn RepoExportedFileList)
         ) -> FcResult<&'rpo mut Self> {
-            let mut state_file = self.state_collection.get_state_file()?;
-            let version = state_file
-                .get_state_ref()?
-                .get_version(version_index)?;
-            let index_id = match version.get_index_id() {
-                Some(index_id) => index_id,
-                // No index, no files to add to the file list.
-                None => return Ok(self),
-            };
-            let mut index_file = self.indexes.get_index_file(&index_id)?;
-            let index = index_file.get_index_ref()?;
+            self.locked(LockMode::Shared, |repo| {
+                let mut state_file = repo.state_collection.get_state_file()?;
+                let version = state_file
+                    .get_state_ref()?
+                    .get_version(version_index)?;
+                let index_id = match version.get_index_id() {
+                    Some(index_id) => index_id,
+                    // No index, no files to add to the file list.
+                    None => return Ok(()),
+                };
+                let mut index_file = repo.indexes.get_index_file(&index_id)?;
+                let index = index_file.get_index_ref()?;
 
-            for (path, tracked_file_aspects) in &index.files {
-                match tracked_file_aspects {
+                for (path, tracked_file_aspects) in &index.files {
+                    match tracked_file_aspects {
                     
-                    TrackedFileAspects::NonExisting(
-                        tracked_non_existing_aspects
-                    ) => {
-                        file_list.add_non_existing(
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            path.clone(),
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            tracked_non_existing_aspects.clone()
-                        )?;
-                    }
+                        TrackedFileAspects::NonExisting(
+                            tracked_non_existing_aspects
+                        ) => {
+                            file_list.add_non_existing(
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                path.clone().into_os_string(),
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                tracked_non_existing_aspects.clone()
+                            )?;
+                        }
                     
-                    TrackedFileAspects::Directory(
-                        tracked_directory_aspects
-                    ) => {
-                        file_list.add_directory(
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            path.clone(),
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            tracked_directory_aspects.clone()
-                        )?;
-                    }
+                        TrackedFileAspects::Directory(
+                            tracked_directory_aspects
+                        ) => {
+                            file_list.add_directory(
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                path.clone().into_os_string(),
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                tracked_directory_aspects.clone()
+                            )?;
+                        }
                     
-                    TrackedFileAspects::Ordinary(
-                        tracked_ordinary_aspects
-                    ) => {
-                        let blob_provider_file = self.blobs.get_file(
-                            &tracked_ordinary_aspects.hash
-                        )?;
-                        let blob_provider =
-                            blob_provider_file.as_tracked_ordinary_blob_provider_box();
-                        file_list.add_ordinary(
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            path.clone(),
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            tracked_ordinary_aspects.clone(),
-                            blob_provider
-                        )?;
-                    }
+                        TrackedFileAspects::Ordinary(
+                            tracked_ordinary_aspects
+                        ) => {
+                            let blob_provider_file = repo.blobs.get_file(
+                                &tracked_ordinary_aspects.hash
+                            )?;
+                            let blob_provider =
+                                blob_provider_file.as_tracked_ordinary_blob_provider_box();
+                            file_list.add_ordinary(
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                path.clone().into_os_string(),
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                tracked_ordinary_aspects.clone(),
+                                blob_provider
+                            )?;
+                        }
 
-                    TrackedFileAspects::Symlink(
-                        tracked_symlink_aspects
-                    ) => {
-                        file_list.add_symlink(
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            path.clone(),
-                            // TODO [clone]: Evaluate and possibly refactor.
-                            tracked_symlink_aspects.clone()
-                        )?;
+                        TrackedFileAspects::Symlink(
+                            tracked_symlink_aspects
+                        ) => {
+                            file_list.add_symlink(
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                path.clone().into_os_string(),
+                                // TODO [clone]: Evaluate and possibly refactor.
+                                tracked_symlink_aspects.clone()
+                            )?;
+                        }
                     }
                 }
-            }
-            
+                
+                Ok(())
+            })?;
             Ok(self)
         }
     }
 
+impl<
+    StateCollection: StateFileCollection,
+    Handler: OpaqueCollectionHandler,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, MiscIndexFileCollection<Handler>, Blobs, Journal> {
+
+        /* Notes:
+            The lock is held exclusively, so repos opened on the same
+            collections at the same time don't migrate the same index files
+            along with us, and so the state it follows is one recovery is
+            done with, which it is once the repo is open.
+        */
+        /// Moves the index files the versions of the repo refer to from
+        /// `source` into its index collection, returning how many were
+        /// moved, see `MiscIndexFileCollection::migrate_indexes_from`.
+        pub fn migrate_indexes_from<Source: OpaqueCollectionHandler>(&mut self, source: &mut Source)
+        -> FcResult<usize> {
+            self.locked(LockMode::Exclusive, |repo| {
+                if !repo.state_collection.has_state()? {
+                    return Ok(0)
+                }
+                let mut state_file = repo.state_collection.get_state_file()?;
+                repo.indexes.migrate_indexes_from(source, state_file.get_state_ref()?)
+            })
+        }
+    }
+
 // trait Accessor {
 
 // }
@@ -0,0 +1,171 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io::{Cursor, Read, Write};
+use std::path::Path;
+use crate::error::FcResult;
+use crate::files::RepoFile;
+use crate::files::blob::spool_blob;
+use crate::files::blob_encoding::{BlobCompression, encode_blob};
+use crate::files::hashable::get_file_name_of_hash;
+use crate::files::index_collection::{IndexFileCollection, MiscIndexFileCollection};
+use crate::files::state::StateFile;
+use crate::files::state_collection::{MiscStateFileCollection, StateFileCollection};
+use crate::files::tracked_ordinary_blob_collection::{MiscTrackedOrdinaryBlobFileCollection, TrackedOrdinaryBlobFileCollection};
+use crate::globals::{BLOBS_DIR_NAME, INDEXES_DIR_NAME, STATE_FILE_NAME};
+use crate::journal;
+use crate::journal::OptimisticDummyJournal;
+use crate::lock::LockMode;
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::opaque_collection_handler::drivers::tar::{TarArchive, TarCollection};
+use super::Repo;
+
+/// How a tar archive of a repo is compressed as a whole.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum ArchiveCompression {
+    /// A plain `.tar`.
+    None,
+    /// A `.tar.zst`, with the specified compression level.
+    Zstd { level: i32 },
+}
+
+/// A repo read straight out of a tar archive, see `Repo::open_tar_archive`.
+pub type TarRepo = Repo<
+    MiscStateFileCollection<TarCollection>,
+    MiscIndexFileCollection<TarCollection>,
+    MiscTrackedOrdinaryBlobFileCollection<TarCollection>,
+    OptimisticDummyJournal
+>;
+
+/// Appends a file to the archive, with nothing about it that would make
+/// archives of the same repo differ.
+fn append_file<Writeable: Write, Readable: Read>(
+    builder: &mut tar::Builder<Writeable>,
+    path: &Path,
+    length: u64,
+    readable: Readable
+) -> FcResult<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(length);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, path, readable)?;
+    Ok(())
+}
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            The archive is laid out like a repo in a local directory, with
+            the state at the top and blobs and indexes in directories of
+            their own, all of them flat. Blobs are stored uncompressed, as
+            compressing them one by one gains little over compressing the
+            archive as a whole. As the length of an entry goes before its
+            content, each blob is spooled to a temporary file on its way
+            into the archive, rather than held in memory.
+
+            Indexes are written as they're serialized now, which might not
+            be how they were serialized when they were put, so an index can
+            end up with a different ID in the archive, which the state in
+            the archive is then changed to refer to.
+
+            Only what the versions refer to is written, so the archive
+            doesn't carry anything left over from interrupted operations.
+        */
+        /// Writes the state of the repo and every index and blob its
+        /// versions refer to into a tar archive, which can be opened
+        /// as a repo again using `Repo::open_tar_archive`.
+        pub fn write_tar_archive<Writeable: Write>(
+            &mut self,
+            writeable: Writeable,
+            compression: ArchiveCompression
+        ) -> FcResult<()> {
+            self.locked(LockMode::Shared, |repo| {
+                match compression {
+                    ArchiveCompression::None => {
+                        repo.write_tar_entries(&mut tar::Builder::new(writeable))
+                    },
+                    ArchiveCompression::Zstd { level } => {
+                        let mut builder = tar::Builder::new(
+                            zstd::Encoder::new(writeable, level)?);
+                        repo.write_tar_entries(&mut builder)?;
+                        builder.into_inner()?.finish()?;
+                        Ok(())
+                    }
+                }
+            })
+        }
+
+        fn write_tar_entries<Writeable: Write>(
+            &mut self,
+            builder: &mut tar::Builder<Writeable>
+        ) -> FcResult<()> {
+            let mut state = self.state_collection.get_state_file()?.get_state_ref()?.clone();
+            let hash_algorithm = state.hash_algorithm;
+            let blobs_dir_path = Path::new(BLOBS_DIR_NAME);
+            let indexes_dir_path = Path::new(INDEXES_DIR_NAME);
+            let mut written_blob_hashes = HashSet::new();
+            let mut written_index_ids = HashSet::new();
+            for version in state.versions.iter_mut() {
+                let index_id = match version.get_index_id() {
+                    Some(index_id) => index_id,
+                    None => continue,
+                };
+                let mut index_file = self.indexes.get_index_file(&index_id)?;
+                for tracked_aspects in index_file.get_index_ref()?.files.values() {
+                    if let TrackedFileAspects::Ordinary(ordinary_aspects) = tracked_aspects {
+                        if !written_blob_hashes.insert(ordinary_aspects.hash.to_owned()) {
+                            continue
+                        }
+                        let blob_file = self.blobs.get_file(&ordinary_aspects.hash)?;
+                        let (spooled_file, length) = spool_blob(|file| encode_blob(
+                            &mut blob_file.get_readable()?, file, BlobCompression::None))?;
+                        append_file(
+                            builder, &blobs_dir_path.join(get_file_name_of_hash(&ordinary_aspects.hash)),
+                            length, spooled_file)?;
+                    }
+                }
+                let mut index_content = vec!();
+                index_file.get_readable()?.read_to_end(&mut index_content)?;
+                let archived_index_id = hash_algorithm.hash_readable(
+                    &mut Cursor::new(&index_content))?;
+                if written_index_ids.insert(archived_index_id.to_owned()) {
+                    append_file(
+                        builder, &indexes_dir_path.join(get_file_name_of_hash(&archived_index_id)),
+                        index_content.len() as u64, &index_content[..])?;
+                }
+                version.set_index_id(&archived_index_id);
+            }
+            let mut state_content = vec!();
+            StateFile::from_state(state).save(&mut state_content)?;
+            append_file(
+                builder, Path::new(STATE_FILE_NAME), state_content.len() as u64, &state_content[..])?;
+            builder.finish()?;
+            Ok(())
+        }
+    }
+
+impl TarRepo {
+    /// Opens the repo in the tar archive at the specified path, as
+    /// written by `Repo::write_tar_archive`, without extracting it. The
+    /// archive may be compressed with zstd.
+    ///
+    /// The repo can only be read, anything changing it fails with
+    /// `ErrorKind::ReadOnlyCollection`.
+    pub fn open_tar_archive<PathRef: AsRef<Path>>(path: PathRef) -> FcResult<Self> {
+        let archive = TarArchive::open(path)?;
+        Repo::open(
+            MiscStateFileCollection::new(
+                archive.get_collection(""), OsString::from(STATE_FILE_NAME)),
+            MiscIndexFileCollection::new(archive.get_collection(INDEXES_DIR_NAME)),
+            MiscTrackedOrdinaryBlobFileCollection::new(archive.get_collection(BLOBS_DIR_NAME)),
+            OptimisticDummyJournal::new(),
+        )
+    }
+}
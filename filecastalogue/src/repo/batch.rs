@@ -0,0 +1,297 @@
+use std::io::Read;
+use crate::error::FcResult;
+use crate::files::index::RepoIndexFile;
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state::RepoStateFile;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::journal::{JournalEntry, JournalEntryId, JournalRecord};
+use crate::lock::LockMode;
+use crate::meta::file_aspects::aspects::directory::{TrackableDirectoryAspects, TrackedDirectoryAspects};
+use crate::meta::file_aspects::aspects::non_existing::{TrackableNonExistingAspects, TrackedNonExistingAspects};
+use crate::meta::file_aspects::aspects::ordinary::{TrackableOrdinaryAspects, TrackedOrdinaryAspects};
+use crate::meta::file_aspects::aspects::symlink::{TrackableSymlinkAspects, TrackedSymlinkAspects};
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::index::accessor::IndexAccessor;
+use crate::meta::state::accessor::StateAccessor;
+use crate::meta::tracked_path::model::TrackedPath;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::meta::version::model::Version;
+use super::Repo;
+
+const BATCH_OPERATION: &str = "batch";
+
+/// Changes to the index of a version, made in memory until they're
+/// committed all at once, see `Repo::batch`.
+///
+/// The `track_*` methods work like their counterparts on `Repo`, except
+/// that nothing but blobs gets written until the batch is committed.
+pub struct RepoBatch<
+    'rpo,
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > {
+        repo: &'rpo mut Repo<StateCollection, Indexes, Blobs, Journal>,
+        journal_entry_id: JournalEntryId,
+        /// What's been recorded in the journal entry so far, so it can be
+        /// rolled back without reading the journal.
+        records: Vec<JournalRecord>,
+        state_file: Box<dyn RepoStateFile>,
+        version_index: usize,
+        version: Version,
+        /// Whether the version is only added once the batch is committed,
+        /// see `Repo::batch_new_version`.
+        is_new_version: bool,
+        index_file: Box<dyn RepoIndexFile>,
+    }
+
+impl<
+    'rpo,
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > RepoBatch<'rpo, StateCollection, Indexes, Blobs, Journal> {
+
+        fn new(
+            repo: &'rpo mut Repo<StateCollection, Indexes, Blobs, Journal>,
+            version_index: usize
+        ) -> FcResult<Self> {
+            let mut state_file = repo.state_collection.get_state_file()?;
+            let version = state_file.get_state_ref()?.get_version(version_index)?;
+            let index_file = match version.get_index_id() {
+                Some(index_id) => repo.indexes.get_index_file(&index_id)?,
+                None => repo.indexes.create_unwritten_empty_index_file_box()
+            };
+            let journal_entry_id = repo.journal.begin(BATCH_OPERATION)?;
+            Ok(Self {
+                repo,
+                journal_entry_id,
+                records: vec!(JournalRecord::Begin {
+                    operation: BATCH_OPERATION.to_owned()
+                }),
+                state_file,
+                version_index,
+                version,
+                is_new_version: false,
+                index_file,
+            })
+        }
+
+        /// Starts a batch for a version that doesn't exist yet, which
+        /// comes after all the existing ones.
+        fn new_for_new_version(repo: &'rpo mut Repo<StateCollection, Indexes, Blobs, Journal>)
+        -> FcResult<Self> {
+            let mut state_file = repo.state_collection.get_state_file()?;
+            let version_index = state_file.get_state_ref()?.versions.len();
+            let index_file = repo.indexes.create_unwritten_empty_index_file_box();
+            let journal_entry_id = repo.journal.begin(BATCH_OPERATION)?;
+            Ok(Self {
+                repo,
+                journal_entry_id,
+                records: vec!(JournalRecord::Begin {
+                    operation: BATCH_OPERATION.to_owned()
+                }),
+                state_file,
+                version_index,
+                version: Version::new(),
+                is_new_version: true,
+                index_file,
+            })
+        }
+
+        pub fn get_version_index(&self) -> usize {
+            self.version_index
+        }
+
+        /// Returns whether the index, as changed so far, tracks the
+        /// specified path.
+        pub fn tracks_file(&mut self, file_path: &TrackedPath) -> FcResult<bool> {
+            Ok(self.index_file.get_index_ref()?.tracks_file(file_path))
+        }
+
+        pub fn track_non_existing(
+            &mut self,
+            file_path: TrackedPath,
+            trackable_aspects: TrackableNonExistingAspects,
+        ) -> FcResult<&mut Self> {
+            self.track_file(
+                file_path,
+                TrackedFileAspects::NonExisting(
+                    TrackedNonExistingAspects::from_trackable(trackable_aspects)
+                )
+            )
+        }
+
+        pub fn track_directory(
+            &mut self,
+            file_path: TrackedPath,
+            trackable_aspects: TrackableDirectoryAspects,
+        ) -> FcResult<&mut Self> {
+            self.track_file(
+                file_path,
+                TrackedFileAspects::Directory(
+                    TrackedDirectoryAspects::from_trackable(trackable_aspects)
+                )
+            )
+        }
+
+        /// Unlike the other changes, this puts the blob right away, which is
+        /// removed again if the batch gets discarded and nothing else
+        /// refers to it.
+        pub fn track_ordinary(
+            &mut self,
+            file_path: TrackedPath,
+            trackable_aspects: TrackableOrdinaryAspects,
+            blob_readable: &mut dyn Read
+        ) -> FcResult<&mut Self> {
+            let hash_algorithm = self.state_file.get_state_ref()?.hash_algorithm;
+            let hash = self.repo.blobs.put_readable(blob_readable, hash_algorithm)?;
+            self.repo.record_put_blob(self.journal_entry_id, &hash)?;
+            self.records.push(JournalRecord::PutBlob { hash: hash.to_owned() });
+            self.track_file(
+                file_path,
+                TrackedFileAspects::Ordinary(
+                    TrackedOrdinaryAspects::from_trackable(trackable_aspects, &hash)
+                )
+            )
+        }
+
+        pub fn track_symlink(
+            &mut self,
+            file_path: TrackedPath,
+            trackable_aspects: TrackableSymlinkAspects,
+        ) -> FcResult<&mut Self> {
+            self.track_file(
+                file_path,
+                TrackedFileAspects::Symlink(
+                    TrackedSymlinkAspects::from_trackable(trackable_aspects)
+                )
+            )
+        }
+
+        /// Stops tracking whatever is tracked at the specified path.
+        pub fn untrack(&mut self, file_path: &TrackedPath) -> FcResult<&mut Self> {
+            self.index_file.get_index_ref()?.untrack_file(file_path)?;
+            Ok(self)
+        }
+
+        fn track_file(
+            &mut self,
+            file_path: TrackedPath,
+            tracked_aspects: TrackedFileAspects,
+        ) -> FcResult<&mut Self> {
+            self.index_file.get_index_ref()?.track_file(file_path, tracked_aspects)?;
+            Ok(self)
+        }
+
+        /// Hands the batch to `operations`, committing it if that works out
+        /// and discarding it otherwise.
+        fn run<T>(
+            mut self,
+            operations: impl FnOnce(&mut Self) -> FcResult<T>
+        ) -> FcResult<T> {
+            match operations(&mut self) {
+                Ok(value) => {
+                    self.commit()?;
+                    Ok(value)
+                },
+                Err(e) => {
+                    self.discard()?;
+                    Err(e)
+                }
+            }
+        }
+
+        /// Writes the index as changed by the batch and points the version
+        /// at it, as long as it adheres to `index_consistency_rules`,
+        /// discarding the batch otherwise.
+        fn commit(mut self) -> FcResult<()> {
+            let index_id = match self.put_index_file() {
+                Ok(index_id) => index_id,
+                Err(e) => {
+                    self.discard()?;
+                    return Err(e)
+                }
+            };
+            self.version.set_index_id(&index_id);
+            let version = self.version;
+            let state = self.state_file.get_state_ref()?;
+            match self.is_new_version {
+                true => { state.add_version(version); },
+                false => { state.put_version(&self.version_index, version); },
+            }
+            self.repo.put_state_file(self.journal_entry_id, self.state_file)
+        }
+
+        fn put_index_file(&mut self) -> FcResult<String> {
+            let hash_algorithm = self.state_file.get_state_ref()?.hash_algorithm;
+            let index_file = std::mem::replace(
+                &mut self.index_file,
+                self.repo.indexes.create_unwritten_empty_index_file_box()
+            );
+            let index_id = self.repo.put_index_file(
+                self.journal_entry_id, index_file, hash_algorithm)?;
+            self.records.push(JournalRecord::PutIndex { hash: index_id.to_owned() });
+            Ok(index_id)
+        }
+
+        /// Removes whatever the batch has put already, unless something
+        /// else refers to it, leaving the repo as it was.
+        fn discard(self) -> FcResult<()> {
+            self.repo.roll_back(&JournalEntry {
+                id: self.journal_entry_id,
+                operation: BATCH_OPERATION.to_owned(),
+                records: self.records,
+            })?;
+            self.repo.journal.complete(self.journal_entry_id)
+        }
+    }
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            The lock is held exclusively for the whole batch, so nothing
+            else can change the version in the meantime.
+        */
+        /// Makes the changes `operations` makes to the specified version
+        /// through the batch it's handed, writing a single index and
+        /// updating the state once, after `operations` has returned.
+        /// If it fails, everything it did is discarded instead.
+        pub fn batch<T>(
+            &mut self,
+            version_index: usize,
+            operations: impl FnOnce(
+                &mut RepoBatch<StateCollection, Indexes, Blobs, Journal>
+            ) -> FcResult<T>
+        ) -> FcResult<T> {
+            self.locked(LockMode::Exclusive, |repo| {
+                RepoBatch::new(repo, version_index)?.run(operations)
+            })
+        }
+
+        /// Makes the changes `operations` makes like `batch` does, to a
+        /// version that's added along with them, returning its index
+        /// alongside what `operations` returns. If it fails, the version
+        /// isn't added either.
+        pub fn batch_new_version<T>(
+            &mut self,
+            operations: impl FnOnce(
+                &mut RepoBatch<StateCollection, Indexes, Blobs, Journal>
+            ) -> FcResult<T>
+        ) -> FcResult<(usize, T)> {
+            self.locked(LockMode::Exclusive, |repo| {
+                let batch = RepoBatch::new_for_new_version(repo)?;
+                let version_index = batch.get_version_index();
+                Ok((version_index, batch.run(operations)?))
+            })
+        }
+    }
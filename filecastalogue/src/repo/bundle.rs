@@ -0,0 +1,325 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+use crate::error::{Error, ErrorKind, FcResult, KeyValuePayload};
+use crate::files::blob::spool_blob;
+use crate::files::hashable::{HashAlgorithm, get_file_name_of_hash, normalize_hash};
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::journal::{JournalEntry, JournalEntryId, JournalRecord};
+use crate::lock::LockMode;
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::state::accessor::StateAccessor;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::meta::version::model::Version;
+use crate::opaque_collection_handler::ContentHashMismatchPayload;
+use super::Repo;
+
+const IMPORT_BUNDLE_OPERATION: &str = "import_bundle";
+/// The format of the bundles written by this version of the crate.
+const BUNDLE_FORMAT_VERSION: u32 = 1;
+const MANIFEST_ENTRY_NAME: &str = "manifest.json";
+const INDEX_ENTRY_NAME: &str = "index.json";
+const BLOBS_ENTRY_DIR_NAME: &str = "blobs";
+
+/// What's in a bundle, written as its first entry.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+pub struct BundleManifest {
+    pub format_version: u32,
+    /// The version as it was in the repo the bundle was written from,
+    /// which is what it's added as when the bundle is imported.
+    pub version: Version,
+    /// The hashes of all blobs the index of the version refers to,
+    /// whether they're in the bundle or not.
+    pub blob_hashes: Vec<String>,
+    /// The index of the version the bundle is a delta against, if it is.
+    /// The blobs that version refers to are left out, so the receiver
+    /// needs to have them already.
+    pub delta_base_index_id: Option<String>,
+    /// The hash of every other entry in the bundle, by the entry's name.
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// What importing a bundle did, see `Repo::import_bundle`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ImportedBundle {
+    /// The index of the version the bundle was added as.
+    pub version_index: usize,
+    /// The hashes of the blobs that were put into the repo.
+    pub imported_blob_hashes: Vec<String>,
+    /// The hashes of the blobs in the bundle the repo had already.
+    pub deduplicated_blob_hashes: Vec<String>,
+}
+
+fn get_invalid_bundle_error(context: &'static str, entry_name: &str) -> Error {
+    error!(
+        ErrorKind::InvalidBundle,
+        context,
+        payload => payload!("Entry: ", Box::new(entry_name.to_owned()))
+    )
+}
+
+/// Appends a file to the bundle, with nothing about it that would make
+/// bundles of the same version differ.
+fn append_entry<Writeable: Write, Readable: Read>(
+    builder: &mut tar::Builder<Writeable>,
+    name: &str,
+    length: u64,
+    readable: Readable
+) -> FcResult<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(length);
+    header.set_mode(0o644);
+    header.set_mtime(0);
+    builder.append_data(&mut header, name, readable)?;
+    Ok(())
+}
+
+fn get_blob_entry_name(hash: &str) -> String {
+    format!("{}/{}", BLOBS_ENTRY_DIR_NAME, get_file_name_of_hash(hash).to_string_lossy())
+}
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /// Returns the hashes of the blobs the index refers to, sorted.
+        fn get_blob_hashes_of_index(&mut self, index_id: &str) -> FcResult<Vec<String>> {
+            let mut index_file = self.indexes.get_index_file(index_id)?;
+            let mut blob_hashes: Vec<String> = index_file.get_index_ref()?.files.values()
+                .filter_map(|tracked_aspects| match tracked_aspects {
+                    TrackedFileAspects::Ordinary(ordinary_aspects) =>
+                        Some(ordinary_aspects.hash.to_owned()),
+                    _ => None,
+                })
+                .collect();
+            blob_hashes.sort();
+            blob_hashes.dedup();
+            Ok(blob_hashes)
+        }
+
+        /* Notes:
+            A bundle is a tar archive with the manifest first, then the
+            index of the version as it's stored, then the blobs, decoded.
+            Every entry is named after what it is rather than its hash, the
+            hash is in the checksums of the manifest, so it can be checked
+            while the bundle is read front to back. Blobs are spooled to a
+            temporary file on their way into the bundle, as the length of
+            an entry goes before its content.
+        */
+        /// Writes the specified version, its index and every blob it refers
+        /// to into a single file, to be added to another repo using
+        /// `Repo::import_bundle`. Returns the manifest of the bundle.
+        ///
+        /// With `delta_base_version_index`, blobs the version of that index
+        /// refers to are left out, for a receiver that has it already.
+        pub fn write_bundle<Writeable: Write>(
+            &mut self,
+            version_index: usize,
+            delta_base_version_index: Option<usize>,
+            writeable: Writeable
+        ) -> FcResult<BundleManifest> {
+            self.locked(LockMode::Shared, |repo| {
+                let mut state_file = repo.state_collection.get_state_file()?;
+                let version = state_file.get_state_ref()?.get_version(version_index)?;
+                let delta_base_index_id = match delta_base_version_index {
+                    Some(base_version_index) => state_file.get_state_ref()?
+                        .get_version(base_version_index)?.get_index_id(),
+                    None => None,
+                };
+                let mut manifest = BundleManifest {
+                    format_version: BUNDLE_FORMAT_VERSION,
+                    version: version.clone(),
+                    blob_hashes: vec!(),
+                    delta_base_index_id: delta_base_index_id.clone(),
+                    checksums: BTreeMap::new(),
+                };
+                let mut index_content = vec!();
+                let mut bundled_blob_hashes = vec!();
+                if let Some(index_id) = version.get_index_id() {
+                    repo.indexes.get_index_readable(&index_id)?.read_to_end(&mut index_content)?;
+                    manifest.checksums.insert(INDEX_ENTRY_NAME.to_owned(), index_id.to_owned());
+                    manifest.blob_hashes = repo.get_blob_hashes_of_index(&index_id)?;
+                    let base_blob_hashes: HashSet<String> = match delta_base_index_id {
+                        Some(base_index_id) => repo.get_blob_hashes_of_index(&base_index_id)?
+                            .into_iter().collect(),
+                        None => HashSet::new(),
+                    };
+                    for hash in manifest.blob_hashes.iter() {
+                        if !base_blob_hashes.contains(hash) {
+                            manifest.checksums.insert(get_blob_entry_name(hash), hash.to_owned());
+                            bundled_blob_hashes.push(hash.to_owned());
+                        }
+                    }
+                }
+
+                let mut builder = tar::Builder::new(writeable);
+                let manifest_content = serde_json::to_vec_pretty(&manifest)?;
+                append_entry(
+                    &mut builder, MANIFEST_ENTRY_NAME,
+                    manifest_content.len() as u64, &manifest_content[..])?;
+                if version.get_index_id().is_some() {
+                    append_entry(
+                        &mut builder, INDEX_ENTRY_NAME,
+                        index_content.len() as u64, &index_content[..])?;
+                }
+                for hash in bundled_blob_hashes.iter() {
+                    let blob_file = repo.blobs.get_file(hash)?;
+                    let (spooled_file, length) = spool_blob(|file| {
+                        io::copy(&mut blob_file.get_readable()?, file)?;
+                        Ok(())
+                    })?;
+                    append_entry(&mut builder, &get_blob_entry_name(hash), length, spooled_file)?;
+                }
+                builder.into_inner()?.flush()?;
+                Ok(manifest)
+            })
+        }
+
+        /* Notes:
+            An index or blob the repo has already is skipped rather than
+            put again, which is also why it isn't checked against its
+            hash: It's not read into the repo.
+        */
+        /// Adds the version in the bundle to the repo as a new version,
+        /// along with the index and blobs in the bundle. Everything read
+        /// from the bundle is checked against the checksums in its
+        /// manifest, failing with `ErrorKind::ContentHashMismatch` for
+        /// anything that doesn't match.
+        ///
+        /// Fails with `ErrorKind::InvalidBundle` if the bundle isn't one
+        /// `Repo::write_bundle` would write, or if it's a delta and the repo
+        /// is missing a blob it left out.
+        pub fn import_bundle<Readable: Read>(&mut self, readable: Readable)
+        -> FcResult<ImportedBundle> {
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin(IMPORT_BUNDLE_OPERATION)?;
+                let mut records = vec!(JournalRecord::Begin {
+                    operation: IMPORT_BUNDLE_OPERATION.to_owned()
+                });
+                let result = repo.import_bundle_entries(
+                    readable, journal_entry_id, &mut records);
+                match result {
+                    Ok((version, mut imported_bundle)) => {
+                        let mut state_file = repo.state_collection.get_state_file()?;
+                        imported_bundle.version_index = state_file.get_state_ref()?
+                            .add_version(version);
+                        repo.put_state_file(journal_entry_id, state_file)?;
+                        Ok(imported_bundle)
+                    },
+                    Err(e) => {
+                        repo.roll_back(&JournalEntry {
+                            id: journal_entry_id,
+                            operation: IMPORT_BUNDLE_OPERATION.to_owned(),
+                            records,
+                        })?;
+                        repo.journal.complete(journal_entry_id)?;
+                        Err(e)
+                    }
+                }
+            })
+        }
+
+        /// Puts the index and blobs in the bundle into the repo, returning
+        /// the version to add for it.
+        fn import_bundle_entries<Readable: Read>(
+            &mut self,
+            readable: Readable,
+            journal_entry_id: JournalEntryId,
+            records: &mut Vec<JournalRecord>
+        ) -> FcResult<(Version, ImportedBundle)> {
+            let mut imported_bundle = ImportedBundle {
+                version_index: 0,
+                imported_blob_hashes: vec!(),
+                deduplicated_blob_hashes: vec!(),
+            };
+            let mut archive = tar::Archive::new(readable);
+            let mut entries = archive.entries()?;
+            let manifest: BundleManifest = match entries.next() {
+                Some(entry) => {
+                    let entry = entry?;
+                    if entry.path()? != Path::new(MANIFEST_ENTRY_NAME) {
+                        return Err(get_invalid_bundle_error(
+                            "Reading the manifest of a bundle.", MANIFEST_ENTRY_NAME))
+                    }
+                    serde_json::from_reader(entry)?
+                },
+                None => return Err(get_invalid_bundle_error(
+                    "Reading the manifest of a bundle.", MANIFEST_ENTRY_NAME)),
+            };
+            if manifest.format_version != BUNDLE_FORMAT_VERSION {
+                return Err(get_invalid_bundle_error(
+                    "Checking the format version of a bundle.", MANIFEST_ENTRY_NAME))
+            }
+
+            let mut unread_entry_names: HashSet<String> = manifest.checksums.keys()
+                .cloned().collect();
+            for entry in entries {
+                let mut entry = entry?;
+                let entry_name = entry.path()?.to_string_lossy().into_owned();
+                let expected_hash = match manifest.checksums.get(&entry_name) {
+                    Some(expected_hash) if unread_entry_names.remove(&entry_name) => expected_hash,
+                    _ => return Err(get_invalid_bundle_error(
+                        "Reading an entry of a bundle not in its manifest.", &entry_name)),
+                };
+                let hash_algorithm = HashAlgorithm::of_hash(expected_hash)?;
+                let actual_hash = if entry_name == INDEX_ENTRY_NAME {
+                    if self.indexes.has_index(expected_hash)? {
+                        continue
+                    }
+                    let actual_hash = self.put_index_readable(
+                        journal_entry_id, &mut entry, hash_algorithm)?;
+                    records.push(JournalRecord::PutIndex { hash: actual_hash.to_owned() });
+                    actual_hash
+                } else {
+                    if self.blobs.has_file(expected_hash)? {
+                        imported_bundle.deduplicated_blob_hashes.push(expected_hash.to_owned());
+                        continue
+                    }
+                    let actual_hash = self.blobs.put_readable(&mut entry, hash_algorithm)?;
+                    self.record_put_blob(journal_entry_id, &actual_hash)?;
+                    records.push(JournalRecord::PutBlob { hash: actual_hash.to_owned() });
+                    imported_bundle.imported_blob_hashes.push(expected_hash.to_owned());
+                    actual_hash
+                };
+                if normalize_hash(&actual_hash) != normalize_hash(expected_hash) {
+                    return Err(error!(
+                        ErrorKind::ContentHashMismatch,
+                        "Verifying an entry read from a bundle.",
+                        payload => ContentHashMismatchPayload {
+                            file_name: OsString::from(entry_name),
+                            actual_hash
+                        }
+                    ))
+                }
+            }
+            if let Some(entry_name) = unread_entry_names.into_iter().next() {
+                return Err(get_invalid_bundle_error(
+                    "Reading an entry of a bundle that's in its manifest.", &entry_name))
+            }
+
+            if let Some(index_id) = manifest.version.get_index_id() {
+                if manifest.checksums.get(INDEX_ENTRY_NAME) != Some(&index_id) {
+                    return Err(get_invalid_bundle_error(
+                        "Checking the index of a bundle against its version.", INDEX_ENTRY_NAME))
+                }
+                let index_blob_hashes = self.get_blob_hashes_of_index(&index_id)?;
+                for hash in index_blob_hashes {
+                    if !self.blobs.has_file(&hash)? {
+                        return Err(get_invalid_bundle_error(
+                            "Checking for a blob left out of a delta bundle.",
+                            &get_blob_entry_name(&hash)))
+                    }
+                }
+            }
+            Ok((manifest.version, imported_bundle))
+        }
+    }
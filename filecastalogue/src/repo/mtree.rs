@@ -0,0 +1,406 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use crate::error::{Error, ErrorKind, FcResult, KeyValuePayload};
+use crate::files::hashable::{HashAlgorithm, HashingReader};
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::lock::LockMode;
+use crate::meta::file_aspects::aspects::directory::TrackableDirectoryAspects;
+use crate::meta::file_aspects::aspects::ordinary::TrackableOrdinaryAspects;
+use crate::meta::file_aspects::aspects::symlink::TrackableSymlinkAspects;
+use crate::meta::file_aspects::attributes::Attributes;
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::repo_exported_file_list::model::UnrepresentedAspect;
+use crate::meta::state::accessor::StateAccessor;
+use crate::meta::tracked_path::model::TrackedPath;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::opaque_collection_handler::ContentHashMismatchPayload;
+use self::spec::{MtreeEntry, encode_name, parse_spec};
+use super::Repo;
+use super::batch::RepoBatch;
+
+mod spec;
+
+/// Keywords of an mtree spec that are tracked, or checked against the
+/// contents of the files, when a spec is imported.
+const TRACKED_KEYWORDS: &[&str] = &[
+    "type", "mode", "uid", "gid", "uname", "gname", "link", "contents",
+    "size", "sha256", "sha256digest", "blake3digest",
+];
+
+/// What importing an mtree spec did, see `Repo::import_mtree_spec`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct ImportedMtreeSpec {
+    pub version_index: usize,
+    pub unrepresented_aspects: Vec<UnrepresentedAspect>,
+}
+
+/// Returns the path as it's written in an mtree spec, relative to its root.
+fn get_spec_path(path: &TrackedPath) -> String {
+    match path.as_relative_path().as_os_str().is_empty() {
+        true => String::from("."),
+        false => format!("./{}", encode_name(path.as_relative_path().as_os_str())),
+    }
+}
+
+/// Returns the owner keywords for the attributes, with owners which are
+/// nothing but a number taken to be IDs, e.g. those of tarball entries
+/// without names.
+fn get_owner_keywords(attributes: &Attributes) -> String {
+    let user_keyword = match attributes.posix_user.parse::<u32>() {
+        Ok(uid) => format!("uid={}", uid),
+        Err(_) => format!("uname={}", encode_name(attributes.posix_user.as_ref())),
+    };
+    let group_keyword = match attributes.posix_group.parse::<u32>() {
+        Ok(gid) => format!("gid={}", gid),
+        Err(_) => format!("gname={}", encode_name(attributes.posix_group.as_ref())),
+    };
+    match attributes.posix_mode {
+        Some(mode) => format!("{} {} mode={:04o}", user_keyword, group_keyword, mode),
+        None => format!("{} {}", user_keyword, group_keyword),
+    }
+}
+
+fn get_keyword<'entry>(entry: &'entry MtreeEntry, keyword: &str) -> Option<&'entry str> {
+    entry.keywords.get(keyword).map(String::as_str)
+}
+
+fn get_spec_mismatch_error(context: &'static str, path: &TrackedPath) -> Error {
+    error!(
+        ErrorKind::InvalidMtreeSpec,
+        context,
+        payload => payload!("Path: ", Box::new(path.clone()))
+    )
+}
+
+/// Returns the attributes of the entry, with owners taken from their
+/// names, or their IDs if there are no names.
+fn get_attributes(
+    entry: &MtreeEntry,
+    unrepresented_aspects: &mut Vec<UnrepresentedAspect>
+) -> FcResult<Attributes> {
+    let mut get_owner = |name_keyword, id_keyword| {
+        match (get_keyword(entry, name_keyword), get_keyword(entry, id_keyword)) {
+            (Some(name), _) => name.to_owned(),
+            (None, Some(id)) => id.to_owned(),
+            (None, None) => {
+                unrepresented_aspects.push(UnrepresentedAspect::new(
+                    &entry.path,
+                    format!("No {} or {}, tracked as root.", name_keyword, id_keyword)
+                ));
+                String::from("root")
+            },
+        }
+    };
+    let posix_user = get_owner("uname", "uid");
+    let posix_group = get_owner("gname", "gid");
+    let posix_mode = match get_keyword(entry, "mode") {
+        Some(mode) => match u32::from_str_radix(mode, 8) {
+            Ok(mode) => Some(mode),
+            Err(_) => return Err(get_spec_mismatch_error(
+                "Reading the mode of an entry of an mtree spec.", &entry.path)),
+        },
+        None => None,
+    };
+    Ok(Attributes {
+        posix_user,
+        posix_group,
+        posix_mode
+    })
+}
+
+/// How big a file's content is and what it hashes to, which is how an
+/// mtree spec describes it.
+struct ContentMeasures {
+    size: u64,
+    sha256_hash: String,
+    blake3_hash: String,
+}
+
+/// Passes through what the wrapped Read provides, taking its measures
+/// on the way, so content can be described or checked while it's being
+/// read for something else anyway.
+struct MeasuringReader<R: Read> {
+    inner: HashingReader<HashingReader<R>>,
+    size: u64,
+}
+
+impl<R: Read> MeasuringReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner: HashingReader::new_with_algorithm(
+                HashingReader::new_with_algorithm(inner, HashAlgorithm::Sha256),
+                HashAlgorithm::Blake3
+            ),
+            size: 0,
+        }
+    }
+
+    /// Returns the measures of everything read so far.
+    fn get_measures(&self) -> ContentMeasures {
+        ContentMeasures {
+            size: self.size,
+            sha256_hash: self.inner.get_ref().finalize(),
+            blake3_hash: self.inner.finalize(),
+        }
+    }
+}
+
+impl<R: Read> Read for MeasuringReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read_count = self.inner.read(buf)?;
+        self.size += read_count as u64;
+        Ok(read_count)
+    }
+}
+
+/// Checks the measures of the content against the digests and size of
+/// the entry, as far as it has any.
+fn verify_content(entry: &MtreeEntry, content_path: &Path, measures: &ContentMeasures)
+-> FcResult<()> {
+    if let Some(size) = get_keyword(entry, "size") {
+        if size.parse::<u64>().ok() != Some(measures.size) {
+            return Err(get_spec_mismatch_error(
+                "Checking the size of a file against an mtree spec.", &entry.path))
+        }
+    }
+    let digests = [
+        (HashAlgorithm::Sha256, &measures.sha256_hash,
+            get_keyword(entry, "sha256digest").or(get_keyword(entry, "sha256"))),
+        (HashAlgorithm::Blake3, &measures.blake3_hash, get_keyword(entry, "blake3digest")),
+    ];
+    for (hash_algorithm, actual_hash, digest) in digests.iter() {
+        if let Some(digest) = digest {
+            if **actual_hash != format!("{}:{}", hash_algorithm, digest) {
+                return Err(error!(
+                    ErrorKind::ContentHashMismatch,
+                    "Checking the digest of a file against an mtree spec.",
+                    payload => ContentHashMismatchPayload {
+                        file_name: content_path.as_os_str().to_owned(),
+                        actual_hash: actual_hash.to_string()
+                    }
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns where the content of the entry is read from, which is its
+/// path in the spec within `content_path`, unless `contents` says
+/// otherwise. That has to be a relative path that stays within
+/// `content_path` too, failing with `ErrorKind::InvalidMtreeSpec`.
+/// Symlinks are resolved, so neither can lead outside of it through one.
+fn get_content_file_path(entry: &MtreeEntry, content_path: &Path) -> FcResult<PathBuf> {
+    let file_path = match get_keyword(entry, "contents") {
+        Some(contents) => match Path::new(contents).components().all(
+            |component| matches!(component, Component::Normal(_) | Component::CurDir)) {
+            true => content_path.join(contents),
+            false => return Err(get_spec_mismatch_error(
+                "Reading the contents path of an entry of an mtree spec.", &entry.path)),
+        },
+        None => content_path.join(entry.path.as_relative_path()),
+    };
+    let resolved_file_path = fs::canonicalize(file_path)?;
+    match resolved_file_path.starts_with(fs::canonicalize(content_path)?) {
+        true => Ok(resolved_file_path),
+        false => Err(get_spec_mismatch_error(
+            "Resolving the contents path of an entry of an mtree spec.", &entry.path)),
+    }
+}
+
+/* Notes:
+    `nlink=1` and `flags=none` say nothing that would need to be
+    represented, so they aren't reported like other keywords that aren't
+    tracked.
+*/
+/// Tracks the entries of an mtree spec through the batch, see
+/// `Repo::track_mtree_spec`.
+fn track_mtree_entries<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    >(
+    batch: &mut RepoBatch<StateCollection, Indexes, Blobs, Journal>,
+    entries: &[MtreeEntry],
+    content_path: &Path
+) -> FcResult<Vec<UnrepresentedAspect>> {
+    let mut unrepresented_aspects = vec!();
+    for entry in entries.iter() {
+        for (keyword, value) in entry.keywords.iter() {
+            if TRACKED_KEYWORDS.contains(&keyword.as_str())
+            || (keyword == "nlink" && value == "1")
+            || (keyword == "flags" && value == "none") {
+                continue
+            }
+            unrepresented_aspects.push(UnrepresentedAspect::new(
+                &entry.path, format!("Keyword {}={}, ignored.", keyword, value)));
+        }
+        match get_keyword(entry, "type") {
+            Some("dir") => {
+                batch.track_directory(
+                    entry.path.clone(),
+                    TrackableDirectoryAspects::new(
+                        get_attributes(entry, &mut unrepresented_aspects)?)
+                )?;
+            },
+            Some("file") => {
+                let file_path = get_content_file_path(entry, content_path)?;
+                // Checked once it's tracked, as it's all discarded
+                // anyway if it doesn't match.
+                let mut measuring_readable = MeasuringReader::new(
+                    File::open(&file_path)?);
+                batch.track_ordinary(
+                    entry.path.clone(),
+                    TrackableOrdinaryAspects::new(
+                        get_attributes(entry, &mut unrepresented_aspects)?),
+                    &mut measuring_readable
+                )?;
+                verify_content(entry, &file_path, &measuring_readable.get_measures())?;
+            },
+            Some("link") => {
+                let linked_to = match get_keyword(entry, "link") {
+                    Some(linked_to) => linked_to,
+                    None => return Err(get_spec_mismatch_error(
+                        "Reading the target of a link in an mtree spec.", &entry.path)),
+                };
+                batch.track_symlink(
+                    entry.path.clone(),
+                    TrackableSymlinkAspects::new(linked_to.to_owned())
+                )?;
+            },
+            Some(entry_type) => {
+                unrepresented_aspects.push(UnrepresentedAspect::new(
+                    &entry.path, format!("Entry of type {}, left out.", entry_type)));
+            },
+            None => return Err(get_spec_mismatch_error(
+                "Reading the type of an entry of an mtree spec.", &entry.path)),
+        }
+    }
+    Ok(unrepresented_aspects)
+}
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            Entries are written with their full path, sorted, and digests
+            are made with both sha256, which every mtree implementation
+            knows, and blake3, regardless of what the repo hashes with.
+        */
+        /// Writes the index of the specified version as an mtree spec, with
+        /// the type, owners, mode, link target, size and digests of each
+        /// file. Returns whatever an mtree spec can't represent, which is
+        /// left out, namely non-existing files and the modes of exclusive
+        /// directories.
+        pub fn write_mtree_spec<Writeable: Write>(
+            &mut self,
+            version_index: usize,
+            mut writeable: Writeable
+        ) -> FcResult<Vec<UnrepresentedAspect>> {
+            self.locked(LockMode::Shared, |repo| {
+                let version = repo.state_collection.get_state_file()?
+                    .get_state_ref()?.get_version(version_index)?;
+                let mut files: Vec<(TrackedPath, TrackedFileAspects)> = match version.get_index_id() {
+                    Some(index_id) => repo.indexes.get_index_file(&index_id)?
+                        .get_index_ref()?.files.clone().into_iter().collect(),
+                    None => vec!(),
+                };
+                files.sort_by(|(path, _), (other_path, _)| path.as_os_str().cmp(other_path.as_os_str()));
+
+                let mut unrepresented_aspects = vec!();
+                writeln!(writeable, "#mtree")?;
+                for (path, tracked_aspects) in files {
+                    let spec_path = get_spec_path(&path);
+                    match tracked_aspects {
+                        TrackedFileAspects::NonExisting(_) => {
+                            unrepresented_aspects.push(UnrepresentedAspect::new(
+                                &path, String::from("Non-existing file, left out.")));
+                        },
+                        TrackedFileAspects::Directory(directory_aspects) => {
+                            if directory_aspects.mode.is_exclusive() {
+                                unrepresented_aspects.push(UnrepresentedAspect::new(
+                                    &path, String::from("Exclusive directory mode.")));
+                            }
+                            writeln!(
+                                writeable, "{} type=dir {}",
+                                spec_path, get_owner_keywords(&directory_aspects.attributes)
+                            )?;
+                        },
+                        TrackedFileAspects::Ordinary(ordinary_aspects) => {
+                            let blob_file = repo.blobs.get_file(&ordinary_aspects.hash)?;
+                            let mut measuring_readable = MeasuringReader::new(
+                                blob_file.get_readable()?);
+                            io::copy(&mut measuring_readable, &mut io::sink())?;
+                            let measures = measuring_readable.get_measures();
+                            writeln!(
+                                writeable, "{} type=file {} size={} sha256digest={} blake3digest={}",
+                                spec_path,
+                                get_owner_keywords(&ordinary_aspects.attributes),
+                                measures.size,
+                                measures.sha256_hash.split_once(':').map_or("", |(_, digest)| digest),
+                                measures.blake3_hash.split_once(':').map_or("", |(_, digest)| digest)
+                            )?;
+                        },
+                        TrackedFileAspects::Symlink(symlink_aspects) => {
+                            writeln!(
+                                writeable, "{} type=link link={}",
+                                spec_path, encode_name(symlink_aspects.linked_to.as_ref())
+                            )?;
+                        },
+                    }
+                }
+                writeable.flush()?;
+                Ok(unrepresented_aspects)
+            })
+        }
+
+        /// Tracks the files the mtree spec describes in the specified
+        /// version, as a single batch, with the contents of ordinary files
+        /// read from `content_path`, where they're expected at their path
+        /// in the spec, unless it says otherwise with `contents`. Contents
+        /// are checked against the size and digests in the spec, failing
+        /// with `ErrorKind::ContentHashMismatch` or
+        /// `ErrorKind::InvalidMtreeSpec`.
+        ///
+        /// Returns whatever the spec describes that can't be tracked, which
+        /// is left out, e.g. devices or modification times.
+        pub fn track_mtree_spec<Readable: Read, PathRef: AsRef<Path>>(
+            &mut self,
+            version_index: usize,
+            mut spec_readable: Readable,
+            content_path: PathRef
+        ) -> FcResult<Vec<UnrepresentedAspect>> {
+            let entries = parse_spec(&mut spec_readable)?;
+            let content_path = content_path.as_ref();
+            self.batch(
+                version_index, |batch| track_mtree_entries(batch, &entries, content_path))
+        }
+
+        /// Adds a new version tracking what the mtree spec describes, see
+        /// `track_mtree_spec`.
+        ///
+        /// If the spec can't be tracked, no version is added.
+        pub fn import_mtree_spec<Readable: Read, PathRef: AsRef<Path>>(
+            &mut self,
+            mut spec_readable: Readable,
+            content_path: PathRef
+        ) -> FcResult<ImportedMtreeSpec> {
+            let entries = parse_spec(&mut spec_readable)?;
+            let content_path = content_path.as_ref();
+            let (version_index, unrepresented_aspects) = self.batch_new_version(
+                |batch| track_mtree_entries(batch, &entries, content_path))?;
+            Ok(ImportedMtreeSpec {
+                version_index,
+                unrepresented_aspects
+            })
+        }
+    }
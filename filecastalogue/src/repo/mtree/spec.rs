@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use crate::error::{Error, ErrorKind, FcResult, KeyValuePayload};
+use crate::meta::tracked_path::model::TrackedPath;
+
+/// An entry of an mtree spec, with the keywords set for it, including
+/// those set by `/set`.
+pub(crate) struct MtreeEntry {
+    pub path: TrackedPath,
+    pub keywords: BTreeMap<String, String>,
+}
+
+fn get_invalid_spec_error(context: &'static str, line_number: usize) -> Error {
+    error!(
+        ErrorKind::InvalidMtreeSpec,
+        context,
+        payload => payload!("Line: ", Box::new(line_number))
+    )
+}
+
+/// Decodes a name encoded the way vis(3) does, which mtree specs use for
+/// anything but printable ASCII.
+fn decode_name(name: &str, line_number: usize) -> FcResult<OsString> {
+    let mut decoded = vec!();
+    let mut bytes = name.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            decoded.push(byte);
+            continue
+        }
+        let escaped = match bytes.next() {
+            Some(b'\\') => b'\\',
+            Some(b's') => b' ',
+            Some(b't') => b'\t',
+            Some(b'n') => b'\n',
+            Some(b'r') => b'\r',
+            Some(first_digit @ b'0'..=b'3') => {
+                let mut value = first_digit - b'0';
+                for _ in 0..2 {
+                    match bytes.next() {
+                        Some(digit @ b'0'..=b'7') => value = value * 8 + (digit - b'0'),
+                        _ => return Err(get_invalid_spec_error(
+                            "Decoding an octal escape in a name in an mtree spec.", line_number)),
+                    }
+                }
+                value
+            },
+            _ => return Err(get_invalid_spec_error(
+                "Decoding an escape in a name in an mtree spec.", line_number)),
+        };
+        decoded.push(escaped);
+    }
+    Ok(OsString::from_vec(decoded))
+}
+
+/// Encodes a name the way vis(3) does, with octal escapes for anything
+/// but printable ASCII, as well as for `\` and `#`.
+pub(crate) fn encode_name(name: &OsStr) -> String {
+    let mut encoded = String::new();
+    for byte in name.as_bytes() {
+        match byte {
+            b'!'..=b'~' if *byte != b'\\' && *byte != b'#' => encoded.push(*byte as char),
+            _ => encoded.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Returns the path of an entry given with its full path, e.g.
+/// `./etc/motd`, which is relative to the root.
+fn get_full_path(name: &OsStr) -> FcResult<TrackedPath> {
+    let bytes = name.as_bytes();
+    let mut path = b"/".to_vec();
+    path.extend_from_slice(bytes.strip_prefix(b"./").unwrap_or(bytes));
+    TrackedPath::new(OsString::from_vec(path))
+}
+
+fn parse_keywords<'line>(
+    keywords: &mut BTreeMap<String, String>,
+    tokens: impl Iterator<Item = &'line str>
+) {
+    for token in tokens {
+        match token.split_once('=') {
+            Some((keyword, value)) => keywords.insert(keyword.to_owned(), value.to_owned()),
+            None => keywords.insert(token.to_owned(), String::new()),
+        };
+    }
+}
+
+/* Notes:
+    Specs come in two flavours, which may be mixed: Entries with a full
+    path, i.e. one with a `/` in it, and entries with just a name, which
+    is relative to the current directory. A relative entry of a directory
+    makes it the current directory, until a `..` goes back up.
+*/
+/// Reads the entries of an mtree spec, as described in mtree(5).
+pub(crate) fn parse_spec(readable: &mut dyn Read) -> FcResult<Vec<MtreeEntry>> {
+    let mut entries = vec!();
+    let mut defaults = BTreeMap::new();
+    let mut current_directory = TrackedPath::new("/")?;
+    let mut continued_line = String::new();
+    for (line_index, line) in BufReader::new(readable).lines().enumerate() {
+        let line_number = line_index + 1;
+        let line = line?;
+        if let Some(continued) = line.strip_suffix('\\') {
+            continued_line.push_str(continued);
+            continued_line.push(' ');
+            continue
+        }
+        continued_line.push_str(&line);
+        let line = std::mem::take(&mut continued_line);
+        let mut tokens = line.split_whitespace();
+        let name = match tokens.next() {
+            Some(name) if !name.starts_with('#') => name,
+            _ => continue,
+        };
+        match name {
+            "/set" => parse_keywords(&mut defaults, tokens),
+            "/unset" => for keyword in tokens {
+                match keyword {
+                    "all" => defaults.clear(),
+                    _ => { defaults.remove(keyword); },
+                }
+            },
+            ".." => current_directory = match current_directory.parent() {
+                Some(parent) => parent,
+                None => return Err(get_invalid_spec_error(
+                    "Going up from the root of an mtree spec.", line_number)),
+            },
+            _ => {
+                let decoded_name = decode_name(name, line_number)?;
+                let mut keywords = defaults.clone();
+                parse_keywords(&mut keywords, tokens);
+                // Names in values are encoded just like those of entries.
+                for keyword in ["link", "contents"] {
+                    if let Some(value) = keywords.get_mut(keyword) {
+                        *value = decode_name(value, line_number)?.to_string_lossy().into_owned();
+                    }
+                }
+                let path = match decoded_name.as_bytes() {
+                    b"." => TrackedPath::new("/")?,
+                    bytes if bytes.contains(&b'/') => get_full_path(&decoded_name)?,
+                    _ => {
+                        let path = current_directory.join(&decoded_name)?;
+                        if keywords.get("type").map(String::as_str) == Some("dir") {
+                            current_directory = path.clone();
+                        }
+                        path
+                    },
+                };
+                entries.push(MtreeEntry { path, keywords });
+            },
+        }
+    }
+    Ok(entries)
+}
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use crate::error::FcResult;
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::lock::{LockMode, RepoLock};
+use crate::journal::{JournalEntry, JournalRecord};
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::version::accessor::VersionAccessor;
+use super::{Repo, hash_state};
+
+impl<
+    'rpo,
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /// Like `new`, but recovers the repo from whatever operations on it
+        /// got interrupted before returning it, see `recover`.
+        pub fn open(
+            state_collection: StateCollection,
+            indexes: Indexes,
+            blobs: Blobs,
+            journal: Journal,
+        ) -> FcResult<Repo<StateCollection, Indexes, Blobs, Journal>> {
+            let mut repo = Self::new(state_collection, indexes, blobs, journal);
+            repo.recover()?;
+            Ok(repo)
+        }
+
+        /// Like `new_with_lock`, but recovers the repo from whatever
+        /// operations on it got interrupted before returning it, see
+        /// `recover`.
+        pub fn open_with_lock(
+            state_collection: StateCollection,
+            indexes: Indexes,
+            blobs: Blobs,
+            journal: Journal,
+            lock: Box<dyn RepoLock>,
+        ) -> FcResult<Repo<StateCollection, Indexes, Blobs, Journal>> {
+            let mut repo = Self::new_with_lock(
+                state_collection, indexes, blobs, journal, lock);
+            repo.recover()?;
+            Ok(repo)
+        }
+
+        /* Notes:
+            An operation which got as far as recording the state it was
+            about to write put everything that state refers to already, so
+            writing it finishes the operation. Anything short of that never
+            changed the state, so all that's left of it are the blobs and
+            indexes it put, which only need to go if nothing else refers to
+            them, as another version might well have the same content.
+
+            Unless, that is, the state has changed since the operation
+            started, e.g. because another process wrote it, in which case
+            writing the recorded state would undo that. The operation is
+            rolled back then instead. A state that can't be read at all is
+            taken to have been cut short by the operation itself.
+
+            Operations are recovered in the order they've begun in, so if
+            more than one of them recorded a state, it's the state of the
+            last one that ends up being written.
+
+            This holds the lock exclusively, as an operation still underway
+            in another process looks just like one that got interrupted.
+        */
+        /// Finishes or rolls back every operation the journal has recorded
+        /// as begun, but not completed, so the state never refers to blobs
+        /// or indexes which don't exist.
+        pub fn recover(&'rpo mut self) -> FcResult<&'rpo mut Self> {
+            self.locked(LockMode::Exclusive, |repo| {
+                for entry in repo.journal.get_unfinished_entries()? {
+                    match entry.get_state_to_put() {
+                        Some(_) if repo.has_state_moved_on(&entry)? => repo.roll_back(&entry)?,
+                        Some(state) => {
+                            let mut state_file = repo.state_collection
+                                .create_unwritten_empty_state_file_box();
+                            state_file.set_state(state.clone())?;
+                            repo.state_collection.put_state_file(state_file)?;
+                        },
+                        None => repo.roll_back(&entry)?,
+                    }
+                    repo.journal.complete(entry.id)?;
+                }
+                Ok(())
+            })?;
+            Ok(self)
+        }
+
+        /// Returns whether the state has changed since the operation of the
+        /// specified entry started, other than by the operation itself.
+        fn has_state_moved_on(&mut self, entry: &JournalEntry) -> FcResult<bool> {
+            let (started_from, state) = match (
+                entry.get_started_from_state_hash(), entry.get_state_to_put()
+            ) {
+                (Some(started_from), Some(state)) => (started_from, state),
+                _ => return Ok(false),
+            };
+            let state_hash = match self.get_state_hash() {
+                Ok(Some(state_hash)) => state_hash,
+                _ => return Ok(false),
+            };
+            Ok(state_hash != started_from && state_hash != hash_state(state.clone())?)
+        }
+
+        /// Removes the blobs and indexes the operation of the specified
+        /// entry put, unless the state refers to them.
+        pub(super) fn roll_back(&mut self, entry: &JournalEntry) -> FcResult<()> {
+            let (index_ids, blob_hashes) = self.get_referenced_hashes()?;
+            for record in &entry.records {
+                match record {
+                    JournalRecord::PutBlob { hash }
+                    if !blob_hashes.contains(hash) && self.blobs.has_file(hash)?
+                        => self.blobs.remove_file(hash)?,
+                    JournalRecord::PutIndex { hash }
+                    if !index_ids.contains(hash) && self.indexes.has_index(hash)?
+                        => self.indexes.remove_index(hash)?,
+                    _ => (),
+                }
+            }
+            Ok(())
+        }
+
+        /// Returns the IDs of all indexes the state refers to and the
+        /// hashes of all blobs those refer to.
+        fn get_referenced_hashes(&mut self)
+        -> FcResult<(HashSet<String>, HashSet<String>)> {
+            let mut index_ids = HashSet::new();
+            let mut blob_hashes = HashSet::new();
+            if !self.state_collection.has_state()? {
+                return Ok((index_ids, blob_hashes))
+            }
+            let mut state_file = self.state_collection.get_state_file()?;
+            for version in state_file.get_state_ref()?.versions.iter() {
+                if let Some(index_id) = version.get_index_id() {
+                    let mut index_file = self.indexes.get_index_file(&index_id)?;
+                    for tracked_aspects in index_file.get_index_ref()?.files.values() {
+                        if let TrackedFileAspects::Ordinary(ordinary_aspects) = tracked_aspects {
+                            blob_hashes.insert(ordinary_aspects.hash.to_owned());
+                        }
+                    }
+                    index_ids.insert(index_id);
+                }
+            }
+            Ok((index_ids, blob_hashes))
+        }
+    }
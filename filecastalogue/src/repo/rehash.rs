@@ -0,0 +1,102 @@
+use std::collections::{HashMap, HashSet};
+use crate::error::FcResult;
+use crate::files::hashable::HashAlgorithm;
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::lock::LockMode;
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::state::accessor::StateAccessor;
+use crate::meta::version::accessor::VersionAccessor;
+use super::Repo;
+
+impl<
+    'rpo,
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            The state is only written once every blob and index has been
+            put again under its new hash, and the old ones are only removed
+            after that, so the repo stays usable if this is interrupted.
+            Failing to remove them doesn't fail the rehash, they're then
+            left behind like blobs and indexes no version refers to.
+            Running it again then picks up where it left off, as blobs and
+            indexes already put under their new hash are simply put again.
+
+            Blobs and indexes no version refers to are left alone.
+        */
+        /// Hashes all blobs and indexes the versions of the repo refer to
+        /// with the specified algorithm, stores them under their new hashes
+        /// and makes it the algorithm of the repo. This also turns hashes
+        /// without an algorithm prefix into prefixed ones.
+        pub fn rehash(&'rpo mut self, hash_algorithm: HashAlgorithm)
+        -> FcResult<&'rpo mut Self> {
+            self.locked(LockMode::Exclusive, |repo| {
+                let journal_entry_id = repo.journal.begin("rehash")?;
+                let mut state_file = repo.state_collection.get_state_file()?;
+                let state = state_file.get_state_ref()?;
+                let mut new_blob_hashes: HashMap<String, String> = HashMap::new();
+                let mut new_index_ids: HashSet<String> = HashSet::new();
+                let mut old_index_ids: HashSet<String> = HashSet::new();
+
+                for version_index in 0..state.versions.len() {
+                    let mut version = state.get_version(version_index)?;
+                    let index_id = match version.get_index_id() {
+                        Some(index_id) => index_id,
+                        None => continue,
+                    };
+                    let mut index_file = repo.indexes.get_index_file(&index_id)?;
+                    for tracked_aspects in index_file.get_index_ref()?.files.values_mut() {
+                        if let TrackedFileAspects::Ordinary(ordinary_aspects) = tracked_aspects {
+                            let new_hash = match new_blob_hashes.get(&ordinary_aspects.hash) {
+                                Some(new_hash) => new_hash.to_owned(),
+                                None => {
+                                    let blob_file = repo.blobs.get_file(&ordinary_aspects.hash)?;
+                                    let new_hash = repo.blobs.put_readable(
+                                        &mut blob_file.get_readable()?,
+                                        hash_algorithm
+                                    )?;
+                                    repo.record_put_blob(journal_entry_id, &new_hash)?;
+                                    new_blob_hashes.insert(
+                                        ordinary_aspects.hash.to_owned(),
+                                        new_hash.to_owned()
+                                    );
+                                    new_hash
+                                }
+                            };
+                            ordinary_aspects.hash = new_hash;
+                        }
+                    }
+                    let new_index_id = repo.put_index_file(
+                        journal_entry_id, index_file, hash_algorithm)?;
+                    version.set_index_id(&new_index_id);
+                    state.put_version(&version_index, version);
+                    old_index_ids.insert(index_id);
+                    new_index_ids.insert(new_index_id);
+                }
+                state.hash_algorithm = hash_algorithm;
+                repo.put_state_file(journal_entry_id, state_file)?;
+
+                // Nothing refers to the old ones anymore, unless they were
+                // already hashed with the new algorithm in the first place.
+                // The rehash is done by now, so any that can't be removed
+                // are simply left behind.
+                let kept_blob_hashes: HashSet<&String> = new_blob_hashes.values().collect();
+                for old_blob_hash in new_blob_hashes.keys() {
+                    if !kept_blob_hashes.contains(old_blob_hash) {
+                        let _ = repo.blobs.remove_file(old_blob_hash);
+                    }
+                }
+                for old_index_id in old_index_ids.difference(&new_index_ids) {
+                    let _ = repo.indexes.remove_index(old_index_id);
+                }
+                Ok(())
+            })?;
+            Ok(self)
+        }
+    }
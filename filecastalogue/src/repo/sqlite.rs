@@ -0,0 +1,185 @@
+use std::ffi::OsString;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+use crate::error::FcResult;
+use crate::files::index_collection::MiscIndexFileCollection;
+use crate::files::state_collection::{MiscStateFileCollection, StateFileCollection};
+use crate::files::tracked_ordinary_blob_collection::MiscTrackedOrdinaryBlobFileCollection;
+use crate::globals::{BLOBS_DIR_NAME, INDEXES_DIR_NAME, JOURNAL_DIR_NAME, LOCK_DIR_NAME, STATE_FILE_NAME};
+use crate::journal::drivers::local::LocalJournal;
+use crate::journal::drivers::sqlite::SqliteJournal;
+use crate::lock::LockMode;
+use crate::lock::drivers::local::LocalLock;
+use crate::opaque_collection_handler::OpaqueCollectionHandler;
+use crate::opaque_collection_handler::drivers::local::LocalDir;
+use crate::opaque_collection_handler::drivers::sqlite::{SqliteCollection, SqliteDatabase};
+use super::Repo;
+
+/// A repo kept in a single SQLite database, see `Repo::open_sqlite`.
+pub type SqliteRepo = Repo<
+    MiscStateFileCollection<SqliteCollection>,
+    MiscIndexFileCollection<SqliteCollection>,
+    MiscTrackedOrdinaryBlobFileCollection<SqliteCollection>,
+    SqliteJournal
+>;
+
+type LocalRepo = Repo<
+    MiscStateFileCollection<LocalDir>,
+    MiscIndexFileCollection<LocalDir>,
+    MiscTrackedOrdinaryBlobFileCollection<LocalDir>,
+    LocalJournal
+>;
+
+impl SqliteRepo {
+    /// Opens the repo in the SQLite database at the specified path,
+    /// creating the database with an empty repo in it if needed.
+    ///
+    /// Every operation changing the repo is a transaction of its own, so
+    /// it either changes everything it's about to, or nothing at all.
+    pub fn open_sqlite<PathRef: AsRef<Path>>(path: PathRef) -> FcResult<Self> {
+        let database = SqliteDatabase::open(path)?;
+        let mut state_collection = MiscStateFileCollection::new(
+            database.get_collection(""), OsString::from(STATE_FILE_NAME));
+        if !state_collection.has_state()? {
+            let state_file = state_collection.create_unwritten_empty_state_file_box();
+            state_collection.put_state_file(state_file)?;
+        }
+        Repo::open(
+            state_collection,
+            MiscIndexFileCollection::new(database.get_collection(INDEXES_DIR_NAME)),
+            MiscTrackedOrdinaryBlobFileCollection::new(database.get_collection(BLOBS_DIR_NAME)),
+            SqliteJournal::new(Arc::clone(&database)),
+        )
+    }
+}
+
+/// Opens the repo in the local directory, recovering it, so there's
+/// nothing left of interrupted operations that would be copied along.
+/// It's locked with the same lock as everything else working on it uses.
+fn open_local_repo(repo_path: &Path) -> FcResult<LocalRepo> {
+    Repo::open_with_lock(
+        MiscStateFileCollection::new(LocalDir::new(repo_path), OsString::from(STATE_FILE_NAME)),
+        MiscIndexFileCollection::new(LocalDir::open(repo_path.join(INDEXES_DIR_NAME))?),
+        MiscTrackedOrdinaryBlobFileCollection::new(
+            LocalDir::open(repo_path.join(BLOBS_DIR_NAME))?),
+        LocalJournal::new(repo_path.join(JOURNAL_DIR_NAME)),
+        Box::new(LocalLock::new(repo_path.join(LOCK_DIR_NAME))),
+    )
+}
+
+/// Copies the files of that name from one collection to another, as
+/// they are, replacing whatever files of the same name are there.
+fn copy_files<Source: OpaqueCollectionHandler, Target: OpaqueCollectionHandler>(
+    source: &Source,
+    file_names: &[OsString],
+    target: &mut Target
+) -> FcResult<()> {
+    target.create_collection_ignore_exists()?;
+    for file_name in file_names {
+        if !target.has_file(file_name)? {
+            target.create_file(file_name)?;
+        }
+        let mut writeable = target.get_file_writeable(file_name)?;
+        io::copy(&mut source.get_file_readable(file_name)?, &mut writeable)?;
+        writeable.flush()?;
+    }
+    Ok(())
+}
+
+fn fail_if_has_state<StateCollection: StateFileCollection>(
+    state_collection: &mut StateCollection
+) -> FcResult<()> {
+    match state_collection.has_state()? {
+        true => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "There's a repo at the destination already."
+        ).into()),
+        false => Ok(()),
+    }
+}
+
+/* Notes:
+    Blobs and indexes are copied as they are, so they keep their names
+    and whatever encoding or compression they're stored with, and the
+    state, which refers to them by name, still does after it's copied.
+    It's copied last, so the destination doesn't have a repo in it until
+    everything is in place.
+
+    The repo is locked shared for the whole conversion, so nothing can
+    change it while it's being copied, which would leave the copy with
+    a state referring to blobs or indexes it doesn't have.
+*/
+/// Copies the repo in the local directory at `repo_path` into a new
+/// SQLite database at `database_path`, all in a single transaction.
+///
+/// Fails if the database has a repo in it already.
+pub fn convert_local_dir_to_sqlite<RepoPathRef: AsRef<Path>, DatabasePathRef: AsRef<Path>>(
+    repo_path: RepoPathRef,
+    database_path: DatabasePathRef
+) -> FcResult<()> {
+    let mut source = open_local_repo(repo_path.as_ref())?;
+    let database = SqliteDatabase::open(database_path)?;
+    let mut state_collection = MiscStateFileCollection::new(
+        database.get_collection(""), OsString::from(STATE_FILE_NAME));
+    source.locked(LockMode::Shared, |source| {
+        database.begin_transaction()?;
+        let result = fail_if_has_state(&mut state_collection)
+            .and_then(|_| copy_files(
+                &source.blobs.handler,
+                &source.blobs.handler.get_file_names()?,
+                &mut database.get_collection(BLOBS_DIR_NAME)
+            ))
+            .and_then(|_| copy_files(
+                &source.indexes.handler,
+                &source.indexes.handler.get_file_names()?,
+                &mut database.get_collection(INDEXES_DIR_NAME)
+            ))
+            .and_then(|_| copy_files(
+                &source.state_collection.handler,
+                &[OsString::from(STATE_FILE_NAME)],
+                &mut state_collection.handler
+            ));
+        match result {
+            Ok(()) => database.commit_transaction(),
+            Err(e) => {
+                // The error of the conversion is the more telling one.
+                let _ = database.rollback_transaction();
+                Err(e)
+            }
+        }
+    })
+}
+
+/// Copies the repo in the SQLite database at `database_path` into the
+/// local directory at `repo_path`, which is created as needed. Its
+/// collections keep the layout they've been set up with, if any, and
+/// get the default one otherwise.
+///
+/// Fails if the directory has a repo in it already.
+pub fn convert_sqlite_to_local_dir<DatabasePathRef: AsRef<Path>, RepoPathRef: AsRef<Path>>(
+    database_path: DatabasePathRef,
+    repo_path: RepoPathRef
+) -> FcResult<()> {
+    let source = SqliteRepo::open_sqlite(database_path)?;
+    let repo_path = repo_path.as_ref();
+    let mut state_handler = LocalDir::new(repo_path);
+    fail_if_has_state(&mut MiscStateFileCollection::new(
+        LocalDir::new(repo_path), OsString::from(STATE_FILE_NAME)))?;
+    state_handler.create_collection_ignore_exists()?;
+    copy_files(
+        &source.blobs.handler,
+        &source.blobs.handler.get_file_names()?,
+        &mut LocalDir::open(repo_path.join(BLOBS_DIR_NAME))?
+    )?;
+    copy_files(
+        &source.indexes.handler,
+        &source.indexes.handler.get_file_names()?,
+        &mut LocalDir::open(repo_path.join(INDEXES_DIR_NAME))?
+    )?;
+    copy_files(
+        &source.state_collection.handler,
+        &[OsString::from(STATE_FILE_NAME)],
+        &mut state_handler
+    )
+}
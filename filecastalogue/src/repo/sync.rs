@@ -0,0 +1,316 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fmt;
+use std::io::Read;
+use crate::error::{Error, ErrorKind, FcResult, Payload};
+use crate::files::hashable::{HashAlgorithm, normalize_hash};
+use crate::files::index::{IndexFile, IndexProvider};
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::journal::{JournalEntry, JournalEntryId, JournalRecord};
+use crate::lock::LockMode;
+use crate::meta::file_aspects::enums::TrackedFileAspects;
+use crate::meta::state::model::State;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::opaque_collection_handler::ContentHashMismatchPayload;
+use super::Repo;
+
+const SYNC_OPERATION: &str = "sync";
+
+/// How a sync turned out, see `Repo::sync_to`.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum SyncOutcome {
+    /// The destination had the same state as the source already.
+    UpToDate,
+    /// The destination has the state of the source now.
+    Updated,
+    /// Only the destination changed since the base, so it was left alone.
+    DestinationAhead,
+}
+
+/// What a sync did, see `Repo::sync_to`.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct SyncReport {
+    pub outcome: SyncOutcome,
+    /// The IDs of the indexes the destination was missing, in the order
+    /// they were transferred in.
+    pub transferred_index_ids: Vec<String>,
+    /// The hashes of the blobs the destination was missing, in the order
+    /// they were transferred in.
+    pub transferred_blob_hashes: Vec<String>,
+    /// The state of the destination after the sync, which is the base to
+    /// pass to the next one.
+    pub state: State,
+}
+
+pub struct SyncConflictPayload {
+    pub source_version_count: usize,
+    pub destination_version_count: usize,
+}
+
+impl fmt::Debug for SyncConflictPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for SyncConflictPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f, "Versions in source: {}, versions in destination: {}.",
+            self.source_version_count, self.destination_version_count
+        )
+    }
+}
+
+impl Payload for SyncConflictPayload {}
+
+fn get_hash_mismatch_error(file_name: &str, actual_hash: String) -> Error {
+    error!(
+        ErrorKind::ContentHashMismatch,
+        "Verifying an object transferred between repos.",
+        payload => ContentHashMismatchPayload {
+            file_name: OsString::from(file_name),
+            actual_hash
+        }
+    )
+}
+
+/// Returns whether the hashes are the same, prefixed or not.
+fn is_same_hash(hash: &str, other_hash: &str) -> bool {
+    normalize_hash(hash) == normalize_hash(other_hash)
+}
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            Whether the destination changed is told by comparing its state
+            to `base`, the state of the last sync between the two, which
+            `SyncReport::state` is. Without a base, e.g. for the first sync,
+            the destination is taken to be unchanged as long as its
+            versions are the first versions of the source, which includes
+            a destination without any.
+
+            An index the destination has already is taken to come with all
+            the blobs it refers to, as indexes are always put after their
+            blobs. Indexes are transferred as they're stored, which is
+            checked against their ID, so they keep it and the state of the
+            source can be put into the destination as it is. Serializing
+            them anew wouldn't do, as that doesn't keep the order of files.
+
+            Everything is transferred as a single operation of the
+            destination, with the state put last, so an interrupted sync
+            is rolled back like any other operation.
+
+            The source is locked shared and the destination exclusively.
+            Which of them is locked first goes by `RepoLock::get_lock_id`,
+            rather than by which is the source, as a sync the other way
+            between the same repos would otherwise lock them the other way
+            around, each holding the lock the other one waits for.
+        */
+        /// Makes the state of the destination that of this repo, pushing
+        /// whatever indexes and blobs the versions of this repo refer to and
+        /// the destination is missing along with it. Every transferred
+        /// object is checked against its hash, failing with
+        /// `ErrorKind::ContentHashMismatch` for any that doesn't match.
+        ///
+        /// Fails with `ErrorKind::SyncConflict` if both repos changed since
+        /// their state was `base`. If only the destination did, it's left
+        /// alone. See `sync_from` to pull instead.
+        pub fn sync_to<
+            DestinationStateCollection: StateFileCollection,
+            DestinationIndexes: IndexFileCollection,
+            DestinationBlobs: TrackedOrdinaryBlobFileCollection,
+            DestinationJournal: journal::Journal
+        >(
+            &mut self,
+            destination: &mut Repo<
+                DestinationStateCollection,
+                DestinationIndexes,
+                DestinationBlobs,
+                DestinationJournal
+            >,
+            base: Option<&State>
+        ) -> FcResult<SyncReport> {
+            match destination.lock.get_lock_id() < self.lock.get_lock_id() {
+                true => destination.locked(LockMode::Exclusive, |destination| {
+                    self.locked(LockMode::Shared, |source| source.sync_to_locked(destination, base))
+                }),
+                false => self.locked(LockMode::Shared, |source| {
+                    destination.locked(LockMode::Exclusive, |destination| {
+                        source.sync_to_locked(destination, base)
+                    })
+                }),
+            }
+        }
+
+        /// Does what `sync_to` does, with both repos locked already.
+        fn sync_to_locked<
+            DestinationStateCollection: StateFileCollection,
+            DestinationIndexes: IndexFileCollection,
+            DestinationBlobs: TrackedOrdinaryBlobFileCollection,
+            DestinationJournal: journal::Journal
+        >(
+            &mut self,
+            destination: &mut Repo<
+                DestinationStateCollection,
+                DestinationIndexes,
+                DestinationBlobs,
+                DestinationJournal
+            >,
+            base: Option<&State>
+        ) -> FcResult<SyncReport> {
+            let source_state = self.state_collection.get_state_file()?
+                .get_state_ref()?.clone();
+            let destination_state = match destination.state_collection.has_state()? {
+                true => destination.state_collection.get_state_file()?
+                    .get_state_ref()?.clone(),
+                false => State::new(),
+            };
+            let mut report = SyncReport {
+                outcome: SyncOutcome::UpToDate,
+                transferred_index_ids: vec!(),
+                transferred_blob_hashes: vec!(),
+                state: destination_state.clone(),
+            };
+            if destination_state == source_state {
+                return Ok(report)
+            }
+            let is_destination_unchanged = match base {
+                Some(base) => destination_state == *base,
+                None => source_state.versions.starts_with(&destination_state.versions),
+            };
+            if !is_destination_unchanged {
+                if base == Some(&source_state) {
+                    report.outcome = SyncOutcome::DestinationAhead;
+                    return Ok(report)
+                }
+                return Err(error!(
+                    ErrorKind::SyncConflict,
+                    "Syncing a repo into another.",
+                    payload => SyncConflictPayload {
+                        source_version_count: source_state.versions.len(),
+                        destination_version_count: destination_state.versions.len()
+                    }
+                ))
+            }
+
+            let journal_entry_id = destination.journal.begin(SYNC_OPERATION)?;
+            let mut records = vec!(JournalRecord::Begin {
+                operation: SYNC_OPERATION.to_owned()
+            });
+            let result = self.transfer_missing(
+                &source_state, destination, journal_entry_id, &mut records, &mut report);
+            if let Err(e) = result {
+                destination.roll_back(&JournalEntry {
+                    id: journal_entry_id,
+                    operation: SYNC_OPERATION.to_owned(),
+                    records,
+                })?;
+                destination.journal.complete(journal_entry_id)?;
+                return Err(e)
+            }
+            let mut state_file = destination.state_collection
+                .create_unwritten_empty_state_file_box();
+            state_file.set_state(source_state.clone())?;
+            destination.put_state_file(journal_entry_id, state_file)?;
+            report.outcome = SyncOutcome::Updated;
+            report.state = source_state;
+            Ok(report)
+        }
+
+        /// Makes the state of this repo that of the source, pulling
+        /// whatever it's missing. This is `sync_to` the other way around.
+        pub fn sync_from<
+            SourceStateCollection: StateFileCollection,
+            SourceIndexes: IndexFileCollection,
+            SourceBlobs: TrackedOrdinaryBlobFileCollection,
+            SourceJournal: journal::Journal
+        >(
+            &mut self,
+            source: &mut Repo<SourceStateCollection, SourceIndexes, SourceBlobs, SourceJournal>,
+            base: Option<&State>
+        ) -> FcResult<SyncReport> {
+            source.sync_to(self, base)
+        }
+
+        /// Puts the indexes and blobs the versions in `source_state` refer
+        /// to into the destination, unless it has them already.
+        fn transfer_missing<
+            DestinationStateCollection: StateFileCollection,
+            DestinationIndexes: IndexFileCollection,
+            DestinationBlobs: TrackedOrdinaryBlobFileCollection,
+            DestinationJournal: journal::Journal
+        >(
+            &mut self,
+            source_state: &State,
+            destination: &mut Repo<
+                DestinationStateCollection,
+                DestinationIndexes,
+                DestinationBlobs,
+                DestinationJournal
+            >,
+            journal_entry_id: JournalEntryId,
+            records: &mut Vec<JournalRecord>,
+            report: &mut SyncReport
+        ) -> FcResult<()> {
+            let mut seen_index_ids = HashSet::new();
+            let mut seen_blob_hashes = HashSet::new();
+            for version in source_state.versions.iter() {
+                let index_id = match version.get_index_id() {
+                    Some(index_id) => index_id,
+                    None => continue,
+                };
+                if !seen_index_ids.insert(index_id.to_owned())
+                || destination.indexes.has_index(&index_id)? {
+                    continue
+                }
+                let mut index_content = vec!();
+                self.indexes.get_index_readable(&index_id)?.read_to_end(&mut index_content)?;
+                let index_hash_algorithm = HashAlgorithm::of_hash(&index_id)?;
+                let actual_index_id = index_hash_algorithm.hash_readable(
+                    &mut index_content.as_slice())?;
+                if !is_same_hash(&actual_index_id, &index_id) {
+                    return Err(get_hash_mismatch_error(&index_id, actual_index_id))
+                }
+                let mut index_file = IndexFile::from_existing(&mut index_content.as_slice())?;
+
+                for tracked_aspects in index_file.get_index_ref()?.files.values() {
+                    let hash = match tracked_aspects {
+                        TrackedFileAspects::Ordinary(ordinary_aspects) => &ordinary_aspects.hash,
+                        _ => continue,
+                    };
+                    if !seen_blob_hashes.insert(hash.to_owned())
+                    || destination.blobs.has_file(hash)? {
+                        continue
+                    }
+                    let actual_hash = destination.blobs.put_readable(
+                        &mut self.blobs.get_file(hash)?.get_readable()?,
+                        HashAlgorithm::of_hash(hash)?
+                    )?;
+                    destination.record_put_blob(journal_entry_id, &actual_hash)?;
+                    records.push(JournalRecord::PutBlob { hash: actual_hash.to_owned() });
+                    if !is_same_hash(&actual_hash, hash) {
+                        return Err(get_hash_mismatch_error(hash, actual_hash))
+                    }
+                    report.transferred_blob_hashes.push(hash.to_owned());
+                }
+
+                let put_index_id = destination.put_index_readable(
+                    journal_entry_id, &mut index_content.as_slice(), index_hash_algorithm)?;
+                records.push(JournalRecord::PutIndex { hash: put_index_id.to_owned() });
+                if !is_same_hash(&put_index_id, &index_id) {
+                    return Err(get_hash_mismatch_error(&index_id, put_index_id))
+                }
+                report.transferred_index_ids.push(index_id);
+            }
+            Ok(())
+        }
+    }
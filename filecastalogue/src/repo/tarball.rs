@@ -0,0 +1,239 @@
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+use crate::error::{Error, ErrorKind, FcResult, KeyValuePayload};
+use crate::files::index_collection::IndexFileCollection;
+use crate::files::state_collection::StateFileCollection;
+use crate::files::tracked_ordinary_blob_collection::TrackedOrdinaryBlobFileCollection;
+use crate::journal;
+use crate::meta::file_aspects::aspects::directory::{DirectoryMode, TrackableDirectoryAspects};
+use crate::meta::file_aspects::aspects::non_existing::TrackableNonExistingAspects;
+use crate::meta::file_aspects::aspects::ordinary::TrackableOrdinaryAspects;
+use crate::meta::file_aspects::aspects::symlink::TrackableSymlinkAspects;
+use crate::meta::file_aspects::attributes::Attributes;
+use crate::meta::repo_exported_file_list::tarball::{OPAQUE_WHITEOUT_NAME, WHITEOUT_PREFIX};
+use crate::meta::tracked_path::model::TrackedPath;
+use super::Repo;
+use super::batch::RepoBatch;
+
+/// Returns the path an entry of a tarball is tracked at, which is its
+/// path in the tarball, taken to be relative to the root.
+fn get_tracked_path(entry_path: &Path) -> FcResult<TrackedPath> {
+    let relative_path: PathBuf = entry_path.components()
+        .filter(|component| !matches!(component, Component::CurDir | Component::RootDir))
+        .collect();
+    TrackedPath::new(Path::new("/").join(relative_path))
+}
+
+/// What a whiteout in an OCI layer is about.
+enum Whiteout {
+    /// The directory at the path is opaque.
+    Opaque(TrackedPath),
+    /// The file at the path is removed.
+    Removed(TrackedPath),
+}
+
+/// Returns what the entry at that path is about if it's a whiteout.
+fn get_whiteout(tracked_path: &TrackedPath) -> FcResult<Option<Whiteout>> {
+    let (parent, file_name) = match (tracked_path.parent(), tracked_path.as_path().file_name()) {
+        (Some(parent), Some(file_name)) => (parent, file_name),
+        _ => return Ok(None),
+    };
+    if file_name == OPAQUE_WHITEOUT_NAME {
+        return Ok(Some(Whiteout::Opaque(parent)))
+    }
+    match file_name.as_bytes().strip_prefix(WHITEOUT_PREFIX.as_bytes()) {
+        Some(removed_name) => Ok(Some(Whiteout::Removed(
+            parent.join(OsStr::from_bytes(removed_name))?))),
+        None => Ok(None),
+    }
+}
+
+/// Returns the attributes of the entry, with owners taken from their
+/// names, or their IDs if there are no names.
+fn get_attributes<Readable: Read>(entry: &tar::Entry<Readable>) -> FcResult<Attributes> {
+    let header = entry.header();
+    Ok(Attributes {
+        posix_user: match header.username_bytes() {
+            Some(user) if !user.is_empty() => String::from_utf8_lossy(user).into_owned(),
+            _ => header.uid()?.to_string(),
+        },
+        posix_group: match header.groupname_bytes() {
+            Some(group) if !group.is_empty() => String::from_utf8_lossy(group).into_owned(),
+            _ => header.gid()?.to_string(),
+        },
+        posix_mode: Some(header.mode()? & 0o7777),
+    })
+}
+
+/* Notes:
+    Directories are tracked last, once it's known whether a layer marks
+    them as opaque, which it might do anywhere in it.
+*/
+/// Tracks the entries of the tarball through the batch, see
+/// `Repo::track_tarball` and `Repo::track_oci_layer`.
+fn track_tar_entries<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal,
+    Readable: Read
+    >(
+    batch: &mut RepoBatch<StateCollection, Indexes, Blobs, Journal>,
+    readable: Readable,
+    is_oci_layer: bool
+) -> FcResult<()> {
+    let mut directories = vec!();
+    let mut opaque_directory_paths = HashSet::new();
+    let mut archive = tar::Archive::new(readable);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if entry_type == tar::EntryType::XGlobalHeader {
+            continue
+        }
+        let tracked_path = get_tracked_path(&entry.path()?)?;
+        if is_oci_layer {
+            match get_whiteout(&tracked_path)? {
+                Some(Whiteout::Opaque(directory_path)) => {
+                    opaque_directory_paths.insert(directory_path);
+                    continue
+                },
+                Some(Whiteout::Removed(removed_path)) => {
+                    batch.track_non_existing(
+                        removed_path,
+                        TrackableNonExistingAspects::new_with_constraints(true, None)
+                    )?;
+                    continue
+                },
+                None => (),
+            }
+        }
+        match entry_type {
+            tar::EntryType::Directory => {
+                directories.push((tracked_path, get_attributes(&entry)?));
+            },
+            tar::EntryType::Regular | tar::EntryType::Continuous => {
+                let attributes = get_attributes(&entry)?;
+                batch.track_ordinary(
+                    tracked_path,
+                    TrackableOrdinaryAspects::new(attributes),
+                    &mut entry
+                )?;
+            },
+            tar::EntryType::Symlink => {
+                let linked_to = match entry.link_name()? {
+                    Some(linked_to) => linked_to.to_string_lossy().into_owned(),
+                    None => String::new(),
+                };
+                batch.track_symlink(
+                    tracked_path, TrackableSymlinkAspects::new(linked_to))?;
+            },
+            _ => return Err(error!(
+                ErrorKind::UnsupportedTarEntry,
+                "Tracking the entries of a tarball.",
+                payload => payload!(
+                    "Path: ", Box::new(tracked_path),
+                    "Entry type: ", Box::new(entry_type)
+                )
+            )),
+        }
+    }
+
+    for (directory_path, attributes) in directories {
+        let mode = match opaque_directory_paths.remove(&directory_path) {
+            true => DirectoryMode::Exclusive { allowed: vec!() },
+            false => DirectoryMode::Shared,
+        };
+        batch.track_directory(
+            directory_path, TrackableDirectoryAspects::new_with_mode(attributes, mode))?;
+    }
+    for directory_path in opaque_directory_paths {
+        batch.track_directory(
+            directory_path,
+            TrackableDirectoryAspects::new_with_mode(
+                Attributes {
+                    posix_user: String::from("root"),
+                    posix_group: String::from("root"),
+                    posix_mode: None
+                },
+                DirectoryMode::Exclusive { allowed: vec!() }
+            )
+        )?;
+    }
+    Ok(())
+}
+
+impl<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    Journal: journal::Journal
+    > Repo<StateCollection, Indexes, Blobs, Journal> {
+
+        /* Notes:
+            Hard links, devices and the like can't be tracked, so rather
+            than leaving them out, which would make the version differ from
+            the tarball in ways that are easy to miss, the whole tarball is
+            refused.
+        */
+        /// Tracks every entry of the tarball in the specified version, at
+        /// its path in the tarball taken relative to the root, as a single
+        /// batch. Directories, files and symlinks are tracked with their
+        /// owners and modes. See `TarballFileList` for the other way around.
+        ///
+        /// Fails with `ErrorKind::UnsupportedTarEntry` for anything else,
+        /// tracking nothing.
+        pub fn track_tarball<Readable: Read>(
+            &mut self,
+            version_index: usize,
+            readable: Readable
+        ) -> FcResult<&mut Self> {
+            self.batch(version_index, |batch| track_tar_entries(batch, readable, false))?;
+            Ok(self)
+        }
+
+        /// Adds a new version tracking what's in the tarball, see
+        /// `track_tarball`, returning its index.
+        ///
+        /// If the tarball can't be tracked, no version is added.
+        pub fn import_tarball<Readable: Read>(&mut self, readable: Readable)
+        -> FcResult<usize> {
+            let (version_index, ()) = self.batch_new_version(
+                |batch| track_tar_entries(batch, readable, false))?;
+            Ok(version_index)
+        }
+
+        /* Notes:
+            Whiteouts remove whatever is at their path in lower layers,
+            along with anything in it, so they're tracked as non-existing
+            recursively. An opaque directory that has no entry of its own
+            in the layer is tracked as owned by root.
+        */
+        /// Tracks the entries of the OCI image layer like `track_tarball`
+        /// does, except whiteouts, which are tracked as non-existing, and
+        /// markers of opaque directories, which make their directory
+        /// exclusive. See `TarballFileList::new_oci_layer` for the other
+        /// way around.
+        pub fn track_oci_layer<Readable: Read>(
+            &mut self,
+            version_index: usize,
+            readable: Readable
+        ) -> FcResult<&mut Self> {
+            self.batch(version_index, |batch| track_tar_entries(batch, readable, true))?;
+            Ok(self)
+        }
+
+        /// Adds a new version tracking what's in the OCI image layer, see
+        /// `track_oci_layer`, returning its index.
+        ///
+        /// If the layer can't be tracked, no version is added.
+        pub fn import_oci_layer<Readable: Read>(&mut self, readable: Readable)
+        -> FcResult<usize> {
+            let (version_index, ()) = self.batch_new_version(
+                |batch| track_tar_entries(batch, readable, true))?;
+            Ok(version_index)
+        }
+    }
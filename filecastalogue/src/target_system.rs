@@ -0,0 +1,3 @@
+pub mod drift;
+pub mod error;
+pub mod local;
\ No newline at end of file
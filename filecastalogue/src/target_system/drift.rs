@@ -0,0 +1,79 @@
+use std::ffi::OsString;
+use crate::meta::tracked_path::model::TrackedPath;
+
+/// The ways a file on a target system can differ from what's tracked.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Drift {
+    /// The file is tracked as existing, but there's nothing at its path.
+    Missing,
+    /// The file is tracked as non-existing, but there's something at its path.
+    UnexpectedlyExists {
+        found_kind: &'static str
+    },
+    /// There's a file of a different kind at the path, e.g. a symlink where
+    /// a directory is tracked.
+    KindDiffers {
+        expected_kind: &'static str,
+        found_kind: &'static str
+    },
+    /// The blob of an ordinary file differs from the tracked one.
+    ContentDiffers {
+        repo_blob_hash: String,
+        found_hash: String
+    },
+    /// A symlink points somewhere else than tracked.
+    LinkTargetDiffers {
+        linked_to: String,
+        found_linked_to: OsString
+    },
+    /// The file isn't tracked, but it's in a directory tracked as exclusive
+    /// and not on its allow list.
+    UntrackedInExclusiveDirectory,
+}
+
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DriftEntry {
+    pub path: TrackedPath,
+    pub drift: Drift,
+}
+
+/// Everything found to differ between a version and a target system.
+///
+/// Entries are sorted by path.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct DriftReport {
+    pub entries: Vec<DriftEntry>,
+}
+
+impl DriftReport {
+
+    pub fn new() -> Self {
+        Self {
+            entries: vec!()
+        }
+    }
+
+    /// Returns true if the target system matches the version.
+    pub fn is_clean(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn add(&mut self, path: TrackedPath, drift: Drift) -> &mut Self {
+        self.entries.push(DriftEntry { path, drift });
+        self
+    }
+
+    /// Returns the drift found for the specified path, if any.
+    pub fn get(&self, path: &TrackedPath) -> Vec<&Drift> {
+        self.entries.iter()
+            .filter(|entry| &entry.path == path)
+            .map(|entry| &entry.drift)
+            .collect()
+    }
+}
+
+impl Default for DriftReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
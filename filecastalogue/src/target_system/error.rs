@@ -0,0 +1,26 @@
+use std::{fmt, path::PathBuf};
+use crate::error::{ErrorPathBuf, Payload};
+
+pub struct TargetSystemErrorPayload {
+    pub path: PathBuf,
+    pub action: &'static str,
+}
+
+impl fmt::Debug for TargetSystemErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self)
+    }
+}
+
+impl fmt::Display for TargetSystemErrorPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Failed {} at path: {}.",
+            self.action,
+            ErrorPathBuf::from(self.path.to_owned()),
+        )
+    }
+}
+
+impl Payload for TargetSystemErrorPayload {}
@@ -0,0 +1,472 @@
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs::{self, File, FileType, Metadata};
+use std::io;
+use std::os::unix::fs::{MetadataExt, symlink};
+use std::path::{Path, PathBuf};
+use crate::error::{Error, ErrorKind, FcResult, WrappedError};
+use crate::files::hashable::{HashAlgorithm, normalize_hash};
+use crate::meta::file_aspects::aspects::directory::RepoExportedDirectoryAspects;
+use crate::meta::file_aspects::aspects::non_existing::RepoExportedNonExistingAspects;
+use crate::meta::file_aspects::aspects::ordinary::RepoExportedOrdinaryAspects;
+use crate::meta::file_aspects::aspects::symlink::RepoExportedSymlinkAspects;
+use crate::meta::file_aspects::enums::RepoExportedFileAspects;
+use crate::meta::repo_exported_file_list::model::RepoExportedFile;
+use crate::meta::tracked_path::model::TrackedPath;
+use super::drift::{Drift, DriftReport};
+use super::error::TargetSystemErrorPayload;
+
+/* Notes:
+    Attributes (user, group) aren't compared or applied yet, as that
+    requires resolving names on the target system. Everything else
+    tracked about a file is.
+*/
+/// A target system rooted at a directory on the local machine, e.g. `/` for
+/// the machine itself or the mount point of an image that's being built.
+///
+/// Tracked paths are always resolved relative to the root, and refused
+/// with `ErrorKind::TargetSystemConflict` if they'd end up outside of it,
+/// e.g. through a parent directory on the target system being a symlink
+/// to an absolute path, so nothing outside of it is ever touched.
+pub struct LocalTargetSystem {
+    root: PathBuf
+}
+
+type TrackedFiles<'files> = BTreeMap<TrackedPath, &'files RepoExportedFileAspects>;
+
+impl LocalTargetSystem {
+
+    pub fn new<PathRef: AsRef<Path>>(root: PathRef) -> Self {
+        Self {
+            root: root.as_ref().to_owned()
+        }
+    }
+
+    /// Returns where the specified tracked path is located on the
+    /// local machine.
+    pub fn get_target_path(&self, path: &TrackedPath) -> PathBuf {
+        self.root.join(path.as_relative_path())
+    }
+
+    /* Notes:
+        Only the parents are resolved, as the file at the path itself is
+        never followed if it's a symlink. Parents which don't exist yet
+        resolve to wherever their closest existing ancestor does.
+    */
+    /// Like `get_target_path`, but making sure the parents of the path
+    /// resolve to somewhere within the root, which has to be resolved
+    /// already, see `get_resolved_root`.
+    fn get_contained_target_path(&self, resolved_root: &Path, path: &TrackedPath)
+    -> FcResult<PathBuf> {
+        let target_path = self.get_target_path(path);
+        for ancestor in target_path.ancestors().skip(1) {
+            match fs::canonicalize(ancestor) {
+                Ok(resolved_ancestor) if resolved_ancestor.starts_with(resolved_root) => break,
+                Ok(_) => return Err(error!(
+                    ErrorKind::TargetSystemConflict,
+                    "Refusing to follow a path outside of the root of the target system.",
+                    payload => TargetSystemErrorPayload {
+                        path: target_path.to_owned(),
+                        action: "resolving parent directory"
+                    }
+                )),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => continue,
+                Err(error) => return Err(fail("resolving parent directory", ancestor)(error)),
+            }
+        }
+        Ok(target_path)
+    }
+
+    /// Returns the root with all symlinks in it resolved.
+    fn get_resolved_root(&self) -> FcResult<PathBuf> {
+        fs::canonicalize(&self.root).map_err(fail("resolving root", &self.root))
+    }
+
+    /// Compare the target system to the specified files, e.g. the files of
+    /// a version obtained with `Repo::get_files`.
+    pub fn get_drift(&self, files: &[Box<dyn RepoExportedFile>])
+    -> FcResult<DriftReport> {
+        let tracked_files = Self::collect_tracked_files(files)?;
+        let mut report = DriftReport::new();
+        let resolved_root = self.get_resolved_root()?;
+
+        for (path, aspects) in &tracked_files {
+            let target_path = self.get_contained_target_path(&resolved_root, path)?;
+            let metadata = self.get_metadata(&target_path)?;
+            match (aspects, metadata) {
+                (RepoExportedFileAspects::NonExisting(_), None) => (),
+                (RepoExportedFileAspects::NonExisting(non_existing_aspects), Some(metadata)) => {
+                    let found_kind = get_found_kind(&metadata.file_type());
+                    if non_existing_aspects.is_in_the_way(found_kind) {
+                        report.add(path.to_owned(), Drift::UnexpectedlyExists {
+                            found_kind
+                        });
+                    }
+                },
+                (_, None) => {
+                    report.add(path.to_owned(), Drift::Missing);
+                },
+                (aspects, Some(metadata)) => {
+                    let found_kind = get_found_kind(&metadata.file_type());
+                    if found_kind != aspects.kind_str() {
+                        report.add(path.to_owned(), Drift::KindDiffers {
+                            expected_kind: aspects.kind_str(),
+                            found_kind
+                        });
+                        continue;
+                    }
+                    self.add_drift_of_existing(
+                        &mut report, path, aspects, &target_path, &tracked_files
+                    )?;
+                }
+            }
+        }
+
+        report.entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(report)
+    }
+
+    /// Bring the target system in line with the specified files.
+    ///
+    /// Parents are handled before their children. Files are replaced
+    /// rather than modified in place, but directories are never replaced
+    /// by anything else, as that would mean removing their contents.
+    pub fn apply(&self, files: &[Box<dyn RepoExportedFile>]) -> FcResult<()> {
+        let tracked_files = Self::collect_tracked_files(files)?;
+        let resolved_root = self.get_resolved_root()?;
+
+        for (path, aspects) in &tracked_files {
+            let target_path = self.get_contained_target_path(&resolved_root, path)?;
+            match aspects {
+                RepoExportedFileAspects::NonExisting(non_existing_aspects) =>
+                    self.apply_non_existing(&target_path, non_existing_aspects)?,
+                RepoExportedFileAspects::Directory(directory_aspects) =>
+                    self.apply_directory(
+                        path, &target_path, directory_aspects, &tracked_files
+                    )?,
+                RepoExportedFileAspects::Ordinary(ordinary_aspects) =>
+                    self.apply_ordinary(&target_path, ordinary_aspects)?,
+                RepoExportedFileAspects::Symlink(symlink_aspects) =>
+                    self.apply_symlink(&target_path, symlink_aspects)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn collect_tracked_files(files: &[Box<dyn RepoExportedFile>])
+    -> FcResult<TrackedFiles<'_>> {
+        let mut tracked_files = BTreeMap::new();
+        for file in files {
+            tracked_files.insert(TrackedPath::new(file.get_path())?, file.get_aspects());
+        }
+        Ok(tracked_files)
+    }
+
+    fn add_drift_of_existing(
+        &self,
+        report: &mut DriftReport,
+        path: &TrackedPath,
+        aspects: &RepoExportedFileAspects,
+        target_path: &Path,
+        tracked_files: &TrackedFiles
+    ) -> FcResult<()> {
+        match aspects {
+            RepoExportedFileAspects::NonExisting(_) => (),
+            RepoExportedFileAspects::Directory(directory_aspects) => {
+                for untracked_path in self.get_disallowed_untracked_children(
+                    path, target_path, directory_aspects, tracked_files
+                )? {
+                    report.add(untracked_path, Drift::UntrackedInExclusiveDirectory);
+                }
+            },
+            RepoExportedFileAspects::Ordinary(ordinary_aspects) => {
+                let mut file = File::open(target_path)
+                    .map_err(fail("opening file to hash it", target_path))?;
+                // Hashing it the way the repo did, so the hashes compare.
+                let found_hash = HashAlgorithm::of_hash(&ordinary_aspects.repo_blob_hash)?
+                    .hash_readable(&mut file)?;
+                if found_hash != normalize_hash(&ordinary_aspects.repo_blob_hash) {
+                    report.add(path.to_owned(), Drift::ContentDiffers {
+                        repo_blob_hash: ordinary_aspects.repo_blob_hash.to_owned(),
+                        found_hash
+                    });
+                }
+            },
+            RepoExportedFileAspects::Symlink(symlink_aspects) => {
+                let found_linked_to = fs::read_link(target_path)
+                    .map_err(fail("reading symlink", target_path))?;
+                if found_linked_to.as_os_str() != OsStr::new(&symlink_aspects.linked_to) {
+                    report.add(path.to_owned(), Drift::LinkTargetDiffers {
+                        linked_to: symlink_aspects.linked_to.to_owned(),
+                        found_linked_to: found_linked_to.into_os_string()
+                    });
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Returns the paths of the children of an exclusive directory which
+    /// are neither tracked nor allowed. Always empty for shared directories.
+    fn get_disallowed_untracked_children(
+        &self,
+        path: &TrackedPath,
+        target_path: &Path,
+        directory_aspects: &RepoExportedDirectoryAspects,
+        tracked_files: &TrackedFiles
+    ) -> FcResult<Vec<TrackedPath>> {
+        let mut disallowed = vec!();
+        if !directory_aspects.mode.is_exclusive() {
+            return Ok(disallowed)
+        }
+        let entries = fs::read_dir(target_path)
+            .map_err(fail("listing directory", target_path))?;
+        for entry in entries {
+            let file_name = entry.map_err(fail("listing directory", target_path))?
+                .file_name();
+            let child_path = path.join(&file_name)?;
+            if !tracked_files.contains_key(&child_path)
+            && !directory_aspects.mode.allows_untracked(&file_name) {
+                disallowed.push(child_path);
+            }
+        }
+        disallowed.sort();
+        Ok(disallowed)
+    }
+
+    fn apply_non_existing(
+        &self,
+        target_path: &Path,
+        non_existing_aspects: &RepoExportedNonExistingAspects
+    ) -> FcResult<()> {
+        let metadata = match self.get_metadata(target_path)? {
+            Some(metadata) => metadata,
+            None => return Ok(()),
+        };
+        if !non_existing_aspects.is_in_the_way(get_found_kind(&metadata.file_type())) {
+            return Ok(())
+        }
+        match metadata.is_dir() {
+            true if non_existing_aspects.recursive => self.remove_tree(target_path),
+            true => fs::remove_dir(target_path)
+                .map_err(fail("removing directory (not recursive)", target_path)),
+            false => fs::remove_file(target_path)
+                .map_err(fail("removing file", target_path)),
+        }
+    }
+
+    fn apply_directory(
+        &self,
+        path: &TrackedPath,
+        target_path: &Path,
+        directory_aspects: &RepoExportedDirectoryAspects,
+        tracked_files: &TrackedFiles
+    ) -> FcResult<()> {
+        match self.get_metadata(target_path)? {
+            Some(metadata) if metadata.is_dir() => (),
+            Some(_) => {
+                fs::remove_file(target_path)
+                    .map_err(fail("removing file to replace it", target_path))?;
+                fs::create_dir(target_path)
+                    .map_err(fail("creating directory", target_path))?;
+            },
+            None => fs::create_dir(target_path)
+                .map_err(fail("creating directory", target_path))?,
+        }
+        for untracked_path in self.get_disallowed_untracked_children(
+            path, target_path, directory_aspects, tracked_files
+        )? {
+            self.remove_untracked(&self.get_target_path(&untracked_path))?;
+        }
+        Ok(())
+    }
+
+    fn apply_ordinary(
+        &self,
+        target_path: &Path,
+        ordinary_aspects: &RepoExportedOrdinaryAspects
+    ) -> FcResult<()> {
+        self.refuse_to_replace_directory(target_path)?;
+        // Writing next to the target and renaming it into place, so
+        // there's never a half-written file at the target path.
+        let tmp_path = get_tmp_path(target_path);
+        let mut blob_readable = ordinary_aspects.blob_provider.get_readable()?;
+        let mut tmp_file = File::create(&tmp_path)
+            .map_err(fail("creating temporary file", &tmp_path))?;
+        io::copy(&mut blob_readable, &mut tmp_file)
+            .map_err(fail("writing temporary file", &tmp_path))?;
+        fs::rename(&tmp_path, target_path)
+            .map_err(fail("renaming temporary file into place", target_path))
+    }
+
+    fn apply_symlink(
+        &self,
+        target_path: &Path,
+        symlink_aspects: &RepoExportedSymlinkAspects
+    ) -> FcResult<()> {
+        self.refuse_to_replace_directory(target_path)?;
+        if let Some(metadata) = self.get_metadata(target_path)? {
+            if metadata.file_type().is_symlink() && fs::read_link(target_path)
+                .map_err(fail("reading symlink", target_path))?
+                .as_os_str() == OsStr::new(&symlink_aspects.linked_to) {
+                return Ok(())
+            }
+            fs::remove_file(target_path)
+                .map_err(fail("removing file to replace it", target_path))?;
+        }
+        symlink(&symlink_aspects.linked_to, target_path)
+            .map_err(fail("creating symlink", target_path))
+    }
+
+    fn remove_untracked(&self, target_path: &Path) -> FcResult<()> {
+        match self.get_metadata(target_path)? {
+            None => Ok(()),
+            Some(metadata) if metadata.is_dir() => self.remove_tree(target_path),
+            Some(_) => fs::remove_file(target_path)
+                .map_err(fail("removing untracked file", target_path)),
+        }
+    }
+
+    /// Removes a directory with all its contents, unless anything in it
+    /// (including the directory itself) is on a different device than
+    /// its parent directory, in which case nothing is removed at all.
+    ///
+    /// That's to make sure that removing e.g. a chroot doesn't also wipe
+    /// whatever's mounted inside of it.
+    fn remove_tree(&self, target_path: &Path) -> FcResult<()> {
+        let parent_path = target_path.parent().unwrap_or(target_path);
+        let parent_device = fs::symlink_metadata(parent_path)
+            .map_err(fail("getting metadata", parent_path))?
+            .dev();
+        self.remove_tree_on_device(target_path, parent_device)
+    }
+
+    /* Notes:
+        The whole tree is checked before anything is removed, and then
+        every entry is checked again right before it's removed or
+        descended into, as something might have been mounted in between.
+        That's also why this doesn't leave the removal to
+        `fs::remove_dir_all`, which would descend into a mount point.
+    */
+    /// Removes the file or directory tree at the path, like `remove_tree`,
+    /// as long as everything in it is on the specified device.
+    pub(crate) fn remove_tree_on_device(&self, target_path: &Path, device: u64)
+    -> FcResult<()> {
+        self.refuse_to_cross_mount_points(target_path, device)?;
+        self.remove_entries_on_device(target_path, device)
+    }
+
+    fn remove_entries_on_device(&self, target_path: &Path, device: u64)
+    -> FcResult<()> {
+        let metadata = self.get_metadata_on_device(target_path, device)?;
+        if !metadata.is_dir() {
+            return fs::remove_file(target_path)
+                .map_err(fail("removing file recursively", target_path))
+        }
+        let entries = fs::read_dir(target_path)
+            .map_err(fail("listing directory", target_path))?;
+        for entry in entries {
+            let entry = entry.map_err(fail("listing directory", target_path))?;
+            self.remove_entries_on_device(&entry.path(), device)?;
+        }
+        fs::remove_dir(target_path)
+            .map_err(fail("removing directory recursively", target_path))
+    }
+
+    fn refuse_to_cross_mount_points(&self, target_path: &Path, device: u64)
+    -> FcResult<()> {
+        let metadata = self.get_metadata_on_device(target_path, device)?;
+        if metadata.is_dir() {
+            let entries = fs::read_dir(target_path)
+                .map_err(fail("listing directory", target_path))?;
+            for entry in entries {
+                let entry = entry.map_err(fail("listing directory", target_path))?;
+                self.refuse_to_cross_mount_points(&entry.path(), device)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the metadata of the file at the path without following
+    /// symlinks, refusing to go on if it's not on the specified device.
+    fn get_metadata_on_device(&self, target_path: &Path, device: u64)
+    -> FcResult<Metadata> {
+        let metadata = fs::symlink_metadata(target_path)
+            .map_err(fail("getting metadata", target_path))?;
+        if metadata.dev() != device {
+            return Err(error!(
+                ErrorKind::TargetSystemConflict,
+                "Refusing to remove a directory tree spanning multiple mount points.",
+                payload => TargetSystemErrorPayload {
+                    path: target_path.to_owned(),
+                    action: "recursing across a mount point"
+                }
+            ))
+        }
+        Ok(metadata)
+    }
+
+    fn refuse_to_replace_directory(&self, target_path: &Path) -> FcResult<()> {
+        match self.get_metadata(target_path)? {
+            Some(metadata) if metadata.is_dir() => Err(error!(
+                ErrorKind::TargetSystemConflict,
+                "Refusing to replace a directory with a file.",
+                payload => TargetSystemErrorPayload {
+                    path: target_path.to_owned(),
+                    action: "replacing directory"
+                }
+            )),
+            _ => Ok(())
+        }
+    }
+
+    /// Returns the metadata of the file at the path without following
+    /// symlinks, or None if there's nothing there.
+    fn get_metadata(&self, target_path: &Path) -> FcResult<Option<Metadata>> {
+        match fs::symlink_metadata(target_path) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(fail("getting metadata", target_path)(error)),
+        }
+    }
+}
+
+/// Returns the kind of a file found on the target system, matching
+/// the kinds of `TrackedFileAspects::kind_str`.
+fn get_found_kind(file_type: &FileType) -> &'static str {
+    if file_type.is_symlink() {
+        "symlink"
+    }
+    else if file_type.is_dir() {
+        "directory"
+    }
+    else if file_type.is_file() {
+        "ordinary"
+    }
+    else {
+        "other"
+    }
+}
+
+fn get_tmp_path(target_path: &Path) -> PathBuf {
+    let mut tmp_file_name = OsStr::new(".").to_owned();
+    if let Some(file_name) = target_path.file_name() {
+        tmp_file_name.push(file_name);
+    }
+    tmp_file_name.push(".fc-tmp");
+    target_path.with_file_name(tmp_file_name)
+}
+
+/// Wraps an io::Error into a TargetSystemOperationFailed error.
+fn fail<'path>(action: &'static str, target_path: &'path Path)
+-> impl FnOnce(io::Error) -> Error + 'path {
+    move |io_error| error!(
+        kind => ErrorKind::TargetSystemOperationFailed,
+        context => "Operating on a local target system.",
+        payload => TargetSystemErrorPayload {
+            path: target_path.to_owned(),
+            action
+        },
+        wrapped => WrappedError::Io(io_error)
+    )
+}
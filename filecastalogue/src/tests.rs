@@ -8,5 +8,8 @@ mod test_utils;
 mod test_fixtures;
 
 // Tests.
+mod files;
 mod meta;
-mod repo;
\ No newline at end of file
+mod lock;
+mod repo;
+mod target_system;
\ No newline at end of file
@@ -6,6 +6,7 @@ pub(crate) const MINIMAL_REPO_SITE: RepoTestSite<TmpTestDir> = RepoTestSite {
     conf: RepoTestConf {
         relative_repo_base_path: "minimal_repo",
         relative_blob_dir_path: "blobs",
+        relative_index_dir_path: "indexes",
         relative_state_file_path: "state.json"
     },
 };
\ No newline at end of file
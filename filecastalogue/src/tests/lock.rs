@@ -0,0 +1,125 @@
+use std::fs::{read, write};
+use std::mem::forget;
+use std::process::Command;
+use std::sync::{Arc, Barrier};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+use crate::error::{Error, ErrorKind, FcTestResult};
+use crate::lock::{LockHolder, LockMode, RepoLock};
+use crate::lock::drivers::local::LocalLock;
+use crate::tests::test_ids::TestIDs;
+use crate::tests::test_utils::{BaseTestDir, SafeTestPathJoin, TmpTestDir};
+
+/// An exclusive holder keeps everybody else out, with the error naming it,
+/// while shared holders only keep out exclusive ones.
+#[test]
+fn exclusive_holder_blocks_others_and_is_named() -> FcTestResult<()> {
+    let test_id = TestIDs::LockExclusiveHolderBlocksOthersAndIsNamed.as_str();
+    let lock_path = TmpTestDir {}.set_up(test_id)?.safe_join("lock")?;
+    let mut writer = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+    let mut reader = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+    let mut other_reader = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+
+    writer.acquire(LockMode::Exclusive)?;
+    let result = reader.acquire(LockMode::Shared);
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::RepoLocked, .. })),
+        "Acquiring a shared lock while it's held exclusively didn't fail: {:?}", result
+    );
+    let message = format!("{:?}", result);
+    assert!(message.contains(&format!("process {}", std::process::id())), "{}", message);
+
+    writer.release()?;
+    reader.acquire(LockMode::Shared)?;
+    other_reader.acquire(LockMode::Shared)?;
+    assert!(writer.acquire(LockMode::Exclusive).is_err());
+    reader.release()?;
+    other_reader.release()?;
+    writer.acquire(LockMode::Exclusive)?;
+    Ok(()).into()
+}
+
+/// A holder file left behind by a process that's gone doesn't keep
+/// anybody out.
+#[test]
+fn stale_holder_of_dead_process_is_removed() -> FcTestResult<()> {
+    let test_id = TestIDs::LockStaleHolderOfDeadProcessIsRemoved.as_str();
+    let lock_path = TmpTestDir {}.set_up(test_id)?.safe_join("lock")?;
+    let mut crashed = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+    crashed.acquire(LockMode::Exclusive)?;
+    // Holding it as a process that has exited, never releasing it.
+    let holder_file_path = lock_path.safe_join("exclusive")?;
+    let mut holder: LockHolder = serde_json::from_slice(&read(&holder_file_path)?)?;
+    let mut child = Command::new("true").spawn()?;
+    holder.pid = child.id();
+    child.wait()?;
+    write(&holder_file_path, serde_json::to_vec(&holder)?)?;
+    forget(crashed);
+
+    let mut lock = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+    lock.acquire(LockMode::Exclusive)?;
+    let holder: LockHolder = serde_json::from_slice(&read(&holder_file_path)?)?;
+    assert_eq!(holder.pid, std::process::id());
+    Ok(()).into()
+}
+
+/// Processes finding the same holder file left behind by a process that's
+/// gone don't remove each other's holder files along with it, so only one
+/// of them gets to hold the lock at a time.
+#[test]
+fn stale_holder_is_removed_once_when_raced_for() -> FcTestResult<()> {
+    const RACER_COUNT: usize = 8;
+    const ROUND_COUNT: usize = 500;
+    let test_id = TestIDs::LockStaleHolderIsRemovedOnceWhenRacedFor.as_str();
+    let lock_path = TmpTestDir {}.set_up(test_id)?.safe_join("lock")?;
+    let holder_file_path = lock_path.safe_join("exclusive")?;
+    let mut crashed = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+    crashed.acquire(LockMode::Exclusive)?;
+    let mut stale_holder: LockHolder = serde_json::from_slice(&read(&holder_file_path)?)?;
+    crashed.release()?;
+    let mut child = Command::new("true").spawn()?;
+    stale_holder.pid = child.id();
+    child.wait()?;
+    let stale_holder_json = serde_json::to_vec(&stale_holder)?;
+
+    for _ in 0..ROUND_COUNT {
+        write(&holder_file_path, &stale_holder_json)?;
+        let barrier = Arc::new(Barrier::new(RACER_COUNT));
+        let holder_count = Arc::new(AtomicUsize::new(0));
+        let racers: Vec<_> = (0..RACER_COUNT).map(|_| {
+            let lock_path = lock_path.clone();
+            let barrier = Arc::clone(&barrier);
+            let holder_count = Arc::clone(&holder_count);
+            // Returns how many others held the lock along with the racer,
+            // if it got to hold it at all.
+            thread::spawn(move || -> Result<Option<usize>, String> {
+                let mut lock = LocalLock::new_with_timeout(&lock_path, Duration::ZERO);
+                barrier.wait();
+                match lock.acquire(LockMode::Exclusive) {
+                    Ok(()) => (),
+                    Err(Error { kind: ErrorKind::RepoLocked, .. }) => return Ok(None),
+                    Err(e) => return Err(format!("{:?}", e.kind)),
+                }
+                let other_holder_count = holder_count.fetch_add(1, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(1));
+                holder_count.fetch_sub(1, Ordering::SeqCst);
+                lock.release().map_err(|e| format!("{:?}", e.kind))?;
+                Ok(Some(other_holder_count))
+            })
+        }).collect();
+        let mut winner_count = 0;
+        for racer in racers {
+            match racer.join().expect("A racer panicked.") {
+                Ok(Some(other_holder_count)) => {
+                    assert_eq!(other_holder_count, 0, "The lock was held by more than one racer.");
+                    winner_count += 1;
+                },
+                Ok(None) => (),
+                Err(e) => panic!("A racer failed: {}", e),
+            }
+        }
+        assert!(winner_count > 0, "Nobody got to hold the lock.");
+    }
+    Ok(()).into()
+}
@@ -1,8 +1,11 @@
-use crate::{error::FcTestResult, meta::state, meta::{state::accessor::StateAccessor, version::model::Version}, meta::version, tests::test_fixtures::{
+use crate::{error::{Error, ErrorKind, FcTestResult}, meta::state, meta::tracked_path::model::TrackedPath, meta::{state::accessor::StateAccessor, version::model::Version}, meta::version, tests::test_fixtures::{
         self,
         models::NON_EXISTENT_VERSION_ID
     }};
 use super::test_fixtures::models::MINIMAL_STATE_VERSION_ID;
+use crate::meta::file_aspects::aspects::directory::{DirectoryMode, TrackableDirectoryAspects};
+use crate::meta::file_aspects::aspects::non_existing::{NonExistingKindConstraint, TrackableNonExistingAspects};
+use crate::tests::test_fixtures::models::create_root_attributes;
 
 // This is a proxy for "is the State struct serializing using serde_json?".
 // It's a baseline check as to whether anything is working at all, really.
@@ -87,4 +90,69 @@ fn del_version() -> () {
     MINIMAL_STATE_VERSION_ID
 );
     assert_ne!(state.has_version(MINIMAL_STATE_VERSION_ID), true);
-}
\ No newline at end of file
+}
+
+#[test]
+fn tracked_path_normalizes_separators() -> FcTestResult<()> {
+    assert_eq!(
+        TrackedPath::new("//etc//foo/")?,
+        TrackedPath::new("/etc/foo")?
+    );
+    assert_eq!(TrackedPath::new("///")?.as_os_str(), "/");
+    Ok(()).into()
+}
+
+#[test]
+fn tracked_path_rejects_invalid_paths() -> () {
+    for path in ["etc/foo", "/etc/./foo", "/etc/../etc/foo", "/etc/fo\0o"] {
+        let result = TrackedPath::new(path);
+        assert!(
+            matches!(result, Err(Error { kind: ErrorKind::InvalidTrackedPath, .. })),
+            "Path {:?} wasn't rejected. Result: {:?}", path, result
+        );
+    }
+}
+
+/// A shared directory, which is what all of them were before there were
+/// modes, is written without one, so indexes keep their hash.
+#[test]
+fn directory_mode_is_left_out_if_shared() -> FcTestResult<()> {
+    let shared = TrackableDirectoryAspects::new(create_root_attributes());
+    let shared_json = serde_json::to_value(&shared)?;
+    assert_eq!(shared_json.get("mode"), None);
+    assert_eq!(serde_json::from_value::<TrackableDirectoryAspects>(shared_json)?, shared);
+
+    let exclusive = TrackableDirectoryAspects::new_with_mode(
+        create_root_attributes(), DirectoryMode::Exclusive { allowed: vec!() });
+    let exclusive_json = serde_json::to_value(&exclusive)?;
+    assert!(exclusive_json.get("mode").is_some());
+    assert_eq!(serde_json::from_value::<TrackableDirectoryAspects>(exclusive_json)?, exclusive);
+    Ok(()).into()
+}
+
+/// A non-existing file without constraints, which is what all of them
+/// were before there were any, is written without them, so indexes keep
+/// their hash.
+#[test]
+fn non_existing_constraints_are_left_out_if_unset() -> FcTestResult<()> {
+    let unconstrained = TrackableNonExistingAspects::new();
+    let unconstrained_json = serde_json::to_value(&unconstrained)?;
+    assert_eq!(unconstrained_json, serde_json::json!({}));
+    assert_eq!(
+        serde_json::from_value::<TrackableNonExistingAspects>(unconstrained_json)?,
+        unconstrained
+    );
+
+    let constrained = TrackableNonExistingAspects::new_with_constraints(
+        true, Some(NonExistingKindConstraint::Directory));
+    let constrained_json = serde_json::to_value(&constrained)?;
+    assert_eq!(
+        constrained_json,
+        serde_json::json!({ "recursive": true, "only_if_kind": "directory" })
+    );
+    assert_eq!(
+        serde_json::from_value::<TrackableNonExistingAspects>(constrained_json)?,
+        constrained
+    );
+    Ok(()).into()
+}
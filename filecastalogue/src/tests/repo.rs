@@ -1,18 +1,51 @@
-use std::ffi::OsString;
-use crate::error::FcTestResult;
-use crate::meta::file_aspects::aspects::directory::TrackableDirectoryAspects;
-use crate::meta::file_aspects::aspects::non_existing::TrackableNonExistingAspects;
+use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
+use std::fs::{File, create_dir_all, read_dir, write};
+use std::io::{self, Cursor, Read, Write};
+use std::os::unix::fs::symlink;
+use std::sync::{Arc, Mutex};
+use crate::error::{Error, ErrorKind, FcResult, FcTestResult};
+use crate::files::hashable::{HashAlgorithm, get_file_name_of_hash, hash_readable};
+use crate::files::index_collection::{IndexFileCollection, MiscIndexFileCollection};
+use crate::files::state_collection::{MiscStateFileCollection, StateFileCollection};
+use crate::files::tracked_ordinary_blob_collection::{MiscTrackedOrdinaryBlobFileCollection, TrackedOrdinaryBlobFileCollection};
+use crate::globals::STATE_FILE_NAME;
+use crate::journal::{Journal, JournalRecord, OptimisticDummyJournal};
+use crate::lock::{LockMode, RepoLock};
+use crate::meta::file_aspects::aspects::directory::{DirectoryMode, TrackableDirectoryAspects, TrackedDirectoryAspects};
+use crate::meta::file_aspects::aspects::non_existing::{NonExistingKindConstraint, TrackableNonExistingAspects, TrackedNonExistingAspects};
+use crate::meta::file_aspects::aspects::ordinary::TrackableOrdinaryAspects;
+use crate::meta::file_aspects::aspects::symlink::TrackableSymlinkAspects;
 use crate::meta::file_aspects::attributes::Attributes;
-use crate::meta::repo_exported_file_list::model::RepoExportedVecFileList;
+use crate::meta::file_aspects::enums::{RepoExportedFileAspects, TrackedFileAspects};
+use crate::meta::index::consistency::IndexConsistencyRules;
+use crate::meta::index::model::Index;
+use crate::meta::repo_exported_file_list::manifest::{Manifest, ManifestFile, ManifestFileAspects, ManifestFileList, ManifestFormat, ManifestHeader, MANIFEST_FORMAT_VERSION};
+use crate::meta::repo_exported_file_list::model::{RepoExportedVecFileList, UnrepresentedAspect};
+use crate::meta::repo_exported_file_list::tarball::TarballFileList;
+use crate::meta::state::accessor::StateAccessor;
+use crate::meta::tracked_path::model::TrackedPath;
+use crate::meta::version::accessor::VersionAccessor;
+use crate::meta::version::model::Version;
+use crate::opaque_collection_handler::OpaqueCollectionHandler;
+use crate::opaque_collection_handler::drivers::fault_injecting::{Fault, FaultInjectingHandler, FaultInjector};
+use crate::opaque_collection_handler::drivers::memory::MemoryCollection;
+use crate::repo::Repo;
+use crate::repo::archive::{ArchiveCompression, TarRepo};
+use crate::repo::sqlite::{SqliteRepo, convert_local_dir_to_sqlite, convert_sqlite_to_local_dir};
+use crate::repo::sync::SyncOutcome;
 // Instead of importing all fixtures directly, we prefix
 // calls to fixtures with `test_fixtures`, to make things clearer.
 use crate::tests::test_fixtures;
+use crate::tests::test_fixtures::models::{EMPTY_STATE_JSON, create_root_attributes};
+use crate::tests::TEST_CONF;
 // For as long as constants aren't used regularly in the code being
 // tested, dropping the "prefix" idea for them is worth the shorter
 // statements. Refactor once this gets confusing for a particular
 // category.
 use crate::tests::test_fixtures::repo::NON_EXISTING_VERSION_INDEX;
 use crate::tests::test_ids::TestIDs;
+use crate::tests::test_utils::{BaseTestDir, TmpTestDir};
 
 #[test]
 fn has_version_returns_false_when_repo_does_not_have_version() -> FcTestResult<()> {
@@ -44,7 +77,7 @@ fn add_version_succeeds() -> FcTestResult<()> {
 /// Comprehensive happy path testing of `Repo::track_non_existing`.
 #[test]
 fn track_non_existing_succeeds() -> FcTestResult<()> {
-    let file_path = OsString::from("/this/does/not/exist");
+    let file_path = TrackedPath::new("/this/does/not/exist")?;
     let trackable_aspects = TrackableNonExistingAspects::new();
     
     let mut repo = test_fixtures::repo::create_minimal_repo_struct(
@@ -62,7 +95,7 @@ fn track_non_existing_succeeds() -> FcTestResult<()> {
     repo.get_files(new_version_index, &mut file_list)?;
 
     assert!(file_list.into_iter().any(
-        |tracked_file| -> bool { tracked_file.get_path() == file_path }
+        |tracked_file| -> bool { tracked_file.get_path() == file_path.as_os_str() }
     ));
 
     Ok(()).into()
@@ -73,12 +106,13 @@ fn track_non_existing_succeeds() -> FcTestResult<()> {
 fn track_directory_succeeds() -> FcTestResult<()> {
     const USER_NAME: &str = "test_user";
     const GROUP_NAME: &str = "test_group";
-    let dir_path = OsString::from("/this/dir/does/not_exist");
+    let dir_path = TrackedPath::new("/this/dir/does/not_exist")?;
     let trackable_aspects: TrackableDirectoryAspects = TrackableDirectoryAspects::new(
         // The `Attribute` struct will actually need some looking-at.
         Attributes {
             posix_user: String::from(USER_NAME),
-            posix_group: String::from(GROUP_NAME)
+            posix_group: String::from(GROUP_NAME),
+            posix_mode: None
         }
     );
 
@@ -97,8 +131,1427 @@ fn track_directory_succeeds() -> FcTestResult<()> {
     repo.get_files(new_version_index, &mut file_list)?;
 
     assert!(file_list.into_iter().any(
-        |tracked_file| -> bool { tracked_file.get_path() == dir_path }
+        |tracked_file| -> bool { tracked_file.get_path() == dir_path.as_os_str() }
     ));
 
     Ok(()).into()
-}
\ No newline at end of file
+}
+
+/// Tracking a directory below a path tracked as non-existing has to be
+/// refused before the index gets written.
+#[test]
+fn track_file_below_non_existing_fails() -> FcTestResult<()> {
+    let mut repo = test_fixtures::repo::create_minimal_repo_struct(
+        TestIDs::RepoTrackFileBelowNonExistingFails.as_str()
+    )?;
+    let version_index = repo.add_version()?;
+    repo.track_non_existing(
+        version_index,
+        TrackedPath::new("/etc/app")?,
+        TrackableNonExistingAspects::new()
+    )?;
+
+    let result = repo.track_directory(
+        version_index,
+        TrackedPath::new("/etc/app/conf.d")?,
+        TrackableDirectoryAspects::new(Attributes {
+            posix_user: String::from("root"),
+            posix_group: String::from("root"),
+            posix_mode: None
+        })
+    ).map(|_| ());
+
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::IndexInconsistent, .. })),
+        "Tracking below a non-existing path didn't fail. Result: {:?}", result
+    );
+    Ok(()).into()
+}
+
+#[test]
+fn track_adds_implied_parent_directories() -> FcTestResult<()> {
+    let attributes = Attributes {
+        posix_user: String::from("root"),
+        posix_group: String::from("root"),
+        posix_mode: None
+    };
+    let mut repo = test_fixtures::repo::create_minimal_repo_struct(
+        TestIDs::RepoTrackAddsImpliedParentDirectories.as_str()
+    )?;
+    repo.index_consistency_rules = IndexConsistencyRules::with_implied_parent_directories(
+        TrackedDirectoryAspects::new(attributes.clone())
+    );
+    let version_index = repo.add_version()?;
+    repo.track_directory(
+        version_index,
+        TrackedPath::new("/etc/app/conf.d")?,
+        TrackableDirectoryAspects::new(attributes)
+    )?;
+
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    let mut paths: Vec<OsString> = file_list.into_iter()
+        .map(|tracked_file| tracked_file.get_path())
+        .collect();
+    paths.sort();
+
+    assert_eq!(paths, vec!["/", "/etc", "/etc/app", "/etc/app/conf.d"]);
+    Ok(()).into()
+}
+
+/// Tracking the same blob twice stores it once, under its hash, and
+/// reading it back from the exported file list yields the original.
+#[test]
+fn track_ordinary_streams_blob() -> FcTestResult<()> {
+    let content: Vec<u8> = (0..300_000u32).map(|i| (i % 251) as u8).collect();
+    let attributes = Attributes {
+        posix_user: String::from("root"),
+        posix_group: String::from("root"),
+        posix_mode: None
+    };
+    let mut repo = test_fixtures::repo::create_minimal_repo_struct(
+        TestIDs::RepoTrackOrdinaryStreamsBlob.as_str()
+    )?;
+    let version_index = repo.add_version()?;
+    for path in ["/etc/first", "/etc/second"] {
+        repo.track_ordinary(
+            version_index,
+            TrackedPath::new(path)?,
+            TrackableOrdinaryAspects::new(attributes.clone()),
+            &mut Cursor::new(&content)
+        )?;
+    }
+    let expected_hash = hash_readable(&mut Cursor::new(&content))?;
+    assert!(repo.blobs.has_file(&expected_hash)?);
+
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    let mut exported_count = 0;
+    for tracked_file in file_list {
+        if let RepoExportedFileAspects::Ordinary(aspects) = tracked_file.get_aspects() {
+            assert_eq!(aspects.repo_blob_hash, expected_hash);
+            let mut exported_content = vec!();
+            aspects.blob_provider.get_readable()?.read_to_end(&mut exported_content)?;
+            assert!(exported_content == content, "Exported blob differs from the tracked one.");
+            exported_count += 1;
+        }
+    }
+    assert_eq!(exported_count, 2);
+    Ok(()).into()
+}
+
+/// Provides `remaining` bytes, keeping track of how far the temporary file
+/// of the blob they're put as lags behind what's been read so far.
+struct LagMeasuringReadable {
+    blob_handler: MemoryCollection,
+    remaining: usize,
+    read_length: usize,
+    max_lag: usize,
+}
+
+impl Read for LagMeasuringReadable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let written_length = self.blob_handler.get_file_names().iter()
+            .filter(|name| name.to_string_lossy().starts_with(".tmp-"))
+            .filter_map(|name| self.blob_handler.get_file_content(name))
+            .map(|content| content.len())
+            .sum::<usize>();
+        self.max_lag = self.max_lag.max(self.read_length - written_length);
+        let length = buf.len().min(self.remaining);
+        buf[..length].fill(b'x');
+        self.remaining -= length;
+        self.read_length += length;
+        Ok(length)
+    }
+}
+
+/// A blob is written as it's read, so what's held in memory in between
+/// stays far below the size of the blob.
+#[test]
+fn put_readable_writes_blob_as_it_goes() -> FcTestResult<()> {
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut readable = LagMeasuringReadable {
+        blob_handler: repo.blobs.handler.clone(),
+        remaining: 8 * 1024 * 1024,
+        read_length: 0,
+        max_lag: 0,
+    };
+    let hash = repo.blobs.put_readable(&mut readable, HashAlgorithm::default())?;
+    assert!(repo.blobs.has_file(&hash)?);
+    assert!(
+        readable.max_lag <= 64 * 1024,
+        "{} bytes were read before being written.", readable.max_lag
+    );
+    Ok(()).into()
+}
+
+/// Whichever step of putting a blob fails, its temporary file doesn't
+/// stay behind in the collection.
+#[test]
+fn put_readable_removes_tmp_file_on_error() -> FcTestResult<()> {
+    let content = vec![b'x'; 100_000];
+    let faults = [Fault::Fail, Fault::TruncateWrite { length: 50_000 }];
+    for fault in faults {
+        for operation_number in 0.. {
+            let blob_handler = MemoryCollection::new();
+            let injector = FaultInjector::new();
+            injector.inject(operation_number, fault);
+            let mut blobs = MiscTrackedOrdinaryBlobFileCollection::new(
+                FaultInjectingHandler::new_with_injector(blob_handler.clone(), injector.clone()));
+            let result = blobs.put_readable(&mut Cursor::new(&content), HashAlgorithm::default());
+            if injector.has_pending_faults() {
+                result?;
+                break;
+            }
+            if result.is_err() {
+                assert_eq!(blob_handler.get_file_names(), Vec::<OsString>::new());
+            }
+        }
+    }
+    Ok(()).into()
+}
+
+/// Rehashing a repo puts its blobs and indexes under hashes made with the
+/// new algorithm and removes the old ones.
+#[test]
+fn rehash_switches_hash_algorithm() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoRehashSwitchesHashAlgorithm.as_str();
+    let content = b"PermitRootLogin no\n";
+    let mut repo = test_fixtures::repo::create_minimal_repo_struct(test_id)?;
+    // The minimal state refers to an index which doesn't exist, which
+    // rehashing rightly refuses to skip over.
+    TEST_CONF::MINIMAL_REPO_SITE.get_state_writeable(test_id)?
+        .write_all(EMPTY_STATE_JSON.as_bytes())?;
+    let version_index = repo.add_version()?;
+    repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/ssh/sshd_config")?,
+        TrackableOrdinaryAspects::new(Attributes {
+            posix_user: String::from("root"),
+            posix_group: String::from("root"),
+            posix_mode: None
+        }),
+        &mut Cursor::new(content)
+    )?;
+    let blake3_hash = hash_readable(&mut Cursor::new(content))?;
+    assert!(blake3_hash.starts_with("blake3:"));
+
+    repo.rehash(HashAlgorithm::Sha256)?;
+
+    let sha256_hash = HashAlgorithm::Sha256.hash_readable(&mut Cursor::new(content))?;
+    assert_eq!(repo.get_hash_algorithm()?, HashAlgorithm::Sha256);
+    assert!(repo.blobs.has_file(&sha256_hash)?);
+    assert!(!repo.blobs.has_file(&blake3_hash)?);
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    for tracked_file in file_list {
+        if let RepoExportedFileAspects::Ordinary(aspects) = tracked_file.get_aspects() {
+            assert_eq!(aspects.repo_blob_hash, sha256_hash);
+            let mut exported_content = vec!();
+            aspects.blob_provider.get_readable()?.read_to_end(&mut exported_content)?;
+            assert_eq!(exported_content, content);
+        }
+    }
+    Ok(()).into()
+}
+
+/// Old blobs and indexes which can't be removed once a repo has been
+/// rehashed are left behind, without failing the rehash.
+#[test]
+fn rehash_leaves_behind_what_it_cannot_remove() -> FcTestResult<()> {
+    let content = b"PermitRootLogin no\n";
+    let set_up = || -> FcResult<_> {
+        let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+        let version_index = repo.add_version()?;
+        repo.track_ordinary(
+            version_index,
+            TrackedPath::new("/etc/ssh/sshd_config")?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+        let injector = FaultInjector::new();
+        let faulty_repo = Repo::new(
+            MiscStateFileCollection::new(
+                repo.state_collection.handler.clone(), OsString::from(STATE_FILE_NAME)),
+            MiscIndexFileCollection::new(repo.indexes.handler.clone()),
+            MiscTrackedOrdinaryBlobFileCollection::new(FaultInjectingHandler::new_with_injector(
+                repo.blobs.handler.clone(), injector.clone())),
+            OptimisticDummyJournal::new()
+        );
+        let index_id = repo.state_collection.get_state_file()?
+            .get_state_ref()?.get_version(version_index)?.get_index_id();
+        Ok((faulty_repo, injector, index_id))
+    };
+    let (mut repo, injector, _) = set_up()?;
+    repo.rehash(HashAlgorithm::Sha256)?;
+    let operation_count = injector.get_operation_count();
+
+    // Removing the old blob is the last thing done with the blobs.
+    let (mut repo, injector, old_index_id) = set_up()?;
+    injector.inject(operation_count - 1, Fault::Fail);
+    repo.rehash(HashAlgorithm::Sha256)?;
+    assert!(!injector.has_pending_faults());
+    assert_eq!(repo.get_hash_algorithm()?, HashAlgorithm::Sha256);
+    assert!(repo.blobs.has_file(&HashAlgorithm::Sha256.hash_readable(&mut Cursor::new(content))?)?);
+    assert!(repo.blobs.has_file(&hash_readable(&mut Cursor::new(content))?)?);
+    assert!(!repo.indexes.has_index(&old_index_id.unwrap())?);
+    Ok(()).into()
+}
+
+fn get_trackable_root_ordinary_aspects() -> TrackableOrdinaryAspects {
+    TrackableOrdinaryAspects::new(Attributes {
+        posix_user: String::from("root"),
+        posix_group: String::from("root"),
+        posix_mode: None
+    })
+}
+
+/// Blobs put by an operation interrupted before it wrote the state are
+/// removed when the repo is opened again, unless the state refers to them.
+#[test]
+fn open_rolls_back_unfinished_operation() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoOpenRollsBackUnfinishedOperation.as_str();
+    let mut repo = test_fixtures::repo::create_empty_journaled_repo_struct(test_id)?;
+    let version_index = repo.add_version()?;
+    repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/hostname")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"tracked\n")
+    )?;
+    let journal_entry_id = repo.journal.begin("track_ordinary")?;
+    let mut put_hashes = vec!();
+    for content in [&b"tracked\n"[..], &b"interrupted\n"[..]] {
+        let hash = repo.blobs.put_readable(&mut Cursor::new(content), HashAlgorithm::default())?;
+        repo.journal.record(journal_entry_id, JournalRecord::PutBlob { hash: hash.to_owned() })?;
+        put_hashes.push(hash);
+    }
+    drop(repo);
+
+    let mut repo = test_fixtures::repo::open_journaled_repo_struct(test_id)?;
+    assert!(repo.blobs.has_file(&put_hashes[0])?);
+    assert!(!repo.blobs.has_file(&put_hashes[1])?);
+    assert!(repo.journal.get_unfinished_entries()?.is_empty());
+    Ok(()).into()
+}
+
+/// An operation interrupted while writing the state is finished when the
+/// repo is opened again.
+#[test]
+fn open_finishes_operation_with_recorded_state() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoOpenFinishesOperationWithRecordedState.as_str();
+    let mut repo = test_fixtures::repo::create_empty_journaled_repo_struct(test_id)?;
+    repo.add_version()?;
+    let mut state_file = repo.state_collection.get_state_file()?;
+    let mut state = state_file.get_state_ref()?.clone();
+    state.hash_algorithm = HashAlgorithm::Sha256;
+    let journal_entry_id = repo.journal.begin("set_hash_algorithm")?;
+    repo.journal.record(journal_entry_id, JournalRecord::PutState { state, started_from: None })?;
+    // Cut short halfway through.
+    TEST_CONF::MINIMAL_REPO_SITE.get_state_writeable(test_id)?.write_all(b"{\"vers")?;
+    drop(repo);
+
+    let mut repo = test_fixtures::repo::open_journaled_repo_struct(test_id)?;
+    assert_eq!(repo.get_hash_algorithm()?, HashAlgorithm::Sha256);
+    assert!(repo.has_version(0)?);
+    assert!(repo.journal.get_unfinished_entries()?.is_empty());
+    Ok(()).into()
+}
+
+/// An operation that fails is rolled back right away, so whatever the repo
+/// goes through after it sticks once it's opened again, wherever it failed.
+#[test]
+fn failed_operation_does_not_undo_later_ones() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoFailedOperationDoesNotUndoLaterOnes.as_str();
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    for operation_number in 0.. {
+        let collections = test_fixtures::repo::MemoryRepoCollections::new()?;
+        let journal_path = tmp_path.join(format!("journal-{}", operation_number));
+        let injector = FaultInjector::new();
+        let mut repo = test_fixtures::repo::open_faulty_memory_repo_struct(
+            &collections, &journal_path, &injector)?;
+        let version_index = repo.add_version()?;
+        injector.inject_from_now(operation_number, Fault::Fail);
+        let result = repo.track_ordinary(
+            version_index,
+            TrackedPath::new("/etc/motd")?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(b"failed\n")
+        ).map(|_| ());
+        // Tracking took fewer operations than that if the fault is left.
+        let is_done = injector.has_pending_faults();
+        injector.clear();
+        repo.add_version()?;
+        let state = repo.state_collection.get_state_file()?.get_state_ref()?.clone();
+        drop(repo);
+
+        let mut repo = test_fixtures::repo::open_memory_repo_struct(&collections, &journal_path)?;
+        assert_eq!(repo.state_collection.get_state_file()?.get_state_ref()?, &state,
+            "Failing operation {} undid the version added after it.", operation_number);
+        assert!(repo.journal.get_unfinished_entries()?.is_empty());
+        if is_done {
+            assert!(result.is_ok(), "Tracking failed without a fault: {:?}", result);
+            break
+        }
+    }
+    Ok(()).into()
+}
+
+fn count_indexes(test_id: &str) -> FcResult<usize> {
+    Ok(read_dir(TEST_CONF::MINIMAL_REPO_SITE.get_index_dir_path(test_id)?)?.count())
+}
+
+/// However many files a batch tracks, it only writes one index.
+#[test]
+fn batch_commits_single_index() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoBatchCommitsSingleIndex.as_str();
+    let mut repo = test_fixtures::repo::create_empty_journaled_repo_struct(test_id)?;
+    let version_index = repo.add_version()?;
+    let index_count = count_indexes(test_id)?;
+
+    repo.batch(version_index, |batch| {
+        batch.track_directory(
+            TrackedPath::new("/etc/ssh")?,
+            TrackableDirectoryAspects::new(Attributes {
+                posix_user: String::from("root"),
+                posix_group: String::from("root"),
+                posix_mode: None
+            })
+        )?;
+        for name in ["sshd_config", "ssh_config"] {
+            batch.track_ordinary(
+                TrackedPath::new(format!("/etc/ssh/{}", name))?,
+                get_trackable_root_ordinary_aspects(),
+                &mut Cursor::new(name.as_bytes())
+            )?;
+        }
+        batch.untrack(&TrackedPath::new("/etc/ssh/ssh_config")?)?;
+        Ok(())
+    })?;
+
+    assert_eq!(count_indexes(test_id)?, index_count + 1);
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    assert_eq!(file_list.into_iter().count(), 2);
+    Ok(()).into()
+}
+
+/// A batch that fails leaves neither an index nor its blobs behind.
+#[test]
+fn batch_discards_everything_on_error() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoBatchDiscardsEverythingOnError.as_str();
+    let mut repo = test_fixtures::repo::create_empty_journaled_repo_struct(test_id)?;
+    let version_index = repo.add_version()?;
+    let index_count = count_indexes(test_id)?;
+    let content = b"discarded\n";
+
+    let result = repo.batch(version_index, |batch| {
+        for _ in 0..2 {
+            batch.track_ordinary(
+                TrackedPath::new("/etc/motd")?,
+                get_trackable_root_ordinary_aspects(),
+                &mut Cursor::new(content)
+            )?;
+        }
+        Ok(())
+    });
+
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::FileAlreadyTracked, .. })),
+        "Tracking the same file twice in a batch didn't fail: {:?}", result
+    );
+    assert_eq!(count_indexes(test_id)?, index_count);
+    assert!(!repo.blobs.has_file(&hash_readable(&mut Cursor::new(content))?)?);
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    assert_eq!(file_list.into_iter().count(), 0);
+    Ok(()).into()
+}
+
+/// Reads everything the version tracks, returning the content of each
+/// ordinary file by path.
+fn read_ordinary_files<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    J: Journal
+>(repo: &mut Repo<StateCollection, Indexes, Blobs, J>, version_index: usize)
+-> FcResult<BTreeMap<OsString, Vec<u8>>> {
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    let mut contents = BTreeMap::new();
+    for tracked_file in file_list {
+        if let RepoExportedFileAspects::Ordinary(aspects) = tracked_file.get_aspects() {
+            let mut content = vec!();
+            aspects.blob_provider.get_readable()?.read_to_end(&mut content)?;
+            contents.insert(tracked_file.get_path(), content);
+        }
+    }
+    Ok(contents)
+}
+
+/// Whichever step of tracking a file fails or gets cut short, the repo
+/// either ends up tracking it or not tracking it once it's opened again,
+/// with everything tracked before left as it was.
+#[test]
+fn faults_leave_repo_recoverable() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoFaultsLeaveRepoRecoverable.as_str();
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    let kept_path = OsString::from("/etc/hostname");
+    let added_path = OsString::from("/etc/motd");
+
+    for (fault_number, fault) in [Fault::Fail, Fault::TruncateWrite { length: 1 }]
+    .iter().copied().enumerate() {
+        for operation_number in 0.. {
+            let collections = test_fixtures::repo::MemoryRepoCollections::new()?;
+            let journal_path = tmp_path.join(
+                format!("journal-{}-{}", fault_number, operation_number));
+            let mut repo = test_fixtures::repo::open_memory_repo_struct(
+                &collections, &journal_path)?;
+            let version_index = repo.add_version()?;
+            repo.track_ordinary(
+                version_index,
+                TrackedPath::new(&kept_path)?,
+                get_trackable_root_ordinary_aspects(),
+                &mut Cursor::new(b"kept\n")
+            )?;
+            drop(repo);
+
+            let injector = FaultInjector::new();
+            let mut repo = test_fixtures::repo::open_faulty_memory_repo_struct(
+                &collections, &journal_path, &injector)?;
+            injector.inject_from_now(operation_number, fault);
+            let result = repo.track_ordinary(
+                version_index,
+                TrackedPath::new(&added_path)?,
+                get_trackable_root_ordinary_aspects(),
+                &mut Cursor::new(b"added\n")
+            ).map(|_| ());
+            drop(repo);
+
+            let mut repo = test_fixtures::repo::open_memory_repo_struct(
+                &collections, &journal_path)?;
+            let contents = read_ordinary_files(&mut repo, version_index)?;
+            assert_eq!(contents.get(&kept_path), Some(&b"kept\n".to_vec()),
+                "{:?} of operation {} broke what was tracked before.", fault, operation_number);
+            match contents.get(&added_path) {
+                Some(content) => assert_eq!(content, b"added\n"),
+                None => assert!(result.is_err(),
+                    "Tracking succeeded despite {:?} of operation {}, but didn't stick.",
+                    fault, operation_number),
+            }
+            if injector.has_pending_faults() {
+                // Tracking took fewer operations than that, so every one of
+                // them has gone wrong by now.
+                assert!(result.is_ok(), "Tracking failed without a fault: {:?}", result);
+                break
+            }
+        }
+    }
+    Ok(()).into()
+}
+
+/// Reads coming in bits and pieces are put back together, while corrupted
+/// reads fail instead of passing for the real thing.
+#[test]
+fn faulty_reads_are_handled() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoFaultyReadsAreHandled.as_str();
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = repo.add_version()?;
+    repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/hostname")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"in pieces\n")
+    )?;
+    let collections = test_fixtures::repo::MemoryRepoCollections {
+        state: repo.state_collection.handler.clone(),
+        indexes: repo.indexes.handler.clone(),
+        blobs: repo.blobs.handler.clone(),
+    };
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    let injector = FaultInjector::new();
+    let mut repo = test_fixtures::repo::open_faulty_memory_repo_struct(
+        &collections, &tmp_path.join("journal"), &injector)?;
+
+    for operation_number in 0..100 {
+        injector.inject_from_now(operation_number, Fault::ShortReads { max_length: 1 });
+    }
+    let contents = read_ordinary_files(&mut repo, version_index)?;
+    assert_eq!(contents.get(&OsString::from("/etc/hostname")), Some(&b"in pieces\n".to_vec()));
+
+    injector.clear();
+    for operation_number in 0..100 {
+        injector.inject_from_now(operation_number, Fault::CorruptRead { offset: 0 });
+    }
+    let result = repo.get_hash_algorithm();
+    assert!(result.is_err(), "Reading a corrupted state succeeded: {:?}", result);
+    injector.clear();
+    assert_eq!(repo.get_hash_algorithm()?, HashAlgorithm::default());
+
+    // Corrupting each read in turn, including the blob's.
+    let mut is_blob_corruption_detected = false;
+    let mut operations_from_now = 0;
+    loop {
+        injector.inject_from_now(operations_from_now, Fault::CorruptRead { offset: 0 });
+        match read_ordinary_files(&mut repo, version_index) {
+            Ok(contents) => assert_eq!(
+                contents.get(&OsString::from("/etc/hostname")),
+                Some(&b"in pieces\n".to_vec())
+            ),
+            Err(error) => is_blob_corruption_detected |=
+                matches!(error, Error { kind: ErrorKind::ContentHashMismatch, .. }),
+        }
+        if injector.has_pending_faults() {
+            injector.clear();
+            break
+        }
+        operations_from_now += 1;
+    }
+    assert!(is_blob_corruption_detected, "Reading a corrupted blob succeeded.");
+    Ok(()).into()
+}
+
+/// A repo written into a tar archive, compressed or not, can be read
+/// straight out of it, but not changed.
+#[test]
+fn tar_archive_opens_as_read_only_repo() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoTarArchiveOpensAsReadOnlyRepo.as_str();
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    for content in [&b"first\n"[..], &b"second\n"[..]].iter() {
+        let version_index = repo.add_version()?;
+        repo.track_ordinary(
+            version_index,
+            TrackedPath::new("/etc/motd")?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+    }
+
+    for (file_name, compression) in [
+        ("repo.tar", ArchiveCompression::None),
+        ("repo.tar.zst", ArchiveCompression::Zstd { level: 3 })
+    ].iter() {
+        let archive_path = tmp_path.join(file_name);
+        repo.write_tar_archive(File::create(&archive_path)?, *compression)?;
+        let mut archived_repo = TarRepo::open_tar_archive(&archive_path)?;
+        for version_index in 0..2 {
+            assert_eq!(
+                read_ordinary_files(&mut archived_repo, version_index)?,
+                read_ordinary_files(&mut repo, version_index)?
+            );
+        }
+        let result = archived_repo.add_version();
+        assert!(
+            matches!(result, Err(Error { kind: ErrorKind::ReadOnlyCollection, .. })),
+            "Changing a repo in a tar archive didn't fail as expected: {:?}", result
+        );
+    }
+    Ok(()).into()
+}
+
+/// Reads some content, then fails, like a file on a disk going bad.
+struct FailingReadable {
+    remaining_content: Cursor<Vec<u8>>,
+}
+
+impl Read for FailingReadable {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.remaining_content.read(buf)? {
+            0 => Err(io::Error::other("Disk went bad.")),
+            read_count => Ok(read_count),
+        }
+    }
+}
+
+/// Operations on a repo in a SQLite database that fail, or never get to
+/// complete, leave nothing behind, neither in the database nor in the way
+/// of other connections.
+#[test]
+fn sqlite_repo_rolls_back_unfinished_operations() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoSqliteRepoRollsBackUnfinishedOperations.as_str();
+    let database_path = TmpTestDir {}.set_up(test_id)?.join("repo.sqlite");
+    let mut repo = SqliteRepo::open_sqlite(&database_path)?;
+    let version_index = repo.add_version()?;
+    repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/hostname")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"kept\n")
+    )?;
+    let kept_files = read_ordinary_files(&mut repo, version_index)?;
+    let blob_file_names = repo.blobs.handler.get_file_names()?;
+
+    let result = repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/motd")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut FailingReadable { remaining_content: Cursor::new(b"partial".to_vec()) }
+    ).map(|_| ());
+    assert!(result.is_err(), "Tracking from a failing Read succeeded.");
+    assert_eq!(repo.blobs.handler.get_file_names()?, blob_file_names);
+
+    // Nor does writing a file that's never flushed.
+    repo.blobs.handler.create_file("unflushed")?;
+    {
+        let mut writeable = repo.blobs.handler.get_file_writeable(OsStr::new("unflushed"))?;
+        writeable.write_all(b"cut short\n")?;
+    }
+    let mut unflushed_content = vec!();
+    repo.blobs.handler.get_file_readable(OsStr::new("unflushed"))?
+        .read_to_end(&mut unflushed_content)?;
+    assert!(unflushed_content.is_empty(), "Unflushed file was stored.");
+    repo.blobs.handler.remove_file(OsStr::new("unflushed"))?;
+
+    // Dying in the middle of an operation never commits it.
+    repo.journal.begin("interrupted")?;
+    repo.blobs.put_readable(&mut Cursor::new(b"orphan\n"), HashAlgorithm::default())?;
+    drop(repo);
+    let mut repo = SqliteRepo::open_sqlite(&database_path)?;
+    assert_eq!(repo.blobs.handler.get_file_names()?, blob_file_names);
+    assert_eq!(read_ordinary_files(&mut repo, version_index)?, kept_files);
+    repo.add_version()?;
+    Ok(()).into()
+}
+
+/// A repo survives being moved from a local directory into a SQLite
+/// database and back unchanged.
+#[test]
+fn sqlite_repo_converts_to_and_from_local_dir() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoSqliteRepoConvertsToAndFromLocalDir.as_str();
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    let mut repo = test_fixtures::repo::create_empty_journaled_repo_struct(test_id)?;
+    let version_index = repo.add_version()?;
+    for (path, content) in [("/etc/hostname", &b"host\n"[..]), ("/etc/motd", &b"hello\n"[..])].iter() {
+        repo.track_ordinary(
+            version_index,
+            TrackedPath::new(path)?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+    }
+    let files = read_ordinary_files(&mut repo, version_index)?;
+    drop(repo);
+
+    let repo_path = TEST_CONF::MINIMAL_REPO_SITE.get_repo_path(test_id)?;
+    let database_path = tmp_path.join("repo.sqlite");
+    convert_local_dir_to_sqlite(&repo_path, &database_path)?;
+    assert_eq!(
+        read_ordinary_files(&mut SqliteRepo::open_sqlite(&database_path)?, version_index)?,
+        files
+    );
+    let result = convert_local_dir_to_sqlite(&repo_path, &database_path);
+    assert!(result.is_err(), "Converting into a database with a repo in it succeeded.");
+
+    let converted_repo_path = tmp_path.join("converted");
+    convert_sqlite_to_local_dir(&database_path, &converted_repo_path)?;
+    let mut converted_repo = test_fixtures::repo::open_local_repo_struct(&converted_repo_path)?;
+    assert_eq!(read_ordinary_files(&mut converted_repo, version_index)?, files);
+    Ok(()).into()
+}
+
+/// Syncing transfers only what the destination is missing, leaves a
+/// destination that's ahead alone and refuses to go on when both repos
+/// changed since they were last synced.
+#[test]
+fn sync_transfers_missing_objects_and_detects_conflicts() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    for (path, content) in [("/etc/hostname", &b"host\n"[..]), ("/etc/motd", &b"hello\n"[..])].iter() {
+        source.track_ordinary(
+            version_index,
+            TrackedPath::new(path)?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+    }
+
+    let report = source.sync_to(&mut destination, None)?;
+    assert_eq!(report.outcome, SyncOutcome::Updated);
+    assert_eq!(
+        report.transferred_index_ids.len(),
+        destination.indexes.handler.get_file_names().len()
+    );
+    assert_eq!(report.transferred_blob_hashes.len(), 2);
+    assert_eq!(
+        read_ordinary_files(&mut destination, version_index)?,
+        read_ordinary_files(&mut source, version_index)?
+    );
+    let report = source.sync_to(&mut destination, Some(&report.state))?;
+    assert_eq!(report.outcome, SyncOutcome::UpToDate);
+
+    source.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/issue")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"welcome\n")
+    )?;
+    let report = destination.sync_from(&mut source, Some(&report.state))?;
+    assert_eq!(report.outcome, SyncOutcome::Updated);
+    assert_eq!(report.transferred_blob_hashes, vec!(hash_readable(&mut &b"welcome\n"[..])?));
+    let base = report.state;
+
+    destination.add_version()?;
+    let report = source.sync_to(&mut destination, Some(&base))?;
+    assert_eq!(report.outcome, SyncOutcome::DestinationAhead);
+    source.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/issue.net")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"goodbye\n")
+    )?;
+    let result = source.sync_to(&mut destination, Some(&base));
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::SyncConflict, .. })),
+        "Syncing repos which both changed didn't fail as expected: {:?}", result
+    );
+    Ok(()).into()
+}
+
+/// A lock that's always free, recording whose lock is acquired when.
+struct RecordingLock {
+    id: String,
+    acquired_ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl RepoLock for RecordingLock {
+    fn acquire(&mut self, _mode: LockMode) -> FcResult<()> {
+        self.acquired_ids.lock().unwrap().push(self.id.to_owned());
+        Ok(())
+    }
+
+    fn release(&mut self) -> FcResult<()> {
+        Ok(())
+    }
+
+    fn get_lock_id(&self) -> Option<String> {
+        Some(self.id.to_owned())
+    }
+}
+
+/// Syncs lock both repos in the same order whichever way they go, so
+/// syncs in opposite directions never hold the lock the other one waits for.
+#[test]
+fn syncs_lock_repos_in_the_same_order_either_way() -> FcTestResult<()> {
+    let acquired_ids = Arc::new(Mutex::new(vec!()));
+    let mut first = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    first.lock = Box::new(RecordingLock {
+        id: String::from("first"), acquired_ids: Arc::clone(&acquired_ids) });
+    let mut second = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    second.lock = Box::new(RecordingLock {
+        id: String::from("second"), acquired_ids: Arc::clone(&acquired_ids) });
+    first.add_version()?;
+
+    acquired_ids.lock().unwrap().clear();
+    second.sync_from(&mut first, None)?;
+    let pulling_order = acquired_ids.lock().unwrap().drain(..).take(2).collect::<Vec<_>>();
+    let report = second.sync_to(&mut first, None)?;
+    assert_eq!(report.outcome, SyncOutcome::UpToDate);
+    let pushing_order = acquired_ids.lock().unwrap().drain(..).take(2).collect::<Vec<_>>();
+    assert_eq!(pulling_order, vec!(String::from("first"), String::from("second")));
+    assert_eq!(pushing_order, pulling_order);
+    Ok(()).into()
+}
+
+/// Index files kept with the blobs, as they used to be, are migrated
+/// while the repo is locked, following the state it's been opened with.
+#[test]
+fn repo_migrates_indexes_while_locked() -> FcTestResult<()> {
+    let legacy_collection = MemoryCollection::new();
+    let mut legacy_indexes = MiscIndexFileCollection::new(legacy_collection.clone());
+    let index_id = legacy_indexes.put_index_file(
+        legacy_indexes.create_unwritten_empty_index_file_box(),
+        HashAlgorithm::default()
+    )?;
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut state_file = repo.state_collection.get_state_file()?;
+    state_file.get_state_ref()?.versions.push(Version::new_with_index(&index_id));
+    repo.state_collection.put_state_file(state_file)?;
+    let acquired_ids = Arc::new(Mutex::new(vec!()));
+    repo.lock = Box::new(RecordingLock {
+        id: String::from("repo"), acquired_ids: Arc::clone(&acquired_ids) });
+
+    assert_eq!(repo.migrate_indexes_from(&mut legacy_collection.clone())?, 1);
+    assert_eq!(*acquired_ids.lock().unwrap(), vec!(String::from("repo")));
+    assert!(repo.indexes.has_index(&index_id)?);
+    assert!(!legacy_indexes.has_index(&index_id)?);
+    assert_eq!(repo.migrate_indexes_from(&mut legacy_collection.clone())?, 0);
+    Ok(()).into()
+}
+
+/// An object that doesn't match its hash isn't synced, and neither is
+/// anything else that would have been along with it.
+#[test]
+fn sync_rejects_objects_not_matching_their_hash() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    for (path, content) in [("/etc/hostname", &b"host\n"[..]), ("/etc/motd", &b"hello\n"[..])].iter() {
+        source.track_ordinary(
+            version_index,
+            TrackedPath::new(path)?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+    }
+    let hash = hash_readable(&mut &b"hello\n"[..])?;
+    source.blobs.handler.get_file_writeable(&get_file_name_of_hash(&hash))?.write_all(b"tampered\n")?;
+
+    let result = source.sync_to(&mut destination, None);
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::ContentHashMismatch, .. })),
+        "Syncing a tampered blob didn't fail as expected: {:?}", result
+    );
+    assert!(destination.blobs.handler.get_file_names().is_empty());
+    assert!(destination.indexes.handler.get_file_names().is_empty());
+    assert!(!destination.has_version(version_index)?);
+    Ok(()).into()
+}
+
+/// A bundle adds its version to another repo, which keeps the blobs it
+/// has already rather than putting them again.
+#[test]
+fn bundle_adds_version_to_another_repo_deduplicating_blobs() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    for (path, content) in [("/etc/hostname", &b"host\n"[..]), ("/etc/motd", &b"hello\n"[..])].iter() {
+        source.track_ordinary(
+            version_index,
+            TrackedPath::new(path)?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(content)
+        )?;
+    }
+    let destination_version_index = destination.add_version()?;
+    destination.track_ordinary(
+        destination_version_index,
+        TrackedPath::new("/etc/hostname")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"host\n")
+    )?;
+
+    let mut bundle = vec!();
+    let manifest = source.write_bundle(version_index, None, &mut bundle)?;
+    assert_eq!(manifest.checksums.len(), 3);
+    let imported_bundle = destination.import_bundle(&bundle[..])?;
+    assert_eq!(imported_bundle.imported_blob_hashes, vec!(hash_readable(&mut &b"hello\n"[..])?));
+    assert_eq!(imported_bundle.deduplicated_blob_hashes, vec!(hash_readable(&mut &b"host\n"[..])?));
+    assert_eq!(
+        read_ordinary_files(&mut destination, imported_bundle.version_index)?,
+        read_ordinary_files(&mut source, version_index)?
+    );
+    Ok(()).into()
+}
+
+/// Indexes coming from another repo are checked against the consistency
+/// rules just like those written by tracking files.
+#[test]
+fn bundle_with_inconsistent_index_is_refused() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    source.track_symlink(
+        version_index,
+        TrackedPath::new("/etc")?,
+        TrackableSymlinkAspects::new(String::from("/usr/etc"))
+    )?;
+    // Written behind the repo's back, as it refuses to write it itself.
+    let mut state_file = source.state_collection.get_state_file()?;
+    let mut version = state_file.get_state_ref()?.get_version(version_index)?;
+    let mut index_file = source.indexes.get_index_file(&version.get_index_id().unwrap_or_default())?;
+    index_file.get_index_ref()?.files.insert(
+        TrackedPath::new("/etc/motd")?,
+        TrackedFileAspects::NonExisting(TrackedNonExistingAspects::new())
+    );
+    version.set_index_id(&source.indexes.put_index_file(index_file, HashAlgorithm::default())?);
+    state_file.get_state_ref()?.put_version(&version_index, version);
+    source.state_collection.put_state_file(state_file)?;
+
+    let mut bundle = vec!();
+    source.write_bundle(version_index, None, &mut bundle)?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let result = destination.import_bundle(&bundle[..]);
+    assert!(matches!(result, Err(Error { kind: ErrorKind::IndexInconsistent, .. })));
+    Ok(()).into()
+}
+
+/// A delta bundle leaves out the blobs of its base version, so it can
+/// only be imported into a repo which has them.
+#[test]
+fn delta_bundle_leaves_out_blobs_of_base_version() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    source.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/hostname")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"host\n")
+    )?;
+    let mut bundle = vec!();
+    source.write_bundle(version_index, None, &mut bundle)?;
+    destination.import_bundle(&bundle[..])?;
+
+    // Tracking puts the changed version in front of the one it changed.
+    source.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/motd")?,
+        get_trackable_root_ordinary_aspects(),
+        &mut Cursor::new(b"hello\n")
+    )?;
+    let mut delta_bundle = vec!();
+    let manifest = source.write_bundle(
+        version_index, Some(version_index + 1), &mut delta_bundle)?;
+    assert_eq!(manifest.blob_hashes.len(), 2);
+    assert_eq!(manifest.checksums.len(), 2);
+
+    let mut empty_repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let result = empty_repo.import_bundle(&delta_bundle[..]);
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::InvalidBundle, .. })),
+        "Importing a delta bundle without its base didn't fail as expected: {:?}", result
+    );
+    assert!(empty_repo.blobs.handler.get_file_names().is_empty());
+
+    let imported_bundle = destination.import_bundle(&delta_bundle[..])?;
+    assert_eq!(
+        read_ordinary_files(&mut destination, imported_bundle.version_index)?,
+        read_ordinary_files(&mut source, version_index)?
+    );
+    Ok(()).into()
+}
+
+fn get_index_of_version<
+    StateCollection: StateFileCollection,
+    Indexes: IndexFileCollection,
+    Blobs: TrackedOrdinaryBlobFileCollection,
+    J: Journal
+>(repo: &mut Repo<StateCollection, Indexes, Blobs, J>, version_index: usize)
+-> FcResult<Index> {
+    let version = repo.state_collection.get_state_file()?
+        .get_state_ref()?.get_version(version_index)?;
+    match version.get_index_id() {
+        Some(index_id) => Ok(repo.indexes.get_index_file(&index_id)?.get_index_ref()?.clone()),
+        None => Ok(Index::new()),
+    }
+}
+
+/// A version streamed into a tarball keeps its owners, be they names or
+/// IDs, modes, symlinks and directories, so importing the tarball again
+/// tracks the same.
+#[test]
+fn tarball_round_trips_a_version() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    let attributes = Attributes {
+        posix_user: String::from("www"),
+        posix_group: String::from("web"),
+        posix_mode: Some(0o750)
+    };
+    source.batch(version_index, |batch| {
+        batch.track_directory(
+            TrackedPath::new("/srv/www")?, TrackableDirectoryAspects::new(attributes.clone()))?;
+        batch.track_ordinary(
+            TrackedPath::new("/srv/www/index.html")?,
+            TrackableOrdinaryAspects::new(Attributes {
+                posix_mode: Some(0o640),
+                ..attributes.clone()
+            }),
+            &mut Cursor::new(b"<html></html>\n")
+        )?;
+        batch.track_ordinary(
+            TrackedPath::new("/srv/www/upload.txt")?,
+            TrackableOrdinaryAspects::new(Attributes {
+                posix_user: String::from("1000"),
+                posix_group: String::from("1001"),
+                posix_mode: Some(0o600)
+            }),
+            &mut Cursor::new(b"uploaded\n")
+        )?;
+        batch.track_symlink(
+            TrackedPath::new("/srv/current")?,
+            TrackableSymlinkAspects::new(String::from("www"))
+        )?;
+        Ok(())
+    })?;
+
+    let mut file_list = TarballFileList::new(vec!());
+    source.get_files(version_index, &mut file_list)?;
+    let tarball = file_list.finish()?;
+    let mut archive = tar::Archive::new(&tarball[..]);
+    for entry in archive.entries()? {
+        let entry = entry?;
+        if entry.path()?.as_os_str() == "srv/www/index.html" {
+            assert_eq!(entry.header().mode()?, 0o640);
+            assert_eq!(entry.header().username_bytes(), Some(&b"www"[..]));
+            assert_eq!(entry.header().groupname_bytes(), Some(&b"web"[..]));
+        }
+        if entry.path()?.as_os_str() == "srv/www/upload.txt" {
+            assert_eq!(entry.header().uid()?, 1000);
+            assert_eq!(entry.header().gid()?, 1001);
+            assert_eq!(entry.header().username_bytes(), Some(&b""[..]));
+            assert_eq!(entry.header().groupname_bytes(), Some(&b""[..]));
+        }
+    }
+
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let imported_version_index = destination.import_tarball(&tarball[..])?;
+    assert_eq!(
+        get_index_of_version(&mut destination, imported_version_index)?,
+        get_index_of_version(&mut source, version_index)?
+    );
+    Ok(()).into()
+}
+
+/// A tarball with entries that can't be tracked, like hard links,
+/// isn't tracked at all, nor is a version added for it when it's
+/// imported, be it as a tarball or an OCI layer.
+#[test]
+fn tarball_with_hard_link_is_refused() -> FcTestResult<()> {
+    let mut builder = tar::Builder::new(vec!());
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Regular);
+    header.set_size(6);
+    header.set_mode(0o644);
+    header.set_uid(0);
+    header.set_gid(0);
+    builder.append_data(&mut header, "etc/motd", &b"hello\n"[..])?;
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Link);
+    header.set_size(0);
+    builder.append_link(&mut header, "etc/issue", "etc/motd")?;
+    let tarball = builder.into_inner()?;
+
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = repo.add_version()?;
+    let result = repo.track_tarball(version_index, &tarball[..]).map(|_| ());
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::UnsupportedTarEntry, .. })),
+        "Tracking a tarball with a hard link didn't fail as expected: {:?}", result
+    );
+    assert!(get_index_of_version(&mut repo, version_index)?.files.is_empty());
+
+    let result = repo.import_tarball(&tarball[..]);
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::UnsupportedTarEntry, .. })),
+        "Importing a tarball with a hard link didn't fail as expected: {:?}", result
+    );
+    assert!(!repo.has_version(version_index + 1)?);
+
+    let result = repo.import_oci_layer(&tarball[..]);
+    assert!(
+        matches!(result, Err(Error { kind: ErrorKind::UnsupportedTarEntry, .. })),
+        "Importing an OCI layer with a hard link didn't fail as expected: {:?}", result
+    );
+    assert!(!repo.has_version(version_index + 1)?);
+    Ok(()).into()
+}
+
+/// An OCI layer of a version carries non-existing files as whiteouts and
+/// exclusive directories as opaque ones, which importing the layer turns
+/// back into what they were.
+#[test]
+fn oci_layer_round_trips_whiteouts_and_opaque_directories() -> FcTestResult<()> {
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    source.batch(version_index, |batch| {
+        batch.track_directory(
+            TrackedPath::new("/etc/sudoers.d")?,
+            TrackableDirectoryAspects::new_with_mode(
+                Attributes {
+                    posix_user: String::from("root"),
+                    posix_group: String::from("root"),
+                    posix_mode: Some(0o750)
+                },
+                DirectoryMode::Exclusive { allowed: vec!() }
+            )
+        )?;
+        batch.track_ordinary(
+            TrackedPath::new("/etc/sudoers.d/admins")?,
+            TrackableOrdinaryAspects::new(Attributes {
+                posix_user: String::from("root"),
+                posix_group: String::from("root"),
+                posix_mode: Some(0o440)
+            }),
+            &mut Cursor::new(b"%admin ALL=(ALL) ALL\n")
+        )?;
+        batch.track_non_existing(
+            TrackedPath::new("/etc/motd")?,
+            TrackableNonExistingAspects::new_with_constraints(true, None)
+        )?;
+        Ok(())
+    })?;
+
+    let mut file_list = TarballFileList::new_oci_layer(vec!());
+    source.get_files(version_index, &mut file_list)?;
+    assert!(file_list.get_unrepresented_aspects().is_empty());
+    let layer = file_list.finish()?;
+    let mut entry_paths = vec!();
+    for entry in tar::Archive::new(&layer[..]).entries()? {
+        entry_paths.push(entry?.path()?.to_string_lossy().into_owned());
+    }
+    entry_paths.sort();
+    assert_eq!(entry_paths, vec!(
+        "etc/.wh.motd",
+        "etc/sudoers.d",
+        "etc/sudoers.d/.wh..wh..opq",
+        "etc/sudoers.d/admins",
+    ));
+
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let imported_version_index = destination.import_oci_layer(&layer[..])?;
+    assert_eq!(
+        get_index_of_version(&mut destination, imported_version_index)?,
+        get_index_of_version(&mut source, version_index)?
+    );
+    Ok(()).into()
+}
+
+/// Patterns an exclusive directory allows can't be carried over into an
+/// OCI layer, nor can the constraints of non-existing files, which is
+/// reported instead of passing silently.
+#[test]
+fn oci_layer_reports_allowed_patterns_of_exclusive_directories() -> FcTestResult<()> {
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = repo.add_version()?;
+    repo.batch(version_index, |batch| {
+        batch.track_directory(
+            TrackedPath::new("/etc/sudoers.d")?,
+            TrackableDirectoryAspects::new_with_mode(
+                create_root_attributes(),
+                DirectoryMode::Exclusive { allowed: vec!(String::from("*.local")) }
+            )
+        )?;
+        batch.track_non_existing(
+            TrackedPath::new("/etc/cron.d")?,
+            TrackableNonExistingAspects::new_with_constraints(
+                true, Some(NonExistingKindConstraint::Directory))
+        )?;
+        batch.track_non_existing(
+            TrackedPath::new("/etc/nologin")?,
+            TrackableNonExistingAspects::new_with_constraints(false, None)
+        )?;
+        Ok(())
+    })?;
+
+    let mut file_list = TarballFileList::new_oci_layer(vec!());
+    repo.get_files(version_index, &mut file_list)?;
+    let mut unrepresented_aspects = file_list.get_unrepresented_aspects().to_vec();
+    unrepresented_aspects.sort_by(|one, other| one.path.cmp(&other.path));
+    assert_eq!(unrepresented_aspects, vec!(
+        UnrepresentedAspect {
+            path: TrackedPath::new("/etc/cron.d")?,
+            description: String::from("Removing only a file of kind directory, left out.")
+        },
+        UnrepresentedAspect {
+            path: TrackedPath::new("/etc/nologin")?,
+            description: String::from("Removing a directory only if it's empty, left out.")
+        },
+        UnrepresentedAspect {
+            path: TrackedPath::new("/etc/sudoers.d")?,
+            description: String::from("Allowed patterns [\"*.local\"], left out.")
+        },
+    ));
+    Ok(()).into()
+}
+
+/// A version written as an mtree spec, with its contents put into a
+/// directory, is tracked the same when the spec is imported again.
+#[test]
+fn mtree_spec_round_trips_a_version() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoMtreeSpecRoundTripsAVersion.as_str();
+    let content_path = TmpTestDir {}.set_up(test_id)?.join("content");
+    let mut source = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = source.add_version()?;
+    let attributes = Attributes {
+        posix_user: String::from("www"),
+        posix_group: String::from("1001"),
+        posix_mode: Some(0o750)
+    };
+    source.batch(version_index, |batch| {
+        batch.track_directory(
+            TrackedPath::new("/srv/my www")?, TrackableDirectoryAspects::new(attributes.clone()))?;
+        batch.track_ordinary(
+            TrackedPath::new("/srv/my www/index.html")?,
+            TrackableOrdinaryAspects::new(Attributes {
+                posix_mode: Some(0o640),
+                ..attributes.clone()
+            }),
+            &mut Cursor::new(b"<html></html>\n")
+        )?;
+        batch.track_symlink(
+            TrackedPath::new("/srv/current")?,
+            TrackableSymlinkAspects::new(String::from("my www"))
+        )?;
+        Ok(())
+    })?;
+
+    let mut spec = vec!();
+    let unrepresented_aspects = source.write_mtree_spec(version_index, &mut spec)?;
+    assert!(unrepresented_aspects.is_empty());
+    let spec_text = String::from_utf8_lossy(&spec).into_owned();
+    assert!(spec_text.contains("./srv/my\\040www type=dir uname=www gid=1001 mode=0750\n"));
+    assert!(spec_text.contains("./srv/current type=link link=my\\040www\n"));
+
+    create_dir_all(content_path.join("srv/my www"))?;
+    write(content_path.join("srv/my www/index.html"), b"<html></html>\n")?;
+    let mut destination = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let imported_mtree_spec = destination.import_mtree_spec(&spec[..], &content_path)?;
+    assert!(imported_mtree_spec.unrepresented_aspects.is_empty());
+    assert_eq!(
+        get_index_of_version(&mut destination, imported_mtree_spec.version_index)?,
+        get_index_of_version(&mut source, version_index)?
+    );
+    Ok(()).into()
+}
+
+/// Importing an mtree spec reports what it can't track, and fails if the
+/// contents don't match the digests in the spec, without adding a version.
+#[test]
+fn mtree_spec_import_reports_and_verifies() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoMtreeSpecImportReportsAndVerifies.as_str();
+    let content_path = TmpTestDir {}.set_up(test_id)?;
+    create_dir_all(content_path.join("etc"))?;
+    write(content_path.join("etc/motd"), b"hello\n")?;
+    let spec = concat!(
+        "#mtree\n",
+        "/set type=file uname=root gname=root mode=0644 nlink=1\n",
+        ". type=dir mode=0755\n",
+        "etc type=dir time=1700000000.0\n",
+        "    motd size=6 \\\n",
+        "        sha256digest=5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03\n",
+        "..\n",
+        "dev type=dir\n",
+        "    null type=char device=native,1,3\n",
+    );
+
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let imported_mtree_spec = repo.import_mtree_spec(spec.as_bytes(), &content_path)?;
+    assert_eq!(imported_mtree_spec.unrepresented_aspects, vec!(
+        UnrepresentedAspect {
+            path: TrackedPath::new("/etc")?,
+            description: String::from("Keyword time=1700000000.0, ignored.")
+        },
+        UnrepresentedAspect {
+            path: TrackedPath::new("/dev/null")?,
+            description: String::from("Keyword device=native,1,3, ignored.")
+        },
+        UnrepresentedAspect {
+            path: TrackedPath::new("/dev/null")?,
+            description: String::from("Entry of type char, left out.")
+        },
+    ));
+    let index = get_index_of_version(&mut repo, imported_mtree_spec.version_index)?;
+    assert!(index.files.contains_key(&TrackedPath::new("/etc/motd")?));
+    assert!(!index.files.contains_key(&TrackedPath::new("/dev/null")?));
+
+    write(content_path.join("etc/motd"), b"howdy\n")?;
+    let result = repo.import_mtree_spec(spec.as_bytes(), &content_path);
+    assert!(matches!(result, Err(Error { kind: ErrorKind::ContentHashMismatch, .. })));
+    assert!(!repo.has_version(imported_mtree_spec.version_index + 1)?);
+    Ok(()).into()
+}
+
+/// The contents of files an mtree spec describes are only read from
+/// within the content directory, whatever `contents` says.
+#[test]
+fn mtree_spec_contents_stay_within_content_path() -> FcTestResult<()> {
+    let test_id = TestIDs::RepoMtreeSpecContentsStayWithinContentPath.as_str();
+    let test_path = TmpTestDir {}.set_up(test_id)?;
+    let content_path = test_path.join("content");
+    create_dir_all(content_path.join("files"))?;
+    write(content_path.join("files/motd"), b"hello\n")?;
+    write(test_path.join("secret"), b"hunter2\n")?;
+
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let spec = "#mtree\n./etc/motd type=file uname=root gname=root contents=./files/motd\n";
+    let imported_mtree_spec = repo.import_mtree_spec(spec.as_bytes(), &content_path)?;
+    let contents = read_ordinary_files(&mut repo, imported_mtree_spec.version_index)?;
+    assert_eq!(contents.get(&OsString::from("/etc/motd")), Some(&b"hello\n".to_vec()));
+
+    for contents in [String::from("../secret"), test_path.join("secret").to_string_lossy().into_owned()] {
+        let spec = format!(
+            "#mtree\n./etc/motd type=file uname=root gname=root contents={}\n", contents);
+        let result = repo.import_mtree_spec(spec.as_bytes(), &content_path);
+        assert!(
+            matches!(result, Err(Error { kind: ErrorKind::InvalidMtreeSpec, .. })),
+            "Importing a spec with contents={} didn't fail as expected: {:?}", contents, result
+        );
+    }
+
+    // Neither may lead outside of it through a symlink within it.
+    symlink(test_path.join("secret"), content_path.join("files/leak"))?;
+    symlink(&test_path, content_path.join("etc"))?;
+    for spec in [
+        "#mtree\n./etc/motd type=file uname=root gname=root contents=./files/leak\n",
+        "#mtree\n./etc/secret type=file uname=root gname=root\n",
+    ] {
+        let result = repo.import_mtree_spec(spec.as_bytes(), &content_path);
+        assert!(
+            matches!(result, Err(Error { kind: ErrorKind::InvalidMtreeSpec, .. })),
+            "Importing the spec {:?} didn't fail as expected: {:?}", spec, result
+        );
+    }
+    assert!(!repo.has_version(imported_mtree_spec.version_index + 1)?);
+    Ok(()).into()
+}
+
+/// A version exported as a JSON manifest lists its files sorted, with
+/// their content if asked for, and as JSON Lines lists the same files
+/// after a header.
+#[test]
+fn manifest_lists_files_of_a_version() -> FcTestResult<()> {
+    let mut repo = test_fixtures::repo::create_empty_memory_repo_struct()?;
+    let version_index = repo.add_version()?;
+    repo.batch(version_index, |batch| {
+        batch.track_ordinary(
+            TrackedPath::new("/etc/motd")?,
+            get_trackable_root_ordinary_aspects(),
+            &mut Cursor::new(b"hello\n")
+        )?;
+        batch.track_symlink(
+            TrackedPath::new("/etc/localtime")?,
+            TrackableSymlinkAspects::new(String::from("/usr/share/zoneinfo/UTC"))
+        )?;
+        batch.track_non_existing(TrackedPath::new("/etc/nologin")?, TrackableNonExistingAspects::new())?;
+        Ok(())
+    })?;
+
+    let mut file_list = ManifestFileList::new_with_content(vec!(), ManifestFormat::Json);
+    repo.get_files(version_index, &mut file_list)?;
+    let manifest: Manifest = serde_json::from_slice(&file_list.finish()?)?;
+    assert_eq!(manifest.format_version, MANIFEST_FORMAT_VERSION);
+    let paths: Vec<&str> = manifest.files.iter().map(|file| file.path.as_str()).collect();
+    assert_eq!(paths, vec!("/etc/localtime", "/etc/motd", "/etc/nologin"));
+    match &manifest.files[1].aspects {
+        ManifestFileAspects::Ordinary(ordinary_aspects) => {
+            assert_eq!(ordinary_aspects.repo_blob_hash, hash_readable(&mut Cursor::new(b"hello\n"))?);
+            assert_eq!(ordinary_aspects.content_base64.as_deref(), Some("aGVsbG8K"));
+        },
+        _ => panic!("Expected /etc/motd to be an ordinary file."),
+    }
+
+    let mut file_list = ManifestFileList::new(vec!(), ManifestFormat::JsonLines);
+    repo.get_files(version_index, &mut file_list)?;
+    let json_lines = file_list.finish()?;
+    let mut lines = json_lines.split(|byte| *byte == b'\n').filter(|line| !line.is_empty());
+    let header: ManifestHeader = serde_json::from_slice(lines.next().unwrap_or_default())?;
+    assert_eq!(header.format_version, MANIFEST_FORMAT_VERSION);
+    let mut files = vec!();
+    for line in lines {
+        let file: ManifestFile = serde_json::from_slice(line)?;
+        files.push(file);
+    }
+    files.sort_by(|file, other_file| file.path.cmp(&other_file.path));
+    for (file, manifest_file) in files.iter().zip(manifest.files.iter()) {
+        match (&file.aspects, &manifest_file.aspects) {
+            (ManifestFileAspects::Ordinary(ordinary_aspects), _) =>
+                assert_eq!(ordinary_aspects.content_base64, None),
+            (aspects, manifest_aspects) => assert_eq!(aspects, manifest_aspects),
+        }
+    }
+    assert_eq!(files.len(), manifest.files.len());
+    Ok(()).into()
+}
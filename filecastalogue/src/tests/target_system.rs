@@ -0,0 +1,157 @@
+use std::fs::{create_dir_all, read_to_string, symlink_metadata, write};
+use std::os::unix::fs::{MetadataExt, symlink};
+use crate::error::{Error, ErrorKind, FcTestResult};
+use crate::meta::file_aspects::aspects::directory::{DirectoryMode, TrackableDirectoryAspects};
+use crate::meta::file_aspects::aspects::non_existing::{NonExistingKindConstraint, TrackedNonExistingAspects};
+use crate::meta::file_aspects::aspects::ordinary::TrackableOrdinaryAspects;
+use crate::meta::repo_exported_file_list::model::{RepoExportedFileList, RepoExportedVecFileList};
+use crate::meta::tracked_path::model::TrackedPath;
+use crate::target_system::drift::Drift;
+use crate::target_system::local::LocalTargetSystem;
+use crate::tests::test_fixtures;
+use crate::tests::test_fixtures::models::create_root_attributes;
+use crate::tests::test_ids::TestIDs;
+use crate::tests::test_utils::{BaseTestDir, SafeTestPathJoin, TmpTestDir};
+
+/// Happy path testing of drift reporting and applying an exclusive
+/// directory, with a rogue file that has to go and an allowed one
+/// that has to stay.
+#[test]
+fn apply_purges_exclusive_directory() -> FcTestResult<()> {
+    let test_id = TestIDs::TargetSystemApplyPurgesExclusiveDirectory.as_str();
+    let mut repo = test_fixtures::repo::create_minimal_repo_struct(test_id)?;
+    let target_root = TmpTestDir {}.get_path(test_id)?.safe_join("target")?;
+    let drop_in_dir = target_root.safe_join("etc/sudoers.d")?;
+    create_dir_all(&drop_in_dir)?;
+    write(drop_in_dir.safe_join("rogue")?, "ALL ALL=(ALL) NOPASSWD: ALL")?;
+    write(drop_in_dir.safe_join("README")?, "Allowed to stay.")?;
+
+    let version_index = repo.add_version()?;
+    repo.track_directory(
+        version_index,
+        TrackedPath::new("/etc")?,
+        TrackableDirectoryAspects::new(create_root_attributes())
+    )?;
+    repo.track_directory(
+        version_index,
+        TrackedPath::new("/etc/sudoers.d")?,
+        TrackableDirectoryAspects::new_with_mode(
+            create_root_attributes(),
+            DirectoryMode::Exclusive { allowed: vec!(String::from("READ*")) }
+        )
+    )?;
+    repo.track_ordinary(
+        version_index,
+        TrackedPath::new("/etc/sudoers.d/admins")?,
+        TrackableOrdinaryAspects::new(create_root_attributes()),
+        &mut "%admin ALL=(ALL) ALL".as_bytes()
+    )?;
+
+    let mut file_list = RepoExportedVecFileList::new();
+    repo.get_files(version_index, &mut file_list)?;
+    let target = LocalTargetSystem::new(&target_root);
+
+    let report = target.get_drift(&file_list.vec)?;
+    assert_eq!(
+        report.get(&TrackedPath::new("/etc/sudoers.d/rogue")?),
+        vec!(&Drift::UntrackedInExclusiveDirectory)
+    );
+    assert_eq!(
+        report.get(&TrackedPath::new("/etc/sudoers.d/admins")?),
+        vec!(&Drift::Missing)
+    );
+    assert!(report.get(&TrackedPath::new("/etc/sudoers.d/README")?).is_empty());
+
+    target.apply(&file_list.vec)?;
+
+    assert!(target.get_drift(&file_list.vec)?.is_clean());
+    assert!(!drop_in_dir.safe_join("rogue")?.exists());
+    assert_eq!(read_to_string(drop_in_dir.safe_join("README")?)?, "Allowed to stay.");
+    assert_eq!(read_to_string(drop_in_dir.safe_join("admins")?)?, "%admin ALL=(ALL) ALL");
+    Ok(()).into()
+}
+
+/// A recursive entry removes a whole tree, whilst one constrained to
+/// ordinary files leaves a directory at its path alone.
+#[test]
+fn apply_removes_non_existing_recursively() -> FcTestResult<()> {
+    let test_id = TestIDs::TargetSystemApplyRemovesNonExistingRecursively.as_str();
+    let target_root = TmpTestDir {}.set_up(test_id)?;
+    create_dir_all(target_root.safe_join("opt/legacy/lib")?)?;
+    write(target_root.safe_join("opt/legacy/lib/libold.so")?, "")?;
+    create_dir_all(target_root.safe_join("etc/motd")?)?;
+
+    let mut file_list = RepoExportedVecFileList::new();
+    file_list.add_non_existing(
+        TrackedPath::new("/opt/legacy")?.into_os_string(),
+        TrackedNonExistingAspects::new_with_constraints(true, None)
+    )?;
+    file_list.add_non_existing(
+        TrackedPath::new("/etc/motd")?.into_os_string(),
+        TrackedNonExistingAspects::new_with_constraints(
+            true, Some(NonExistingKindConstraint::Ordinary)
+        )
+    )?;
+    let target = LocalTargetSystem::new(&target_root);
+
+    assert_eq!(
+        target.get_drift(&file_list.vec)?.get(&TrackedPath::new("/opt/legacy")?),
+        vec!(&Drift::UnexpectedlyExists { found_kind: "directory" })
+    );
+    target.apply(&file_list.vec)?;
+
+    assert!(target.get_drift(&file_list.vec)?.is_clean());
+    assert!(!target_root.safe_join("opt/legacy")?.exists());
+    assert!(target_root.safe_join("etc/motd")?.is_dir());
+    Ok(()).into()
+}
+
+/// A parent directory on the target system that's a symlink pointing
+/// outside of the root isn't followed.
+#[test]
+fn apply_refuses_paths_outside_of_root() -> FcTestResult<()> {
+    let test_id = TestIDs::TargetSystemApplyRefusesPathsOutsideOfRoot.as_str();
+    let tmp_path = TmpTestDir {}.set_up(test_id)?;
+    let target_root = tmp_path.safe_join("target")?;
+    let outside_path = tmp_path.safe_join("outside")?;
+    create_dir_all(&target_root)?;
+    create_dir_all(outside_path.safe_join("secrets")?)?;
+    symlink(&outside_path, target_root.safe_join("etc")?)?;
+
+    let mut file_list = RepoExportedVecFileList::new();
+    file_list.add_non_existing(
+        TrackedPath::new("/etc/secrets")?.into_os_string(),
+        TrackedNonExistingAspects::new_with_constraints(true, None)
+    )?;
+    let target = LocalTargetSystem::new(&target_root);
+
+    let result = target.apply(&file_list.vec);
+    assert!(matches!(result, Err(Error { kind: ErrorKind::TargetSystemConflict, .. })));
+    assert!(outside_path.safe_join("secrets")?.is_dir());
+    Ok(()).into()
+}
+
+/* Notes:
+    Mounting something takes privileges tests don't have, so the tree is
+    removed as if it was on another device than it is, which is what a
+    mount point inside of it would look like.
+*/
+/// A directory tree on another device than expected isn't removed, not
+/// even in part.
+#[test]
+fn remove_tree_refuses_other_devices() -> FcTestResult<()> {
+    let test_id = TestIDs::TargetSystemRemoveTreeRefusesOtherDevices.as_str();
+    let target_root = TmpTestDir {}.set_up(test_id)?;
+    let tree_path = target_root.safe_join("mnt/data")?;
+    create_dir_all(tree_path.safe_join("lib")?)?;
+    write(tree_path.safe_join("lib/libdata.so")?, "")?;
+    let device = symlink_metadata(&tree_path)?.dev();
+    let target = LocalTargetSystem::new(&target_root);
+
+    let result = target.remove_tree_on_device(&tree_path, device.wrapping_add(1));
+    assert!(matches!(result, Err(Error { kind: ErrorKind::TargetSystemConflict, .. })));
+    assert!(tree_path.safe_join("lib/libdata.so")?.exists());
+    target.remove_tree_on_device(&tree_path, device)?;
+    assert!(!tree_path.exists());
+    Ok(()).into()
+}
@@ -1,2 +1,4 @@
+pub(crate) mod http;
 pub(crate) mod models;
-pub(crate) mod repo;
\ No newline at end of file
+pub(crate) mod repo;
+pub(crate) mod s3;
\ No newline at end of file
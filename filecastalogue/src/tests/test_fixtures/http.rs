@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use crate::error::FcResult;
+use crate::files::hashable::hash_readable;
+
+/// A request the StaticHttpServer has answered.
+pub(in crate::tests) struct ServedRequest {
+    pub method: String,
+    pub url: String,
+    pub status: u16,
+    pub authorization: Option<String>,
+}
+
+/// A static web server running in a thread of its own for as long as it's
+/// around, serving whatever is in `files` by URL path, with ETags.
+pub(in crate::tests) struct StaticHttpServer {
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+    pub files: Arc<Mutex<HashMap<String, Vec<u8>>>>,
+    pub served_requests: Arc<Mutex<Vec<ServedRequest>>>,
+}
+
+impl StaticHttpServer {
+    pub(in crate::tests) fn start() -> FcResult<Self> {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0")
+            .map_err(|e| io::Error::other(e.to_string()))?);
+        let files: Arc<Mutex<HashMap<String, Vec<u8>>>> = Arc::new(Mutex::new(HashMap::new()));
+        let served_requests = Arc::new(Mutex::new(vec!()));
+        let thread = {
+            let server = Arc::clone(&server);
+            let files = Arc::clone(&files);
+            let served_requests = Arc::clone(&served_requests);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let get_header = |name: &'static str| request.headers().iter()
+                        .find(|header| header.field.equiv(name))
+                        .map(|header| header.value.as_str().to_owned());
+                    let if_none_match = get_header("If-None-Match");
+                    let authorization = get_header("Authorization");
+                    let content = files.lock().unwrap().get(request.url()).cloned();
+                    let mut served_request = ServedRequest {
+                        method: request.method().to_string(),
+                        url: request.url().to_owned(),
+                        status: 404,
+                        authorization,
+                    };
+                    let response = match content {
+                        None => tiny_http::Response::from_data(vec!()).with_status_code(404),
+                        Some(content) => {
+                            let etag = format!(
+                                "\"{}\"", hash_readable(&mut content.as_slice()).unwrap());
+                            served_request.status = match if_none_match {
+                                Some(if_none_match) if if_none_match == etag => 304,
+                                _ => 200,
+                            };
+                            let body = match served_request.status {
+                                304 => vec!(),
+                                _ => content,
+                            };
+                            tiny_http::Response::from_data(body)
+                                .with_status_code(served_request.status)
+                                .with_header(tiny_http::Header::from_bytes(
+                                    "ETag", etag.as_bytes()).unwrap())
+                        }
+                    };
+                    served_requests.lock().unwrap().push(served_request);
+                    let _ = request.respond(response);
+                }
+            })
+        };
+        Ok(Self {
+            server,
+            thread: Some(thread),
+            files,
+            served_requests,
+        })
+    }
+
+    /// Returns the URL the server is at, without a trailing "/".
+    pub(in crate::tests) fn get_url(&self) -> String {
+        let port = self.server.server_addr().to_ip().map_or(0, |address| address.port());
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    pub(in crate::tests) fn put_file<PathRef: AsRef<str>>(&self, url_path: PathRef, content: Vec<u8>) {
+        self.files.lock().unwrap().insert(url_path.as_ref().to_owned(), content);
+    }
+}
+
+impl Drop for StaticHttpServer {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
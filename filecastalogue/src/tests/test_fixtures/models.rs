@@ -1,4 +1,6 @@
 use crate::error::{Error, ErrorKind};
+use crate::files::hashable::HashAlgorithm;
+use crate::meta::file_aspects::attributes::Attributes;
 use crate::meta::state::model::State;
 use crate::meta::version::model::Version;
 use crate::meta::state::error::{
@@ -17,6 +19,8 @@ r#"{
     ]
 }"#;
 
+pub(crate) const EMPTY_STATE_JSON: &str = r#"{"versions": []}"#;
+
 pub(crate) const VERSION_ENTRY_ALREADY_EXISTS_ERROR_CONTEXT_DESCRIPTION: &str =
 "This is a mock of the error for the case when a version entry already exists.";
 pub(crate) const VERSION_ENTRY_DOES_NOT_EXIST_ERROR_DESCRIPTION: &str = 
@@ -29,7 +33,8 @@ pub(in crate::tests) fn create_minimal_state_struct() -> State {
         Version::new_with_index("MOCKHASH")
     );
     State {
-        versions: mock_versions
+        versions: mock_versions,
+        hash_algorithm: HashAlgorithm::default()
     }
 }
 
@@ -54,4 +59,12 @@ pub(in crate::tests) fn create_minimal_state_VersionEntryDoesNotExistError()
                 version_index: MINIMAL_STATE_VERSION_ID,
         }
     )
-}
\ No newline at end of file
+}
+
+pub(in crate::tests) fn create_root_attributes() -> Attributes {
+    Attributes {
+        posix_user: String::from("root"),
+        posix_group: String::from("root"),
+        posix_mode: None
+    }
+}
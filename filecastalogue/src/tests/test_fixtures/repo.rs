@@ -1,14 +1,21 @@
-use std::ffi::OsString;
+use std::ffi::{OsStr, OsString};
+use std::io::Write;
+use std::path::Path;
 use crate::error::FcResult;
 use crate::files::index_collection::MiscIndexFileCollection;
-use crate::files::state_collection::MiscStateFileCollection;
+use crate::files::state_collection::{MiscStateFileCollection, StateFileCollection};
 use crate::files::tracked_ordinary_blob_collection::MiscTrackedOrdinaryBlobFileCollection;
-use crate::globals::STATE_FILE_NAME;
+use crate::globals::{BLOBS_DIR_NAME, INDEXES_DIR_NAME, JOURNAL_DIR_NAME, STATE_FILE_NAME};
 use crate::journal::OptimisticDummyJournal;
+use crate::journal::drivers::local::LocalJournal;
+use crate::opaque_collection_handler::OpaqueCollectionHandler;
+use crate::opaque_collection_handler::drivers::fault_injecting::{FaultInjectingHandler, FaultInjector};
 use crate::opaque_collection_handler::drivers::local::LocalDir;
+use crate::opaque_collection_handler::drivers::memory::MemoryCollection;
 use crate::repo::Repo;
 
 use super::super::TEST_CONF;
+use super::models::EMPTY_STATE_JSON;
 
 pub(crate) const NON_EXISTING_VERSION_INDEX: usize = 1;
 pub(crate) const ADDED_VERSION_INDEX: usize = 100;
@@ -27,12 +34,156 @@ pub(in crate::tests) fn create_minimal_repo_struct(test_id: &str)
         MiscStateFileCollection::new(LocalDir::new(
             TEST_CONF::MINIMAL_REPO_SITE.get_repo_path(test_id)?), OsString::from(STATE_FILE_NAME)),
         // TODO 2: Create mock index collection.
-        MiscIndexFileCollection::new(LocalDir::new(
-            TEST_CONF::MINIMAL_REPO_SITE.get_blob_dir_path(test_id)?)),
+        MiscIndexFileCollection::new(LocalDir::open(
+            TEST_CONF::MINIMAL_REPO_SITE.get_index_dir_path(test_id)?)?),
         // TODO 3: Create mock blobs collection.
         MiscTrackedOrdinaryBlobFileCollection::new(
-            LocalDir::new(
-                TEST_CONF::MINIMAL_REPO_SITE.get_blob_dir_path(test_id)?)),
+            LocalDir::open(
+                TEST_CONF::MINIMAL_REPO_SITE.get_blob_dir_path(test_id)?)?),
         OptimisticDummyJournal::new(),
     ))
-}
\ No newline at end of file
+}
+
+/// Sets up a repo with no versions and opens it with a journal.
+pub(in crate::tests) fn create_empty_journaled_repo_struct(test_id: &str)
+-> FcResult<Repo<
+    MiscStateFileCollection<LocalDir>,
+    MiscIndexFileCollection<LocalDir>,
+    MiscTrackedOrdinaryBlobFileCollection<LocalDir>,
+    LocalJournal
+>> {
+    TEST_CONF::MINIMAL_REPO_SITE.set_up(test_id)?;
+    TEST_CONF::MINIMAL_REPO_SITE.get_state_writeable(test_id)?
+        .write_all(EMPTY_STATE_JSON.as_bytes())?;
+    open_journaled_repo_struct(test_id)
+}
+
+/// Opens the repo in the local directory at the specified path, with its
+/// journal, the way a repo is usually laid out.
+pub(in crate::tests) fn open_local_repo_struct(repo_path: &Path)
+-> FcResult<Repo<
+    MiscStateFileCollection<LocalDir>,
+    MiscIndexFileCollection<LocalDir>,
+    MiscTrackedOrdinaryBlobFileCollection<LocalDir>,
+    LocalJournal
+>> {
+    Repo::open(
+        MiscStateFileCollection::new(
+            LocalDir::new(repo_path), OsString::from(STATE_FILE_NAME)),
+        MiscIndexFileCollection::new(LocalDir::open(repo_path.join(INDEXES_DIR_NAME))?),
+        MiscTrackedOrdinaryBlobFileCollection::new(
+            LocalDir::open(repo_path.join(BLOBS_DIR_NAME))?),
+        LocalJournal::new(repo_path.join(JOURNAL_DIR_NAME)),
+    )
+}
+
+/// Opens the repo set up for the test again, recovering it the way a
+/// repo is when it's opened after a crash.
+pub(in crate::tests) fn open_journaled_repo_struct(test_id: &str)
+-> FcResult<Repo<
+    MiscStateFileCollection<LocalDir>,
+    MiscIndexFileCollection<LocalDir>,
+    MiscTrackedOrdinaryBlobFileCollection<LocalDir>,
+    LocalJournal
+>> {
+    let repo_path = TEST_CONF::MINIMAL_REPO_SITE.get_repo_path(test_id)?;
+    Repo::open(
+        MiscStateFileCollection::new(
+            LocalDir::new(&repo_path), OsString::from(STATE_FILE_NAME)),
+        MiscIndexFileCollection::new(LocalDir::open(
+            TEST_CONF::MINIMAL_REPO_SITE.get_index_dir_path(test_id)?)?),
+        MiscTrackedOrdinaryBlobFileCollection::new(LocalDir::open(
+            TEST_CONF::MINIMAL_REPO_SITE.get_blob_dir_path(test_id)?)?),
+        LocalJournal::new(repo_path.join(JOURNAL_DIR_NAME)),
+    )
+}
+
+/// Creates a repo with no versions which is kept in memory entirely,
+/// so there's no test site to set up.
+pub(in crate::tests) fn create_empty_memory_repo_struct()
+-> FcResult<Repo<
+    MiscStateFileCollection<MemoryCollection>,
+    MiscIndexFileCollection<MemoryCollection>,
+    MiscTrackedOrdinaryBlobFileCollection<MemoryCollection>,
+    OptimisticDummyJournal
+>> {
+    let mut state_collection = MiscStateFileCollection::new(
+        MemoryCollection::new(), OsString::from(STATE_FILE_NAME));
+    let state_file = state_collection.create_unwritten_empty_state_file_box();
+    state_collection.put_state_file(state_file)?;
+    Ok(Repo::new(
+        state_collection,
+        MiscIndexFileCollection::new(MemoryCollection::new()),
+        MiscTrackedOrdinaryBlobFileCollection::new(MemoryCollection::new()),
+        OptimisticDummyJournal::new(),
+    ))
+}
+
+/// The collections of a repo kept in memory, which outlive the repos
+/// opened on them.
+pub(in crate::tests) struct MemoryRepoCollections {
+    pub state: MemoryCollection,
+    pub indexes: MemoryCollection,
+    pub blobs: MemoryCollection,
+}
+
+impl MemoryRepoCollections {
+    /// Collections of a repo with no versions.
+    pub(in crate::tests) fn new() -> FcResult<Self> {
+        let state = MemoryCollection::new();
+        state.create_file(STATE_FILE_NAME)?;
+        state.get_file_writeable(OsStr::new(STATE_FILE_NAME))?
+            .write_all(EMPTY_STATE_JSON.as_bytes())?;
+        Ok(Self {
+            state,
+            indexes: MemoryCollection::new(),
+            blobs: MemoryCollection::new(),
+        })
+    }
+}
+
+/// Opens a repo on the collections, recovering it with the journal at
+/// the specified path.
+pub(in crate::tests) fn open_memory_repo_struct(
+    collections: &MemoryRepoCollections,
+    journal_path: &Path
+) -> FcResult<Repo<
+    MiscStateFileCollection<MemoryCollection>,
+    MiscIndexFileCollection<MemoryCollection>,
+    MiscTrackedOrdinaryBlobFileCollection<MemoryCollection>,
+    LocalJournal
+>> {
+    Repo::open(
+        MiscStateFileCollection::new(
+            collections.state.clone(), OsString::from(STATE_FILE_NAME)),
+        MiscIndexFileCollection::new(collections.indexes.clone()),
+        MiscTrackedOrdinaryBlobFileCollection::new(collections.blobs.clone()),
+        LocalJournal::new(journal_path),
+    )
+}
+
+/// Like `open_memory_repo_struct`, but with every collection going
+/// wrong whenever the injector says so.
+pub(in crate::tests) fn open_faulty_memory_repo_struct(
+    collections: &MemoryRepoCollections,
+    journal_path: &Path,
+    injector: &FaultInjector
+) -> FcResult<Repo<
+    MiscStateFileCollection<FaultInjectingHandler<MemoryCollection>>,
+    MiscIndexFileCollection<FaultInjectingHandler<MemoryCollection>>,
+    MiscTrackedOrdinaryBlobFileCollection<FaultInjectingHandler<MemoryCollection>>,
+    LocalJournal
+>> {
+    Repo::open(
+        MiscStateFileCollection::new(
+            FaultInjectingHandler::new_with_injector(
+                collections.state.clone(), injector.clone()),
+            OsString::from(STATE_FILE_NAME)
+        ),
+        MiscIndexFileCollection::new(FaultInjectingHandler::new_with_injector(
+            collections.indexes.clone(), injector.clone())),
+        MiscTrackedOrdinaryBlobFileCollection::new(FaultInjectingHandler::new_with_injector(
+            collections.blobs.clone(), injector.clone())),
+        LocalJournal::new(journal_path),
+    )
+}
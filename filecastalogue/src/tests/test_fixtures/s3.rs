@@ -0,0 +1,213 @@
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use crate::error::FcResult;
+
+/// An object the FakeS3Server stores, with its ETag.
+pub(in crate::tests) struct FakeS3Object {
+    pub content: Vec<u8>,
+    pub etag: String,
+}
+
+#[derive(Default)]
+pub(in crate::tests) struct FakeS3Storage {
+    pub buckets: HashSet<String>,
+    /// By bucket name and key.
+    pub objects: BTreeMap<(String, String), FakeS3Object>,
+    etag_count: usize,
+}
+
+fn decode_percent(encoded: &str) -> String {
+    let bytes = encoded.as_bytes();
+    let mut decoded = vec!();
+    let mut index = 0;
+    while index < bytes.len() {
+        match (bytes[index], encoded.get(index + 1..index + 3)) {
+            (b'%', Some(hex)) if u8::from_str_radix(hex, 16).is_ok() => {
+                decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                index += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// A stand-in for an S3-compatible object storage, running in a thread of
+/// its own for as long as it's around, understanding just enough of the
+/// API for an S3Collection: buckets, objects, copies, conditional writes
+/// and ListObjectsV2, which returns at most two keys at a time, so
+/// continuing listings are exercised.
+///
+/// Requests without a signature are refused.
+pub(in crate::tests) struct FakeS3Server {
+    server: Arc<tiny_http::Server>,
+    thread: Option<JoinHandle<()>>,
+    pub storage: Arc<Mutex<FakeS3Storage>>,
+}
+
+impl FakeS3Server {
+    pub(in crate::tests) fn start() -> FcResult<Self> {
+        let server = Arc::new(tiny_http::Server::http("127.0.0.1:0")
+            .map_err(|e| io::Error::other(e.to_string()))?);
+        let storage = Arc::new(Mutex::new(FakeS3Storage::default()));
+        let thread = {
+            let server = Arc::clone(&server);
+            let storage = Arc::clone(&storage);
+            thread::spawn(move || {
+                for mut request in server.incoming_requests() {
+                    let mut body = vec!();
+                    let _ = request.as_reader().read_to_end(&mut body);
+                    let (status, etag, response_body) = Self::handle(
+                        &mut storage.lock().unwrap(), &request, body);
+                    let mut response = tiny_http::Response::from_data(response_body)
+                        .with_status_code(status);
+                    if let Some(etag) = etag {
+                        response = response.with_header(tiny_http::Header::from_bytes(
+                            "ETag", etag.as_bytes()).unwrap());
+                    }
+                    let _ = request.respond(response);
+                }
+            })
+        };
+        Ok(Self {
+            server,
+            thread: Some(thread),
+            storage,
+        })
+    }
+
+    /// Returns the status, ETag and body to respond to the request with.
+    fn handle(storage: &mut FakeS3Storage, request: &tiny_http::Request, body: Vec<u8>)
+    -> (u16, Option<String>, Vec<u8>) {
+        let get_header = |name: &'static str| request.headers().iter()
+            .find(|header| header.field.equiv(name))
+            .map(|header| header.value.as_str().to_owned());
+        if !get_header("Authorization").unwrap_or_default()
+        .starts_with("AWS4-HMAC-SHA256 Credential=") {
+            return (403, None, vec!())
+        }
+        let (path, query) = request.url().split_once('?').unwrap_or((request.url(), ""));
+        let query: BTreeMap<String, String> = query.split('&')
+            .filter_map(|parameter| parameter.split_once('='))
+            .map(|(name, value)| (decode_percent(name), decode_percent(value)))
+            .collect();
+        let (bucket, key) = match path.trim_start_matches('/').split_once('/') {
+            Some((bucket, key)) => (decode_percent(bucket), Some(decode_percent(key))),
+            None => (decode_percent(path.trim_start_matches('/')), None),
+        };
+        let method = request.method().to_string();
+        let key = match key {
+            Some(key) => key,
+            None => return match (method.as_str(), storage.buckets.contains(&bucket)) {
+                ("PUT", true) => (409, None, vec!()),
+                ("PUT", false) => {
+                    storage.buckets.insert(bucket);
+                    (200, None, vec!())
+                },
+                ("HEAD", exists) => (if exists { 200 } else { 404 }, None, vec!()),
+                ("GET", true) if query.get("list-type").map(String::as_str) == Some("2")
+                    => (200, None, Self::list(storage, &bucket, &query).into_bytes()),
+                _ => (404, None, vec!()),
+            }
+        };
+        if !storage.buckets.contains(&bucket) {
+            return (404, None, vec!())
+        }
+        let object_id = (bucket, key);
+        let current_etag = storage.objects.get(&object_id).map(|object| object.etag.to_owned());
+        match method.as_str() {
+            "HEAD" | "GET" => match storage.objects.get(&object_id) {
+                Some(object) => (
+                    200,
+                    Some(object.etag.to_owned()),
+                    if method == "GET" { object.content.to_owned() } else { vec!() }
+                ),
+                None => (404, None, vec!()),
+            },
+            "PUT" => {
+                let is_precondition_met = match (get_header("If-Match"), get_header("If-None-Match")) {
+                    (Some(if_match), _) => current_etag.as_ref() == Some(&if_match),
+                    (_, Some(_)) => current_etag.is_none(),
+                    _ => true,
+                };
+                if !is_precondition_met {
+                    return (412, None, vec!())
+                }
+                let content = match get_header("x-amz-copy-source") {
+                    Some(copy_source) => {
+                        let copy_source = decode_percent(copy_source.trim_start_matches('/'));
+                        let source_id = match copy_source.split_once('/') {
+                            Some((bucket, key)) => (bucket.to_owned(), key.to_owned()),
+                            None => return (400, None, vec!()),
+                        };
+                        match storage.objects.get(&source_id) {
+                            Some(object) => object.content.to_owned(),
+                            None => return (404, None, vec!()),
+                        }
+                    },
+                    None => body,
+                };
+                storage.etag_count += 1;
+                let etag = format!("\"{}\"", storage.etag_count);
+                storage.objects.insert(object_id, FakeS3Object {
+                    content,
+                    etag: etag.to_owned()
+                });
+                (200, Some(etag), vec!())
+            },
+            "DELETE" => {
+                storage.objects.remove(&object_id);
+                (204, None, vec!())
+            },
+            _ => (405, None, vec!()),
+        }
+    }
+
+    fn list(storage: &FakeS3Storage, bucket: &str, query: &BTreeMap<String, String>) -> String {
+        let prefix = query.get("prefix").cloned().unwrap_or_default();
+        let start_after = query.get("continuation-token").cloned().unwrap_or_default();
+        let keys: Vec<&String> = storage.objects.keys()
+            .filter(|(object_bucket, key)| object_bucket == bucket
+                && key.starts_with(&prefix) && key.as_str() > start_after.as_str())
+            .map(|(_, key)| key)
+            .collect();
+        let page = &keys[..keys.len().min(2)];
+        let mut listing = String::from("<ListBucketResult>");
+        for key in page {
+            listing.push_str(&format!("<Contents><Key>{}</Key></Contents>", escape_xml(key)));
+        }
+        match (keys.len() > page.len(), page.last()) {
+            (true, Some(last_key)) => listing.push_str(&format!(
+                "<IsTruncated>true</IsTruncated><NextContinuationToken>{}</NextContinuationToken>",
+                escape_xml(last_key)
+            )),
+            _ => listing.push_str("<IsTruncated>false</IsTruncated>"),
+        }
+        listing.push_str("</ListBucketResult>");
+        listing
+    }
+
+    /// Returns the endpoint URL of the server, without a trailing "/".
+    pub(in crate::tests) fn get_url(&self) -> String {
+        let port = self.server.server_addr().to_ip().map_or(0, |address| address.port());
+        format!("http://127.0.0.1:{}", port)
+    }
+}
+
+impl Drop for FakeS3Server {
+    fn drop(&mut self) {
+        self.server.unblock();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
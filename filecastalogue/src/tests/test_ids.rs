@@ -12,7 +12,37 @@ pub enum TestIDs {
     RepoHasVersionReturnsFalseWhenRepoDoesNotHaveVersion,
     RepoAddVersionSucceeds,
     RepoTrackNonExistingSucceeds,
-    RepoTrackDirectorySucceeds
+    RepoTrackDirectorySucceeds,
+    RepoTrackFileBelowNonExistingFails,
+    RepoTrackAddsImpliedParentDirectories,
+    RepoTrackOrdinaryStreamsBlob,
+    RepoRehashSwitchesHashAlgorithm,
+    RepoOpenRollsBackUnfinishedOperation,
+    RepoOpenFinishesOperationWithRecordedState,
+    FilesBlobCollectionReadsCompressedAndUncompressedBlobs,
+    FilesEncryptedBlobCollectionHidesContentAndHashes,
+    FilesLocalDirMigratesToShardedLayout,
+    FilesIndexCollectionMigratesIndexesFromBlobDir,
+    TargetSystemApplyPurgesExclusiveDirectory,
+    TargetSystemApplyRemovesNonExistingRecursively,
+    LockExclusiveHolderBlocksOthersAndIsNamed,
+    LockStaleHolderOfDeadProcessIsRemoved,
+    RepoBatchCommitsSingleIndex,
+    RepoBatchDiscardsEverythingOnError,
+    RepoFaultsLeaveRepoRecoverable,
+    RepoFaultyReadsAreHandled,
+    RepoTarArchiveOpensAsReadOnlyRepo,
+    RepoSqliteRepoRollsBackUnfinishedOperations,
+    RepoSqliteRepoConvertsToAndFromLocalDir,
+    RepoMtreeSpecRoundTripsAVersion,
+    RepoMtreeSpecImportReportsAndVerifies,
+    RepoFailedOperationDoesNotUndoLaterOnes,
+    TargetSystemApplyRefusesPathsOutsideOfRoot,
+    TargetSystemRemoveTreeRefusesOtherDevices,
+    FilesLocalDirFindsFilesOfPreviousLayout,
+    FilesHashNamedFilesAreStoredWithoutColons,
+    RepoMtreeSpecContentsStayWithinContentPath,
+    LockStaleHolderIsRemovedOnceWhenRacedFor
 }
 
 impl TestIDs {
@@ -22,7 +52,67 @@ impl TestIDs {
                 => "repo_has_version_returns_false_when_repo_does_not_have_version",
             TestIDs::RepoAddVersionSucceeds => "repo_add_version_succeeds",
             TestIDs::RepoTrackNonExistingSucceeds => "repo_track_non_existing_succeeds",
-            TestIDs::RepoTrackDirectorySucceeds => "repo_track_directory_succeeds"
+            TestIDs::RepoTrackDirectorySucceeds => "repo_track_directory_succeeds",
+            TestIDs::RepoTrackFileBelowNonExistingFails
+                => "repo_track_file_below_non_existing_fails",
+            TestIDs::RepoTrackAddsImpliedParentDirectories
+                => "repo_track_adds_implied_parent_directories",
+            TestIDs::RepoTrackOrdinaryStreamsBlob
+                => "repo_track_ordinary_streams_blob",
+            TestIDs::RepoRehashSwitchesHashAlgorithm
+                => "repo_rehash_switches_hash_algorithm",
+            TestIDs::RepoOpenRollsBackUnfinishedOperation
+                => "repo_open_rolls_back_unfinished_operation",
+            TestIDs::RepoOpenFinishesOperationWithRecordedState
+                => "repo_open_finishes_operation_with_recorded_state",
+            TestIDs::FilesBlobCollectionReadsCompressedAndUncompressedBlobs
+                => "files_blob_collection_reads_compressed_and_uncompressed_blobs",
+            TestIDs::FilesEncryptedBlobCollectionHidesContentAndHashes
+                => "files_encrypted_blob_collection_hides_content_and_hashes",
+            TestIDs::FilesLocalDirMigratesToShardedLayout
+                => "files_local_dir_migrates_to_sharded_layout",
+            TestIDs::FilesIndexCollectionMigratesIndexesFromBlobDir
+                => "files_index_collection_migrates_indexes_from_blob_dir",
+            TestIDs::TargetSystemApplyPurgesExclusiveDirectory
+                => "target_system_apply_purges_exclusive_directory",
+            TestIDs::TargetSystemApplyRemovesNonExistingRecursively
+                => "target_system_apply_removes_non_existing_recursively",
+            TestIDs::LockExclusiveHolderBlocksOthersAndIsNamed
+                => "lock_exclusive_holder_blocks_others_and_is_named",
+            TestIDs::LockStaleHolderOfDeadProcessIsRemoved
+                => "lock_stale_holder_of_dead_process_is_removed",
+            TestIDs::RepoBatchCommitsSingleIndex
+                => "repo_batch_commits_single_index",
+            TestIDs::RepoBatchDiscardsEverythingOnError
+                => "repo_batch_discards_everything_on_error",
+            TestIDs::RepoFaultsLeaveRepoRecoverable
+                => "repo_faults_leave_repo_recoverable",
+            TestIDs::RepoFaultyReadsAreHandled
+                => "repo_faulty_reads_are_handled",
+            TestIDs::RepoTarArchiveOpensAsReadOnlyRepo
+                => "repo_tar_archive_opens_as_read_only_repo",
+            TestIDs::RepoSqliteRepoRollsBackUnfinishedOperations
+                => "repo_sqlite_repo_rolls_back_unfinished_operations",
+            TestIDs::RepoSqliteRepoConvertsToAndFromLocalDir
+                => "repo_sqlite_repo_converts_to_and_from_local_dir",
+            TestIDs::RepoMtreeSpecRoundTripsAVersion
+                => "repo_mtree_spec_round_trips_a_version",
+            TestIDs::RepoMtreeSpecImportReportsAndVerifies
+                => "repo_mtree_spec_import_reports_and_verifies",
+            TestIDs::RepoFailedOperationDoesNotUndoLaterOnes
+                => "repo_failed_operation_does_not_undo_later_ones",
+            TestIDs::TargetSystemApplyRefusesPathsOutsideOfRoot
+                => "target_system_apply_refuses_paths_outside_of_root",
+            TestIDs::TargetSystemRemoveTreeRefusesOtherDevices
+                => "target_system_remove_tree_refuses_other_devices",
+            TestIDs::FilesLocalDirFindsFilesOfPreviousLayout
+                => "files_local_dir_finds_files_of_previous_layout",
+            TestIDs::FilesHashNamedFilesAreStoredWithoutColons
+                => "files_hash_named_files_are_stored_without_colons",
+            TestIDs::RepoMtreeSpecContentsStayWithinContentPath
+                => "repo_mtree_spec_contents_stay_within_content_path",
+            TestIDs::LockStaleHolderIsRemovedOnceWhenRacedFor
+                => "lock_stale_holder_is_removed_once_when_raced_for"
         }
     }
 }
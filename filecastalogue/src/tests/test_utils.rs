@@ -42,6 +42,7 @@ impl SafeTestPathJoin for Path {
 pub(crate) struct RepoTestConf {
     pub(super) relative_repo_base_path: &'static str,
     pub(super) relative_blob_dir_path: &'static str,
+    pub(super) relative_index_dir_path: &'static str,
     pub(super) relative_state_file_path: &'static str,
 }
 
@@ -59,6 +60,11 @@ impl RepoTestConf {
         self.get_repo_path(base_path)?.safe_join(self.relative_blob_dir_path)
     }
 
+    pub(crate) fn get_index_dir_path(&self, base_path: &Path)
+    -> FcResult<PathBuf> {
+        self.get_repo_path(base_path)?.safe_join(self.relative_index_dir_path)
+    }
+
     pub(crate) fn get_state_file_path(&self, base_path: &Path)
     -> FcResult<PathBuf> {
         self.get_repo_path(base_path)?.safe_join(self.relative_state_file_path)
@@ -86,6 +92,7 @@ impl<Base: BaseTestDir> RepoTestSite<Base> {
         
         create_dir_all(self.conf.get_repo_path(&base_path)?)?;
         create_dir_all(self.conf.get_blob_dir_path(&base_path)?)?;
+        create_dir_all(self.conf.get_index_dir_path(&base_path)?)?;
         self.set_up_state_file(test_id)?;
         
         Ok(repo_path)
@@ -103,6 +110,10 @@ impl<Base: BaseTestDir> RepoTestSite<Base> {
         self.conf.get_blob_dir_path(&self.base_dir.get_path(test_id)?)
     }
 
+    pub(crate) fn get_index_dir_path(&self, test_id: &str) -> FcResult<PathBuf> {
+        self.conf.get_index_dir_path(&self.base_dir.get_path(test_id)?)
+    }
+
     /// Get a Write for the state file for this RepoTestSite.
     /// 
     /// Calling this assumes that our .set_up method has already
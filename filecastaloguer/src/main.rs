@@ -8,15 +8,17 @@ use filecastalogue::{
         state_collection::MiscStateFileCollection,
         tracked_ordinary_blob_collection::MiscTrackedOrdinaryBlobFileCollection
     },
-    journal::OptimisticDummyJournal,
+    journal::drivers::local::LocalJournal,
+    lock::drivers::local::LocalLock,
     opaque_collection_handler::drivers::local::LocalDir,
     repo::Repo,
 };
 use std::env::args;
 use std::result::Result;
-use std::{env::current_dir, ffi::OsString, path::PathBuf};
+use std::{env::current_dir, ffi::OsString, path::PathBuf, time::Duration};
 
 const ABOUT_REPO: &str = "Path to the repo directory. Defaults to the current directory.";
+const ABOUT_LOCK_TIMEOUT: &str = "Seconds to wait for others working on the repo to finish.";
 const ABOUT_VERSION: &str = "Manage state versions.";
 const ABOUT_ADD_VERSION: &str = "Add a new version with the specified ID to the state.";
 
@@ -32,27 +34,34 @@ const ABOUT_ADD_VERSION: &str = "Add a new version with the specified ID to the
 /// Doc goes here, example, variants
 fn create_local_repo(
     repo_path: PathBuf,
+    lock_timeout: Duration,
 ) -> Result<
     Repo<
         MiscStateFileCollection<LocalDir>,
         MiscIndexFileCollection<LocalDir>,
         MiscTrackedOrdinaryBlobFileCollection<LocalDir>,
-        OptimisticDummyJournal
+        LocalJournal
     >,
     Error,
 > {
     let blob_dir_path = PathBuf::from(&repo_path).join(OsString::from("blobs"));
-    // Indexes go into the same directory as blobs.
-    let index_dir_path = PathBuf::from(&repo_path).join(OsString::from("blobs"));
-    Ok(Repo::new(
-        MiscStateFileCollection::new(LocalDir::new(&repo_path), OsString::from("state.json")),
-        MiscIndexFileCollection::new(LocalDir::new(&index_dir_path)),
+    let index_dir_path = PathBuf::from(&repo_path).join(OsString::from("indexes"));
+    let journal_dir_path = PathBuf::from(&repo_path).join(OsString::from("journal"));
+    let lock_dir_path = PathBuf::from(&repo_path).join(OsString::from("lock"));
+    let state_collection = MiscStateFileCollection::new(
+        LocalDir::new(&repo_path), OsString::from("state.json"));
+    let mut repo = Repo::open_with_lock(
+        state_collection,
+        MiscIndexFileCollection::new(LocalDir::open(&index_dir_path)?),
         // TODO [prio:critical]: repo_path is actually wrong here,
         // it's just there to test the typing atm.
-        MiscTrackedOrdinaryBlobFileCollection::new(LocalDir::new(&blob_dir_path)),
-        OptimisticDummyJournal::new()
-
-    ))
+        MiscTrackedOrdinaryBlobFileCollection::new(LocalDir::open(&blob_dir_path)?),
+        LocalJournal::new(&journal_dir_path),
+        Box::new(LocalLock::new_with_timeout(&lock_dir_path, lock_timeout))
+    )?;
+    // Repos used to keep their indexes in the same directory as blobs.
+    repo.migrate_indexes_from(&mut LocalDir::open(&blob_dir_path)?)?;
+    Ok(repo)
 }
 
 // #[derive(Display)];
@@ -83,6 +92,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
                 .default_value_os(&default_repo_path)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("lock-timeout")
+                .long("lock-timeout")
+                .help(ABOUT_LOCK_TIMEOUT)
+                .default_value("10")
+                .takes_value(true),
+        )
         // .subcommand(SubCommand::with_name("version")
         //     .about(ABOUT_VERSION)
         //     .subcommand(SubCommand::with_name("add"))
@@ -266,6 +282,7 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .get_matches();
 
     //println!("{:?}", matches);
+    let lock_timeout = Duration::from_secs(value_t!(matches, "lock-timeout", u64)?);
     println!("{:#?}", matches);
 
     if let Some(matches) = matches.subcommand_matches("list") {
@@ -282,11 +299,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             if matches.is_present("path") {
                 //value_of("path") should be validated somewhere
                 let path = matches.value_of("path").unwrap();
-                create_local_repo(PathBuf::from(path))?;
+                create_local_repo(PathBuf::from(path), lock_timeout)?;
 
                 println!("create new repository in {} ", path);
             } else {
-                create_local_repo(PathBuf::from(&default_repo_path))?;
+                create_local_repo(PathBuf::from(&default_repo_path), lock_timeout)?;
                 println!("create new repository in {:?} ", default_repo_path);
             }
         }